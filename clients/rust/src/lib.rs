@@ -1,38 +1,128 @@
+use chrono::prelude::*;
 use reqwest;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::{Certificate, Method};
 use std::error::Error;
+use std::fs;
+use std::path::Path;
 use url;
 use url::Url;
 use uuid::Uuid;
 
+mod pages;
 #[cfg(test)]
 mod tests;
+mod transport;
 mod types;
 
+pub use crate::pages::Pages;
+#[cfg(unix)]
+use crate::transport::UnixTransport;
+use crate::transport::{Request, Transport};
 pub use crate::types::{
-    MessageIdList, MessageList, PullConfig, RawMessage, RawMessageList, Subscription,
-    SubscriptionCreateConfig, SubscriptionList, SubscriptionNameList, SubscriptionUpdateConfig,
-    Topic, TopicCreateConfig, TopicList, TopicUpdateConfig,
+    ApiError, Consumer, ConsumerList, DeliveryStatus, ErrorCode, ListOptions, Message,
+    MessageIdList, MessageList, MessageStatus, Namespace, NamespaceCreateConfig, NamespaceList,
+    NamespaceUpdateConfig, OffsetMessageList, OrderBy, Origin, PublishedMessageList, PullConfig,
+    PurgeResult, RawMessage, RawMessageList, ReadQuery, ReleaseResult, Subscription,
+    SubscriptionCreateConfig, SubscriptionDeliveryStatus, SubscriptionList, SubscriptionNameList,
+    SubscriptionUpdateConfig, Topic, TopicCreateConfig, TopicList, TopicPurgeConfig,
+    TopicUpdateConfig,
 };
 
 static HEARTBEAT_PATH: &'static str = "/api/v1/heartbeat";
+static ORIGIN_PATH: &str = "/api/v1/origin";
 static TOPICS_PATH: &'static str = "/api/v1/topics";
 static SUBSCRIPTIONS_PATH: &'static str = "/api/v1/subscriptions";
+static MESSAGES_PATH: &str = "/api/v1/messages";
+static NAMESPACES_PATH: &str = "/api/v1/namespaces";
+static PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
 
+/// Decode error responses of the courier service into an [ApiError](struct.ApiError.html).
+trait CheckStatus: Sized {
+    fn check_status(self) -> Result<Self, Box<dyn Error>>;
+}
+
+impl CheckStatus for reqwest::Response {
+    fn check_status(mut self) -> Result<Self, Box<dyn Error>> {
+        let status = self.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(self);
+        }
+        match self.json::<ApiError>() {
+            Ok(error) => Err(Box::new(error)),
+            Err(_) => Ok(self.error_for_status()?),
+        }
+    }
+}
+
+/// A client of the courier HTTP API.
+///
+/// Errors returned by the service can be inspected by downcasting to an
+/// [ApiError](struct.ApiError.html) and matching on its [ErrorCode](enum.ErrorCode.html).
 pub struct Client {
     base_url: url::Url,
-    http: reqwest::Client,
+    transport: Transport,
+    topics_path: String,
+    subscriptions_path: String,
+    messages_path: String,
 }
 
 impl Client {
     pub fn new(base_url: &str) -> Result<Self, Box<dyn Error>> {
+        Self::with_http_builder(base_url, reqwest::ClientBuilder::new())
+    }
+
+    /// Create a client which trusts the certificates of a PEM encoded CA bundle in addition to
+    /// the system's root certificates.
+    pub fn with_ca_bundle(base_url: &str, ca_bundle: &Path) -> Result<Self, Box<dyn Error>> {
+        let bundle = fs::read_to_string(ca_bundle)?;
+        let mut builder = reqwest::ClientBuilder::new();
+        for pem in bundle
+            .split_terminator(PEM_CERTIFICATE_END)
+            .filter(|pem| !pem.trim().is_empty())
+        {
+            let pem = format!("{}\n{}\n", pem.trim(), PEM_CERTIFICATE_END);
+            builder = builder.add_root_certificate(Certificate::from_pem(pem.as_bytes())?);
+        }
+        Self::with_http_builder(base_url, builder)
+    }
+
+    /// Create a client which sends its requests to a courier service serving the api on the Unix
+    /// domain socket at `path`.
+    #[cfg(unix)]
+    pub fn with_unix_socket(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut client = Self::new("http://localhost")?;
+        client.transport = Transport::Unix(Box::new(UnixTransport::new(path)?));
+        Ok(client)
+    }
+
+    fn with_http_builder(
+        base_url: &str,
+        builder: reqwest::ClientBuilder,
+    ) -> Result<Self, Box<dyn Error>> {
         let base_url = Url::parse(base_url)?;
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        let http = reqwest::ClientBuilder::new()
-            .default_headers(headers)
-            .build()?;
-        Ok(Client { base_url, http })
+        let http = builder.default_headers(headers).build()?;
+        Ok(Client {
+            base_url,
+            transport: Transport::Tcp(http),
+            topics_path: String::from(TOPICS_PATH),
+            subscriptions_path: String::from(SUBSCRIPTIONS_PATH),
+            messages_path: String::from(MESSAGES_PATH),
+        })
+    }
+
+    fn request(&self, method: Method, url: Url) -> Request<'_> {
+        Request::new(&self.transport, method, url)
+    }
+
+    /// Scope all topic, subscription, and message operations of the client to a namespace.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.topics_path = format!("{}/{}/topics", NAMESPACES_PATH, namespace);
+        self.subscriptions_path = format!("{}/{}/subscriptions", NAMESPACES_PATH, namespace);
+        self.messages_path = format!("{}/{}/messages", NAMESPACES_PATH, namespace);
+        self
     }
 
     pub fn heartbeat(&self) -> bool {
@@ -40,24 +130,95 @@ impl Client {
             Ok(url) => url,
             Err(_) => return false,
         };
-        match self.http.put(url).send() {
+        match self.request(Method::PUT, url).send() {
             Ok(_) => true,
             Err(_) => false,
         }
     }
 
+    /// Get the name of the instance which is attached to the messages it bridges.
+    pub fn get_origin(&self) -> Result<Origin, Box<dyn Error>> {
+        let url = self.base_url.join(ORIGIN_PATH)?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    pub fn create_namespace(
+        &self,
+        name: &str,
+        config: &NamespaceCreateConfig,
+    ) -> Result<Namespace, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", NAMESPACES_PATH, name))?;
+        Ok(self
+            .request(Method::PUT, url)
+            .json(config)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    pub fn update_namespace(
+        &self,
+        name: &str,
+        config: &NamespaceUpdateConfig,
+    ) -> Result<Namespace, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", NAMESPACES_PATH, name))?;
+        Ok(self
+            .request(Method::PATCH, url)
+            .json(config)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    pub fn delete_namespace(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", NAMESPACES_PATH, name))?;
+        self.request(Method::DELETE, url).send()?.check_status()?;
+        Ok(())
+    }
+
+    pub fn get_namespace(&self, name: &str) -> Result<Namespace, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", NAMESPACES_PATH, name))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    pub fn list_namespaces(&self) -> Result<NamespaceList, Box<dyn Error>> {
+        let url = self.base_url.join(&format!("{}/", NAMESPACES_PATH))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
     pub fn create_topic(
         &self,
         name: &str,
         config: &TopicCreateConfig,
     ) -> Result<Topic, Box<dyn Error>> {
-        let url = self.base_url.join(&format!("{}/{}", TOPICS_PATH, name))?;
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", self.topics_path, name))?;
         Ok(self
-            .http
-            .put(url)
+            .request(Method::PUT, url)
             .json(config)
             .send()?
-            .error_for_status()?
+            .check_status()?
             .json()?)
     }
 
@@ -73,61 +234,208 @@ impl Client {
         name: &str,
         config: &TopicUpdateConfig,
     ) -> Result<Topic, Box<dyn Error>> {
-        let url = self.base_url.join(&format!("{}/{}", TOPICS_PATH, name))?;
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", self.topics_path, name))?;
         Ok(self
-            .http
-            .patch(url)
+            .request(Method::PATCH, url)
             .json(&config)
             .send()?
-            .error_for_status()?
+            .check_status()?
             .json()?)
     }
 
     pub fn delete_topic(&self, name: &str) -> Result<(), Box<dyn Error>> {
-        let url = self.base_url.join(&format!("{}/{}", TOPICS_PATH, name))?;
-        self.http.delete(url).send()?.error_for_status()?;
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", self.topics_path, name))?;
+        self.request(Method::DELETE, url).send()?.check_status()?;
         Ok(())
     }
 
     pub fn get_topic(&self, name: &str) -> Result<Topic, Box<dyn Error>> {
-        let url = self.base_url.join(&format!("{}/{}", TOPICS_PATH, name))?;
-        Ok(self.http.get(url).send()?.error_for_status()?.json()?)
+        let url = self
+            .base_url
+            .join(&format!("{}/{}", self.topics_path, name))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
     }
 
     pub fn list_topics(&self) -> Result<TopicList, Box<dyn Error>> {
-        let url = self.base_url.join(&format!("{}/", TOPICS_PATH))?;
-        Ok(self.http.get(url).send()?.error_for_status()?.json()?)
+        self.list_topics_page(&ListOptions::new())
+    }
+
+    pub fn list_topics_page(&self, options: &ListOptions) -> Result<TopicList, Box<dyn Error>> {
+        let url = self.base_url.join(&format!("{}/", self.topics_path))?;
+        Ok(self
+            .request(Method::GET, url)
+            .query(options)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Iterate over all topics matching the options requesting a page at a time.
+    pub fn iter_topics(&self, options: ListOptions) -> Pages<'_, Topic> {
+        Pages::new(
+            options,
+            Box::new(move |options| {
+                let page = self.list_topics_page(options)?;
+                Ok((page.topics, page.next_page_token))
+            }),
+        )
     }
 
-    pub fn publish_one(&self, topic: &str, data: String) -> Result<MessageIdList, Box<dyn Error>> {
+    pub fn publish_one(
+        &self,
+        topic: &str,
+        data: String,
+    ) -> Result<PublishedMessageList, Box<dyn Error>> {
         self.publish(topic, vec![data])
     }
 
-    pub fn publish(&self, topic: &str, data: Vec<String>) -> Result<MessageIdList, Box<dyn Error>> {
+    pub fn publish(
+        &self,
+        topic: &str,
+        data: Vec<String>,
+    ) -> Result<PublishedMessageList, Box<dyn Error>> {
+        self.publish_raw(topic, data.into_iter().map(RawMessage::new).collect())
+    }
+
+    /// Publish messages which may have a partition key.
+    pub fn publish_raw(
+        &self,
+        topic: &str,
+        raw_messages: Vec<RawMessage>,
+    ) -> Result<PublishedMessageList, Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}/publish", TOPICS_PATH, topic))?;
-        let mut raw_messages = Vec::with_capacity(data.len());
-        for d in data {
-            raw_messages.push(RawMessage::new(d));
-        }
+            .join(&format!("{}/{}/publish", self.topics_path, topic))?;
         Ok(self
-            .http
-            .post(url)
+            .request(Method::POST, url)
             .json(&RawMessageList::new(raw_messages))
             .send()?
-            .error_for_status()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Read messages of a topic starting at `from_offset` without a subscription.
+    ///
+    /// Reading starts at the oldest message if `from_offset` is None. Pass the `next_offset` of
+    /// the result as the `from_offset` of the next read.
+    pub fn read(
+        &self,
+        topic: &str,
+        from_offset: Option<u64>,
+        limit: Option<usize>,
+    ) -> Result<OffsetMessageList, Box<dyn Error>> {
+        self.read_partition(topic, 0, from_offset, limit)
+    }
+
+    /// Read messages of a partition of a topic starting at `from_offset` without a subscription.
+    pub fn read_partition(
+        &self,
+        topic: &str,
+        partition: u32,
+        from_offset: Option<u64>,
+        limit: Option<usize>,
+    ) -> Result<OffsetMessageList, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}/messages", self.topics_path, topic))?;
+        Ok(self
+            .request(Method::GET, url)
+            .query(&ReadQuery {
+                partition: Some(partition),
+                from_offset,
+                limit,
+            })
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Purge all messages of a topic or only the messages published before `before`.
+    pub fn purge_topic(
+        &self,
+        topic: &str,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<PurgeResult, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}/purge", self.topics_path, topic))?;
+        Ok(self
+            .request(Method::POST, url)
+            .json(&TopicPurgeConfig { before })
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Get a message of a topic by id if it is still retained.
+    pub fn get_message(&self, topic: &str, id: Uuid) -> Result<Message, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}/messages/{}", self.topics_path, topic, id))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Get the delivery status of a retained message for every subscription of its topic.
+    pub fn get_message_status(&self, id: Uuid) -> Result<MessageStatus, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/{}/status", self.messages_path, id))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
             .json()?)
     }
 
     pub fn get_topic_subscriptions(
         &self,
         topic: &str,
+    ) -> Result<SubscriptionNameList, Box<dyn Error>> {
+        self.get_topic_subscriptions_page(topic, &ListOptions::new())
+    }
+
+    pub fn get_topic_subscriptions_page(
+        &self,
+        topic: &str,
+        options: &ListOptions,
     ) -> Result<SubscriptionNameList, Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}/subscriptions", TOPICS_PATH, topic))?;
-        Ok(self.http.get(url).send()?.error_for_status()?.json()?)
+            .join(&format!("{}/{}/subscriptions", self.topics_path, topic))?;
+        Ok(self
+            .request(Method::GET, url)
+            .query(options)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Iterate over the names of all subscriptions to a topic matching the options requesting a
+    /// page at a time.
+    pub fn iter_topic_subscriptions<'a>(
+        &'a self,
+        topic: &'a str,
+        options: ListOptions,
+    ) -> Pages<'a, String> {
+        Pages::new(
+            options,
+            Box::new(move |options| {
+                let page = self.get_topic_subscriptions_page(topic, options)?;
+                Ok((page.subscription_names, page.next_page_token))
+            }),
+        )
     }
 
     pub fn create_subscription(
@@ -137,13 +445,12 @@ impl Client {
     ) -> Result<Subscription, Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}", SUBSCRIPTIONS_PATH, name))?;
+            .join(&format!("{}/{}", self.subscriptions_path, name))?;
         Ok(self
-            .http
-            .put(url)
+            .request(Method::PUT, url)
             .json(config)
             .send()?
-            .error_for_status()?
+            .check_status()?
             .json()?)
     }
 
@@ -161,34 +468,62 @@ impl Client {
     ) -> Result<Subscription, Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}", SUBSCRIPTIONS_PATH, name))?;
+            .join(&format!("{}/{}", self.subscriptions_path, name))?;
         Ok(self
-            .http
-            .patch(url)
+            .request(Method::PATCH, url)
             .json(&config)
             .send()?
-            .error_for_status()?
+            .check_status()?
             .json()?)
     }
 
     pub fn delete_subscription(&self, name: &str) -> Result<(), Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}", SUBSCRIPTIONS_PATH, name))?;
-        self.http.delete(url).send()?.error_for_status()?;
+            .join(&format!("{}/{}", self.subscriptions_path, name))?;
+        self.request(Method::DELETE, url).send()?.check_status()?;
         Ok(())
     }
 
     pub fn get_subscription(&self, name: &str) -> Result<Subscription, Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}", SUBSCRIPTIONS_PATH, name))?;
-        Ok(self.http.get(url).send()?.error_for_status()?.json()?)
+            .join(&format!("{}/{}", self.subscriptions_path, name))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
     }
 
     pub fn list_subscriptions(&self) -> Result<SubscriptionList, Box<dyn Error>> {
-        let url = self.base_url.join(&format!("{}/", SUBSCRIPTIONS_PATH))?;
-        Ok(self.http.get(url).send()?.error_for_status()?.json()?)
+        self.list_subscriptions_page(&ListOptions::new())
+    }
+
+    pub fn list_subscriptions_page(
+        &self,
+        options: &ListOptions,
+    ) -> Result<SubscriptionList, Box<dyn Error>> {
+        let url = self
+            .base_url
+            .join(&format!("{}/", self.subscriptions_path))?;
+        Ok(self
+            .request(Method::GET, url)
+            .query(options)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Iterate over all subscriptions matching the options requesting a page at a time.
+    pub fn iter_subscriptions(&self, options: ListOptions) -> Pages<'_, Subscription> {
+        Pages::new(
+            options,
+            Box::new(move |options| {
+                let page = self.list_subscriptions_page(options)?;
+                Ok((page.subscriptions, page.next_page_token))
+            }),
+        )
     }
 
     pub fn pull_one(&self, subscription: &str) -> Result<MessageList, Box<dyn Error>> {
@@ -200,15 +535,81 @@ impl Client {
         subscription: &str,
         max_messages: usize,
     ) -> Result<MessageList, Box<dyn Error>> {
-        let url = self
-            .base_url
-            .join(&format!("{}/{}/pull", SUBSCRIPTIONS_PATH, subscription))?;
+        self.pull_partitions(subscription, max_messages, None)
+    }
+
+    /// Pull messages only from the listed partitions or from all partitions if `partitions` is
+    /// None.
+    pub fn pull_partitions(
+        &self,
+        subscription: &str,
+        max_messages: usize,
+        partitions: Option<Vec<u32>>,
+    ) -> Result<MessageList, Box<dyn Error>> {
+        let mut config = PullConfig::new(max_messages);
+        config.partitions = partitions;
+        self.pull_with_config(subscription, &config)
+    }
+
+    /// Pull messages with all options, set the `consumer_id` of the config so the messages held
+    /// by this consumer can be listed and released.
+    pub fn pull_with_config(
+        &self,
+        subscription: &str,
+        config: &PullConfig,
+    ) -> Result<MessageList, Box<dyn Error>> {
+        let url = self.base_url.join(&format!(
+            "{}/{}/pull",
+            self.subscriptions_path, subscription
+        ))?;
+        Ok(self
+            .request(Method::POST, url)
+            .json(config)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// List the consumers of a subscription which identified themselves when pulling.
+    pub fn get_consumers(&self, subscription: &str) -> Result<ConsumerList, Box<dyn Error>> {
+        let url = self.base_url.join(&format!(
+            "{}/{}/consumers",
+            self.subscriptions_path, subscription
+        ))?;
+        Ok(self
+            .request(Method::GET, url)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Release all messages held by a consumer so they are resent immediately.
+    pub fn release_consumer(
+        &self,
+        subscription: &str,
+        consumer_id: &str,
+    ) -> Result<ReleaseResult, Box<dyn Error>> {
+        let url = self.base_url.join(&format!(
+            "{}/{}/consumers/{}/release",
+            self.subscriptions_path, subscription, consumer_id
+        ))?;
+        Ok(self
+            .request(Method::POST, url)
+            .send()?
+            .check_status()?
+            .json()?)
+    }
+
+    /// Purge the backlog of a subscription as if every message was acked.
+    pub fn purge_subscription(&self, subscription: &str) -> Result<PurgeResult, Box<dyn Error>> {
+        let url = self.base_url.join(&format!(
+            "{}/{}/purge",
+            self.subscriptions_path, subscription
+        ))?;
         Ok(self
-            .http
-            .post(url)
-            .json(&PullConfig::new(max_messages))
+            .request(Method::POST, url)
             .send()?
-            .error_for_status()?
+            .check_status()?
             .json()?)
     }
 
@@ -227,13 +628,12 @@ impl Client {
     ) -> Result<MessageIdList, Box<dyn Error>> {
         let url = self
             .base_url
-            .join(&format!("{}/{}/ack", SUBSCRIPTIONS_PATH, subscription))?;
+            .join(&format!("{}/{}/ack", self.subscriptions_path, subscription))?;
         Ok(self
-            .http
-            .post(url)
+            .request(Method::POST, url)
             .json(&MessageIdList::new(message_ids))
             .send()?
-            .error_for_status()?
+            .check_status()?
             .json()?)
     }
 }
@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::vec;
+
+use crate::types::ListOptions;
+
+type Fetch<'a, T> =
+    Box<dyn FnMut(&ListOptions) -> Result<(Vec<T>, Option<String>), Box<dyn Error>> + 'a>;
+
+/// An iterator over every item of a listing which lazily requests one page at a time.
+///
+/// Iteration stops after the first error.
+pub struct Pages<'a, T> {
+    fetch: Fetch<'a, T>,
+    options: ListOptions,
+    items: vec::IntoIter<T>,
+    done: bool,
+}
+
+impl<'a, T> Pages<'a, T> {
+    pub(crate) fn new(options: ListOptions, fetch: Fetch<'a, T>) -> Self {
+        Self {
+            fetch,
+            options,
+            items: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+}
+
+impl<'a, T> Iterator for Pages<'a, T> {
+    type Item = Result<T, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.items.next() {
+                return Some(Ok(item));
+            }
+            if self.done {
+                return None;
+            }
+            match (self.fetch)(&self.options) {
+                Ok((items, next_page_token)) => {
+                    self.items = items.into_iter();
+                    self.done = next_page_token.is_none();
+                    self.options.page_token = next_page_token;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::types::*;
 use crate::Client;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::iter::FromIterator;
 use uuid::Uuid;
 
@@ -26,6 +26,9 @@ fn client() {
             &TopicCreateConfig {
                 ttl: Some(3600),
                 message_ttl: Some(60),
+                partitions: None,
+                labels: None,
+                description: None,
             },
         )
         .unwrap();
@@ -38,9 +41,13 @@ fn client() {
             &sub_name,
             &SubscriptionCreateConfig {
                 topic: String::from(topic_name),
+                topic_namespace: None,
                 ttl: Some(3600),
+                consumer_timeout: None,
                 ack_deadline: Some(60),
                 historical: Some(false),
+                labels: None,
+                description: None,
             },
         )
         .unwrap();
@@ -56,12 +63,19 @@ fn client() {
             &TopicUpdateConfig {
                 ttl: Some(5000),
                 message_ttl: Some(12),
+                labels: Some(BTreeMap::from_iter(vec![(
+                    String::from("team"),
+                    String::from("rust"),
+                )])),
+                description: Some(String::from("Rust client topic")),
             },
         )
         .unwrap();
     assert_eq!(topic.name, topic_name);
     assert_eq!(topic.ttl, 5000);
     assert_eq!(topic.message_ttl, 12);
+    assert_eq!(topic.labels.get("team").map(String::as_str), Some("rust"));
+    assert_eq!(topic.description, "Rust client topic");
 
     let sub = client
         .update_subscription(
@@ -69,6 +83,9 @@ fn client() {
             &SubscriptionUpdateConfig {
                 ttl: Some(12000),
                 ack_deadline: Some(72),
+                consumer_timeout: None,
+                labels: None,
+                description: None,
             },
         )
         .unwrap();
@@ -94,6 +111,9 @@ fn client() {
         .create_topic_with_uuid(&TopicCreateConfig {
             ttl: None,
             message_ttl: None,
+            partitions: None,
+            labels: None,
+            description: None,
         })
         .unwrap();
     let topics = client.list_topics().unwrap().topics;
@@ -105,9 +125,13 @@ fn client() {
     let sub = client
         .create_subscription_with_uuid(&SubscriptionCreateConfig {
             topic: String::from(topic_name),
+            topic_namespace: None,
             ttl: None,
+            consumer_timeout: None,
             ack_deadline: None,
             historical: None,
+            labels: None,
+            description: None,
         })
         .unwrap();
     let subs = client.list_subscriptions().unwrap().subscriptions;
@@ -122,6 +146,29 @@ fn client() {
         .subscription_names;
     assert_eq!(sub_names_truth, HashSet::from_iter(sub_names));
 
+    // Test iterating a page at a time
+    let mut options = ListOptions::new();
+    options.page_size = Some(1);
+    let sub_names = client
+        .iter_subscriptions(options.clone())
+        .map(|s| s.unwrap().name);
+    assert_eq!(sub_names_truth, HashSet::from_iter(sub_names));
+    let sub_names = client
+        .iter_topic_subscriptions(topic_name, options.clone())
+        .map(|s| s.unwrap());
+    assert_eq!(sub_names_truth, HashSet::from_iter(sub_names));
+    let topic_names = client.iter_topics(options).map(|t| t.unwrap().name);
+    assert_eq!(topic_names_truth, HashSet::from_iter(topic_names));
+
+    // Test selecting by labels
+    let mut options = ListOptions::new();
+    options.labels = Some(String::from("team=rust"));
+    let topic_names: Vec<String> = client
+        .iter_topics(options)
+        .map(|t| t.unwrap().name)
+        .collect();
+    assert_eq!(topic_names, vec![String::from(topic_name)]);
+
     // Test delete and list
     client.delete_subscription(&sub.name).unwrap();
     let subs = client.list_subscriptions().unwrap().subscriptions;
@@ -130,6 +177,9 @@ fn client() {
     assert_eq!(sub_names_truth, HashSet::from_iter(sub_names));
 
     client.delete_topic(&topic.name).unwrap();
+    let error = client.get_topic(&topic.name).unwrap_err();
+    let error = error.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(error.code, ErrorCode::TopicNotFound);
     let topics = client.list_topics().unwrap().topics;
     let topic_names = topics.iter().map(|t| t.name.clone());
     let topic_names_truth: HashSet<String> = HashSet::from_iter(vec![String::from(topic_name)]);
@@ -154,6 +204,16 @@ fn client() {
     assert_eq!(message2.data, "data2");
     assert_eq!(message3.data, "data3");
 
+    let message = client.get_message(topic_name, message1.id).unwrap();
+    assert_eq!(message.data, "data1");
+    let status = client.get_message_status(message1.id).unwrap();
+    assert_eq!(status.topic, topic_name);
+    assert_eq!(status.subscriptions.len(), 1);
+    match status.subscriptions[0].status {
+        DeliveryStatus::Pending { tries: 1, .. } => (),
+        ref status => panic!("unexpected status {:?}", status),
+    }
+
     let message_ids = client
         .ack(sub_name, vec![message1.id, message2.id, message3.id])
         .unwrap()
@@ -162,6 +222,75 @@ fn client() {
         HashSet::from_iter(vec![message1.id, message2.id, message3.id]);
     assert_eq!(message_ids_truth, HashSet::from_iter(message_ids));
 
+    // Test reading by offset
+    let read = client.read(topic_name, None, None).unwrap();
+    assert_eq!(read.messages[0].id, message1.id);
+    let read = client
+        .read(topic_name, Some(message2.offset), Some(2))
+        .unwrap();
+    let read_ids: Vec<Uuid> = read.messages.iter().map(|m| m.id).collect();
+    assert_eq!(read_ids, vec![message2.id, message3.id]);
+    assert_eq!(read.next_offset, message3.offset + 1);
+
+    // Test purge
+    client
+        .publish(
+            topic_name,
+            vec![String::from("data4"), String::from("data5")],
+        )
+        .unwrap();
+
+    // Test consumers
+    let mut config = PullConfig::new(1);
+    config.consumer_id = Some(String::from("rust-consumer"));
+    let message4 = &client.pull_with_config(sub_name, &config).unwrap().messages[0];
+    assert_eq!(message4.data, "data4");
+    let consumers = client.get_consumers(sub_name).unwrap().consumers;
+    assert_eq!(consumers.len(), 1);
+    assert_eq!(consumers[0].id, "rust-consumer");
+    assert_eq!(consumers[0].in_flight, 1);
+    let released = client.release_consumer(sub_name, "rust-consumer").unwrap();
+    assert_eq!(released.released, 1);
+    assert!(client.get_consumers(sub_name).unwrap().consumers.is_empty());
+
+    assert_eq!(client.purge_subscription(sub_name).unwrap().purged, 2);
+    assert!(client.pull_one(sub_name).unwrap().messages.is_empty());
+    assert_eq!(client.purge_topic(topic_name, None).unwrap().purged, 5);
+    assert!(client
+        .read(topic_name, None, None)
+        .unwrap()
+        .messages
+        .is_empty());
+
+    // Test partitions
+    let partitioned = client
+        .create_topic_with_uuid(&TopicCreateConfig {
+            ttl: None,
+            message_ttl: None,
+            partitions: Some(2),
+            labels: None,
+            description: None,
+        })
+        .unwrap();
+    assert_eq!(partitioned.partitions, 2);
+    let published = client
+        .publish_raw(
+            &partitioned.name,
+            vec![
+                RawMessage::with_partition_key(String::from("data1"), String::from("key")),
+                RawMessage::with_partition_key(String::from("data2"), String::from("key")),
+            ],
+        )
+        .unwrap();
+    let partition = published.partitions[0];
+    assert_eq!(published.partitions[1], partition);
+    let read = client
+        .read_partition(&partitioned.name, partition, None, Some(10))
+        .unwrap();
+    let read_ids: Vec<Uuid> = read.messages.iter().map(|m| m.id).collect();
+    assert_eq!(read_ids, published.message_ids);
+    let _ = client.delete_topic(&partitioned.name);
+
     // Delete what we created
     let _ = client.delete_subscription(sub_name);
     let _ = client.delete_topic(topic_name);
@@ -0,0 +1,140 @@
+#[cfg(unix)]
+use futures::{Future, Stream};
+#[cfg(unix)]
+use hyper::client::connect::{Connect, Connected, Destination};
+use reqwest::Method;
+use serde::Serialize;
+use std::error::Error;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::Mutex;
+#[cfg(unix)]
+use tokio::runtime::Runtime;
+#[cfg(unix)]
+use tokio_uds::UnixStream;
+use url::Url;
+
+/// How a client reaches the courier service.
+pub(crate) enum Transport {
+    Tcp(reqwest::Client),
+    /// Plain HTTP over a Unix domain socket.
+    #[cfg(unix)]
+    Unix(Box<UnixTransport>),
+}
+
+/// A request which is sent over the transport of a client.
+///
+/// Like a `reqwest::RequestBuilder`, errors building the request are returned when it is sent.
+pub(crate) struct Request<'a> {
+    transport: &'a Transport,
+    method: Method,
+    url: Url,
+    body: Option<Vec<u8>>,
+    error: Option<Box<dyn Error>>,
+}
+
+impl<'a> Request<'a> {
+    pub(crate) fn new(transport: &'a Transport, method: Method, url: Url) -> Self {
+        Request {
+            transport,
+            method,
+            url,
+            body: None,
+            error: None,
+        }
+    }
+
+    pub(crate) fn json<T: Serialize + ?Sized>(mut self, json: &T) -> Self {
+        match serde_json::to_vec(json) {
+            Ok(body) => self.body = Some(body),
+            Err(e) => self.error = Some(Box::new(e)),
+        }
+        self
+    }
+
+    pub(crate) fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Self {
+        match serde_urlencoded::to_string(query) {
+            Ok(ref query) if query.is_empty() => {}
+            Ok(query) => self.url.set_query(Some(&query)),
+            Err(e) => self.error = Some(Box::new(e)),
+        }
+        self
+    }
+
+    pub(crate) fn send(self) -> Result<reqwest::Response, Box<dyn Error>> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        match self.transport {
+            Transport::Tcp(http) => {
+                let mut request = http.request(self.method, self.url);
+                if let Some(body) = self.body {
+                    request = request.body(body);
+                }
+                Ok(request.send()?)
+            }
+            #[cfg(unix)]
+            Transport::Unix(unix) => unix.send(self.method, &self.url, self.body),
+        }
+    }
+}
+
+/// Connects hyper to a Unix domain socket whatever the host of the request is.
+#[cfg(unix)]
+struct UnixConnector(PathBuf);
+
+#[cfg(unix)]
+impl Connect for UnixConnector {
+    type Transport = UnixStream;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (UnixStream, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, _: Destination) -> Self::Future {
+        Box::new(UnixStream::connect(&self.0).map(|stream| (stream, Connected::new())))
+    }
+}
+
+/// A hyper client on a Unix domain socket and the runtime its requests are run on.
+#[cfg(unix)]
+pub(crate) struct UnixTransport {
+    http: hyper::Client<UnixConnector>,
+    runtime: Mutex<Runtime>,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub(crate) fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(UnixTransport {
+            http: hyper::Client::builder().build(UnixConnector(path.to_path_buf())),
+            runtime: Mutex::new(Runtime::new()?),
+        })
+    }
+
+    // Send a request and wait for the whole response.
+    fn send(
+        &self,
+        method: Method,
+        url: &Url,
+        body: Option<Vec<u8>>,
+    ) -> Result<reqwest::Response, Box<dyn Error>> {
+        let request = hyper::Request::builder()
+            .method(method)
+            .uri(url.as_str())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(body.unwrap_or_default()))?;
+        let response = self.http.request(request).and_then(|response| {
+            let (parts, body) = response.into_parts();
+            body.concat2()
+                .map(|body| http::Response::from_parts(parts, body.to_vec()))
+        });
+        let response = self
+            .runtime
+            .lock()
+            .map_err(|_| "The runtime of the client panicked")?
+            .block_on(response)?;
+        Ok(reqwest::Response::from(response))
+    }
+}
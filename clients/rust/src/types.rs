@@ -1,31 +1,125 @@
 use chrono::prelude::*;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
 use uuid::Uuid;
 
+/// A stable code identifying the kind of an [ApiError](struct.ApiError.html).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    NamespaceNotFound,
+    NamespaceAlreadyExists,
+    NamespaceNotShared,
+    TopicNotFound,
+    TopicAlreadyExists,
+    SubscriptionNotFound,
+    SubscriptionAlreadyExists,
+    MessageNotFound,
+    OffsetExpired,
+    PartitionNotFound,
+    ConsumerNotFound,
+    InvalidRequest,
+    RouteNotFound,
+    Unavailable,
+    Internal,
+}
+
+/// An error returned by the courier service.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ApiError {
+    /// Code identifying the kind of error.
+    pub code: ErrorCode,
+    /// Human readable description of the error.
+    pub message: String,
+    /// The resource the error refers to if any.
+    pub resource: Option<String>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl Error for ApiError {}
+
 /// A message which can be published to a [Topic](struct.Topic.html).
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct Message {
     /// Unique identifier for this message.
     pub id: Uuid,
+    /// Partition of its topic the message was published to.
+    pub partition: u32,
+    /// Position of the message in its partition.
+    pub offset: u64,
     /// Time the message was published.
     pub time: DateTime<Utc>,
     /// Number of times the message has been tried (pulled).
     pub tries: u32,
     /// Actual message data.
     pub data: String,
+    /// Name of the Courier instance the message was first published to if it was bridged from
+    /// another instance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+/// The delivery status of a [Message](struct.Message.html) for a subscription.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum DeliveryStatus {
+    /// The message has not been pulled yet.
+    NotDelivered,
+    /// The message was pulled and is waiting to be acked before its deadline.
+    Pending { tries: u32, deadline: DateTime<Utc> },
+    /// The message was pulled but not acked before its deadline, it will be resent.
+    Expired { tries: u32, deadline: DateTime<Utc> },
+    /// The message was acked.
+    Acked,
+}
+
+/// The [DeliveryStatus](enum.DeliveryStatus.html) of a message for a single subscription.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubscriptionDeliveryStatus {
+    pub namespace: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+}
+
+/// A retained [Message](struct.Message.html) and its delivery status for every subscription of
+/// its topic.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MessageStatus {
+    pub topic_namespace: String,
+    pub topic: String,
+    pub message: Message,
+    pub subscriptions: Vec<SubscriptionDeliveryStatus>,
 }
 
 /// A subscription meta type used for serialization.
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct Subscription {
-    /// Unique name for this subscription.
+    /// Namespace of the subscription.
+    pub namespace: String,
+    /// Unique name for this subscription within its namespace.
     pub name: String,
+    /// Namespace of the topic the subscription is subscribed to.
+    pub topic_namespace: String,
     /// Topic name the subscription is subscribed to.
     pub topic: String,
     /// Amount of time given to ack a message in seconds.
     pub ack_deadline: i64,
     /// Time to live of the subscription in seconds.
     pub ttl: i64,
+    /// Time after which the messages of an inactive consumer are released in seconds.
+    pub consumer_timeout: i64,
+    /// Labels of the subscription.
+    pub labels: BTreeMap<String, String>,
+    /// Description of the subscription.
+    pub description: String,
     /// Time the subscription was created.
     pub created: DateTime<Utc>,
     /// Time the subscription was last updated.
@@ -35,22 +129,79 @@ pub struct Subscription {
 /// A topic meta type used for serialization.
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct Topic {
-    /// Unique name of the topic.
+    /// Namespace of the topic.
+    pub namespace: String,
+    /// Unique name of the topic within its namespace.
     pub name: String,
     /// Message time to live in seconds.
     pub message_ttl: i64,
     /// Time to live of the topic in seconds.
     pub ttl: i64,
+    /// Number of partitions of the topic.
+    pub partitions: u32,
+    /// Labels of the topic.
+    pub labels: BTreeMap<String, String>,
+    /// Description of the topic.
+    pub description: String,
     /// Time the topic was created.
     pub created: DateTime<Utc>,
     /// Time the topic was updated.
     pub updated: DateTime<Utc>,
 }
 
+/// A namespace meta type used for serialization.
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Namespace {
+    /// Unique name of the namespace.
+    pub name: String,
+    /// Default message time to live in seconds of topics in the namespace.
+    pub default_message_ttl: Option<i64>,
+    /// Default time to live in seconds of topics in the namespace.
+    pub default_topic_ttl: Option<i64>,
+    /// Default time to live in seconds of subscriptions in the namespace.
+    pub default_subscription_ttl: Option<i64>,
+    /// Other namespaces allowed to subscribe to topics in the namespace.
+    pub shared_with: Vec<String>,
+    /// Time the namespace was created.
+    pub created: DateTime<Utc>,
+    /// Time the namespace was updated.
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct NamespaceCreateConfig {
+    pub default_message_ttl: Option<u32>,
+    pub default_topic_ttl: Option<u32>,
+    pub default_subscription_ttl: Option<u32>,
+    pub shared_with: Option<Vec<String>>,
+}
+
+impl NamespaceCreateConfig {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct NamespaceUpdateConfig {
+    pub default_message_ttl: Option<u32>,
+    pub default_topic_ttl: Option<u32>,
+    pub default_subscription_ttl: Option<u32>,
+    pub shared_with: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct NamespaceList {
+    pub namespaces: Vec<Namespace>,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct TopicCreateConfig {
     pub message_ttl: Option<u32>,
     pub ttl: Option<u32>,
+    pub partitions: Option<u32>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub description: Option<String>,
 }
 
 impl TopicCreateConfig {
@@ -63,27 +214,89 @@ impl TopicCreateConfig {
 pub struct TopicUpdateConfig {
     pub message_ttl: Option<u32>,
     pub ttl: Option<u32>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct TopicList {
     pub topics: Vec<Topic>,
+    pub next_page_token: Option<String>,
 }
 
 impl TopicList {
-    pub fn new(topics: Vec<Topic>) -> Self {
-        Self { topics }
+    pub fn new(topics: Vec<Topic>, next_page_token: Option<String>) -> Self {
+        Self {
+            topics,
+            next_page_token,
+        }
+    }
+}
+
+/// The order of a listing.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBy {
+    /// Order by name.
+    #[default]
+    Name,
+    /// Order by creation time, ties are ordered by name.
+    Created,
+}
+
+/// Options to filter, order, and page a listing.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct ListOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Label selector, for example `team=payments,env!=dev`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<String>,
+    pub order_by: OrderBy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_token: Option<String>,
+}
+
+impl ListOptions {
+    pub fn new() -> Self {
+        Default::default()
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct RawMessage {
     pub data: String,
+    /// Messages with the same key are published to the same partition.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_key: Option<String>,
+    /// Partition to publish the message to, takes precedence over the partition key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<u32>,
+    /// Name of the Courier instance the message was first published to, set when bridging
+    /// messages between instances.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
 }
 
 impl RawMessage {
     pub fn new(data: String) -> Self {
-        Self { data }
+        Self {
+            data,
+            partition_key: None,
+            partition: None,
+            origin: None,
+        }
+    }
+
+    pub fn with_partition_key(data: String, partition_key: String) -> Self {
+        Self {
+            data,
+            partition_key: Some(partition_key),
+            partition: None,
+            origin: None,
+        }
     }
 }
 
@@ -98,6 +311,13 @@ impl RawMessageList {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct PublishedMessageList {
+    pub message_ids: Vec<Uuid>,
+    pub partitions: Vec<u32>,
+    pub offsets: Vec<u64>,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct MessageIdList {
     pub message_ids: Vec<Uuid>,
@@ -109,26 +329,45 @@ impl MessageIdList {
     }
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct TopicPurgeConfig {
+    pub before: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct PurgeResult {
+    pub purged: usize,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct SubscriptionNameList {
     pub subscription_names: Vec<String>,
+    pub next_page_token: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct SubscriptionCreateConfig {
     pub topic: String,
+    pub topic_namespace: Option<String>,
     pub ack_deadline: Option<u32>,
     pub ttl: Option<u32>,
+    pub consumer_timeout: Option<u32>,
     pub historical: Option<bool>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub description: Option<String>,
 }
 
 impl SubscriptionCreateConfig {
     pub fn new(topic: &str) -> Self {
         SubscriptionCreateConfig {
             topic: String::from(topic),
+            topic_namespace: None,
             ack_deadline: None,
             ttl: None,
+            consumer_timeout: None,
             historical: None,
+            labels: None,
+            description: None,
         }
     }
 }
@@ -137,16 +376,23 @@ impl SubscriptionCreateConfig {
 pub struct SubscriptionUpdateConfig {
     pub ack_deadline: Option<u32>,
     pub ttl: Option<u32>,
+    pub consumer_timeout: Option<u32>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct SubscriptionList {
     pub subscriptions: Vec<Subscription>,
+    pub next_page_token: Option<String>,
 }
 
 impl SubscriptionList {
-    pub fn new(subscriptions: Vec<Subscription>) -> Self {
-        Self { subscriptions }
+    pub fn new(subscriptions: Vec<Subscription>, next_page_token: Option<String>) -> Self {
+        Self {
+            subscriptions,
+            next_page_token,
+        }
     }
 }
 
@@ -161,15 +407,65 @@ impl MessageList {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct OffsetMessageList {
+    pub messages: Vec<Message>,
+    pub next_offset: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct ReadQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct PullConfig {
     pub max_messages: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partitions: Option<Vec<u32>>,
+    /// Identifies the consumer holding the pulled messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_id: Option<String>,
 }
 
 impl PullConfig {
     pub fn new(max_messages: usize) -> Self {
         Self {
             max_messages: Some(max_messages),
+            partitions: None,
+            consumer_id: None,
         }
     }
 }
+
+/// A consumer of a subscription which identified itself when pulling.
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Consumer {
+    /// Identifier chosen by the consumer.
+    pub id: String,
+    /// Time the consumer last pulled.
+    pub last_seen: DateTime<Utc>,
+    /// Number of messages pulled by the consumer which are waiting to be acked.
+    pub in_flight: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct ConsumerList {
+    pub consumers: Vec<Consumer>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct ReleaseResult {
+    pub released: usize,
+}
+
+/// The name of a Courier instance attached to the messages it bridges to other instances.
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Origin {
+    pub origin: String,
+}
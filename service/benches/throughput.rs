@@ -0,0 +1,109 @@
+//! Measure the publish, pull, and ack throughput of the registry with an increasing number of
+//! threads each working on its own topic and subscription.
+//!
+//! Run with `cargo bench --bench throughput`. Since topics and subscriptions are locked
+//! individually the throughput should scale with the number of threads up to the number of cores.
+
+use chrono::Duration;
+use courier::{RawMessage, Registry, SharedRegistry, DEFAULT_NAMESPACE};
+use std::collections::HashMap;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Instant;
+
+const MESSAGES_PER_THREAD: usize = 100_000;
+const BATCH_SIZE: usize = 10;
+
+fn setup(registry: &SharedRegistry, threads: usize) {
+    for i in 0..threads {
+        let topic = format!("topic-{}", i);
+        registry
+            .create_topic(
+                DEFAULT_NAMESPACE,
+                &topic,
+                HashMap::new(),
+                String::new(),
+                Duration::seconds(0),
+                Duration::seconds(0),
+                1,
+            )
+            .unwrap();
+        registry
+            .create_subscription(
+                DEFAULT_NAMESPACE,
+                &format!("subscription-{}", i),
+                DEFAULT_NAMESPACE,
+                &topic,
+                HashMap::new(),
+                String::new(),
+                Duration::seconds(60),
+                Duration::seconds(0),
+                Duration::seconds(0),
+                false,
+            )
+            .unwrap();
+    }
+}
+
+fn run(threads: usize) -> f64 {
+    let registry = Registry::new();
+    setup(&registry, threads);
+    let barrier = Arc::new(Barrier::new(threads + 1));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|i| {
+            let registry = Arc::clone(&registry);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let topic = format!("topic-{}", i);
+                let subscription = format!("subscription-{}", i);
+                let data: Vec<RawMessage> = (0..BATCH_SIZE)
+                    .map(|j| RawMessage::new(j.to_string()))
+                    .collect();
+                barrier.wait();
+                for _ in 0..MESSAGES_PER_THREAD / BATCH_SIZE {
+                    registry
+                        .publish(DEFAULT_NAMESPACE, &topic, data.clone())
+                        .unwrap();
+                    let ids: Vec<_> = registry
+                        .pull(DEFAULT_NAMESPACE, &subscription, BATCH_SIZE, None, None)
+                        .unwrap()
+                        .into_iter()
+                        .map(|m| m.id)
+                        .collect();
+                    registry
+                        .ack(DEFAULT_NAMESPACE, &subscription, &ids)
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+    (threads * MESSAGES_PER_THREAD) as f64 / seconds
+}
+
+fn main() {
+    let max_threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut threads = 1;
+    let mut baseline = None;
+    while threads <= max_threads {
+        let throughput = run(threads);
+        let baseline = *baseline.get_or_insert(throughput);
+        println!(
+            "{:>3} threads: {:>12.0} msgs/sec ({:.2}x)",
+            threads,
+            throughput,
+            throughput / baseline
+        );
+        threads *= 2;
+    }
+}
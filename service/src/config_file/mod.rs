@@ -0,0 +1,461 @@
+//! Settings read from a TOML configuration file.
+//!
+//! Every field of the configuration of the service can be set in the file, flags and environment
+//! variables take precedence over it. The file is reloaded on SIGHUP and when it changes: the
+//! defaults of the apis are applied right away while the other settings need a restart.
+use chrono::Duration;
+use courier::bridge::BridgeConfig;
+use courier::cluster::ClusterConfig;
+use log::{error, info, warn};
+use serde::de::{Deserialize, Deserializer, Error};
+use serde_derive::Deserialize;
+use signal_hook::SIGHUP;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use crate::http_protocol::{Config, SharedConfig, TlsConfig, UnixSocketConfig};
+
+#[cfg(test)]
+mod tests;
+
+/// Host the service listens on when it is not set.
+pub const DEFAULT_HOST: &str = "0.0.0.0";
+/// Port the service listens on when it is not set.
+pub const DEFAULT_PORT: u16 = 3140;
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// Settings of the service, those which are unset take their default.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Seconds.
+    pub default_topic_ttl: Option<i64>,
+    /// Seconds.
+    pub default_subscription_ttl: Option<i64>,
+    /// Seconds.
+    pub default_message_ttl: Option<i64>,
+    /// Seconds.
+    pub default_ack_deadline: Option<i64>,
+    /// Seconds.
+    pub default_consumer_timeout: Option<i64>,
+    pub default_max_messages: Option<usize>,
+    pub tls: Option<TlsSettings>,
+    pub unix_socket: Option<UnixSocketSettings>,
+    pub cluster: Option<ClusterSettings>,
+    pub origin: Option<String>,
+    /// As `[<namespace>/]<subscription>=[<namespace>/]<topic>@<url>`.
+    #[serde(deserialize_with = "bridges")]
+    pub bridges: Option<Vec<BridgeConfig>>,
+    pub grpc_port: Option<u16>,
+    pub mqtt_port: Option<u16>,
+    pub stomp_port: Option<u16>,
+    pub redis_port: Option<u16>,
+    pub kafka_port: Option<u16>,
+    pub nats_port: Option<u16>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+    pub client_identities: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnixSocketSettings {
+    pub path: Option<PathBuf>,
+    /// An octal mode like `"660"`.
+    #[serde(deserialize_with = "mode")]
+    pub mode: Option<u32>,
+    pub exclusive: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterSettings {
+    pub node_id: Option<u64>,
+    pub listen: Option<String>,
+    /// As `<id>=<address>`.
+    #[serde(deserialize_with = "peers")]
+    pub peers: Option<Vec<(u64, String)>>,
+    pub data_dir: Option<PathBuf>,
+    pub snapshot_entries: Option<u64>,
+}
+
+impl Settings {
+    /// Take the settings of `other` which are not set in these settings.
+    pub fn or(self, other: Settings) -> Settings {
+        Settings {
+            host: self.host.or(other.host),
+            port: self.port.or(other.port),
+            default_topic_ttl: self.default_topic_ttl.or(other.default_topic_ttl),
+            default_subscription_ttl: self
+                .default_subscription_ttl
+                .or(other.default_subscription_ttl),
+            default_message_ttl: self.default_message_ttl.or(other.default_message_ttl),
+            default_ack_deadline: self.default_ack_deadline.or(other.default_ack_deadline),
+            default_consumer_timeout: self
+                .default_consumer_timeout
+                .or(other.default_consumer_timeout),
+            default_max_messages: self.default_max_messages.or(other.default_max_messages),
+            tls: or_nested(self.tls, other.tls, TlsSettings::or),
+            unix_socket: or_nested(self.unix_socket, other.unix_socket, UnixSocketSettings::or),
+            cluster: or_nested(self.cluster, other.cluster, ClusterSettings::or),
+            origin: self.origin.or(other.origin),
+            bridges: self.bridges.or(other.bridges),
+            grpc_port: self.grpc_port.or(other.grpc_port),
+            mqtt_port: self.mqtt_port.or(other.mqtt_port),
+            stomp_port: self.stomp_port.or(other.stomp_port),
+            redis_port: self.redis_port.or(other.redis_port),
+            kafka_port: self.kafka_port.or(other.kafka_port),
+            nats_port: self.nats_port.or(other.nats_port),
+        }
+    }
+
+    /// Build the configuration of the service.
+    pub fn into_config(self) -> Result<Config, String> {
+        let host = self.host.unwrap_or_else(|| String::from(DEFAULT_HOST));
+        let port = self.port.unwrap_or(DEFAULT_PORT);
+        let tls = match self.tls {
+            Some(TlsSettings {
+                cert: Some(cert),
+                key: Some(key),
+                client_ca,
+                client_identities,
+            }) => Some(TlsConfig {
+                cert,
+                key,
+                client_ca,
+                client_identities: client_identities.unwrap_or_default(),
+            }),
+            Some(_) => return Err(String::from("The tls settings require a cert and a key")),
+            None => None,
+        };
+        let unix_socket = match self.unix_socket {
+            Some(UnixSocketSettings {
+                path: Some(path),
+                mode,
+                exclusive,
+            }) => Some(UnixSocketConfig {
+                path,
+                mode: mode.unwrap_or(0o660),
+                exclusive: exclusive.unwrap_or(false),
+            }),
+            Some(_) => return Err(String::from("The unix_socket settings require a path")),
+            None => None,
+        };
+        let cluster = match self.cluster {
+            Some(ClusterSettings {
+                node_id: Some(id),
+                listen: Some(listen),
+                peers,
+                data_dir: Some(data_dir),
+                snapshot_entries,
+            }) => {
+                let mut config =
+                    ClusterConfig::new(id, &listen, peers.unwrap_or_default(), data_dir);
+                if let Some(entries) = snapshot_entries {
+                    config.snapshot_entries = entries;
+                }
+                Some(config)
+            }
+            Some(_) => {
+                return Err(String::from(
+                    "The cluster settings require a node_id, listen and data_dir",
+                ))
+            }
+            None => None,
+        };
+        Ok(Config {
+            origin: self.origin.unwrap_or_else(|| format!("{}:{}", host, port)),
+            host,
+            port,
+            default_topic_ttl: Duration::seconds(self.default_topic_ttl.unwrap_or(0)),
+            default_subscription_ttl: Duration::seconds(self.default_subscription_ttl.unwrap_or(0)),
+            default_message_ttl: Duration::seconds(self.default_message_ttl.unwrap_or(3600)),
+            default_ack_deadline: Duration::seconds(self.default_ack_deadline.unwrap_or(60)),
+            default_consumer_timeout: Duration::seconds(self.default_consumer_timeout.unwrap_or(0)),
+            default_max_messages: self.default_max_messages.unwrap_or(1),
+            tls,
+            unix_socket,
+            cluster,
+            bridges: self.bridges.unwrap_or_default(),
+            grpc_port: self.grpc_port,
+            mqtt_port: self.mqtt_port,
+            stomp_port: self.stomp_port,
+            redis_port: self.redis_port,
+            kafka_port: self.kafka_port,
+            nats_port: self.nats_port,
+        })
+    }
+}
+
+impl Default for Config {
+    /// The configuration of a service started without any settings.
+    fn default() -> Self {
+        Settings::default()
+            .into_config()
+            .expect("The default settings are valid")
+    }
+}
+
+impl TlsSettings {
+    fn or(self, other: TlsSettings) -> TlsSettings {
+        TlsSettings {
+            cert: self.cert.or(other.cert),
+            key: self.key.or(other.key),
+            client_ca: self.client_ca.or(other.client_ca),
+            client_identities: self.client_identities.or(other.client_identities),
+        }
+    }
+}
+
+impl UnixSocketSettings {
+    fn or(self, other: UnixSocketSettings) -> UnixSocketSettings {
+        UnixSocketSettings {
+            path: self.path.or(other.path),
+            mode: self.mode.or(other.mode),
+            exclusive: self.exclusive.or(other.exclusive),
+        }
+    }
+}
+
+impl ClusterSettings {
+    fn or(self, other: ClusterSettings) -> ClusterSettings {
+        ClusterSettings {
+            node_id: self.node_id.or(other.node_id),
+            listen: self.listen.or(other.listen),
+            peers: self.peers.or(other.peers),
+            data_dir: self.data_dir.or(other.data_dir),
+            snapshot_entries: self.snapshot_entries.or(other.snapshot_entries),
+        }
+    }
+}
+
+// Merge the settings of a table when both have it.
+fn or_nested<T>(settings: Option<T>, other: Option<T>, or: fn(T, T) -> T) -> Option<T> {
+    match (settings, other) {
+        (Some(settings), Some(other)) => Some(or(settings, other)),
+        (settings, other) => settings.or(other),
+    }
+}
+
+/// The settings which changed when the configuration was reloaded.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    /// Settings which were applied.
+    pub applied: Vec<&'static str>,
+    /// Settings which only take effect after a restart.
+    pub restart: Vec<&'static str>,
+}
+
+impl Changes {
+    fn apply<T: PartialEq>(&mut self, name: &'static str, current: &mut T, new: T) {
+        if *current != new {
+            *current = new;
+            self.applied.push(name);
+        }
+    }
+
+    fn restart<T: PartialEq>(&mut self, name: &'static str, current: &T, new: &T) {
+        if current != new {
+            self.restart.push(name);
+        }
+    }
+}
+
+/// Apply the settings of `new` which can change while the service runs to `config`.
+pub fn reload(config: &mut Config, new: Config) -> Changes {
+    // Destructured so that every new setting has to be handled here
+    let Config {
+        host,
+        port,
+        default_topic_ttl,
+        default_subscription_ttl,
+        default_message_ttl,
+        default_ack_deadline,
+        default_consumer_timeout,
+        default_max_messages,
+        tls,
+        unix_socket,
+        cluster,
+        origin,
+        bridges,
+        grpc_port,
+        mqtt_port,
+        stomp_port,
+        redis_port,
+        kafka_port,
+        nats_port,
+    } = new;
+    let mut changes = Changes::default();
+    changes.apply(
+        "default_topic_ttl",
+        &mut config.default_topic_ttl,
+        default_topic_ttl,
+    );
+    changes.apply(
+        "default_subscription_ttl",
+        &mut config.default_subscription_ttl,
+        default_subscription_ttl,
+    );
+    changes.apply(
+        "default_message_ttl",
+        &mut config.default_message_ttl,
+        default_message_ttl,
+    );
+    changes.apply(
+        "default_ack_deadline",
+        &mut config.default_ack_deadline,
+        default_ack_deadline,
+    );
+    changes.apply(
+        "default_consumer_timeout",
+        &mut config.default_consumer_timeout,
+        default_consumer_timeout,
+    );
+    changes.apply(
+        "default_max_messages",
+        &mut config.default_max_messages,
+        default_max_messages,
+    );
+    changes.restart("host", &config.host, &host);
+    changes.restart("port", &config.port, &port);
+    changes.restart("tls", &config.tls, &tls);
+    changes.restart("unix_socket", &config.unix_socket, &unix_socket);
+    changes.restart("cluster", &config.cluster, &cluster);
+    changes.restart("origin", &config.origin, &origin);
+    changes.restart("bridges", &config.bridges, &bridges);
+    changes.restart("grpc_port", &config.grpc_port, &grpc_port);
+    changes.restart("mqtt_port", &config.mqtt_port, &mqtt_port);
+    changes.restart("stomp_port", &config.stomp_port, &stomp_port);
+    changes.restart("redis_port", &config.redis_port, &redis_port);
+    changes.restart("kafka_port", &config.kafka_port, &kafka_port);
+    changes.restart("nats_port", &config.nats_port, &nats_port);
+    changes
+}
+
+/// Read the configuration file at `path`, the `overrides` take precedence over it.
+pub fn load(path: &Path, overrides: Settings) -> Result<Config, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Can not read '{}': {}", path.display(), e))?;
+    let settings: Settings = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid configuration file '{}': {}", path.display(), e))?;
+    overrides.or(settings).into_config()
+}
+
+/// Reload the configuration file at `path` in the background on SIGHUP and when it changes.
+pub fn watch(path: &Path, overrides: Settings, config: &SharedConfig) -> io::Result<()> {
+    let hangup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, Arc::clone(&hangup))?;
+    let path = path.to_path_buf();
+    let config = Arc::clone(config);
+    let mut last_modified = modified(&path);
+    thread::Builder::new()
+        .name(String::from("config-file"))
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            let changed = modified(&path);
+            if !hangup.swap(false, Ordering::Relaxed) && changed == last_modified {
+                continue;
+            }
+            last_modified = changed;
+            let new = match load(&path, overrides.clone()) {
+                Ok(new) => new,
+                Err(e) => {
+                    error!("Can not reload the configuration: {}", e);
+                    continue;
+                }
+            };
+            let changes = reload(&mut config.write(), new);
+            if !changes.applied.is_empty() {
+                info!(
+                    "Reloaded {} from '{}'.",
+                    changes.applied.join(", "),
+                    path.display()
+                );
+            }
+            if !changes.restart.is_empty() {
+                warn!(
+                    "Changed {} in '{}', restart the service to apply them.",
+                    changes.restart.join(", "),
+                    path.display()
+                );
+            }
+            if changes == Changes::default() {
+                info!("Reloaded '{}', no setting changed.", path.display());
+            }
+        })?;
+    Ok(())
+}
+
+// When the file at `path` was last modified.
+fn modified(path: &Path) -> Option<time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!(
+            "Expected an octal mode like '660' but got '{}'",
+            mode
+        )),
+    }
+}
+
+pub fn parse_peer(peer: &str) -> Result<(u64, String), String> {
+    let mut parts = peer.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(id), Some(address)) => id
+            .parse()
+            .map(|id| (id, String::from(address)))
+            .map_err(|e| format!("Invalid node id '{}': {}", id, e)),
+        _ => Err(format!("Expected '<id>=<address>' but got '{}'", peer)),
+    }
+}
+
+// Deserialize a list of strings in the format of the corresponding flag.
+fn parse_list<'de, D, T>(
+    deserializer: D,
+    parse: fn(&str) -> Result<T, String>,
+) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| parse(s).map_err(D::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn bridges<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<BridgeConfig>>, D::Error> {
+    parse_list(deserializer, str::parse)
+}
+
+fn peers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<(u64, String)>>, D::Error> {
+    parse_list(deserializer, parse_peer)
+}
+
+fn mode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    parse_mode(&String::deserialize(deserializer)?)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
@@ -0,0 +1,124 @@
+use super::*;
+
+const FILE: &str = r#"
+port = 3141
+default_message_ttl = 60
+default_max_messages = 10
+bridges = ["orders=orders@http://127.0.0.1:3142"]
+nats_port = 4222
+
+[tls]
+cert = "cert.pem"
+key = "key.pem"
+client_identities = ["worker"]
+
+[unix_socket]
+path = "/run/courier.sock"
+mode = "600"
+
+[cluster]
+node_id = 1
+listen = "127.0.0.1:4001"
+peers = ["2=127.0.0.1:4002"]
+data_dir = "/var/lib/courier"
+snapshot_entries = 500
+"#;
+
+fn settings(file: &str) -> Result<Settings, String> {
+    toml::from_str(file).map_err(|e| e.to_string())
+}
+
+#[test]
+fn config_file_settings() {
+    let config = settings(FILE).unwrap().into_config().unwrap();
+    assert_eq!(config.host, DEFAULT_HOST);
+    assert_eq!(config.port, 3141);
+    assert_eq!(config.origin, "0.0.0.0:3141");
+    assert_eq!(config.default_message_ttl, Duration::seconds(60));
+    assert_eq!(config.default_ack_deadline, Duration::seconds(60));
+    assert_eq!(config.default_max_messages, 10);
+    assert_eq!(
+        config.bridges,
+        vec!["orders=orders@http://127.0.0.1:3142"
+            .parse::<BridgeConfig>()
+            .unwrap()]
+    );
+    assert_eq!(config.nats_port, Some(4222));
+    assert_eq!(config.grpc_port, None);
+    let tls = config.tls.unwrap();
+    assert_eq!(tls.cert, PathBuf::from("cert.pem"));
+    assert_eq!(tls.client_ca, None);
+    assert_eq!(tls.client_identities, vec!["worker"]);
+    let unix_socket = config.unix_socket.unwrap();
+    assert_eq!(unix_socket.mode, 0o600);
+    assert!(!unix_socket.exclusive);
+    let cluster = config.cluster.unwrap();
+    assert_eq!(cluster.id, 1);
+    assert_eq!(cluster.peers, vec![(2, String::from("127.0.0.1:4002"))]);
+    assert_eq!(cluster.snapshot_entries, 500);
+
+    // Overrides take precedence over the file, down to the settings of a table
+    let overrides = Settings {
+        port: Some(3150),
+        default_max_messages: Some(5),
+        unix_socket: Some(UnixSocketSettings {
+            exclusive: Some(true),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let config = overrides.or(settings(FILE).unwrap()).into_config().unwrap();
+    assert_eq!(config.port, 3150);
+    assert_eq!(config.default_message_ttl, Duration::seconds(60));
+    assert_eq!(config.default_max_messages, 5);
+    let unix_socket = config.unix_socket.unwrap();
+    assert_eq!(unix_socket.path, PathBuf::from("/run/courier.sock"));
+    assert_eq!(unix_socket.mode, 0o600);
+    assert!(unix_socket.exclusive);
+
+    // Unset settings take their default
+    let config = Settings::default().into_config().unwrap();
+    assert_eq!(config.port, DEFAULT_PORT);
+    assert_eq!(config.default_message_ttl, Duration::seconds(3600));
+    assert_eq!(config.default_max_messages, 1);
+    assert_eq!(config.tls, None);
+
+    for file in &[
+        "unknown = 1",
+        "port = \"http\"",
+        "bridges = [\"orders\"]",
+        "[unix_socket]\nmode = \"999\"",
+        "[cluster]\npeers = [\"two\"]",
+    ] {
+        assert!(settings(file).is_err(), "{}", file);
+    }
+    for file in &[
+        "[tls]\ncert = \"cert.pem\"",
+        "[unix_socket]\nmode = \"600\"",
+        "[cluster]\nnode_id = 1",
+    ] {
+        assert!(settings(file).unwrap().into_config().is_err(), "{}", file);
+    }
+}
+
+#[test]
+fn config_file_reload() {
+    let mut config = settings(FILE).unwrap().into_config().unwrap();
+    let file = FILE
+        .replace("port = 3141", "port = 3143")
+        .replace("default_message_ttl = 60", "default_message_ttl = 120")
+        .replace("mode = \"600\"", "mode = \"660\"");
+    let new = settings(&file).unwrap().into_config().unwrap();
+    let changes = reload(&mut config, new.clone());
+    assert_eq!(changes.applied, vec!["default_message_ttl"]);
+    assert_eq!(changes.restart, vec!["port", "unix_socket", "origin"]);
+
+    // Only the settings which can change while the service runs are applied
+    assert_eq!(config.default_message_ttl, Duration::seconds(120));
+    assert_eq!(config.port, 3141);
+    assert_eq!(config.unix_socket.as_ref().unwrap().mode, 0o600);
+    assert_ne!(config, new);
+
+    let same = config.clone();
+    assert_eq!(reload(&mut config, same), Changes::default());
+}
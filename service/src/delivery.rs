@@ -0,0 +1,83 @@
+//! Delivery of the messages of the subscriptions of a protocol session to its client.
+//!
+//! The MQTT, STOMP, and NATS sessions push messages to their clients instead of being pulled
+//! from. Each session runs [deliver](fn.deliver.html) on its own thread which pulls the
+//! subscriptions of the session and blocks on the signal of the session when there is nothing to
+//! deliver. The registry raises the signal when messages are published or released, the session
+//! raises it when it can receive more messages, and since messages whose ack deadline is reached
+//! are resent without any event the subscriptions are also pulled again after a timeout.
+use log::debug;
+use std::io;
+use std::sync::Arc;
+use std::time;
+
+use courier::signal::Signal;
+use courier::{Message, SharedRegistry, DEFAULT_NAMESPACE};
+
+/// How long a session waits for its signal before pulling its subscriptions again.
+const RECHECK_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+/// A session of a client receiving the messages of its subscriptions.
+pub trait Subscriber {
+    /// A subscription of the session.
+    type Subscription;
+
+    /// Check if the session ended.
+    fn closed(&self) -> bool;
+
+    /// The signal the session waits on, it is raised by the session when it subscribes, can
+    /// receive more messages, or ends.
+    fn signal(&self) -> &Arc<Signal>;
+
+    /// The consumer id the session pulls with.
+    fn consumer(&self) -> &str;
+
+    /// Get the subscriptions of the session.
+    fn subscriptions(&self) -> Vec<Self::Subscription>;
+
+    /// Name of a subscription in the default namespace.
+    fn name(subscription: &Self::Subscription) -> &str;
+
+    /// The number of messages of a subscription the client can receive, 0 to skip it.
+    fn available(&self, subscription: &Self::Subscription) -> usize;
+
+    /// Send the messages pulled from a subscription to the client.
+    fn send(&self, subscription: &Self::Subscription, messages: Vec<Message>) -> io::Result<()>;
+}
+
+/// Deliver the messages of the subscriptions of `subscriber` until the session ends or sending
+/// to its client fails.
+pub fn deliver<S: Subscriber>(registry: &SharedRegistry, subscriber: &S) -> io::Result<()> {
+    registry.listen(subscriber.signal());
+    while !subscriber.closed() {
+        let mut delivered = false;
+        for subscription in subscriber.subscriptions() {
+            let max = subscriber.available(&subscription);
+            if max == 0 {
+                continue;
+            }
+            let name = S::name(&subscription);
+            let messages = match registry.pull(
+                DEFAULT_NAMESPACE,
+                name,
+                max,
+                None,
+                Some(subscriber.consumer()),
+            ) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    debug!("Can not pull subscription '{}': {}", name, e);
+                    continue;
+                }
+            };
+            if !messages.is_empty() {
+                delivered = true;
+                subscriber.send(&subscription, messages)?;
+            }
+        }
+        if !delivered {
+            subscriber.signal().wait(RECHECK_INTERVAL);
+        }
+    }
+    Ok(())
+}
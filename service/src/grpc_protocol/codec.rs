@@ -0,0 +1,370 @@
+//! Encoding and decoding of the protocol buffer wire format and of gRPC message frames.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::prelude::*;
+use courier::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::str;
+
+/// The largest message accepted in a gRPC frame.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LENGTH_DELIMITED: u32 = 2;
+const FIXED32: u32 = 5;
+
+/// A message which can be encoded as a protocol buffer.
+pub trait Encode {
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+/// A message which can be decoded from a protocol buffer.
+pub trait Decode: Default {
+    /// Merge a field of the message, unknown fields are ignored.
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error>;
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut message = Self::default();
+        let mut decoder = Decoder { bytes };
+        while let Some((field, value)) = decoder.next()? {
+            message.merge(field, value)?;
+        }
+        Ok(message)
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::invalid_request(format!("Invalid protocol buffer: {}", message))
+}
+
+/// The value of a decoded field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn uint64(self) -> Result<u64, Error> {
+        match self {
+            Value::Varint(value) => Ok(value),
+            _ => Err(invalid("expected a varint")),
+        }
+    }
+
+    pub fn uint32(self) -> Result<u32, Error> {
+        self.uint64().map(|value| value as u32)
+    }
+
+    pub fn int64(self) -> Result<i64, Error> {
+        self.uint64().map(|value| value as i64)
+    }
+
+    pub fn bool(self) -> Result<bool, Error> {
+        self.uint64().map(|value| value != 0)
+    }
+
+    pub fn bytes(self) -> Result<&'a [u8], Error> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(invalid("expected a length delimited field")),
+        }
+    }
+
+    pub fn string(self) -> Result<String, Error> {
+        str::from_utf8(self.bytes()?)
+            .map(String::from)
+            .map_err(|_| invalid("a string is not valid UTF-8"))
+    }
+
+    pub fn message<T: Decode>(self) -> Result<T, Error> {
+        T::decode(self.bytes()?)
+    }
+
+    /// Push a value of a repeated `uint32` field which is either packed or not.
+    pub fn push_uint32(self, values: &mut Vec<u32>) -> Result<(), Error> {
+        match self {
+            Value::Bytes(mut bytes) => {
+                while !bytes.is_empty() {
+                    values.push(read_varint(&mut bytes)? as u32);
+                }
+                Ok(())
+            }
+            value => {
+                values.push(value.uint32()?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Insert an entry of a `map<string, string>` field.
+    pub fn insert_entry(self, map: &mut HashMap<String, String>) -> Result<(), Error> {
+        let entry: Entry = self.message()?;
+        map.insert(entry.key, entry.value);
+        Ok(())
+    }
+
+    /// Decode a `google.protobuf.Timestamp`.
+    pub fn timestamp(self) -> Result<DateTime<Utc>, Error> {
+        let timestamp: Timestamp = self.message()?;
+        match Utc.timestamp_opt(timestamp.seconds, timestamp.nanos) {
+            chrono::LocalResult::Single(time) => Ok(time),
+            _ => Err(invalid("a timestamp is out of range")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    key: String,
+    value: String,
+}
+
+impl Decode for Entry {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.key = value.string()?,
+            2 => self.value = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Timestamp {
+    seconds: i64,
+    nanos: u32,
+}
+
+impl Decode for Timestamp {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.seconds = value.int64()?,
+            2 => self.nanos = value.uint32()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| invalid("a varint is truncated"))?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("a varint is too long"))
+}
+
+fn split<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if bytes.len() < len {
+        return Err(invalid("a field is truncated"));
+    }
+    let (value, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(value)
+}
+
+/// Reads the fields of an encoded message.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    /// Read the next field number and value or None at the end of the message.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<(u32, Value<'a>)>, Error> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let key = read_varint(&mut self.bytes)?;
+        let field = (key >> 3) as u32;
+        let value = match key as u32 & 0x7 {
+            VARINT => Value::Varint(read_varint(&mut self.bytes)?),
+            FIXED64 => {
+                let bytes = split(&mut self.bytes, 8)?;
+                let mut value = [0; 8];
+                value.copy_from_slice(bytes);
+                Value::Fixed64(u64::from_le_bytes(value))
+            }
+            LENGTH_DELIMITED => {
+                let len = read_varint(&mut self.bytes)? as usize;
+                Value::Bytes(split(&mut self.bytes, len)?)
+            }
+            FIXED32 => {
+                let bytes = split(&mut self.bytes, 4)?;
+                let mut value = [0; 4];
+                value.copy_from_slice(bytes);
+                Value::Fixed32(u32::from_le_bytes(value))
+            }
+            _ => return Err(invalid("unsupported wire type")),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+/// Writes the fields of a message, fields with default values are omitted unless they are
+/// `optional`.
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.varint(u64::from(field << 3 | wire_type));
+    }
+
+    pub fn optional_uint64(&mut self, field: u32, value: Option<u64>) {
+        if let Some(value) = value {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+    }
+
+    pub fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.optional_uint64(field, Some(value));
+        }
+    }
+
+    pub fn uint32(&mut self, field: u32, value: u32) {
+        self.uint64(field, u64::from(value));
+    }
+
+    pub fn int64(&mut self, field: u32, value: i64) {
+        self.uint64(field, value as u64);
+    }
+
+    pub fn optional_bytes(&mut self, field: u32, value: Option<&[u8]>) {
+        if let Some(value) = value {
+            self.key(field, LENGTH_DELIMITED);
+            self.varint(value.len() as u64);
+            self.buf.extend_from_slice(value);
+        }
+    }
+
+    pub fn optional_string(&mut self, field: u32, value: Option<&str>) {
+        self.optional_bytes(field, value.map(str::as_bytes));
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) {
+        if !value.is_empty() {
+            self.optional_string(field, Some(value));
+        }
+    }
+
+    /// Write a repeated `string` field.
+    pub fn strings<S: AsRef<str>>(&mut self, field: u32, values: impl IntoIterator<Item = S>) {
+        for value in values {
+            self.optional_string(field, Some(value.as_ref()));
+        }
+    }
+
+    pub fn message<T: Encode + ?Sized>(&mut self, field: u32, message: &T) {
+        let mut encoder = Encoder::new();
+        message.encode(&mut encoder);
+        self.optional_bytes(field, Some(&encoder.buf));
+    }
+
+    /// Write a repeated message field.
+    pub fn messages<'a, T: Encode + 'a>(
+        &mut self,
+        field: u32,
+        messages: impl IntoIterator<Item = &'a T>,
+    ) {
+        for message in messages {
+            self.message(field, message);
+        }
+    }
+
+    /// Write a packed repeated `uint64` field.
+    pub fn packed_uint64(&mut self, field: u32, values: impl IntoIterator<Item = u64>) {
+        let mut encoder = Encoder::new();
+        for value in values {
+            encoder.varint(value);
+        }
+        if !encoder.buf.is_empty() {
+            self.optional_bytes(field, Some(&encoder.buf));
+        }
+    }
+
+    /// Write a `map<string, string>` field.
+    pub fn map(&mut self, field: u32, map: &BTreeMap<String, String>) {
+        for (key, value) in map {
+            let mut entry = Encoder::new();
+            entry.string(1, key);
+            entry.string(2, value);
+            self.optional_bytes(field, Some(&entry.buf));
+        }
+    }
+
+    /// Write a `google.protobuf.Timestamp` field.
+    pub fn timestamp(&mut self, field: u32, time: DateTime<Utc>) {
+        let mut timestamp = Encoder::new();
+        timestamp.int64(1, time.timestamp());
+        timestamp.uint32(2, time.timestamp_subsec_nanos());
+        self.optional_bytes(field, Some(&timestamp.buf));
+    }
+}
+
+/// Encode a message in a gRPC frame.
+pub fn frame<T: Encode + ?Sized>(message: &T) -> Bytes {
+    let mut encoder = Encoder::new();
+    message.encode(&mut encoder);
+    let mut frame = BytesMut::with_capacity(5 + encoder.buf.len());
+    frame.put_u8(0);
+    frame.put_u32_be(encoder.buf.len() as u32);
+    frame.put_slice(&encoder.buf);
+    frame.freeze()
+}
+
+/// Split the first complete gRPC frame off `buf` and decode its message, None if `buf` does not
+/// hold a complete frame yet.
+pub fn unframe<T: Decode>(buf: &mut BytesMut) -> Result<Option<T>, Error> {
+    if buf.len() < 5 {
+        return Ok(None);
+    }
+    if buf[0] != 0 {
+        return Err(Error::invalid_request(String::from(
+            "Compressed messages are not supported",
+        )));
+    }
+    let len = (u32::from(buf[1]) << 24
+        | u32::from(buf[2]) << 16
+        | u32::from(buf[3]) << 8
+        | u32::from(buf[4])) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(Error::invalid_request(format!(
+            "Messages are limited to {} bytes",
+            MAX_MESSAGE_SIZE
+        )));
+    }
+    if buf.len() < 5 + len {
+        return Ok(None);
+    }
+    let frame = buf.split_to(5 + len);
+    T::decode(&frame[5..]).map(Some)
+}
@@ -0,0 +1,57 @@
+use courier::{Error, ErrorCode};
+use http::header::{HeaderMap, HeaderValue};
+
+/// The gRPC status code of a successful call.
+pub const OK: u32 = 0;
+
+/// Get the gRPC status code of an error code, it matches the HTTP status of the JSON API.
+pub fn status(code: ErrorCode) -> u32 {
+    match code {
+        ErrorCode::NamespaceNotFound
+        | ErrorCode::TopicNotFound
+        | ErrorCode::SubscriptionNotFound
+        | ErrorCode::MessageNotFound
+        | ErrorCode::PartitionNotFound
+        | ErrorCode::ConsumerNotFound => 5, // NOT_FOUND
+        ErrorCode::NamespaceAlreadyExists
+        | ErrorCode::TopicAlreadyExists
+        | ErrorCode::SubscriptionAlreadyExists => 6, // ALREADY_EXISTS
+        ErrorCode::NamespaceNotShared => 7, // PERMISSION_DENIED
+        ErrorCode::OffsetExpired => 11,     // OUT_OF_RANGE
+        ErrorCode::InvalidRequest => 3,     // INVALID_ARGUMENT
+        ErrorCode::RouteNotFound => 12,     // UNIMPLEMENTED
+        ErrorCode::Unavailable => 14,       // UNAVAILABLE
+        ErrorCode::Internal => 13,          // INTERNAL
+    }
+}
+
+// Percent encode a status message as required by the gRPC protocol.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for &byte in message.as_bytes() {
+        if (b' '..=b'~').contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The trailers ending a call, with the status of `error` or an OK status.
+pub fn trailers(error: Option<&Error>) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    let status = error.map_or(OK, |error| status(error.code));
+    trailers.insert("grpc-status", HeaderValue::from(status));
+    if let Some(error) = error {
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(&error.message)) {
+            trailers.insert("grpc-message", message);
+        }
+        // The code is serialized as a JSON string, for example `"TOPIC_NOT_FOUND"`
+        let code = serde_json::to_string(&error.code).unwrap_or_default();
+        if let Ok(code) = HeaderValue::from_str(code.trim_matches('"')) {
+            trailers.insert("courier-error-code", code);
+        }
+    }
+    trailers
+}
@@ -0,0 +1,349 @@
+//! A gRPC server exposing the services of `proto/courier/v1/courier.proto`.
+//!
+//! The server runs next to the HTTP server on its own port and shares its registry and
+//! configuration. Calls are served over HTTP/2 without TLS and messages must not be compressed.
+
+mod codec;
+mod error;
+mod subscription_service;
+#[cfg(test)]
+mod tests;
+mod topic_service;
+mod types;
+
+use bytes::{Bytes, BytesMut};
+use futures::sync::mpsc;
+use futures::{future, stream, Async, Future, Poll, Sink, Stream};
+use h2::server::{self, SendResponse};
+use h2::{RecvStream, SendStream};
+use http::{Request, Response};
+use log::{debug, info, warn};
+use std::io;
+use std::marker::PhantomData;
+use std::net;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use tokio::net::TcpListener;
+use tokio::reactor::Handle;
+
+use self::codec::{Decode, Encode};
+use crate::http_protocol::SharedConfig;
+use courier::{Error, Result, SharedRegistry};
+
+/// How long a streaming pull waits before pulling again when the subscription has no messages.
+const STREAMING_PULL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// The state shared by the calls of the gRPC server.
+pub struct GrpcState {
+    pub registry: SharedRegistry,
+    pub config: SharedConfig,
+}
+
+/// The response messages of a call as gRPC frames.
+type Responses = Box<dyn Stream<Item = Bytes, Error = Error> + Send>;
+
+/// Start the gRPC server on `address` in the background.
+pub fn start(address: &str, registry: &SharedRegistry, config: &SharedConfig) -> io::Result<()> {
+    let listener = net::TcpListener::bind(address)?;
+    let state = Arc::new(GrpcState {
+        registry: Arc::clone(registry),
+        config: Arc::clone(config),
+    });
+    info!("Serving gRPC on '{}'.", listener.local_addr()?);
+    thread::Builder::new()
+        .name(String::from("grpc"))
+        .spawn(move || tokio::run(serve(listener, state)))?;
+    Ok(())
+}
+
+fn serve(listener: net::TcpListener, state: Arc<GrpcState>) -> impl Future<Item = (), Error = ()> {
+    future::lazy(move || TcpListener::from_std(listener, &Handle::default()))
+        .map_err(|e| warn!("Can not listen for gRPC connections: {}", e))
+        .and_then(move |listener| {
+            listener
+                .incoming()
+                .then(|socket| match socket {
+                    Ok(socket) => Ok(Some(socket)),
+                    Err(e) => {
+                        warn!("Can not accept a gRPC connection: {}", e);
+                        Ok(None)
+                    }
+                })
+                .filter_map(|socket| socket)
+                .for_each(move |socket| {
+                    let _ = socket.set_nodelay(true);
+                    let state = Arc::clone(&state);
+                    let connection = server::handshake(socket)
+                        .and_then(move |connection| {
+                            connection.for_each(move |(request, respond)| {
+                                tokio::spawn(call(request, respond, Arc::clone(&state)));
+                                Ok(())
+                            })
+                        })
+                        .map_err(|e| debug!("gRPC connection closed: {}", e));
+                    tokio::spawn(connection);
+                    Ok(())
+                })
+        })
+}
+
+// Serve a single call.
+fn call(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    state: Arc<GrpcState>,
+) -> impl Future<Item = (), Error = ()> {
+    let path = request.uri().path().to_string();
+    let responses = route(&path, request.into_body(), state);
+    let response = Response::builder()
+        .header("content-type", "application/grpc")
+        .body(())
+        .expect("a valid response");
+    match respond.send_response(response, false) {
+        Ok(stream) => future::Either::A(SendResponses {
+            responses,
+            stream,
+            pending: None,
+        }),
+        Err(e) => {
+            debug!("Can not respond to gRPC call '{}': {}", path, e);
+            future::Either::B(future::ok(()))
+        }
+    }
+}
+
+fn route(path: &str, body: RecvStream, state: Arc<GrpcState>) -> Responses {
+    match path {
+        "/courier.v1.TopicService/CreateTopic" => unary(body, state, topic_service::create),
+        "/courier.v1.TopicService/UpdateTopic" => unary(body, state, topic_service::update),
+        "/courier.v1.TopicService/DeleteTopic" => unary(body, state, topic_service::delete),
+        "/courier.v1.TopicService/GetTopic" => unary(body, state, topic_service::get),
+        "/courier.v1.TopicService/ListTopics" => unary(body, state, topic_service::list),
+        "/courier.v1.TopicService/ListTopicSubscriptions" => {
+            unary(body, state, topic_service::subscriptions)
+        }
+        "/courier.v1.TopicService/Publish" => unary(body, state, topic_service::publish),
+        "/courier.v1.TopicService/PurgeTopic" => unary(body, state, topic_service::purge),
+        "/courier.v1.TopicService/ReadMessages" => unary(body, state, topic_service::messages),
+        "/courier.v1.TopicService/GetMessage" => unary(body, state, topic_service::message),
+        "/courier.v1.SubscriptionService/CreateSubscription" => {
+            unary(body, state, subscription_service::create)
+        }
+        "/courier.v1.SubscriptionService/UpdateSubscription" => {
+            unary(body, state, subscription_service::update)
+        }
+        "/courier.v1.SubscriptionService/DeleteSubscription" => {
+            unary(body, state, subscription_service::delete)
+        }
+        "/courier.v1.SubscriptionService/GetSubscription" => {
+            unary(body, state, subscription_service::get)
+        }
+        "/courier.v1.SubscriptionService/ListSubscriptions" => {
+            unary(body, state, subscription_service::list)
+        }
+        "/courier.v1.SubscriptionService/Pull" => unary(body, state, |state, request| {
+            subscription_service::pull(state, &request)
+        }),
+        "/courier.v1.SubscriptionService/StreamingPull" => streaming_pull(body, state),
+        "/courier.v1.SubscriptionService/Ack" => unary(body, state, subscription_service::ack),
+        "/courier.v1.SubscriptionService/StreamingAck" => streaming_ack(body, state),
+        "/courier.v1.SubscriptionService/PurgeSubscription" => {
+            unary(body, state, subscription_service::purge)
+        }
+        "/courier.v1.SubscriptionService/ListConsumers" => {
+            unary(body, state, subscription_service::consumers)
+        }
+        "/courier.v1.SubscriptionService/ReleaseConsumer" => {
+            unary(body, state, subscription_service::release)
+        }
+        _ => Box::new(stream::once(Err(Error::route_not_found(path)))),
+    }
+}
+
+// Run `f` on a thread of the runtime which may block, the registry blocks while it waits for the
+// cluster or the commit log.
+fn blocking<T, F>(f: F) -> impl Future<Item = T, Error = Error>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let mut f = Some(f);
+    future::poll_fn(move || {
+        match tokio_threadpool::blocking(|| (f.take().expect("polled after completion"))()) {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => Err(Error::internal(e.to_string())),
+        }
+    })
+}
+
+// A call with a single request and a single response.
+fn unary<Req, Res>(
+    body: RecvStream,
+    state: Arc<GrpcState>,
+    handler: impl FnOnce(&GrpcState, Req) -> Result<Res> + Send + 'static,
+) -> Responses
+where
+    Req: Decode + Send + 'static,
+    Res: Encode + 'static,
+{
+    let response = RequestMessages::<Req>::new(body)
+        .into_future()
+        .map_err(|(error, _)| error)
+        .and_then(|(request, _)| {
+            request.ok_or_else(|| Error::invalid_request(String::from("Missing request message")))
+        })
+        .and_then(move |request| blocking(move || handler(&state, request)))
+        .map(|response| codec::frame(&response));
+    Box::new(response.into_stream())
+}
+
+// Pull on a separate thread until the call is cancelled, a response is sent for every non empty
+// pull.
+fn streaming_pull(body: RecvStream, state: Arc<GrpcState>) -> Responses {
+    let responses = RequestMessages::<types::PullRequest>::new(body)
+        .into_future()
+        .map_err(|(error, _)| error)
+        .and_then(|(request, _)| {
+            request.ok_or_else(|| Error::invalid_request(String::from("Missing request message")))
+        })
+        .map(move |request| {
+            let (mut sender, receiver) = mpsc::channel(0);
+            thread::spawn(move || {
+                while !sender.is_closed() {
+                    let response = match subscription_service::pull(&state, &request) {
+                        Ok(ref messages) if messages.messages.is_empty() => {
+                            thread::sleep(STREAMING_PULL_INTERVAL);
+                            continue;
+                        }
+                        Ok(messages) => Ok(codec::frame(&messages)),
+                        Err(error) => Err(error),
+                    };
+                    let failed = response.is_err();
+                    sender = match sender.send(response).wait() {
+                        Ok(sender) if !failed => sender,
+                        _ => return,
+                    };
+                }
+            });
+            receiver.then(|response| match response {
+                Ok(response) => response,
+                Err(()) => Err(Error::internal(String::from("Streaming pull stopped"))),
+            })
+        })
+        .flatten_stream();
+    Box::new(responses)
+}
+
+// Ack the messages of every request, a response is sent for every request.
+fn streaming_ack(body: RecvStream, state: Arc<GrpcState>) -> Responses {
+    let responses = RequestMessages::<types::AckRequest>::new(body)
+        .and_then(move |request| {
+            let state = Arc::clone(&state);
+            blocking(move || subscription_service::ack(&state, request))
+        })
+        .map(|response| codec::frame(&response));
+    Box::new(responses)
+}
+
+/// The decoded request messages of a call.
+struct RequestMessages<T> {
+    body: RecvStream,
+    buf: BytesMut,
+    message: PhantomData<T>,
+}
+
+impl<T> RequestMessages<T> {
+    fn new(body: RecvStream) -> Self {
+        RequestMessages {
+            body,
+            buf: BytesMut::new(),
+            message: PhantomData,
+        }
+    }
+}
+
+impl<T: Decode> Stream for RequestMessages<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, Error> {
+        loop {
+            if let Some(message) = codec::unframe(&mut self.buf)? {
+                return Ok(Async::Ready(Some(message)));
+            }
+            let data = self
+                .body
+                .poll()
+                .map_err(|e| Error::invalid_request(e.to_string()))?;
+            match data {
+                Async::Ready(Some(data)) => {
+                    // Let the client send more data once a frame is buffered
+                    let _ = self.body.release_capacity().release_capacity(data.len());
+                    self.buf.extend_from_slice(&data);
+                }
+                Async::Ready(None) if self.buf.is_empty() => return Ok(Async::Ready(None)),
+                Async::Ready(None) => {
+                    return Err(Error::invalid_request(String::from(
+                        "The request ended within a message",
+                    )))
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Sends the response messages of a call followed by its status in the trailers.
+struct SendResponses {
+    responses: Responses,
+    stream: SendStream<Bytes>,
+    // A message waiting for the client to accept more data.
+    pending: Option<Bytes>,
+}
+
+impl Future for SendResponses {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            // The client cancelled the call or the connection closed
+            match self.stream.poll_reset() {
+                Ok(Async::NotReady) => {}
+                _ => return Ok(Async::Ready(())),
+            }
+            if let Some(mut data) = self.pending.take() {
+                self.stream.reserve_capacity(data.len());
+                let capacity = self.stream.capacity();
+                if capacity == 0 {
+                    self.pending = Some(data);
+                    match self.stream.poll_capacity() {
+                        Ok(Async::Ready(Some(_))) => continue,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(())),
+                    }
+                }
+                let chunk = data.split_to(capacity.min(data.len()));
+                if self.stream.send_data(chunk, false).is_err() {
+                    return Ok(Async::Ready(()));
+                }
+                if !data.is_empty() {
+                    self.pending = Some(data);
+                }
+                continue;
+            }
+            let error = match self.responses.poll() {
+                Ok(Async::Ready(Some(data))) => {
+                    self.pending = Some(data);
+                    continue;
+                }
+                Ok(Async::Ready(None)) => None,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error) => Some(error),
+            };
+            let _ = self.stream.send_trailers(error::trailers(error.as_ref()));
+            return Ok(Async::Ready(()));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! The `courier.v1.SubscriptionService`, it mirrors the subscription end points of the HTTP API.
+
+use chrono::Duration;
+use courier::{Result, SubscriptionMeta};
+use uuid::Uuid;
+
+use crate::grpc_protocol::types::{self, namespace};
+use crate::grpc_protocol::GrpcState;
+use crate::http_protocol::subscription_handlers::create_subscription;
+use crate::http_protocol::types::{
+    ConsumerList, MessageIdList, MessageList, PurgeResult, ReleaseResult, SubscriptionList,
+};
+
+pub fn create(
+    state: &GrpcState,
+    request: types::CreateSubscriptionRequest,
+) -> Result<SubscriptionMeta> {
+    let namespace = String::from(namespace(&request.namespace));
+    let name = if request.name.is_empty() {
+        Uuid::new_v4().to_string()
+    } else {
+        request.name.clone()
+    };
+    create_subscription(
+        &namespace,
+        &name,
+        &request.config(),
+        &state.registry,
+        &state.config.read(),
+    )
+}
+
+pub fn update(
+    state: &GrpcState,
+    request: types::UpdateSubscriptionRequest,
+) -> Result<SubscriptionMeta> {
+    let ack_deadline = request
+        .ack_deadline
+        .map(|ack_deadline| Duration::seconds(i64::from(ack_deadline)));
+    let ttl = request.ttl.map(|ttl| Duration::seconds(i64::from(ttl)));
+    let consumer_timeout = request
+        .consumer_timeout
+        .map(|timeout| Duration::seconds(i64::from(timeout)));
+    state.registry.update_subscription(
+        namespace(&request.namespace),
+        &request.name,
+        request.labels,
+        request.description,
+        ack_deadline,
+        ttl,
+        consumer_timeout,
+    )
+}
+
+pub fn delete(state: &GrpcState, request: types::ResourceRequest) -> Result<types::Empty> {
+    state
+        .registry
+        .delete_subscription(namespace(&request.namespace), &request.name)?;
+    Ok(types::Empty)
+}
+
+pub fn get(state: &GrpcState, request: types::ResourceRequest) -> Result<SubscriptionMeta> {
+    state
+        .registry
+        .get_subscription(namespace(&request.namespace), &request.name)
+}
+
+pub fn list(state: &GrpcState, request: types::ListRequest) -> Result<SubscriptionList> {
+    let page = state
+        .registry
+        .list_subscriptions(namespace(&request.namespace), &request.options)?;
+    Ok(SubscriptionList::new(page.items, page.next_page_token))
+}
+
+pub fn pull(state: &GrpcState, request: &types::PullRequest) -> Result<MessageList> {
+    let max = request
+        .max_messages
+        .map_or(state.config.read().default_max_messages, |max| max as usize);
+    let partitions = if request.partitions.is_empty() {
+        None
+    } else {
+        Some(&request.partitions[..])
+    };
+    let messages = state.registry.pull(
+        namespace(&request.namespace),
+        &request.name,
+        max,
+        partitions,
+        request.consumer_id.as_deref(),
+    )?;
+    Ok(MessageList::new(messages))
+}
+
+pub fn ack(state: &GrpcState, request: types::AckRequest) -> Result<MessageIdList> {
+    let ids = state.registry.ack(
+        namespace(&request.namespace),
+        &request.name,
+        &request.message_ids,
+    )?;
+    Ok(MessageIdList::new(ids))
+}
+
+pub fn purge(state: &GrpcState, request: types::ResourceRequest) -> Result<PurgeResult> {
+    let purged = state
+        .registry
+        .purge_subscription(namespace(&request.namespace), &request.name)?;
+    Ok(PurgeResult::new(purged))
+}
+
+pub fn consumers(state: &GrpcState, request: types::ResourceRequest) -> Result<ConsumerList> {
+    let consumers = state
+        .registry
+        .consumers(namespace(&request.namespace), &request.name)?;
+    Ok(ConsumerList::new(consumers))
+}
+
+pub fn release(state: &GrpcState, request: types::ReleaseConsumerRequest) -> Result<ReleaseResult> {
+    let released = state.registry.release_consumer(
+        namespace(&request.namespace),
+        &request.name,
+        &request.consumer_id,
+    )?;
+    Ok(ReleaseResult::new(released))
+}
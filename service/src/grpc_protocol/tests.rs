@@ -0,0 +1,326 @@
+use super::codec::Encoder;
+use super::*;
+use crate::testing::{config, unused_port};
+use chrono::prelude::*;
+use courier::Registry;
+use h2::client::{self, SendRequest};
+use http::HeaderMap;
+use std::collections::BTreeMap;
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+// A decoded message which keeps every field.
+#[derive(Debug, Default)]
+struct Fields(Vec<(u32, Option<u64>, Vec<u8>)>);
+
+impl Decode for Fields {
+    fn merge(&mut self, field: u32, value: codec::Value<'_>) -> Result<()> {
+        match value {
+            codec::Value::Bytes(bytes) => self.0.push((field, None, bytes.to_vec())),
+            value => self.0.push((field, Some(value.uint64()?), Vec::new())),
+        }
+        Ok(())
+    }
+}
+
+impl Fields {
+    fn uint64(&self, field: u32) -> u64 {
+        self.0
+            .iter()
+            .find(|f| f.0 == field)
+            .and_then(|f| f.1)
+            .unwrap_or(0)
+    }
+
+    fn strings(&self, field: u32) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|f| f.0 == field)
+            .map(|f| String::from_utf8(f.2.clone()).unwrap())
+            .collect()
+    }
+
+    fn string(&self, field: u32) -> String {
+        self.strings(field).pop().unwrap_or_default()
+    }
+
+    fn messages(&self, field: u32) -> Vec<Fields> {
+        self.0
+            .iter()
+            .filter(|f| f.0 == field)
+            .map(|f| Fields::decode(&f.2).unwrap())
+            .collect()
+    }
+}
+
+struct Request<F>(F);
+
+impl<F: Fn(&mut Encoder)> Encode for Request<F> {
+    fn encode(&self, encoder: &mut Encoder) {
+        (self.0)(encoder)
+    }
+}
+
+fn request(f: impl Fn(&mut Encoder)) -> Bytes {
+    codec::frame(&Request(f))
+}
+
+// Receive the next response message of a call or None at its end.
+fn next_response(body: &mut RecvStream, buf: &mut BytesMut) -> Option<Fields> {
+    loop {
+        if let Some(message) = codec::unframe(buf).unwrap() {
+            return Some(message);
+        }
+        let data = future::poll_fn(|| body.poll()).wait().unwrap()?;
+        let _ = body.release_capacity().release_capacity(data.len());
+        buf.extend_from_slice(&data);
+    }
+}
+
+struct Client {
+    runtime: Runtime,
+    send: SendRequest<Bytes>,
+}
+
+impl Client {
+    fn connect(port: u16) -> Self {
+        let mut runtime = Runtime::new().unwrap();
+        let address = format!("127.0.0.1:{}", port).parse().unwrap();
+        let (send, connection) = runtime
+            .block_on(
+                TcpStream::connect(&address).then(|socket| client::handshake(socket.unwrap())),
+            )
+            .unwrap();
+        runtime.spawn(connection.map_err(|_| ()));
+        Client { runtime, send }
+    }
+
+    // Start a call and send its requests, the requests end unless `open`.
+    fn start(
+        &mut self,
+        path: &str,
+        requests: &[Bytes],
+        open: bool,
+    ) -> (RecvStream, SendStream<Bytes>) {
+        let request = http::Request::post(format!("http://127.0.0.1{}", path))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .body(())
+            .unwrap();
+        let mut send = self.runtime.block_on(self.send.clone().ready()).unwrap();
+        let (response, mut stream) = send.send_request(request, false).unwrap();
+        for (i, data) in requests.iter().enumerate() {
+            let end = !open && i + 1 == requests.len();
+            stream.send_data(data.clone(), end).unwrap();
+        }
+        let response = self.runtime.block_on(response).unwrap();
+        assert_eq!(response.status(), 200);
+        (response.into_body(), stream)
+    }
+
+    // Make a call and receive all its responses and its trailers.
+    fn call(&mut self, path: &str, requests: &[Bytes]) -> (Vec<Fields>, HeaderMap) {
+        let (mut body, _stream) = self.start(path, requests, false);
+        let mut buf = BytesMut::new();
+        let mut responses = Vec::new();
+        while let Some(message) = next_response(&mut body, &mut buf) {
+            responses.push(message);
+        }
+        let trailers = future::poll_fn(|| body.poll_trailers())
+            .wait()
+            .unwrap()
+            .unwrap();
+        (responses, trailers)
+    }
+
+    fn unary(&mut self, path: &str, request: Bytes) -> Fields {
+        let (mut responses, trailers) = self.call(path, &[request]);
+        assert_eq!(trailers["grpc-status"], "0", "{:?}", trailers);
+        assert_eq!(responses.len(), 1);
+        responses.remove(0)
+    }
+}
+
+#[test]
+fn grpc_codec() {
+    let mut labels = BTreeMap::new();
+    labels.insert(String::from("team"), String::from("payments"));
+    let time = Utc.timestamp_opt(1_500_000_000, 5).unwrap();
+    let mut encoded = BytesMut::from(request(|e| {
+        e.uint64(1, 300);
+        e.uint64(2, 0);
+        e.optional_uint64(3, Some(0));
+        e.string(4, "");
+        e.string(5, "courier");
+        e.packed_uint64(6, vec![1, 2, 150]);
+        e.map(7, &labels);
+        e.timestamp(8, time);
+        e.int64(9, -1);
+    }));
+    assert_eq!(&request(|_| {})[..], &[0, 0, 0, 0, 0]);
+
+    let fields: Fields = codec::unframe(&mut encoded).unwrap().unwrap();
+    assert!(encoded.is_empty());
+    assert_eq!(fields.uint64(1), 300);
+    assert!(fields.0.iter().all(|f| f.0 != 2 && f.0 != 4));
+    assert_eq!(fields.0.iter().filter(|f| f.0 == 3).count(), 1);
+    assert_eq!(fields.string(5), "courier");
+    let mut partitions = Vec::new();
+    codec::Value::Bytes(&fields.0.iter().find(|f| f.0 == 6).unwrap().2)
+        .push_uint32(&mut partitions)
+        .unwrap();
+    assert_eq!(partitions, vec![1, 2, 150]);
+    let entry = &fields.messages(7)[0];
+    assert_eq!(entry.string(1), "team");
+    assert_eq!(entry.string(2), "payments");
+    let timestamp = &fields.0.iter().find(|f| f.0 == 8).unwrap().2;
+    assert_eq!(codec::Value::Bytes(timestamp).timestamp().unwrap(), time);
+    assert_eq!(fields.uint64(9) as i64, -1);
+
+    // Truncated frames wait for more data, invalid messages are rejected
+    let mut partial = BytesMut::from(&[0, 0, 0, 0, 2, 8][..]);
+    assert!(codec::unframe::<Fields>(&mut partial).unwrap().is_none());
+    let mut invalid = BytesMut::from(&[0, 0, 0, 0, 2, 8, 0x80][..]);
+    assert!(codec::unframe::<Fields>(&mut invalid).is_err());
+    let mut compressed = BytesMut::from(&[1, 0, 0, 0, 0][..]);
+    assert!(codec::unframe::<Fields>(&mut compressed).is_err());
+    assert!(Fields::decode(&[0x0b]).is_err());
+
+    // Repeated fields are accepted unpacked
+    let mut partitions = Vec::new();
+    let unpacked = request(|e| {
+        e.uint64(4, 7);
+        e.uint64(4, 8);
+    });
+    for field in Fields::decode(&unpacked[5..]).unwrap().0 {
+        codec::Value::Varint(field.1.unwrap())
+            .push_uint32(&mut partitions)
+            .unwrap();
+    }
+    assert_eq!(partitions, vec![7, 8]);
+}
+
+#[test]
+fn grpc_protocol() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+    let mut client = Client::connect(port);
+
+    let topic = client.unary(
+        "/courier.v1.TopicService/CreateTopic",
+        request(|e| {
+            e.string(2, "topic");
+            e.uint64(5, 2);
+            e.string(7, "events");
+        }),
+    );
+    assert_eq!(topic.string(1), "default");
+    assert_eq!(topic.string(2), "topic");
+    assert_eq!(topic.string(4), "events");
+    assert_eq!(topic.uint64(5), 3600);
+    assert_eq!(topic.uint64(7), 2);
+
+    // Errors end the call with the status and code of the error
+    let (responses, trailers) = client.call(
+        "/courier.v1.TopicService/GetTopic",
+        &[request(|e| e.string(2, "missing"))],
+    );
+    assert!(responses.is_empty());
+    assert_eq!(trailers["grpc-status"], "5");
+    assert_eq!(
+        trailers["grpc-message"],
+        "Topic 'default/missing' does not exist"
+    );
+    assert_eq!(trailers["courier-error-code"], "TOPIC_NOT_FOUND");
+    let (_, trailers) = client.call("/courier.v1.TopicService/Missing", &[]);
+    assert_eq!(trailers["grpc-status"], "12");
+    assert_eq!(trailers["courier-error-code"], "ROUTE_NOT_FOUND");
+
+    client.unary(
+        "/courier.v1.SubscriptionService/CreateSubscription",
+        request(|e| {
+            e.string(2, "sub");
+            e.string(4, "topic");
+        }),
+    );
+    let published = client.unary(
+        "/courier.v1.TopicService/Publish",
+        request(|e| {
+            e.string(2, "topic");
+            for data in &["a", "b"] {
+                e.message(3, &Request(move |e: &mut Encoder| e.string(1, data)));
+            }
+        }),
+    );
+    assert_eq!(published.strings(1).len(), 2);
+    let listed = client.unary(
+        "/courier.v1.TopicService/ListTopicSubscriptions",
+        request(|e| e.string(2, "topic")),
+    );
+    assert_eq!(listed.strings(1), vec!["sub"]);
+
+    let pulled = client.unary(
+        "/courier.v1.SubscriptionService/Pull",
+        request(|e| {
+            e.string(2, "sub");
+            e.optional_uint64(3, Some(10));
+        }),
+    );
+    let mut messages = pulled.messages(1);
+    messages.sort_by_key(|m| m.string(6));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].string(6), "a");
+    assert_eq!(messages[0].uint64(5), 1);
+    assert_eq!(messages[1].string(6), "b");
+
+    // Every ack request of a stream gets a response
+    let acks: Vec<_> = messages
+        .iter()
+        .map(|m| {
+            let id = m.string(1);
+            request(move |e| {
+                e.string(2, "sub");
+                e.string(3, &id);
+            })
+        })
+        .collect();
+    let (responses, trailers) = client.call("/courier.v1.SubscriptionService/StreamingAck", &acks);
+    assert_eq!(trailers["grpc-status"], "0");
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0].strings(1), vec![messages[0].string(1)]);
+    assert_eq!(responses[1].strings(1), vec![messages[1].string(1)]);
+
+    // A streaming pull sends the messages as they are published until it is cancelled
+    let (mut body, stream) = client.start(
+        "/courier.v1.SubscriptionService/StreamingPull",
+        &[request(|e| e.string(2, "sub"))],
+        true,
+    );
+    let mut buf = BytesMut::new();
+    for data in &["c", "d"] {
+        client.unary(
+            "/courier.v1.TopicService/Publish",
+            request(|e| {
+                e.string(2, "topic");
+                e.message(3, &Request(|e: &mut Encoder| e.string(1, data)));
+            }),
+        );
+        let pulled = next_response(&mut body, &mut buf).unwrap();
+        assert_eq!(pulled.messages(1)[0].string(6), *data);
+    }
+    drop((body, stream));
+    thread::sleep(STREAMING_PULL_INTERVAL * 3);
+    client.unary(
+        "/courier.v1.TopicService/Publish",
+        request(|e| {
+            e.string(2, "topic");
+            e.message(3, &Request(|e: &mut Encoder| e.string(1, "e")));
+        }),
+    );
+    let pulled = client.unary(
+        "/courier.v1.SubscriptionService/Pull",
+        request(|e| e.string(2, "sub")),
+    );
+    assert_eq!(pulled.messages(1)[0].string(6), "e");
+}
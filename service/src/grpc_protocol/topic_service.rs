@@ -0,0 +1,119 @@
+//! The `courier.v1.TopicService`, it mirrors the topic end points of the HTTP API.
+
+use chrono::Duration;
+use courier::{Message, TopicMeta};
+use uuid::Uuid;
+
+use crate::grpc_protocol::types::{self, namespace};
+use crate::grpc_protocol::GrpcState;
+use crate::http_protocol::topic_handlers::create_topic;
+use crate::http_protocol::types::{
+    OffsetMessageList, PublishedMessageList, PurgeResult, SubscriptionNameList, TopicList,
+};
+use courier::Result;
+
+pub fn create(state: &GrpcState, request: types::CreateTopicRequest) -> Result<TopicMeta> {
+    let namespace = String::from(namespace(&request.namespace));
+    let name = if request.name.is_empty() {
+        Uuid::new_v4().to_string()
+    } else {
+        request.name.clone()
+    };
+    create_topic(
+        &namespace,
+        &name,
+        &request.config(),
+        &state.registry,
+        &state.config.read(),
+    )
+}
+
+pub fn update(state: &GrpcState, request: types::UpdateTopicRequest) -> Result<TopicMeta> {
+    let message_ttl = request
+        .message_ttl
+        .map(|message_ttl| Duration::seconds(i64::from(message_ttl)));
+    let ttl = request.ttl.map(|ttl| Duration::seconds(i64::from(ttl)));
+    state.registry.update_topic(
+        namespace(&request.namespace),
+        &request.name,
+        request.labels,
+        request.description,
+        message_ttl,
+        ttl,
+    )
+}
+
+pub fn delete(state: &GrpcState, request: types::ResourceRequest) -> Result<types::Empty> {
+    state
+        .registry
+        .delete_topic(namespace(&request.namespace), &request.name)?;
+    Ok(types::Empty)
+}
+
+pub fn get(state: &GrpcState, request: types::ResourceRequest) -> Result<TopicMeta> {
+    state
+        .registry
+        .get_topic(namespace(&request.namespace), &request.name)
+}
+
+pub fn list(state: &GrpcState, request: types::ListRequest) -> Result<TopicList> {
+    let page = state
+        .registry
+        .list_topics(namespace(&request.namespace), &request.options)?;
+    Ok(TopicList::new(page.items, page.next_page_token))
+}
+
+pub fn subscriptions(
+    state: &GrpcState,
+    request: types::ListTopicSubscriptionsRequest,
+) -> Result<SubscriptionNameList> {
+    let page = state.registry.list_topic_subscriptions(
+        namespace(&request.namespace),
+        &request.name,
+        &request.options,
+    )?;
+    Ok(SubscriptionNameList::new(page.items, page.next_page_token))
+}
+
+pub fn publish(state: &GrpcState, request: types::PublishRequest) -> Result<PublishedMessageList> {
+    let published = state.registry.publish(
+        namespace(&request.namespace),
+        &request.name,
+        request.messages,
+    )?;
+    Ok(PublishedMessageList::new(published))
+}
+
+pub fn purge(state: &GrpcState, request: types::PurgeTopicRequest) -> Result<PurgeResult> {
+    let purged =
+        state
+            .registry
+            .purge_topic(namespace(&request.namespace), &request.name, request.before)?;
+    Ok(PurgeResult::new(purged))
+}
+
+pub fn messages(
+    state: &GrpcState,
+    request: types::ReadMessagesRequest,
+) -> Result<OffsetMessageList> {
+    let max = request
+        .limit
+        .map_or(state.config.read().default_max_messages, |limit| {
+            limit as usize
+        });
+    let (messages, next_offset) = state.registry.read(
+        namespace(&request.namespace),
+        &request.name,
+        request.partition,
+        request.from_offset,
+        max,
+    )?;
+    Ok(OffsetMessageList::new(messages, next_offset))
+}
+
+pub fn message(state: &GrpcState, request: types::GetMessageRequest) -> Result<Message> {
+    let id = types::message_id(&request.id)?;
+    state
+        .registry
+        .get_message(namespace(&request.namespace), &request.name, id)
+}
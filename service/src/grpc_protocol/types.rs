@@ -0,0 +1,546 @@
+//! The messages of `proto/courier/v1/courier.proto`.
+//!
+//! Requests are decoded into the types below, responses are encoded from the types of the HTTP
+//! API.
+
+use chrono::prelude::*;
+use courier::{
+    Consumer, Error, ListOptions, Message, OrderBy, RawMessage, SubscriptionMeta, TopicMeta,
+    DEFAULT_NAMESPACE,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::grpc_protocol::codec::{Decode, Encode, Encoder, Value};
+use crate::http_protocol::types;
+
+/// The namespace of a request, the default namespace if it is empty.
+pub fn namespace(namespace: &str) -> &str {
+    if namespace.is_empty() {
+        DEFAULT_NAMESPACE
+    } else {
+        namespace
+    }
+}
+
+pub fn message_id(id: &str) -> Result<Uuid, Error> {
+    Uuid::parse_str(id)
+        .map_err(|_| Error::invalid_request(format!("'{}' is not a valid message id", id)))
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// A request for a single topic or subscription.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceRequest {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl Decode for ResourceRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Decode for ListOptions {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.prefix = non_empty(value.string()?),
+            2 => self.labels = non_empty(value.string()?),
+            3 => {
+                self.order_by = match value.uint32()? {
+                    0 => OrderBy::Name,
+                    1 => OrderBy::Created,
+                    order_by => {
+                        return Err(Error::invalid_request(format!(
+                            "Unknown order '{}'",
+                            order_by
+                        )))
+                    }
+                }
+            }
+            4 => self.page_size = Some(value.uint32()? as usize).filter(|&size| size > 0),
+            5 => self.page_token = non_empty(value.string()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// A request listing the topics or subscriptions of a namespace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListRequest {
+    pub namespace: String,
+    pub options: ListOptions,
+}
+
+impl Decode for ListRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.options = value.message()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListTopicSubscriptionsRequest {
+    pub namespace: String,
+    pub name: String,
+    pub options: ListOptions,
+}
+
+impl Decode for ListTopicSubscriptionsRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.options = value.message()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// Labels replacing the labels of a topic or subscription.
+#[derive(Default)]
+struct Labels(HashMap<String, String>);
+
+impl Decode for Labels {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        if field == 1 {
+            value.insert_entry(&mut self.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CreateTopicRequest {
+    pub namespace: String,
+    pub name: String,
+    pub message_ttl: Option<u32>,
+    pub ttl: Option<u32>,
+    pub partitions: u32,
+    pub labels: HashMap<String, String>,
+    pub description: String,
+}
+
+impl CreateTopicRequest {
+    pub fn config(self) -> types::TopicCreateConfig {
+        types::TopicCreateConfig {
+            message_ttl: self.message_ttl,
+            ttl: self.ttl,
+            partitions: Some(self.partitions).filter(|&partitions| partitions > 0),
+            labels: Some(self.labels),
+            description: Some(self.description),
+        }
+    }
+}
+
+impl Decode for CreateTopicRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.message_ttl = Some(value.uint32()?),
+            4 => self.ttl = Some(value.uint32()?),
+            5 => self.partitions = value.uint32()?,
+            6 => value.insert_entry(&mut self.labels)?,
+            7 => self.description = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UpdateTopicRequest {
+    pub namespace: String,
+    pub name: String,
+    pub message_ttl: Option<u32>,
+    pub ttl: Option<u32>,
+    pub labels: Option<HashMap<String, String>>,
+    pub description: Option<String>,
+}
+
+impl Decode for UpdateTopicRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.message_ttl = Some(value.uint32()?),
+            4 => self.ttl = Some(value.uint32()?),
+            5 => self.labels = Some(value.message::<Labels>()?.0),
+            6 => self.description = Some(value.string()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Decode for RawMessage {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.data = value.string()?,
+            2 => self.partition_key = non_empty(value.string()?),
+            3 => self.origin = non_empty(value.string()?),
+            4 => self.partition = Some(value.uint32()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PublishRequest {
+    pub namespace: String,
+    pub name: String,
+    pub messages: Vec<RawMessage>,
+}
+
+impl Decode for PublishRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.messages.push(value.message()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PurgeTopicRequest {
+    pub namespace: String,
+    pub name: String,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl Decode for PurgeTopicRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.before = Some(value.timestamp()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadMessagesRequest {
+    pub namespace: String,
+    pub name: String,
+    pub partition: u32,
+    pub from_offset: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+impl Decode for ReadMessagesRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.partition = value.uint32()?,
+            4 => self.from_offset = Some(value.uint64()?),
+            5 => self.limit = Some(value.uint32()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GetMessageRequest {
+    pub namespace: String,
+    pub name: String,
+    pub id: String,
+}
+
+impl Decode for GetMessageRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.id = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CreateSubscriptionRequest {
+    pub namespace: String,
+    pub name: String,
+    pub topic_namespace: String,
+    pub topic: String,
+    pub ack_deadline: Option<u32>,
+    pub ttl: Option<u32>,
+    pub consumer_timeout: Option<u32>,
+    pub historical: bool,
+    pub labels: HashMap<String, String>,
+    pub description: String,
+}
+
+impl CreateSubscriptionRequest {
+    pub fn config(self) -> types::SubscriptionCreateConfig {
+        types::SubscriptionCreateConfig {
+            topic: self.topic,
+            topic_namespace: non_empty(self.topic_namespace),
+            ack_deadline: self.ack_deadline,
+            ttl: self.ttl,
+            consumer_timeout: self.consumer_timeout,
+            historical: Some(self.historical),
+            labels: Some(self.labels),
+            description: Some(self.description),
+        }
+    }
+}
+
+impl Decode for CreateSubscriptionRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.topic_namespace = value.string()?,
+            4 => self.topic = value.string()?,
+            5 => self.ack_deadline = Some(value.uint32()?),
+            6 => self.ttl = Some(value.uint32()?),
+            7 => self.consumer_timeout = Some(value.uint32()?),
+            8 => self.historical = value.bool()?,
+            9 => value.insert_entry(&mut self.labels)?,
+            10 => self.description = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UpdateSubscriptionRequest {
+    pub namespace: String,
+    pub name: String,
+    pub ack_deadline: Option<u32>,
+    pub ttl: Option<u32>,
+    pub consumer_timeout: Option<u32>,
+    pub labels: Option<HashMap<String, String>>,
+    pub description: Option<String>,
+}
+
+impl Decode for UpdateSubscriptionRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.ack_deadline = Some(value.uint32()?),
+            4 => self.ttl = Some(value.uint32()?),
+            5 => self.consumer_timeout = Some(value.uint32()?),
+            6 => self.labels = Some(value.message::<Labels>()?.0),
+            7 => self.description = Some(value.string()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PullRequest {
+    pub namespace: String,
+    pub name: String,
+    pub max_messages: Option<u32>,
+    pub partitions: Vec<u32>,
+    pub consumer_id: Option<String>,
+}
+
+impl Decode for PullRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.max_messages = Some(value.uint32()?),
+            4 => value.push_uint32(&mut self.partitions)?,
+            5 => self.consumer_id = non_empty(value.string()?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AckRequest {
+    pub namespace: String,
+    pub name: String,
+    pub message_ids: Vec<Uuid>,
+}
+
+impl Decode for AckRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.message_ids.push(message_id(&value.string()?)?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReleaseConsumerRequest {
+    pub namespace: String,
+    pub name: String,
+    pub consumer_id: String,
+}
+
+impl Decode for ReleaseConsumerRequest {
+    fn merge(&mut self, field: u32, value: Value<'_>) -> Result<(), Error> {
+        match field {
+            1 => self.namespace = value.string()?,
+            2 => self.name = value.string()?,
+            3 => self.consumer_id = value.string()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// An empty response.
+pub struct Empty;
+
+impl Encode for Empty {
+    fn encode(&self, _: &mut Encoder) {}
+}
+
+impl Encode for TopicMeta {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.string(1, &self.namespace);
+        encoder.string(2, &self.name);
+        encoder.map(3, &self.labels);
+        encoder.string(4, &self.description);
+        encoder.int64(5, self.message_ttl);
+        encoder.int64(6, self.ttl);
+        encoder.uint32(7, self.partitions);
+        encoder.timestamp(8, self.created);
+        encoder.timestamp(9, self.updated);
+    }
+}
+
+impl Encode for SubscriptionMeta {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.string(1, &self.namespace);
+        encoder.string(2, &self.name);
+        encoder.string(3, &self.topic_namespace);
+        encoder.string(4, &self.topic);
+        encoder.map(5, &self.labels);
+        encoder.string(6, &self.description);
+        encoder.int64(7, self.ack_deadline);
+        encoder.int64(8, self.ttl);
+        encoder.int64(9, self.consumer_timeout);
+        encoder.timestamp(10, self.created);
+        encoder.timestamp(11, self.updated);
+    }
+}
+
+impl Encode for Message {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.string(1, &self.id.to_string());
+        encoder.uint32(2, self.partition);
+        encoder.uint64(3, self.offset);
+        encoder.timestamp(4, self.time);
+        encoder.uint32(5, self.tries);
+        encoder.string(6, &self.data);
+        encoder.string(7, self.origin.as_deref().unwrap_or_default());
+    }
+}
+
+impl Encode for Consumer {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.string(1, &self.id);
+        encoder.timestamp(2, self.last_seen);
+        encoder.uint64(3, self.in_flight as u64);
+    }
+}
+
+impl Encode for types::TopicList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.messages(1, &self.topics);
+        encoder.string(2, self.next_page_token.as_deref().unwrap_or_default());
+    }
+}
+
+impl Encode for types::SubscriptionNameList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.strings(1, &self.subscription_names);
+        encoder.string(2, self.next_page_token.as_deref().unwrap_or_default());
+    }
+}
+
+impl Encode for types::SubscriptionList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.messages(1, &self.subscriptions);
+        encoder.string(2, self.next_page_token.as_deref().unwrap_or_default());
+    }
+}
+
+impl Encode for types::PublishedMessageList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.strings(1, self.message_ids.iter().map(Uuid::to_string));
+        encoder.packed_uint64(2, self.partitions.iter().map(|&p| u64::from(p)));
+        encoder.packed_uint64(3, self.offsets.iter().cloned());
+    }
+}
+
+impl Encode for types::PurgeResult {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.uint64(1, self.purged as u64);
+    }
+}
+
+impl Encode for types::OffsetMessageList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.messages(1, &self.messages);
+        encoder.uint64(2, self.next_offset);
+    }
+}
+
+impl Encode for types::MessageList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.messages(1, &self.messages);
+    }
+}
+
+impl Encode for types::MessageIdList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.strings(1, self.message_ids.iter().map(Uuid::to_string));
+    }
+}
+
+impl Encode for types::ConsumerList {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.messages(1, &self.consumers);
+    }
+}
+
+impl Encode for types::ReleaseResult {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.uint64(1, self.released as u64);
+    }
+}
@@ -0,0 +1,73 @@
+use actix_web::http::StatusCode;
+use actix_web::middleware::{Middleware, Response};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use courier::{Error, ErrorCode};
+use std::error;
+use std::fmt;
+
+/// A courier [Error](../../courier/struct.Error.html) returned by an HTTP handler.
+#[derive(Debug)]
+pub struct HttpError(pub Error);
+
+/// Get the HTTP status code of an error code.
+pub fn status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::NamespaceNotFound
+        | ErrorCode::TopicNotFound
+        | ErrorCode::SubscriptionNotFound
+        | ErrorCode::MessageNotFound
+        | ErrorCode::PartitionNotFound
+        | ErrorCode::ConsumerNotFound
+        | ErrorCode::RouteNotFound => StatusCode::NOT_FOUND,
+        ErrorCode::NamespaceAlreadyExists
+        | ErrorCode::TopicAlreadyExists
+        | ErrorCode::SubscriptionAlreadyExists => StatusCode::CONFLICT,
+        ErrorCode::NamespaceNotShared => StatusCode::FORBIDDEN,
+        ErrorCode::OffsetExpired => StatusCode::GONE,
+        ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+        ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl From<Error> for HttpError {
+    fn from(error: Error) -> Self {
+        HttpError(error)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for HttpError {}
+
+impl ResponseError for HttpError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(status(self.0.code)).json(&self.0)
+    }
+}
+
+/// Middleware which replaces the error responses not created from an
+/// [HttpError](struct.HttpError.html), for example a body that fails to parse or a route that
+/// does not exist, with a JSON error.
+pub struct JsonErrors;
+
+impl<S> Middleware<S> for JsonErrors {
+    fn response(&self, req: &HttpRequest<S>, resp: HttpResponse) -> actix_web::Result<Response> {
+        let status = resp.status();
+        if !(status.is_client_error() || status.is_server_error()) {
+            return Ok(Response::Done(resp));
+        }
+        let error = match resp.error() {
+            Some(e) if e.downcast_ref::<HttpError>().is_some() => return Ok(Response::Done(resp)),
+            Some(e) if status.is_client_error() => Error::invalid_request(e.to_string()),
+            Some(e) => Error::internal(e.to_string()),
+            None if status == StatusCode::NOT_FOUND => Error::route_not_found(req.path()),
+            None => return Ok(Response::Done(resp)),
+        };
+        Ok(Response::Done(HttpError(error).error_response()))
+    }
+}
@@ -1,11 +1,11 @@
 #![cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 
-use actix_web::{FromRequest, HttpRequest, HttpResponse, State};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Json, State};
+use courier::cluster::ClusterStatus;
 use serde_json;
-use std::clone::Clone;
-use std::sync::Arc;
 
-use crate::http_protocol::HttpState;
+use crate::http_protocol::types::{BridgeList, Identity, Origin};
+use crate::http_protocol::{ClientIdentity, HttpState};
 
 pub fn heartbeat(_: HttpRequest<HttpState>) -> &'static str {
     "heartbeat"
@@ -13,9 +13,29 @@ pub fn heartbeat(_: HttpRequest<HttpState>) -> &'static str {
 
 pub fn metrics(req: HttpRequest<HttpState>) -> HttpResponse {
     let state = State::extract(&req);
-    let reg = Arc::clone(&state.registry);
-    let metrics_wrapper = reg.metrics();
-    let metrics = metrics_wrapper.read();
-    let json = serde_json::to_string(&*metrics).unwrap_or_else(|_| String::from("{}"));
+    let metrics = state.registry.metrics();
+    let json = serde_json::to_string(&metrics).unwrap_or_else(|_| String::from("{}"));
     HttpResponse::Ok().body(json)
 }
+
+pub fn cluster(state: State<HttpState>) -> Json<Option<ClusterStatus>> {
+    Json(state.registry.cluster_status())
+}
+
+pub fn origin(state: State<HttpState>) -> Json<Origin> {
+    Json(Origin::new(state.config.read().origin.clone()))
+}
+
+pub fn identity(req: HttpRequest<HttpState>) -> Json<Identity> {
+    Json(Identity::new(ClientIdentity::of(&req)))
+}
+
+pub fn bridges(state: State<HttpState>) -> Json<BridgeList> {
+    Json(BridgeList::new(
+        state
+            .bridges
+            .iter()
+            .map(|bridge| bridge.metrics())
+            .collect(),
+    ))
+}
@@ -0,0 +1,14 @@
+#![cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+
+use crate::http_protocol::error::HttpError;
+use crate::http_protocol::state::HttpState;
+use crate::http_protocol::types;
+use actix_web::{Json, Path, State};
+use courier::MessageStatus;
+
+pub fn status(
+    (path, state): (Path<types::MessagePath>, State<HttpState>),
+) -> Result<Json<MessageStatus>, HttpError> {
+    let reg = &state.registry;
+    Ok(Json(reg.message_status(&path.namespace, path.id)?))
+}
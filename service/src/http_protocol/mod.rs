@@ -1,51 +1,51 @@
+mod error;
 mod general_handlers;
+mod message_handlers;
+mod namespace_handlers;
 mod state;
-mod subscription_handlers;
+pub mod subscription_handlers;
 #[cfg(test)]
 mod tests;
-mod topic_handlers;
-mod types;
+#[cfg(feature = "tls")]
+mod tls;
+pub mod topic_handlers;
+pub mod types;
 
 use actix;
+#[cfg(feature = "tls")]
+use actix_net::service::NewServiceExt;
+#[cfg(feature = "tls")]
+use actix_net::ssl;
 use actix_web::http::{header, Method, NormalizePath};
 use actix_web::middleware::{cors, Logger};
-use actix_web::{server, App, HttpRequest, HttpResponse};
+use actix_web::{server, App, HttpRequest, HttpResponse, Scope};
 use include_dir::{include_dir, include_dir_impl, Dir};
-use log::debug;
+use log::info;
 use mime_guess::guess_mime_type;
+use std::fs;
+use std::io;
 use std::sync::Arc;
-use std::thread;
-use std::time;
 
-pub use self::state::{Config, HttpState};
-use courier::Registry;
+pub use self::state::{
+    ClientIdentity, Config, HttpState, SharedConfig, TlsConfig, UnixSocketConfig,
+};
+use courier::bridge::Bridge;
+use courier::SharedRegistry;
 
 const WEB: Dir<'_> = include_dir!("../web/dist");
 
 static LOGGER_FORMAT: &'static str = "%a \"%r\" (%s %Ts %bB)";
 
+/// Build the factory of the web user interface and the HTTP api apps.
 pub fn create(
-    config: Config,
+    registry: &SharedRegistry,
+    shared_config: &SharedConfig,
+    bridges: &[Arc<Bridge>],
 ) -> impl Fn() -> Vec<Box<dyn server::HttpHandler<Task = Box<dyn server::HttpHandlerTask>>>> + Clone
 {
-    let registry = Registry::new();
-    let registry_cleanup = Arc::clone(&registry);
-
-    let cleanup_interval = match config.cleanup_interval.to_std() {
-        Ok(duration) => duration,
-        _ => time::Duration::from_secs(0),
-    };
-
-    thread::spawn(move || loop {
-        let (topics_removed, subscriptions_removed, messages_removed) = registry_cleanup.cleanup();
-        debug!(
-            "Removed '{}' topics, '{}' subscriptions, '{}' messages ",
-            topics_removed, subscriptions_removed, messages_removed
-        );
-
-        thread::sleep(cleanup_interval)
-    });
-
+    let registry = Arc::clone(registry);
+    let shared_config = Arc::clone(shared_config);
+    let bridges = bridges.to_vec();
     move || {
         let mut web_app = App::new().prefix("/ui");
         // Add the static files to the web app
@@ -77,40 +77,34 @@ pub fn create(
                 .middleware(Logger::new(LOGGER_FORMAT))
                 .middleware(cors::Cors::build().finish())
                 .boxed(),
-            App::with_state(HttpState::new(&registry, &config))
+            App::with_state(HttpState::new(&registry, &shared_config, &bridges))
                 .prefix("/api/v1")
                 .route("/heartbeat", Method::GET, general_handlers::heartbeat)
                 .route("/metrics", Method::GET, general_handlers::metrics)
-                .scope("/topics", |scope| {
+                .route("/cluster", Method::GET, general_handlers::cluster)
+                .route("/origin", Method::GET, general_handlers::origin)
+                .route("/identity", Method::GET, general_handlers::identity)
+                .route("/bridges", Method::GET, general_handlers::bridges)
+                .scope("/topics", topic_routes)
+                .scope("/subscriptions", subscription_routes)
+                .scope("/messages", message_routes)
+                .scope("/namespaces", |scope| {
                     scope
-                        .route("/{name}", Method::PUT, topic_handlers::create_with_name)
-                        .route("/", Method::PUT, topic_handlers::create_without_name)
-                        .route("/{name}", Method::PATCH, topic_handlers::update)
-                        .route("/{name}", Method::DELETE, topic_handlers::delete)
-                        .route("/{name}", Method::GET, topic_handlers::get)
-                        .route("/", Method::GET, topic_handlers::list)
+                        .nested("/{namespace}/topics", topic_routes)
+                        .nested("/{namespace}/subscriptions", subscription_routes)
+                        .nested("/{namespace}/messages", message_routes)
+                        .route("/{namespace}", Method::PUT, namespace_handlers::create)
+                        .route("/{namespace}", Method::PATCH, namespace_handlers::update)
+                        .route("/{namespace}", Method::DELETE, namespace_handlers::delete)
+                        .route("/{namespace}", Method::GET, namespace_handlers::get)
+                        .route("/", Method::GET, namespace_handlers::list)
                         .route(
-                            "/{name}/subscriptions",
+                            "/{namespace}/metrics",
                             Method::GET,
-                            topic_handlers::subscriptions,
-                        )
-                        .route("/{name}/publish", Method::POST, topic_handlers::publish)
-                })
-                .scope("/subscriptions", |scope| {
-                    scope
-                        .route(
-                            "/{name}",
-                            Method::PUT,
-                            subscription_handlers::create_with_name,
+                            namespace_handlers::metrics,
                         )
-                        .route("/", Method::PUT, subscription_handlers::create_without_name)
-                        .route("/{name}", Method::PATCH, subscription_handlers::update)
-                        .route("/{name}", Method::DELETE, subscription_handlers::delete)
-                        .route("/{name}", Method::GET, subscription_handlers::get)
-                        .route("/", Method::GET, subscription_handlers::list)
-                        .route("/{name}/pull", Method::POST, subscription_handlers::pull)
-                        .route("/{name}/ack", Method::POST, subscription_handlers::ack)
                 })
+                .middleware(error::JsonErrors)
                 .middleware(Logger::new(LOGGER_FORMAT))
                 .middleware(cors::Cors::build().finish())
                 .boxed(),
@@ -118,13 +112,170 @@ pub fn create(
     }
 }
 
-pub fn start(config: Config) {
+fn topic_routes(scope: Scope<HttpState>) -> Scope<HttpState> {
+    scope
+        .route("/{name}", Method::PUT, topic_handlers::create_with_name)
+        .route("/", Method::PUT, topic_handlers::create_without_name)
+        .route("/{name}", Method::PATCH, topic_handlers::update)
+        .route("/{name}", Method::DELETE, topic_handlers::delete)
+        .route("/{name}", Method::GET, topic_handlers::get)
+        .route("/", Method::GET, topic_handlers::list)
+        .route(
+            "/{name}/subscriptions",
+            Method::GET,
+            topic_handlers::subscriptions,
+        )
+        .route("/{name}/publish", Method::POST, topic_handlers::publish)
+        .route("/{name}/purge", Method::POST, topic_handlers::purge)
+        .route("/{name}/messages", Method::GET, topic_handlers::messages)
+        .route(
+            "/{name}/messages/{id}",
+            Method::GET,
+            topic_handlers::message,
+        )
+}
+
+fn subscription_routes(scope: Scope<HttpState>) -> Scope<HttpState> {
+    scope
+        .route(
+            "/{name}",
+            Method::PUT,
+            subscription_handlers::create_with_name,
+        )
+        .route("/", Method::PUT, subscription_handlers::create_without_name)
+        .route("/{name}", Method::PATCH, subscription_handlers::update)
+        .route("/{name}", Method::DELETE, subscription_handlers::delete)
+        .route("/{name}", Method::GET, subscription_handlers::get)
+        .route("/", Method::GET, subscription_handlers::list)
+        .route("/{name}/pull", Method::POST, subscription_handlers::pull)
+        .route("/{name}/ack", Method::POST, subscription_handlers::ack)
+        .route("/{name}/purge", Method::POST, subscription_handlers::purge)
+        .route(
+            "/{name}/consumers",
+            Method::GET,
+            subscription_handlers::consumers,
+        )
+        .route(
+            "/{name}/consumers/{consumer}/release",
+            Method::POST,
+            subscription_handlers::release,
+        )
+}
+
+fn message_routes(scope: Scope<HttpState>) -> Scope<HttpState> {
+    scope.route("/{id}/status", Method::GET, message_handlers::status)
+}
+
+/// Serve the web user interface and the HTTP api until the process is stopped.
+pub fn start(registry: &SharedRegistry, config: SharedConfig, bridges: &[Arc<Bridge>]) {
     let sys = actix::System::new("courier");
-    let address = format!("{}:{}", &config.host, &config.port);
-    server::new(create(config))
-        .bind(address.clone())
-        .expect(&format!("Can not bind to '{}'!", address))
-        .shutdown_timeout(30)
-        .start();
+    let (address, tls, unix_socket) = {
+        let config = config.read();
+        let address = format!("{}:{}", &config.host, &config.port);
+        (address, config.tls.clone(), config.unix_socket.clone())
+    };
+    let factory = create(registry, &config, bridges);
+    if let Some(unix_socket) = &unix_socket {
+        bind_unix_socket(server::new(factory.clone()), unix_socket)
+            .unwrap_or_else(|e| panic!("Can not bind to '{}': {}", unix_socket.path.display(), e));
+    }
+    if !unix_socket.is_some_and(|unix_socket| unix_socket.exclusive) {
+        let server = server::new(factory);
+        let server = match tls {
+            Some(tls) => bind_tls(server, &address, &tls),
+            None => server.bind(address.clone()),
+        };
+        server
+            .expect(&format!("Can not bind to '{}'!", address))
+            .shutdown_timeout(30)
+            .start();
+    }
     sys.run();
 }
+
+#[cfg(unix)]
+fn bind_unix_socket<H, F>(
+    server: server::HttpServer<H, F>,
+    config: &UnixSocketConfig,
+) -> io::Result<()>
+where
+    H: server::IntoHttpHandler + 'static,
+    F: Fn() -> H + Send + Clone + 'static,
+{
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+
+    // Replace a socket left behind by a previous run unless another instance still serves on it
+    match fs::symlink_metadata(&config.path) {
+        Ok(ref metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the path exists and is not a socket",
+            ));
+        }
+        Ok(_) if UnixStream::connect(&config.path).is_ok() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another process serves on the socket",
+            ));
+        }
+        Ok(_) => fs::remove_file(&config.path)?,
+        Err(_) => {}
+    }
+    let listener = tokio::net::UnixListener::bind(&config.path)?;
+    fs::set_permissions(&config.path, fs::Permissions::from_mode(config.mode))?;
+    info!("Serving the api on '{}'.", config.path.display());
+    // Serving a stream of connections is deprecated but the only way to serve a Unix socket
+    #[allow(deprecated)]
+    server.start_incoming(listener.incoming(), false);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn bind_unix_socket<H, F>(_: server::HttpServer<H, F>, _: &UnixSocketConfig) -> io::Result<()>
+where
+    H: server::IntoHttpHandler + 'static,
+    F: Fn() -> H + Send + Clone + 'static,
+{
+    Err(io::Error::other(
+        "Unix domain sockets are only supported on Unix",
+    ))
+}
+
+#[cfg(feature = "tls")]
+fn bind_tls<H, F>(
+    server: server::HttpServer<H, F>,
+    address: &str,
+    tls: &TlsConfig,
+) -> io::Result<server::HttpServer<H, F>>
+where
+    H: server::IntoHttpHandler + 'static,
+    F: Fn() -> H + Send + Clone + 'static,
+{
+    let acceptor = tls::acceptor(tls).map_err(io::Error::other)?;
+    let acceptor = server::openssl_acceptor_with_flags(
+        acceptor,
+        server::ServerFlags::HTTP1 | server::ServerFlags::HTTP2,
+    )?;
+    // Keep the identity of the client certificate with the connection for the handlers
+    server.bind_with(address, move || {
+        ssl::OpensslAcceptor::new(acceptor.clone())
+            .map(tls::IdentifiedStream::new)
+            .map_err(|_| ())
+    })
+}
+
+#[cfg(not(feature = "tls"))]
+fn bind_tls<H, F>(
+    _: server::HttpServer<H, F>,
+    _: &str,
+    _: &TlsConfig,
+) -> io::Result<server::HttpServer<H, F>>
+where
+    H: server::IntoHttpHandler + 'static,
+    F: Fn() -> H + Send + Clone + 'static,
+{
+    Err(io::Error::other(
+        "courier was built without the 'tls' feature",
+    ))
+}
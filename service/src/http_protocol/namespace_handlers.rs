@@ -0,0 +1,72 @@
+#![cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+
+use crate::http_protocol::error::HttpError;
+use crate::http_protocol::state::HttpState;
+use crate::http_protocol::types;
+use actix_web::{HttpResponse, Json, Path, State};
+use chrono::Duration;
+use courier::NamespaceMeta;
+
+fn to_duration(seconds: Option<u32>) -> Option<Duration> {
+    seconds.map(|s| Duration::seconds(i64::from(s)))
+}
+
+pub fn create(
+    (path, config, state): (
+        Path<types::NamespacePath>,
+        Json<types::NamespaceCreateConfig>,
+        State<HttpState>,
+    ),
+) -> Result<HttpResponse, HttpError> {
+    let config = config.into_inner();
+    let namespace = state.registry.create_namespace(
+        &path.namespace,
+        to_duration(config.default_message_ttl),
+        to_duration(config.default_topic_ttl),
+        to_duration(config.default_subscription_ttl),
+        config.shared_with.unwrap_or_default(),
+    )?;
+    Ok(HttpResponse::Created().json(namespace))
+}
+
+pub fn update(
+    (path, config, state): (
+        Path<types::NamespacePath>,
+        Json<types::NamespaceUpdateConfig>,
+        State<HttpState>,
+    ),
+) -> Result<Json<NamespaceMeta>, HttpError> {
+    let config = config.into_inner();
+    let namespace = state.registry.update_namespace(
+        &path.namespace,
+        to_duration(config.default_message_ttl),
+        to_duration(config.default_topic_ttl),
+        to_duration(config.default_subscription_ttl),
+        config.shared_with,
+    )?;
+    Ok(Json(namespace))
+}
+
+pub fn delete(
+    (path, state): (Path<types::NamespacePath>, State<HttpState>),
+) -> Result<HttpResponse, HttpError> {
+    state.registry.delete_namespace(&path.namespace)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn get(
+    (path, state): (Path<types::NamespacePath>, State<HttpState>),
+) -> Result<Json<NamespaceMeta>, HttpError> {
+    Ok(Json(state.registry.get_namespace(&path.namespace)?))
+}
+
+pub fn list(state: State<HttpState>) -> Json<types::NamespaceList> {
+    Json(types::NamespaceList::new(state.registry.list_namespaces()))
+}
+
+pub fn metrics(
+    (path, state): (Path<types::NamespacePath>, State<HttpState>),
+) -> Result<HttpResponse, HttpError> {
+    let namespace_metrics = state.registry.namespace_metrics(&path.namespace)?;
+    Ok(HttpResponse::Ok().json(namespace_metrics))
+}
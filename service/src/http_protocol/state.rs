@@ -1,8 +1,16 @@
+use actix_web::HttpRequest;
 use chrono::Duration;
+use parking_lot::RwLock;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use courier::bridge::{Bridge, BridgeConfig};
+use courier::cluster::ClusterConfig;
 use courier::SharedRegistry;
 
+/// The configuration shared by the apis, the defaults are changed when it is reloaded.
+pub type SharedConfig = Arc<RwLock<Config>>;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Config {
     pub host: String,
@@ -11,20 +19,83 @@ pub struct Config {
     pub default_subscription_ttl: Duration,
     pub default_message_ttl: Duration,
     pub default_ack_deadline: Duration,
+    pub default_consumer_timeout: Duration,
     pub default_max_messages: usize,
-    pub cleanup_interval: Duration,
+    pub tls: Option<TlsConfig>,
+    /// Serves the api on a Unix domain socket when set.
+    pub unix_socket: Option<UnixSocketConfig>,
+    /// Replicates the registry through a cluster of nodes when set.
+    pub cluster: Option<ClusterConfig>,
+    /// Name of this instance attached to the messages it bridges to other instances.
+    pub origin: String,
+    /// Subscriptions forwarded to topics of other instances.
+    pub bridges: Vec<BridgeConfig>,
+    /// Port the gRPC api is served on if any.
+    pub grpc_port: Option<u16>,
+    /// Port MQTT is served on if any.
+    pub mqtt_port: Option<u16>,
+    /// Port STOMP is served on if any.
+    pub stomp_port: Option<u16>,
+    /// Port the Redis streams commands are served on if any.
+    pub redis_port: Option<u16>,
+    /// Port the Kafka protocol is served on if any.
+    pub kafka_port: Option<u16>,
+    /// Port the NATS protocol is served on if any.
+    pub nats_port: Option<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain presented to clients.
+    pub cert: PathBuf,
+    /// PEM encoded private key of the certificate.
+    pub key: PathBuf,
+    /// PEM encoded CA bundle used to verify client certificates. When set, clients must present a
+    /// valid certificate (mTLS).
+    pub client_ca: Option<PathBuf>,
+    /// Client certificate common names (identities) allowed to connect. When empty, any
+    /// certificate signed by `client_ca` is accepted.
+    pub client_identities: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnixSocketConfig {
+    /// Path the socket is created at, a socket left behind by a previous run is replaced.
+    pub path: PathBuf,
+    /// File permissions of the socket.
+    pub mode: u32,
+    /// Serve the api only on the socket and not on the TCP port.
+    pub exclusive: bool,
+}
+
+/// The identity of the client certificate a connection was verified with.
+///
+/// It is kept in the extensions of the connection, see `tls::IdentifiedStream`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientIdentity(pub String);
+
+impl ClientIdentity {
+    /// Get the identity of the client which sent a request over mTLS.
+    pub fn of<S>(req: &HttpRequest<S>) -> Option<String> {
+        req.request()
+            .stream_extensions()?
+            .get::<ClientIdentity>()
+            .map(|identity| identity.0.clone())
+    }
 }
 
 pub struct HttpState {
     pub registry: SharedRegistry,
-    pub config: Config,
+    pub config: SharedConfig,
+    pub bridges: Vec<Arc<Bridge>>,
 }
 
 impl HttpState {
-    pub fn new(registry: &SharedRegistry, config: &Config) -> Self {
+    pub fn new(registry: &SharedRegistry, config: &SharedConfig, bridges: &[Arc<Bridge>]) -> Self {
         HttpState {
             registry: Arc::clone(registry),
-            config: config.clone(),
+            config: Arc::clone(config),
+            bridges: bridges.to_vec(),
         }
     }
 }
@@ -1,22 +1,26 @@
 #![cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 
+use crate::http_protocol::error::HttpError;
 use crate::http_protocol::state::HttpState;
 use crate::http_protocol::types;
 use crate::http_protocol::Config;
-use actix_web::dev::HttpResponseBuilder;
-use actix_web::{HttpResponse, Json, Path, State};
+use actix_web::{HttpResponse, Json, Path, Query, State};
 use chrono::Duration;
 use uuid::Uuid;
 
 use courier::SharedRegistry;
-use courier::SubscriptionMeta;
+use courier::{Error, ListOptions, SubscriptionMeta};
 
-fn create(
+/// Create a subscription, the settings not in `config` default to the settings of the namespace
+/// and then of the service.
+pub fn create_subscription(
+    namespace: &str,
     name: &str,
     config: &types::SubscriptionCreateConfig,
     reg: &SharedRegistry,
     cfg: &Config,
-) -> Option<HttpResponse> {
+) -> Result<SubscriptionMeta, Error> {
+    let topic_namespace = config.topic_namespace.as_deref().unwrap_or(namespace);
     let ack_deadline = config
         .ack_deadline
         .map(|ack_deadline| Duration::seconds(i64::from(ack_deadline)))
@@ -24,99 +28,190 @@ fn create(
     let ttl = config
         .ttl
         .map(|ttl| Duration::seconds(i64::from(ttl)))
+        .or_else(|| {
+            reg.get_namespace(namespace)
+                .ok()
+                .and_then(|n| n.default_subscription_ttl)
+                .map(Duration::seconds)
+        })
         .unwrap_or(cfg.default_subscription_ttl);
-    let subscribe = reg.create_subscription(
-        &name,
+    let consumer_timeout = config
+        .consumer_timeout
+        .map(|timeout| Duration::seconds(i64::from(timeout)))
+        .unwrap_or(cfg.default_consumer_timeout);
+    reg.create_subscription(
+        namespace,
+        name,
+        topic_namespace,
         &config.topic,
+        config.labels.clone().unwrap_or_default(),
+        config.description.clone().unwrap_or_default(),
         ack_deadline,
         ttl,
+        consumer_timeout,
         config.historical.unwrap_or(false),
-    );
-    subscribe.map(|(created, subscription)| {
-        let mut response = if created {
-            HttpResponse::Created()
-        } else {
-            HttpResponse::Conflict()
-        };
-        response.json(subscription)
-    })
+    )
+}
+
+fn create(
+    namespace: &str,
+    name: &str,
+    config: &types::SubscriptionCreateConfig,
+    reg: &SharedRegistry,
+    cfg: &Config,
+) -> Result<HttpResponse, HttpError> {
+    let subscription = create_subscription(namespace, name, config, reg, cfg)?;
+    Ok(HttpResponse::Created().json(subscription))
 }
 
 pub fn create_with_name(
-    (name, config, state): (
-        Path<String>,
+    (path, config, state): (
+        Path<types::ResourcePath>,
         Json<types::SubscriptionCreateConfig>,
         State<HttpState>,
     ),
-) -> Option<HttpResponse> {
+) -> Result<HttpResponse, HttpError> {
     create(
-        &name.into_inner(),
+        &path.namespace,
+        &path.name,
         &config.into_inner(),
         &state.registry,
-        &state.config,
+        &state.config.read(),
     )
 }
 
 pub fn create_without_name(
-    (config, state): (Json<types::SubscriptionCreateConfig>, State<HttpState>),
-) -> Option<HttpResponse> {
+    (path, config, state): (
+        Path<types::NamespacePath>,
+        Json<types::SubscriptionCreateConfig>,
+        State<HttpState>,
+    ),
+) -> Result<HttpResponse, HttpError> {
     create(
+        &path.namespace,
         &Uuid::new_v4().to_string(),
         &config.into_inner(),
         &state.registry,
-        &state.config,
+        &state.config.read(),
     )
 }
 
 pub fn update(
-    (name, config, state): (
-        Path<String>,
+    (path, config, state): (
+        Path<types::ResourcePath>,
         Json<types::SubscriptionUpdateConfig>,
         State<HttpState>,
     ),
-) -> Option<Json<SubscriptionMeta>> {
+) -> Result<Json<SubscriptionMeta>, HttpError> {
     let reg = &state.registry;
     let config = config.into_inner();
     let ack_deadline = config
         .ack_deadline
         .map(|ack_deadline| Duration::seconds(i64::from(ack_deadline)));
     let ttl = config.ttl.map(|ttl| Duration::seconds(i64::from(ttl)));
-    reg.update_subscription(&name, ack_deadline, ttl).map(Json)
+    let consumer_timeout = config
+        .consumer_timeout
+        .map(|timeout| Duration::seconds(i64::from(timeout)));
+    let subscription = reg.update_subscription(
+        &path.namespace,
+        &path.name,
+        config.labels,
+        config.description,
+        ack_deadline,
+        ttl,
+        consumer_timeout,
+    )?;
+    Ok(Json(subscription))
 }
 
-pub fn delete((name, state): (Path<String>, State<HttpState>)) -> HttpResponseBuilder {
-    if state.registry.delete_subscription(&name) {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::NotFound()
-    }
+pub fn delete(
+    (path, state): (Path<types::ResourcePath>, State<HttpState>),
+) -> Result<HttpResponse, HttpError> {
+    state
+        .registry
+        .delete_subscription(&path.namespace, &path.name)?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub fn get((name, state): (Path<String>, State<HttpState>)) -> Option<Json<SubscriptionMeta>> {
-    state.registry.get_subscription(&name).map(Json)
+pub fn get(
+    (path, state): (Path<types::ResourcePath>, State<HttpState>),
+) -> Result<Json<SubscriptionMeta>, HttpError> {
+    let subscription = state
+        .registry
+        .get_subscription(&path.namespace, &path.name)?;
+    Ok(Json(subscription))
 }
 
-pub fn list(state: State<HttpState>) -> Json<types::SubscriptionList> {
-    Json(types::SubscriptionList::new(
-        state.registry.list_subscriptions(),
-    ))
+pub fn list(
+    (path, options, state): (
+        Path<types::NamespacePath>,
+        Query<ListOptions>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::SubscriptionList>, HttpError> {
+    let page = state
+        .registry
+        .list_subscriptions(&path.namespace, &options)?;
+    Ok(Json(types::SubscriptionList::new(
+        page.items,
+        page.next_page_token,
+    )))
 }
 
 pub fn pull(
-    (name, config, state): (Path<String>, Json<types::PullConfig>, State<HttpState>),
-) -> Option<Json<types::MessageList>> {
+    (path, config, state): (
+        Path<types::ResourcePath>,
+        Json<types::PullConfig>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::MessageList>, HttpError> {
     let config = config.into_inner();
     let reg = &state.registry;
-    let cfg = &state.config;
-    let max = config.max_messages.unwrap_or(cfg.default_max_messages);
-    reg.pull(&name, max)
-        .map(|messages| Json(types::MessageList::new(messages)))
+    let max = config
+        .max_messages
+        .unwrap_or(state.config.read().default_max_messages);
+    let messages = reg.pull(
+        &path.namespace,
+        &path.name,
+        max,
+        config.partitions.as_deref(),
+        config.consumer_id.as_deref(),
+    )?;
+    Ok(Json(types::MessageList::new(messages)))
+}
+
+pub fn consumers(
+    (path, state): (Path<types::ResourcePath>, State<HttpState>),
+) -> Result<Json<types::ConsumerList>, HttpError> {
+    let consumers = state.registry.consumers(&path.namespace, &path.name)?;
+    Ok(Json(types::ConsumerList::new(consumers)))
+}
+
+pub fn release(
+    (path, state): (Path<types::ConsumerPath>, State<HttpState>),
+) -> Result<Json<types::ReleaseResult>, HttpError> {
+    let released = state
+        .registry
+        .release_consumer(&path.namespace, &path.name, &path.consumer)?;
+    Ok(Json(types::ReleaseResult::new(released)))
+}
+
+pub fn purge(
+    (path, state): (Path<types::ResourcePath>, State<HttpState>),
+) -> Result<Json<types::PurgeResult>, HttpError> {
+    let reg = &state.registry;
+    let purged = reg.purge_subscription(&path.namespace, &path.name)?;
+    Ok(Json(types::PurgeResult::new(purged)))
 }
 
 pub fn ack(
-    (name, ids, state): (Path<String>, Json<types::MessageIdList>, State<HttpState>),
-) -> Option<Json<types::MessageIdList>> {
+    (path, ids, state): (
+        Path<types::ResourcePath>,
+        Json<types::MessageIdList>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::MessageIdList>, HttpError> {
     let reg = &state.registry;
-    reg.ack(&name, &ids.into_inner().message_ids)
-        .map(|ids| Json(types::MessageIdList::new(ids)))
+    let ids = reg.ack(&path.namespace, &path.name, &ids.into_inner().message_ids)?;
+    Ok(Json(types::MessageIdList::new(ids)))
 }
@@ -2,11 +2,35 @@ use crate::http_protocol::types::*;
 use crate::http_protocol::*;
 use actix_web::http::{Method, StatusCode};
 use actix_web::{test, HttpMessage};
-use chrono::Duration;
-use courier::{SubscriptionMeta, TopicMeta};
+use chrono::prelude::*;
+use courier::{
+    DeliveryStatus, Error, ErrorCode, Message, MessageStatus, NamespaceMeta, Registry,
+    SubscriptionMeta, TopicMeta,
+};
 use futures::Future;
+#[cfg(feature = "tls")]
+use openssl::asn1::Asn1Time;
+#[cfg(feature = "tls")]
+use openssl::bn::BigNum;
+#[cfg(feature = "tls")]
+use openssl::hash::MessageDigest;
+#[cfg(feature = "tls")]
+use openssl::pkcs12::Pkcs12;
+#[cfg(feature = "tls")]
+use openssl::pkey::{PKey, Private};
+#[cfg(feature = "tls")]
+use openssl::rsa::Rsa;
+#[cfg(feature = "tls")]
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+#[cfg(feature = "tls")]
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+use parking_lot::RwLock;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "tls")]
+use std::fs;
+use std::sync::Arc;
 use std::thread;
 use std::time;
 
@@ -58,15 +82,11 @@ where
 fn get_server() -> (Config, test::TestServer) {
     let config = Config {
         host: String::from("localhost"),
-        port: 3140,
-        default_topic_ttl: Duration::seconds(0),
-        default_subscription_ttl: Duration::seconds(0),
-        default_message_ttl: Duration::seconds(3600),
-        default_ack_deadline: Duration::seconds(60),
-        default_max_messages: 1,
-        cleanup_interval: Duration::seconds(1),
-    };
-    let server = test::TestServer::with_factory(create(config.clone()));
+        origin: String::from("test"),
+        ..Config::default()
+    };
+    let shared_config = Arc::new(RwLock::new(config.clone()));
+    let server = test::TestServer::with_factory(create(&Registry::new(), &shared_config, &[]));
     (config, server)
 }
 
@@ -78,13 +98,20 @@ fn http_protocol_create_topic() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     let (status, body): (_, TopicMeta) =
         get_response(&mut server, "topics/test", Method::PUT, topic_config);
     let expected = TopicMeta {
+        namespace: String::from("default"),
         name: String::from("test"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         message_ttl: config.default_message_ttl.num_seconds(),
         ttl: 0,
+        partitions: 1,
         created: body.created,
         updated: body.updated,
     };
@@ -94,21 +121,33 @@ fn http_protocol_create_topic() {
     let topic_config = TopicCreateConfig {
         message_ttl: Some(30),
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
-    let (status, body) = get_response(&mut server, "topics/test", Method::PUT, topic_config);
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/test", Method::PUT, topic_config);
     assert_eq!(StatusCode::CONFLICT, status);
-    assert_eq!(expected, body);
+    assert_eq!(ErrorCode::TopicAlreadyExists, body.code);
+    assert_eq!(Some(String::from("default/test")), body.resource);
     // Create a topic with no name
     let topic_config = TopicCreateConfig {
         message_ttl: Some(12),
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     let (status, body): (_, TopicMeta) =
         get_response(&mut server, "topics/", Method::PUT, topic_config);
     let expected = TopicMeta {
+        namespace: String::from("default"),
         name: body.name.clone(),
+        labels: BTreeMap::new(),
+        description: String::new(),
         message_ttl: 12,
         ttl: 0,
+        partitions: 1,
         created: body.created,
         updated: body.updated,
     };
@@ -124,12 +163,17 @@ fn http_protocol_update_topic() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/test_topic", Method::PUT, topic_config);
     // Update the topic
     let topic_config = TopicUpdateConfig {
         message_ttl: Some(60),
         ttl: Some(73),
+        labels: None,
+        description: None,
     };
     let (status, body): (_, TopicMeta) = get_response(
         &mut server,
@@ -138,9 +182,13 @@ fn http_protocol_update_topic() {
         topic_config,
     );
     let mut expected = TopicMeta {
+        namespace: String::from("default"),
         name: String::from("test_topic"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         message_ttl: 60,
         ttl: 73,
+        partitions: 1,
         created: body.created,
         updated: body.updated,
     };
@@ -150,6 +198,8 @@ fn http_protocol_update_topic() {
     let topic_config = TopicUpdateConfig {
         message_ttl: None,
         ttl: None,
+        labels: None,
+        description: None,
     };
     let (status, body): (_, TopicMeta) = get_response(
         &mut server,
@@ -178,14 +228,21 @@ fn http_protocol_delete_and_get_topic() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/test", Method::PUT, topic_config);
     // Get the topic
     let (status, body): (_, TopicMeta) = get_response(&mut server, "topics/test", Method::GET, ());
     let expected = TopicMeta {
+        namespace: String::from("default"),
         name: String::from("test"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         message_ttl: config.default_message_ttl.num_seconds(),
         ttl: 0,
+        partitions: 1,
         created: body.created,
         updated: body.updated,
     };
@@ -210,15 +267,22 @@ fn http_protocol_create_subscription() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/test_topic", Method::PUT, topic_config);
 
     // Create a new subscription
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("test_topic"),
+        topic_namespace: None,
         ack_deadline: None,
         ttl: None,
+        consumer_timeout: None,
         historical: Some(false),
+        labels: None,
+        description: None,
     };
     let (status, body): (_, SubscriptionMeta) = get_response(
         &mut server,
@@ -227,10 +291,15 @@ fn http_protocol_create_subscription() {
         subscription_config,
     );
     let expected = SubscriptionMeta {
+        namespace: String::from("default"),
         name: String::from("test"),
+        topic_namespace: String::from("default"),
         topic: String::from("test_topic"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         ack_deadline: config.default_ack_deadline.num_seconds(),
         ttl: 0,
+        consumer_timeout: 0,
         created: body.created,
         updated: body.updated,
     };
@@ -239,24 +308,33 @@ fn http_protocol_create_subscription() {
     // Try and create a subscription that already exists
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("test_topic"),
+        topic_namespace: None,
         ack_deadline: Some(45),
         ttl: None,
+        consumer_timeout: None,
         historical: None,
+        labels: None,
+        description: None,
     };
-    let (status, body) = get_response(
+    let (status, body): (_, Error) = get_response(
         &mut server,
         "subscriptions/test",
         Method::PUT,
         subscription_config,
     );
     assert_eq!(status, StatusCode::CONFLICT);
-    assert_eq!(expected, body);
+    assert_eq!(ErrorCode::SubscriptionAlreadyExists, body.code);
+    assert_eq!(Some(String::from("default/test")), body.resource);
     // Create a subscription with no name
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("test_topic"),
+        topic_namespace: None,
         ack_deadline: Some(67),
         ttl: None,
+        consumer_timeout: None,
         historical: Some(false),
+        labels: None,
+        description: None,
     };
     let (status, body): (_, SubscriptionMeta) = get_response(
         &mut server,
@@ -265,10 +343,15 @@ fn http_protocol_create_subscription() {
         subscription_config,
     );
     let expected = SubscriptionMeta {
+        namespace: String::from("default"),
         name: body.name.clone(),
+        topic_namespace: String::from("default"),
         topic: String::from("test_topic"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         ack_deadline: 67,
         ttl: 0,
+        consumer_timeout: 0,
         created: body.created,
         updated: body.updated,
     };
@@ -284,15 +367,22 @@ fn http_protocol_update_subscription() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/test_topic", Method::PUT, topic_config);
 
     // Create a new subscription
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("test_topic"),
+        topic_namespace: None,
         ack_deadline: None,
         ttl: None,
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -304,6 +394,9 @@ fn http_protocol_update_subscription() {
     let subscription_config = SubscriptionUpdateConfig {
         ack_deadline: Some(60),
         ttl: Some(73),
+        consumer_timeout: None,
+        labels: None,
+        description: None,
     };
     let (status, body): (_, SubscriptionMeta) = get_response(
         &mut server,
@@ -312,10 +405,15 @@ fn http_protocol_update_subscription() {
         subscription_config,
     );
     let mut expected = SubscriptionMeta {
+        namespace: String::from("default"),
         name: String::from("test_subscription"),
+        topic_namespace: String::from("default"),
         topic: String::from("test_topic"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         ack_deadline: 60,
         ttl: 73,
+        consumer_timeout: 0,
         created: body.created,
         updated: body.updated,
     };
@@ -325,6 +423,9 @@ fn http_protocol_update_subscription() {
     let subscription_config = SubscriptionUpdateConfig {
         ack_deadline: None,
         ttl: None,
+        consumer_timeout: None,
+        labels: None,
+        description: None,
     };
     let (status, body): (_, SubscriptionMeta) = get_response(
         &mut server,
@@ -353,15 +454,22 @@ fn http_protocol_delete_and_get_subscription() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/test_topic", Method::PUT, topic_config);
 
     // Create a new subscription
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("test_topic"),
+        topic_namespace: None,
         ack_deadline: None,
         ttl: None,
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -373,10 +481,15 @@ fn http_protocol_delete_and_get_subscription() {
     let (status, body): (_, SubscriptionMeta) =
         get_response(&mut server, "subscriptions/test", Method::GET, ());
     let expected = SubscriptionMeta {
+        namespace: String::from("default"),
         name: String::from("test"),
+        topic_namespace: String::from("default"),
         topic: String::from("test_topic"),
+        labels: BTreeMap::new(),
+        description: String::new(),
         ack_deadline: config.default_ack_deadline.num_seconds(),
         ttl: 0,
+        consumer_timeout: 0,
         created: body.created,
         updated: body.updated,
     };
@@ -406,6 +519,9 @@ fn http_protocol_lists() {
     let topic_config = TopicCreateConfig {
         message_ttl: None,
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -418,9 +534,13 @@ fn http_protocol_lists() {
     // Create new subscriptions
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("topic0"),
+        topic_namespace: None,
         ack_deadline: None,
         ttl: None,
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -436,9 +556,13 @@ fn http_protocol_lists() {
     );
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("topic1"),
+        topic_namespace: None,
         ack_deadline: None,
         ttl: None,
+        consumer_timeout: None,
         historical: Some(false),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -452,22 +576,33 @@ fn http_protocol_lists() {
     assert_eq!(StatusCode::OK, status);
     assert_eq!(2, body.topics.len());
     body.topics.sort();
-    let expected = TopicList::new(vec![
-        TopicMeta {
-            name: String::from("topic0"),
-            message_ttl: config.default_message_ttl.num_seconds(),
-            ttl: 0,
-            created: body.topics[0].created,
-            updated: body.topics[0].updated,
-        },
-        TopicMeta {
-            name: String::from("topic1"),
-            message_ttl: config.default_message_ttl.num_seconds(),
-            ttl: 0,
-            created: body.topics[1].created,
-            updated: body.topics[1].updated,
-        },
-    ]);
+    let expected = TopicList::new(
+        vec![
+            TopicMeta {
+                namespace: String::from("default"),
+                name: String::from("topic0"),
+                labels: BTreeMap::new(),
+                description: String::new(),
+                message_ttl: config.default_message_ttl.num_seconds(),
+                ttl: 0,
+                partitions: 1,
+                created: body.topics[0].created,
+                updated: body.topics[0].updated,
+            },
+            TopicMeta {
+                namespace: String::from("default"),
+                name: String::from("topic1"),
+                labels: BTreeMap::new(),
+                description: String::new(),
+                message_ttl: config.default_message_ttl.num_seconds(),
+                ttl: 0,
+                partitions: 1,
+                created: body.topics[1].created,
+                updated: body.topics[1].updated,
+            },
+        ],
+        None,
+    );
     assert_eq!(expected, body);
     // List the subscriptions
     let (status, mut body): (_, SubscriptionList) =
@@ -475,38 +610,56 @@ fn http_protocol_lists() {
     assert_eq!(StatusCode::OK, status);
     assert_eq!(3, body.subscriptions.len());
     body.subscriptions.sort();
-    let expected = SubscriptionList::new(vec![
-        SubscriptionMeta {
-            name: String::from("subscription0"),
-            topic: String::from("topic0"),
-            ack_deadline: config.default_ack_deadline.num_seconds(),
-            ttl: 0,
-            created: body.subscriptions[0].created,
-            updated: body.subscriptions[0].updated,
-        },
-        SubscriptionMeta {
-            name: String::from("subscription1"),
-            topic: String::from("topic0"),
-            ack_deadline: config.default_ack_deadline.num_seconds(),
-            ttl: 0,
-            created: body.subscriptions[1].created,
-            updated: body.subscriptions[1].updated,
-        },
-        SubscriptionMeta {
-            name: String::from("subscription2"),
-            topic: String::from("topic1"),
-            ack_deadline: config.default_ack_deadline.num_seconds(),
-            ttl: 0,
-            created: body.subscriptions[2].created,
-            updated: body.subscriptions[2].updated,
-        },
-    ]);
+    let expected = SubscriptionList::new(
+        vec![
+            SubscriptionMeta {
+                namespace: String::from("default"),
+                name: String::from("subscription0"),
+                topic_namespace: String::from("default"),
+                topic: String::from("topic0"),
+                labels: BTreeMap::new(),
+                description: String::new(),
+                ack_deadline: config.default_ack_deadline.num_seconds(),
+                ttl: 0,
+                consumer_timeout: 0,
+                created: body.subscriptions[0].created,
+                updated: body.subscriptions[0].updated,
+            },
+            SubscriptionMeta {
+                namespace: String::from("default"),
+                name: String::from("subscription1"),
+                topic_namespace: String::from("default"),
+                topic: String::from("topic0"),
+                labels: BTreeMap::new(),
+                description: String::new(),
+                ack_deadline: config.default_ack_deadline.num_seconds(),
+                ttl: 0,
+                consumer_timeout: 0,
+                created: body.subscriptions[1].created,
+                updated: body.subscriptions[1].updated,
+            },
+            SubscriptionMeta {
+                namespace: String::from("default"),
+                name: String::from("subscription2"),
+                topic_namespace: String::from("default"),
+                topic: String::from("topic1"),
+                labels: BTreeMap::new(),
+                description: String::new(),
+                ack_deadline: config.default_ack_deadline.num_seconds(),
+                ttl: 0,
+                consumer_timeout: 0,
+                created: body.subscriptions[2].created,
+                updated: body.subscriptions[2].updated,
+            },
+        ],
+        None,
+    );
     assert_eq!(expected, body);
     // List the topic subscriptions
-    let expected = SubscriptionNameList::new(vec![
-        String::from("subscription0"),
-        String::from("subscription1"),
-    ]);
+    let expected = SubscriptionNameList::new(
+        vec![String::from("subscription0"), String::from("subscription1")],
+        None,
+    );
     let (status, mut body): (_, SubscriptionNameList) =
         get_response(&mut server, "topics/topic0/subscriptions", Method::GET, ());
     assert_eq!(StatusCode::OK, status);
@@ -520,7 +673,7 @@ fn http_protocol_lists() {
         (),
     );
     // List the topic subscriptions
-    let expected = SubscriptionNameList::new(vec![String::from("subscription0")]);
+    let expected = SubscriptionNameList::new(vec![String::from("subscription0")], None);
     let (status, mut body): (_, SubscriptionNameList) =
         get_response(&mut server, "topics/topic0/subscriptions", Method::GET, ());
     assert_eq!(StatusCode::OK, status);
@@ -528,6 +681,803 @@ fn http_protocol_lists() {
     assert_eq!(expected, body);
 }
 
+#[test]
+fn http_protocol_pagination() {
+    let (_, mut server) = get_server();
+
+    // Create topics in reverse name order
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
+    };
+    for name in &["b0", "a4", "a3", "a2", "a1", "a0"] {
+        get_status(
+            &mut server,
+            &format!("topics/{}", name),
+            Method::PUT,
+            topic_config.clone(),
+        );
+    }
+
+    // Page through the topics with a prefix
+    let mut names = Vec::new();
+    let mut path = String::from("topics/?prefix=a&page_size=2");
+    loop {
+        let (status, body): (_, TopicList) = get_response(&mut server, &path, Method::GET, ());
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.topics.len() <= 2);
+        names.extend(body.topics.into_iter().map(|t| t.name));
+        match body.next_page_token {
+            Some(token) => {
+                path = format!("topics/?prefix=a&page_size=2&page_token={}", token);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(vec!["a0", "a1", "a2", "a3", "a4"], names);
+
+    // Order the topics by when they were created
+    let (status, body): (_, TopicList) =
+        get_response(&mut server, "topics/?order_by=created", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    let names: Vec<String> = body.topics.into_iter().map(|t| t.name).collect();
+    assert_eq!(vec!["b0", "a4", "a3", "a2", "a1", "a0"], names);
+    let (status, body): (_, TopicList) = get_response(
+        &mut server,
+        "topics/?order_by=created&page_size=4",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(4, body.topics.len());
+    let (status, body): (_, TopicList) = get_response(
+        &mut server,
+        &format!(
+            "topics/?order_by=created&page_size=4&page_token={}",
+            body.next_page_token.unwrap()
+        ),
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    let names: Vec<String> = body.topics.into_iter().map(|t| t.name).collect();
+    assert_eq!(vec!["a1", "a0"], names);
+    assert_eq!(None, body.next_page_token);
+
+    // Page through the subscriptions and the topic subscriptions
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("a0"),
+        topic_namespace: None,
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: None,
+        historical: None,
+        labels: None,
+        description: None,
+    };
+    for name in &["sub2", "sub1", "sub0"] {
+        get_status(
+            &mut server,
+            &format!("subscriptions/{}", name),
+            Method::PUT,
+            subscription_config.clone(),
+        );
+    }
+    let (status, body): (_, SubscriptionList) =
+        get_response(&mut server, "subscriptions/?page_size=2", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    let names: Vec<String> = body.subscriptions.into_iter().map(|s| s.name).collect();
+    assert_eq!(vec!["sub0", "sub1"], names);
+    assert_eq!(Some(String::from("sub1")), body.next_page_token);
+    let (status, body): (_, SubscriptionNameList) = get_response(
+        &mut server,
+        "topics/a0/subscriptions?page_size=1&page_token=sub1",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(
+        SubscriptionNameList::new(vec![String::from("sub2")], None),
+        body
+    );
+
+    // Invalid options
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/?page_size=0", Method::GET, ());
+    assert_eq!(StatusCode::BAD_REQUEST, status);
+    assert_eq!(ErrorCode::InvalidRequest, body.code);
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/?order_by=size", Method::GET, ());
+    assert_eq!(StatusCode::BAD_REQUEST, status);
+    assert_eq!(ErrorCode::InvalidRequest, body.code);
+}
+
+#[test]
+fn http_protocol_labels() {
+    let (_, mut server) = get_server();
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (String::from(*k), String::from(*v)))
+            .collect()
+    }
+
+    // Create labeled topics
+    let topics = vec![
+        ("orders", labels(&[("team", "payments"), ("env", "prod")])),
+        ("refunds", labels(&[("team", "payments"), ("env", "dev")])),
+        ("queries", labels(&[("team", "search")])),
+    ];
+    for (name, topic_labels) in topics {
+        let topic_config = TopicCreateConfig {
+            message_ttl: None,
+            ttl: None,
+            partitions: None,
+            labels: Some(topic_labels.clone()),
+            description: Some(format!("All of the {}", name)),
+        };
+        let (status, body): (_, TopicMeta) = get_response(
+            &mut server,
+            &format!("topics/{}", name),
+            Method::PUT,
+            topic_config,
+        );
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(
+            topic_labels.into_iter().collect::<BTreeMap<_, _>>(),
+            body.labels
+        );
+        assert_eq!(format!("All of the {}", name), body.description);
+    }
+
+    // Select topics by their labels
+    let mut select = |selector: &str| -> Vec<String> {
+        let (status, body): (_, TopicList) = get_response(
+            &mut server,
+            &format!("topics/?labels={}", selector),
+            Method::GET,
+            (),
+        );
+        assert_eq!(StatusCode::OK, status);
+        body.topics.into_iter().map(|t| t.name).collect()
+    };
+    assert_eq!(vec!["orders"], select("team=payments,env!=dev"));
+    assert_eq!(vec!["orders", "refunds"], select("team=payments"));
+    assert_eq!(vec!["queries"], select("!env"));
+    assert_eq!(vec!["orders", "refunds"], select("env"));
+    assert_eq!(Vec::<String>::new(), select("team=billing"));
+
+    // Update the labels of a topic
+    let topic_config = TopicUpdateConfig {
+        message_ttl: None,
+        ttl: None,
+        labels: Some(labels(&[("team", "search"), ("env", "dev")])),
+        description: None,
+    };
+    let (status, body): (_, TopicMeta) =
+        get_response(&mut server, "topics/queries", Method::PATCH, topic_config);
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(Some(&String::from("dev")), body.labels.get("env"));
+    assert_eq!("All of the queries", body.description);
+
+    // Select subscriptions by their labels
+    for (name, env) in &[("sub0", "prod"), ("sub1", "dev")] {
+        let subscription_config = SubscriptionCreateConfig {
+            topic: String::from("orders"),
+            topic_namespace: None,
+            ack_deadline: None,
+            ttl: None,
+            consumer_timeout: None,
+            historical: None,
+            labels: Some(labels(&[("env", env)])),
+            description: None,
+        };
+        get_status(
+            &mut server,
+            &format!("subscriptions/{}", name),
+            Method::PUT,
+            subscription_config,
+        );
+    }
+    let (status, body): (_, SubscriptionList) = get_response(
+        &mut server,
+        "subscriptions/?labels=env=dev",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.subscriptions.len());
+    assert_eq!("sub1", body.subscriptions[0].name);
+    let (status, body): (_, SubscriptionNameList) = get_response(
+        &mut server,
+        "topics/orders/subscriptions?labels=env!=dev",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(vec![String::from("sub0")], body.subscription_names);
+
+    // Labels are included in the metrics
+    let request = server
+        .client(Method::GET, "api/v1/metrics")
+        .finish()
+        .unwrap();
+    let response = server.execute(request.send()).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let body = server.execute(response.body()).unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let namespace = &body["namespaces"]["default"];
+    assert_eq!("payments", namespace["topics"]["orders"]["labels"]["team"]);
+    assert_eq!("dev", namespace["subscriptions"]["sub1"]["labels"]["env"]);
+
+    // Invalid selectors
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/?labels=team=a,=b", Method::GET, ());
+    assert_eq!(StatusCode::BAD_REQUEST, status);
+    assert_eq!(ErrorCode::InvalidRequest, body.code);
+}
+
+#[test]
+fn http_protocol_offsets() {
+    let (_, mut server) = get_server();
+
+    let topic_config = TopicCreateConfig {
+        message_ttl: Some(1),
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
+    };
+    get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
+
+    // Published messages are assigned increasing offsets
+    let messages = RawMessageList::new(vec![
+        RawMessage::new(String::from("first")),
+        RawMessage::new(String::from("second")),
+        RawMessage::new(String::from("third")),
+    ]);
+    let (status, body): (_, PublishedMessageList) =
+        get_response(&mut server, "topics/topic0/publish", Method::POST, messages);
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(vec![0, 1, 2], body.offsets);
+    let ids = body.message_ids;
+
+    // Read the topic a page at a time
+    let (status, body): (_, OffsetMessageList) = get_response(
+        &mut server,
+        "topics/topic0/messages?from_offset=0&limit=2",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(2, body.messages.len());
+    assert_eq!(ids[0], body.messages[0].id);
+    assert_eq!(0, body.messages[0].offset);
+    assert_eq!("second", &*body.messages[1].data);
+    assert_eq!(1, body.messages[1].offset);
+    assert_eq!(2, body.next_offset);
+    let (status, body): (_, OffsetMessageList) = get_response(
+        &mut server,
+        "topics/topic0/messages?from_offset=2&limit=2",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.messages.len());
+    assert_eq!(ids[2], body.messages[0].id);
+    assert_eq!(3, body.next_offset);
+    let (status, body): (_, OffsetMessageList) = get_response(
+        &mut server,
+        "topics/topic0/messages?from_offset=3",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert!(body.messages.is_empty());
+    assert_eq!(3, body.next_offset);
+
+    // Reading does not affect subscriptions
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("topic0"),
+        topic_namespace: None,
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: None,
+        historical: Some(true),
+        labels: None,
+        description: None,
+    };
+    get_status(
+        &mut server,
+        "subscriptions/sub0",
+        Method::PUT,
+        subscription_config,
+    );
+    let (status, body): (_, MessageList) = get_response(
+        &mut server,
+        "subscriptions/sub0/pull",
+        Method::POST,
+        PullConfig::new(1),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(ids[0], body.messages[0].id);
+    assert_eq!(0, body.messages[0].offset);
+
+    // Wait for the messages to expire
+    thread::sleep(time::Duration::from_millis(2500));
+
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "topics/topic0/messages?from_offset=0",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::GONE, status);
+    assert_eq!(ErrorCode::OffsetExpired, body.code);
+
+    // Reading without an offset starts at the oldest message
+    let messages = RawMessageList::new(vec![RawMessage::new(String::from("fourth"))]);
+    let (_, body): (_, PublishedMessageList) =
+        get_response(&mut server, "topics/topic0/publish", Method::POST, messages);
+    assert_eq!(vec![3], body.offsets);
+    let (status, body): (_, OffsetMessageList) =
+        get_response(&mut server, "topics/topic0/messages", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.messages.len());
+    assert_eq!(3, body.messages[0].offset);
+    assert_eq!(4, body.next_offset);
+
+    // Read from a nonexistent topic
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/topic1/messages", Method::GET, ());
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::TopicNotFound, body.code);
+}
+
+#[test]
+fn http_protocol_partitions() {
+    let (_, mut server) = get_server();
+
+    // A topic must have at least one partition
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: Some(0),
+        labels: None,
+        description: None,
+    };
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/topic0", Method::PUT, topic_config);
+    assert_eq!(StatusCode::BAD_REQUEST, status);
+    assert_eq!(ErrorCode::InvalidRequest, body.code);
+
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: Some(2),
+        labels: None,
+        description: None,
+    };
+    let (status, body): (_, TopicMeta) =
+        get_response(&mut server, "topics/topic0", Method::PUT, topic_config);
+    assert_eq!(StatusCode::CREATED, status);
+    assert_eq!(2, body.partitions);
+
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("topic0"),
+        topic_namespace: None,
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: None,
+        historical: Some(true),
+        labels: None,
+        description: None,
+    };
+    get_status(
+        &mut server,
+        "subscriptions/sub0",
+        Method::PUT,
+        subscription_config,
+    );
+
+    // Messages without a key are published round robin, messages with a key stay together
+    let messages = RawMessageList::new(vec![
+        RawMessage::new(String::from("first")),
+        RawMessage::new(String::from("second")),
+        RawMessage::with_partition_key(String::from("third"), String::from("key")),
+        RawMessage::with_partition_key(String::from("fourth"), String::from("key")),
+    ]);
+    let (status, body): (_, PublishedMessageList) =
+        get_response(&mut server, "topics/topic0/publish", Method::POST, messages);
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(vec![0, 1], body.partitions[..2].to_vec());
+    assert_eq!(vec![0, 0], body.offsets[..2].to_vec());
+    let keyed = body.partitions[2];
+    assert_eq!(keyed, body.partitions[3]);
+    assert_eq!(body.offsets[2] + 1, body.offsets[3]);
+
+    // Each partition is read separately
+    let (status, body): (_, OffsetMessageList) = get_response(
+        &mut server,
+        &format!("topics/topic0/messages?partition={}&limit=10", keyed),
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(3, body.messages.len());
+    assert!(body.messages.iter().all(|m| m.partition == keyed));
+    assert_eq!("fourth", &*body.messages[2].data);
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "topics/topic0/messages?partition=2",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::PartitionNotFound, body.code);
+
+    // Pulls can be restricted to assigned partitions
+    let mut config = PullConfig::new(10);
+    config.partitions = Some(vec![keyed]);
+    let (status, body): (_, MessageList) =
+        get_response(&mut server, "subscriptions/sub0/pull", Method::POST, config);
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(3, body.messages.len());
+    assert!(body.messages.iter().all(|m| m.partition == keyed));
+    let (status, body): (_, MessageList) = get_response(
+        &mut server,
+        "subscriptions/sub0/pull",
+        Method::POST,
+        PullConfig::new(10),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.messages.len());
+    assert_ne!(keyed, body.messages[0].partition);
+
+    let mut config = PullConfig::new(10);
+    config.partitions = Some(vec![2]);
+    let (status, body): (_, Error) =
+        get_response(&mut server, "subscriptions/sub0/pull", Method::POST, config);
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::PartitionNotFound, body.code);
+
+    // Messages can be published to a given partition
+    let message = RawMessage {
+        data: String::from("fifth"),
+        partition: Some(1),
+        ..RawMessage::default()
+    };
+    let messages = RawMessageList::new(vec![message.clone()]);
+    let (status, body): (_, PublishedMessageList) =
+        get_response(&mut server, "topics/topic0/publish", Method::POST, messages);
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(vec![1], body.partitions);
+    let messages = RawMessageList::new(vec![RawMessage {
+        partition: Some(2),
+        ..message
+    }]);
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/topic0/publish", Method::POST, messages);
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::PartitionNotFound, body.code);
+}
+
+#[test]
+fn http_protocol_consumers() {
+    let (_, mut server) = get_server();
+
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
+    };
+    get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("topic0"),
+        topic_namespace: None,
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: Some(1),
+        historical: Some(true),
+        labels: None,
+        description: None,
+    };
+    let (_, body): (_, SubscriptionMeta) = get_response(
+        &mut server,
+        "subscriptions/sub0",
+        Method::PUT,
+        subscription_config,
+    );
+    assert_eq!(1, body.consumer_timeout);
+    let messages = RawMessageList::new(vec![
+        RawMessage::new(String::from("first")),
+        RawMessage::new(String::from("second")),
+        RawMessage::new(String::from("third")),
+    ]);
+    get_status(&mut server, "topics/topic0/publish", Method::POST, messages);
+
+    // Consumers identify themselves when pulling
+    let mut config = PullConfig::new(2);
+    config.consumer_id = Some(String::from("worker0"));
+    let (_, body): (_, MessageList) =
+        get_response(&mut server, "subscriptions/sub0/pull", Method::POST, config);
+    let held = body.messages;
+    assert_eq!(2, held.len());
+    let (status, body): (_, ConsumerList) =
+        get_response(&mut server, "subscriptions/sub0/consumers", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.consumers.len());
+    assert_eq!("worker0", body.consumers[0].id);
+    assert_eq!(2, body.consumers[0].in_flight);
+
+    // Releasing a consumer resends its messages immediately
+    let (status, body): (_, ReleaseResult) = get_response(
+        &mut server,
+        "subscriptions/sub0/consumers/worker0/release",
+        Method::POST,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(2, body.released);
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "subscriptions/sub0/consumers/worker0/release",
+        Method::POST,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::ConsumerNotFound, body.code);
+    let mut config = PullConfig::new(1);
+    config.consumer_id = Some(String::from("worker1"));
+    let (_, body): (_, MessageList) =
+        get_response(&mut server, "subscriptions/sub0/pull", Method::POST, config);
+    assert_eq!(held[0].id, body.messages[0].id);
+    assert_eq!(2, body.messages[0].tries);
+
+    // The messages of a consumer which stopped pulling are released automatically
+    thread::sleep(time::Duration::from_millis(1500));
+    let (_, body): (_, ConsumerList) =
+        get_response(&mut server, "subscriptions/sub0/consumers", Method::GET, ());
+    assert!(body.consumers.is_empty());
+    let (_, body): (_, MessageList) = get_response(
+        &mut server,
+        "subscriptions/sub0/pull",
+        Method::POST,
+        PullConfig::new(3),
+    );
+    let ids: Vec<uuid::Uuid> = body.messages.iter().map(|m| m.id).collect();
+    assert_eq!(3, ids.len());
+    assert!(ids.contains(&held[0].id));
+    assert!(ids.contains(&held[1].id));
+}
+
+#[test]
+fn http_protocol_message_status() {
+    let (_, mut server) = get_server();
+
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
+    };
+    get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
+    for name in &["sub0", "sub1"] {
+        let subscription_config = SubscriptionCreateConfig {
+            topic: String::from("topic0"),
+            topic_namespace: None,
+            ack_deadline: None,
+            ttl: None,
+            consumer_timeout: None,
+            historical: None,
+            labels: None,
+            description: None,
+        };
+        get_status(
+            &mut server,
+            &format!("subscriptions/{}", name),
+            Method::PUT,
+            subscription_config,
+        );
+    }
+    let messages = RawMessageList::new(vec![RawMessage::new(String::from("first"))]);
+    let (_, body): (_, PublishedMessageList) =
+        get_response(&mut server, "topics/topic0/publish", Method::POST, messages);
+    let id = body.message_ids[0];
+
+    // Get a message by id
+    let (status, body): (_, Message) = get_response(
+        &mut server,
+        &format!("topics/topic0/messages/{}", id),
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(id, body.id);
+    assert_eq!("first", &*body.data);
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        &format!("topics/topic0/messages/{}", uuid::Uuid::new_v4()),
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::MessageNotFound, body.code);
+
+    // Pull the message from one subscription
+    get_status(
+        &mut server,
+        "subscriptions/sub0/pull",
+        Method::POST,
+        PullConfig::new(1),
+    );
+    let (status, body): (_, MessageStatus) = get_response(
+        &mut server,
+        &format!("messages/{}/status", id),
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!("topic0", body.topic);
+    assert_eq!(id, body.message.id);
+    assert_eq!(2, body.subscriptions.len());
+    assert_eq!("sub0", body.subscriptions[0].name);
+    match body.subscriptions[0].status {
+        DeliveryStatus::Pending { tries: 1, .. } => (),
+        ref status => panic!("unexpected status {:?}", status),
+    }
+    assert_eq!("sub1", body.subscriptions[1].name);
+    assert_eq!(DeliveryStatus::NotDelivered, body.subscriptions[1].status);
+
+    // Ack the message
+    get_status(
+        &mut server,
+        "subscriptions/sub0/ack",
+        Method::POST,
+        MessageIdList::new(vec![id]),
+    );
+    let (_, body): (_, MessageStatus) = get_response(
+        &mut server,
+        &format!("messages/{}/status", id),
+        Method::GET,
+        (),
+    );
+    assert_eq!(DeliveryStatus::Acked, body.subscriptions[0].status);
+
+    // Messages are only found in their namespace
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        &format!("namespaces/other/messages/{}/status", id),
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::MessageNotFound, body.code);
+}
+
+#[test]
+fn http_protocol_purge() {
+    let (_, mut server) = get_server();
+
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
+    };
+    get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("topic0"),
+        topic_namespace: None,
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: None,
+        historical: None,
+        labels: None,
+        description: None,
+    };
+    get_status(
+        &mut server,
+        "subscriptions/sub0",
+        Method::PUT,
+        subscription_config,
+    );
+    let messages = RawMessageList::new(vec![
+        RawMessage::new(String::from("first")),
+        RawMessage::new(String::from("second")),
+    ]);
+    get_status(&mut server, "topics/topic0/publish", Method::POST, messages);
+    thread::sleep(time::Duration::from_millis(10));
+    let before = Utc::now();
+    let messages = RawMessageList::new(vec![RawMessage::new(String::from("third"))]);
+    get_status(&mut server, "topics/topic0/publish", Method::POST, messages);
+
+    // Purge a subscription's backlog
+    get_status(
+        &mut server,
+        "subscriptions/sub0/pull",
+        Method::POST,
+        PullConfig::new(1),
+    );
+    let (status, body): (_, PurgeResult) =
+        get_response(&mut server, "subscriptions/sub0/purge", Method::POST, ());
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(3, body.purged);
+    let (status, body): (_, MessageList) = get_response(
+        &mut server,
+        "subscriptions/sub0/pull",
+        Method::POST,
+        PullConfig::new(1),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert!(body.messages.is_empty());
+
+    // Purge the messages of a topic published before a time
+    let (status, body): (_, PurgeResult) = get_response(
+        &mut server,
+        "topics/topic0/purge",
+        Method::POST,
+        TopicPurgeConfig {
+            before: Some(before),
+        },
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(2, body.purged);
+    let (_, body): (_, OffsetMessageList) =
+        get_response(&mut server, "topics/topic0/messages", Method::GET, ());
+    assert_eq!(1, body.messages.len());
+    assert_eq!("third", &*body.messages[0].data);
+
+    // Purge all messages of a topic
+    let (status, body): (_, PurgeResult) = get_response(
+        &mut server,
+        "topics/topic0/purge",
+        Method::POST,
+        TopicPurgeConfig { before: None },
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.purged);
+    let (_, body): (_, OffsetMessageList) =
+        get_response(&mut server, "topics/topic0/messages", Method::GET, ());
+    assert!(body.messages.is_empty());
+
+    // Purges are included in the metrics
+    let request = server
+        .client(Method::GET, "api/v1/metrics")
+        .finish()
+        .unwrap();
+    let response = server.execute(request.send()).unwrap();
+    let body = server.execute(response.body()).unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let namespace = &body["namespaces"]["default"];
+    assert_eq!(3, namespace["topics"]["topic0"]["purged_all_time"]);
+    assert_eq!(0, namespace["topics"]["topic0"]["messages"]);
+    assert_eq!(3, namespace["subscriptions"]["sub0"]["purged_all_time"]);
+    assert_eq!(0, namespace["subscriptions"]["sub0"]["pending"]);
+
+    // Purge nonexistent resources
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "topics/topic1/purge",
+        Method::POST,
+        TopicPurgeConfig { before: None },
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::TopicNotFound, body.code);
+    let (status, body): (_, Error) =
+        get_response(&mut server, "subscriptions/sub1/purge", Method::POST, ());
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::SubscriptionNotFound, body.code);
+}
+
 #[test]
 fn http_protocol_ttls() {
     let (_, mut server) = get_server();
@@ -536,27 +1486,41 @@ fn http_protocol_ttls() {
     let topic_config = TopicCreateConfig {
         message_ttl: Some(2),
         ttl: Some(0),
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
     let topic_config = TopicCreateConfig {
         message_ttl: Some(2),
         ttl: Some(1),
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/topic1", Method::PUT, topic_config);
 
     // Create subscriptions
     let topic_config = SubscriptionCreateConfig {
         topic: String::from("topic0"),
+        topic_namespace: None,
         ack_deadline: Some(1),
         ttl: None,
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "subscriptions/sub0", Method::PUT, topic_config);
     let topic_config = SubscriptionCreateConfig {
         topic: String::from("topic0"),
+        topic_namespace: None,
         ack_deadline: Some(1),
         ttl: Some(1),
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "subscriptions/sub1", Method::PUT, topic_config);
 
@@ -582,15 +1546,22 @@ fn http_protocol_delete_topic_subscriptions() {
     let topic_config = TopicCreateConfig {
         message_ttl: Some(2),
         ttl: Some(0),
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
 
     // Create subscriptions
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("topic0"),
+        topic_namespace: None,
         ack_deadline: Some(1),
         ttl: None,
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -600,9 +1571,13 @@ fn http_protocol_delete_topic_subscriptions() {
     );
     let subscription_config = SubscriptionCreateConfig {
         topic: String::from("topic0"),
+        topic_namespace: None,
         ack_deadline: Some(1),
         ttl: Some(1),
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -629,6 +1604,9 @@ fn http_protocol_basic() {
     let topic_config = TopicCreateConfig {
         message_ttl: Some(2),
         ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
     get_status(&mut server, "topics/topic0", Method::PUT, topic_config);
     // Publish messages
@@ -649,9 +1627,13 @@ fn http_protocol_basic() {
     // Create a new subscriptions
     let mut subscription_config = SubscriptionCreateConfig {
         topic: String::from("topic0"),
+        topic_namespace: None,
         ack_deadline: Some(1),
         ttl: None,
+        consumer_timeout: None,
         historical: Some(true),
+        labels: None,
+        description: None,
     };
     get_status(
         &mut server,
@@ -677,8 +1659,8 @@ fn http_protocol_basic() {
     );
     assert_eq!(StatusCode::OK, status);
     assert_eq!(pull_config.max_messages.unwrap(), body.messages.len());
-    assert_eq!(body.messages[0].data, String::from("first"));
-    assert_eq!(body.messages[1].data, String::from("second"));
+    assert_eq!(&*body.messages[0].data, String::from("first"));
+    assert_eq!(&*body.messages[1].data, String::from("second"));
     let saved_messages = body;
     // Try and pull messages from not historical subscription1
     let pull_config = PullConfig::new(100);
@@ -712,9 +1694,9 @@ fn http_protocol_basic() {
     );
     assert_eq!(StatusCode::OK, status);
     assert_eq!(2, body.messages.len());
-    assert_eq!(String::from("first"), body.messages[0].data);
+    assert_eq!(String::from("first"), &*body.messages[0].data);
     assert_eq!(2, body.messages[0].tries);
-    assert_eq!(String::from("third"), body.messages[1].data);
+    assert_eq!(String::from("third"), &*body.messages[1].data);
     assert_eq!(1, body.messages[1].tries);
 
     // Publish more messages
@@ -743,8 +1725,8 @@ fn http_protocol_basic() {
     );
     assert_eq!(StatusCode::OK, status);
     assert_eq!(2, body.messages.len());
-    assert_eq!(String::from("fourth"), body.messages[0].data);
-    assert_eq!(String::from("fifth"), body.messages[1].data);
+    assert_eq!(String::from("fourth"), &*body.messages[0].data);
+    assert_eq!(String::from("fifth"), &*body.messages[1].data);
 
     // Try and publish to nonexistent topic
     let messages = RawMessageList::new(vec![]);
@@ -785,7 +1767,401 @@ fn http_protocol_general_handlers() {
     assert_eq!(StatusCode::OK, status);
     let status = get_status(&mut server, "metrics", Method::GET, ());
     assert_eq!(StatusCode::OK, status);
+    let (status, body): (_, Origin) = get_response(&mut server, "origin", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!("test", body.origin);
+    let (status, body): (_, BridgeList) = get_response(&mut server, "bridges", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    assert!(body.bridges.is_empty());
 
     let status = get_status_with_prefix(&mut server, "/ui", "", Method::GET, ());
     assert_eq!(StatusCode::OK, status);
 }
+
+#[test]
+fn http_protocol_namespaces() {
+    let (config, mut server) = get_server();
+
+    // Create a namespace with defaults that is shared with the billing namespace
+    let namespace_config = NamespaceCreateConfig {
+        default_message_ttl: Some(30),
+        default_topic_ttl: None,
+        default_subscription_ttl: Some(45),
+        shared_with: Some(vec![String::from("billing")]),
+    };
+    let (status, body): (_, NamespaceMeta) = get_response(
+        &mut server,
+        "namespaces/payments",
+        Method::PUT,
+        namespace_config.clone(),
+    );
+    let expected = NamespaceMeta {
+        name: String::from("payments"),
+        default_message_ttl: Some(30),
+        default_topic_ttl: None,
+        default_subscription_ttl: Some(45),
+        shared_with: vec![String::from("billing")],
+        created: body.created,
+        updated: body.updated,
+    };
+    assert_eq!(StatusCode::CREATED, status);
+    assert_eq!(expected, body);
+    let status = get_status(
+        &mut server,
+        "namespaces/payments",
+        Method::PUT,
+        namespace_config,
+    );
+    assert_eq!(StatusCode::CONFLICT, status);
+
+    // Create topics with the same name in different namespaces
+    let topic_config = TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
+    };
+    let (status, body): (_, TopicMeta) = get_response(
+        &mut server,
+        "namespaces/payments/topics/orders",
+        Method::PUT,
+        topic_config.clone(),
+    );
+    assert_eq!(StatusCode::CREATED, status);
+    assert_eq!(String::from("payments"), body.namespace);
+    assert_eq!(30, body.message_ttl);
+    let (status, body): (_, TopicMeta) =
+        get_response(&mut server, "topics/orders", Method::PUT, topic_config);
+    assert_eq!(StatusCode::CREATED, status);
+    assert_eq!(String::from("default"), body.namespace);
+    assert_eq!(config.default_message_ttl.num_seconds(), body.message_ttl);
+    let (status, body): (_, TopicList) =
+        get_response(&mut server, "namespaces/payments/topics/", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.topics.len());
+
+    // Only namespaces the topic's namespace is shared with can subscribe to it
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("orders"),
+        topic_namespace: Some(String::from("payments")),
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: None,
+        historical: Some(true),
+        labels: None,
+        description: None,
+    };
+    let status = get_status(
+        &mut server,
+        "namespaces/analytics/subscriptions/sub",
+        Method::PUT,
+        subscription_config.clone(),
+    );
+    assert_eq!(StatusCode::FORBIDDEN, status);
+    let (status, body): (_, SubscriptionMeta) = get_response(
+        &mut server,
+        "namespaces/billing/subscriptions/sub",
+        Method::PUT,
+        subscription_config,
+    );
+    let expected = SubscriptionMeta {
+        namespace: String::from("billing"),
+        name: String::from("sub"),
+        topic_namespace: String::from("payments"),
+        topic: String::from("orders"),
+        labels: BTreeMap::new(),
+        description: String::new(),
+        ack_deadline: config.default_ack_deadline.num_seconds(),
+        ttl: 0,
+        consumer_timeout: 0,
+        created: body.created,
+        updated: body.updated,
+    };
+    assert_eq!(StatusCode::CREATED, status);
+    assert_eq!(expected, body);
+    let (status, body): (_, SubscriptionNameList) = get_response(
+        &mut server,
+        "namespaces/payments/topics/orders/subscriptions",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(vec![String::from("billing/sub")], body.subscription_names);
+
+    // Publish and pull across namespaces
+    let messages = RawMessageList::new(vec![RawMessage::new(String::from("first"))]);
+    get_status(
+        &mut server,
+        "namespaces/payments/topics/orders/publish",
+        Method::POST,
+        messages,
+    );
+    let (status, body): (_, MessageList) = get_response(
+        &mut server,
+        "namespaces/billing/subscriptions/sub/pull",
+        Method::POST,
+        PullConfig::new(10),
+    );
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, body.messages.len());
+    assert_eq!(String::from("first"), &*body.messages[0].data);
+
+    // Metrics are grouped by namespace
+    let status = get_status(&mut server, "namespaces/payments/metrics", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    let status = get_status(&mut server, "namespaces/analytics/metrics", Method::GET, ());
+    assert_eq!(StatusCode::NOT_FOUND, status);
+
+    // List the namespaces
+    let (status, body): (_, NamespaceList) =
+        get_response(&mut server, "namespaces/", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+    let mut names: Vec<String> = body.namespaces.into_iter().map(|n| n.name).collect();
+    names.sort();
+    assert_eq!(
+        vec![
+            String::from("billing"),
+            String::from("default"),
+            String::from("payments")
+        ],
+        names
+    );
+
+    // Deleting a namespace deletes its topics and their subscriptions
+    let status = get_status(&mut server, "namespaces/payments", Method::DELETE, ());
+    assert_eq!(StatusCode::OK, status);
+    let status = get_status(
+        &mut server,
+        "namespaces/payments/topics/orders",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    let status = get_status(
+        &mut server,
+        "namespaces/billing/subscriptions/sub",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    let status = get_status(&mut server, "topics/orders", Method::GET, ());
+    assert_eq!(StatusCode::OK, status);
+}
+
+#[test]
+fn http_protocol_errors() {
+    let (_, mut server) = get_server();
+
+    // Resources that do not exist are distinguished by their code
+    let (status, body): (_, Error) =
+        get_response(&mut server, "topics/does_not_exist", Method::GET, ());
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(Error::topic_not_found("default", "does_not_exist"), body);
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "namespaces/other/subscriptions/does_not_exist",
+        Method::GET,
+        (),
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(
+        Error::subscription_not_found("other", "does_not_exist"),
+        body
+    );
+    let (status, body): (_, Error) =
+        get_response(&mut server, "namespaces/does_not_exist", Method::GET, ());
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(Error::namespace_not_found("does_not_exist"), body);
+
+    // Subscribing to a topic that does not exist
+    let subscription_config = SubscriptionCreateConfig {
+        topic: String::from("does_not_exist"),
+        topic_namespace: None,
+        ack_deadline: None,
+        ttl: None,
+        consumer_timeout: None,
+        historical: None,
+        labels: None,
+        description: None,
+    };
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "subscriptions/sub",
+        Method::PUT,
+        subscription_config,
+    );
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::TopicNotFound, body.code);
+
+    // Routes that do not exist
+    let (status, body): (_, Error) = get_response(&mut server, "does/not/exist", Method::GET, ());
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(ErrorCode::RouteNotFound, body.code);
+
+    // Bodies that can not be parsed
+    let (status, body): (_, Error) = get_response(
+        &mut server,
+        "topics/test",
+        Method::PUT,
+        vec!["not", "a", "config"],
+    );
+    assert_eq!(StatusCode::BAD_REQUEST, status);
+    assert_eq!(ErrorCode::InvalidRequest, body.code);
+    assert_eq!(None, body.resource);
+}
+
+#[cfg(feature = "tls")]
+fn generate_certificate(
+    common_name: &str,
+    issuer: Option<(&X509, &PKey<Private>)>,
+) -> (X509, PKey<Private>) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", common_name).unwrap();
+    let name = name.build();
+    let serial = BigNum::from_u32(rand::random())
+        .unwrap()
+        .to_asn1_integer()
+        .unwrap();
+
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let alternative_name = SubjectAlternativeName::new()
+        .dns("localhost")
+        .ip("127.0.0.1")
+        .build(&builder.x509v3_context(issuer.map(|(cert, _)| &**cert), None))
+        .unwrap();
+    builder.append_extension(alternative_name).unwrap();
+    match issuer {
+        Some((issuer_cert, issuer_key)) => {
+            builder.set_issuer_name(issuer_cert.subject_name()).unwrap();
+            builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        }
+        None => {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+            builder.set_issuer_name(&name).unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+        }
+    }
+    (builder.build(), key)
+}
+
+#[cfg(feature = "tls")]
+fn start_tls_server(tls: TlsConfig) -> String {
+    let address = test::TestServer::unused_addr();
+    let config = Config {
+        host: address.ip().to_string(),
+        port: address.port(),
+        tls: Some(tls),
+        ..Config::default()
+    };
+    thread::spawn(move || start(&Registry::new(), Arc::new(RwLock::new(config)), &[]));
+    // Wait for the server to start listening
+    for _ in 0..50 {
+        if std::net::TcpStream::connect(address).is_ok() {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    format!("https://localhost:{}", address.port())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn http_protocol_tls() {
+    let directory = std::env::temp_dir().join(format!("courier-tls-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    let write_pem = |name: &str, pem: Vec<u8>| {
+        let path = directory.join(name);
+        fs::write(&path, pem).unwrap();
+        path
+    };
+
+    // Generate a CA along with a server and two client certificates signed by it
+    let (ca_cert, ca_key) = generate_certificate("courier-test-ca", None);
+    let (server_cert, server_key) = generate_certificate("localhost", Some((&ca_cert, &ca_key)));
+    let (client0_cert, client0_key) = generate_certificate("client0", Some((&ca_cert, &ca_key)));
+    let (client1_cert, client1_key) = generate_certificate("client1", Some((&ca_cert, &ca_key)));
+    let ca_path = write_pem("ca.pem", ca_cert.to_pem().unwrap());
+    let cert_path = write_pem("cert.pem", server_cert.to_pem().unwrap());
+    let key_path = write_pem("key.pem", server_key.private_key_to_pem_pkcs8().unwrap());
+
+    // Serve over tls without client certificates
+    let url = start_tls_server(TlsConfig {
+        cert: cert_path.clone(),
+        key: key_path.clone(),
+        client_ca: None,
+        client_identities: vec![],
+    });
+    let client = courier_client::Client::with_ca_bundle(&url, &ca_path).unwrap();
+    assert!(client.heartbeat());
+    let client = courier_client::Client::new(&url).unwrap();
+    assert!(!client.heartbeat());
+    let ca = reqwest::Certificate::from_pem(&ca_cert.to_pem().unwrap()).unwrap();
+    let identity: Identity = reqwest::Client::builder()
+        .add_root_certificate(ca)
+        .build()
+        .unwrap()
+        .get(&format!("{}/api/v1/identity", url))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(identity.identity, None);
+
+    // Serve over tls requiring a client certificate with the identity "client0"
+    let url = start_tls_server(TlsConfig {
+        cert: cert_path,
+        key: key_path,
+        client_ca: Some(ca_path.clone()),
+        client_identities: vec![String::from("client0")],
+    });
+    let client = courier_client::Client::with_ca_bundle(&url, &ca_path).unwrap();
+    assert!(!client.heartbeat());
+    let get = |cert: &X509, key: &PKey<Private>, path: &str| {
+        let pkcs12 = Pkcs12::builder()
+            .name("client")
+            .pkey(key)
+            .cert(cert)
+            .build2("")
+            .unwrap()
+            .to_der()
+            .unwrap();
+        reqwest::Client::builder()
+            .add_root_certificate(
+                reqwest::Certificate::from_pem(&ca_cert.to_pem().unwrap()).unwrap(),
+            )
+            .identity(reqwest::Identity::from_pkcs12_der(&pkcs12, "").unwrap())
+            .build()
+            .unwrap()
+            .get(&format!("{}/api/v1/{}", url, path))
+            .send()
+    };
+    assert_eq!(
+        StatusCode::OK,
+        get(&client0_cert, &client0_key, "heartbeat")
+            .unwrap()
+            .status()
+    );
+    assert!(get(&client1_cert, &client1_key, "heartbeat").is_err());
+
+    // The handlers get the identity of the client certificate
+    let identity: Identity = get(&client0_cert, &client0_key, "identity")
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(identity.identity.as_deref(), Some("client0"));
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,150 @@
+use actix_web::server::IoStream;
+use actix_web::Extensions;
+use futures::Poll;
+use log::{debug, warn};
+use openssl::error::ErrorStack;
+use openssl::nid::Nid;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::{X509Name, X509Ref, X509StoreContextRef};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::rc::Rc;
+use std::time;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_openssl::SslStream;
+
+use crate::http_protocol::state::{ClientIdentity, TlsConfig};
+
+/// Get the identity of a certificate which is the common name of its subject.
+pub fn identity(cert: &X509Ref) -> Option<String> {
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| String::from_utf8(entry.data().as_slice().to_vec()).ok())
+}
+
+/// Create an ssl acceptor builder from a tls config.
+///
+/// If the config has a client CA, clients are required to present a certificate signed by it. If
+/// the config also has client identities, the client certificate's identity must be one of them.
+pub fn acceptor(config: &TlsConfig) -> Result<SslAcceptorBuilder, ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key_file(&config.key, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(&config.cert)?;
+    builder.check_private_key()?;
+
+    if let Some(client_ca) = config.client_ca.as_ref() {
+        builder.set_ca_file(client_ca)?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(client_ca)?);
+
+        let identities: HashSet<String> = config.client_identities.iter().cloned().collect();
+        builder.set_verify_callback(
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            move |preverified, ctx| verify_identity(preverified, ctx, &identities),
+        );
+    }
+
+    Ok(builder)
+}
+
+fn verify_identity(
+    preverified: bool,
+    ctx: &mut X509StoreContextRef,
+    identities: &HashSet<String>,
+) -> bool {
+    // Only the leaf certificate identifies the client, the rest of the chain only needs to pass
+    // the default verification.
+    if !preverified || ctx.error_depth() != 0 {
+        return preverified;
+    }
+    let identity = match ctx.current_cert().and_then(identity) {
+        Some(identity) => identity,
+        None => {
+            warn!("Rejected a client certificate without a common name.");
+            return false;
+        }
+    };
+    if !identities.is_empty() && !identities.contains(&identity) {
+        warn!(
+            "Rejected client certificate for unknown identity '{}'.",
+            identity
+        );
+        return false;
+    }
+    debug!("Accepted client certificate for identity '{}'.", identity);
+    true
+}
+
+/// A tls connection which provides the identity of its verified client certificate to the
+/// requests sent over it as a `ClientIdentity` extension.
+pub struct IdentifiedStream<T> {
+    stream: SslStream<T>,
+    identity: Option<String>,
+}
+
+impl<T> IdentifiedStream<T> {
+    pub fn new(stream: SslStream<T>) -> Self {
+        // A peer certificate is only requested, and then verified, when a client CA is set
+        let identity = stream
+            .get_ref()
+            .ssl()
+            .peer_certificate()
+            .and_then(|cert| identity(&cert));
+        IdentifiedStream { stream, identity }
+    }
+}
+
+impl<T: IoStream> IoStream for IdentifiedStream<T> {
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        IoStream::shutdown(&mut self.stream, how)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+
+    fn set_linger(&mut self, dur: Option<time::Duration>) -> io::Result<()> {
+        self.stream.set_linger(dur)
+    }
+
+    fn set_keepalive(&mut self, dur: Option<time::Duration>) -> io::Result<()> {
+        self.stream.set_keepalive(dur)
+    }
+
+    fn extensions(&self) -> Option<Rc<Extensions>> {
+        self.identity.as_ref().map(|identity| {
+            let mut extensions = Extensions::new();
+            extensions.insert(ClientIdentity(identity.clone()));
+            Rc::new(extensions)
+        })
+    }
+}
+
+impl<T: Read + Write> Read for IdentifiedStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<T: Read + Write> Write for IdentifiedStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for IdentifiedStream<T> {}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for IdentifiedStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.stream)
+    }
+}
@@ -1,116 +1,241 @@
 #![cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 
+use crate::http_protocol::error::HttpError;
 use crate::http_protocol::state::HttpState;
 use crate::http_protocol::types;
 use crate::http_protocol::Config;
-use actix_web::dev::HttpResponseBuilder;
-use actix_web::{HttpResponse, Json, Path, State};
+use actix_web::{HttpResponse, Json, Path, Query, State};
 use chrono::Duration;
 use courier::SharedRegistry;
-use courier::TopicMeta;
+use courier::{Error, ErrorCode, ListOptions, Message, TopicMeta};
 use uuid::Uuid;
 
-fn create(
+/// Create a topic, the settings not in `config` default to the settings of the namespace and then
+/// of the service.
+pub fn create_topic(
+    namespace: &str,
     name: &str,
     config: &types::TopicCreateConfig,
     reg: &SharedRegistry,
     cfg: &Config,
-) -> HttpResponse {
+) -> Result<TopicMeta, Error> {
+    let defaults = reg.get_namespace(namespace).ok();
     let message_ttl = config
         .message_ttl
         .map(|ttl| Duration::seconds(i64::from(ttl)))
+        .or_else(|| {
+            defaults
+                .as_ref()
+                .and_then(|n| n.default_message_ttl)
+                .map(Duration::seconds)
+        })
         .unwrap_or(cfg.default_message_ttl);
     let ttl = config
         .ttl
         .map(|ttl| Duration::seconds(i64::from(ttl)))
+        .or_else(|| {
+            defaults
+                .as_ref()
+                .and_then(|n| n.default_topic_ttl)
+                .map(Duration::seconds)
+        })
         .unwrap_or(cfg.default_topic_ttl);
-    let (created, topic) = reg.create_topic(&name, message_ttl, ttl);
-    let mut response = if created {
-        HttpResponse::Created()
-    } else {
-        HttpResponse::Conflict()
+    reg.create_topic(
+        namespace,
+        name,
+        config.labels.clone().unwrap_or_default(),
+        config.description.clone().unwrap_or_default(),
+        message_ttl,
+        ttl,
+        config.partitions.unwrap_or(1),
+    )
+}
+
+/// Create a topic with the default settings unless it already exists.
+pub fn ensure_topic(
+    namespace: &str,
+    name: &str,
+    reg: &SharedRegistry,
+    cfg: &Config,
+) -> Result<(), Error> {
+    match reg.get_topic(namespace, name) {
+        Err(ref e) if e.code == ErrorCode::TopicNotFound => {}
+        result => return result.map(|_| ()),
+    }
+    let config = types::TopicCreateConfig {
+        message_ttl: None,
+        ttl: None,
+        partitions: None,
+        labels: None,
+        description: None,
     };
-    response.json(topic)
+    match create_topic(namespace, name, &config, reg, cfg) {
+        Err(ref e) if e.code == ErrorCode::TopicAlreadyExists => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+fn create(
+    namespace: &str,
+    name: &str,
+    config: &types::TopicCreateConfig,
+    reg: &SharedRegistry,
+    cfg: &Config,
+) -> Result<HttpResponse, HttpError> {
+    let topic = create_topic(namespace, name, config, reg, cfg)?;
+    Ok(HttpResponse::Created().json(topic))
 }
 
 pub fn create_with_name(
-    (name, config, state): (
-        Path<String>,
+    (path, config, state): (
+        Path<types::ResourcePath>,
         Json<types::TopicCreateConfig>,
         State<HttpState>,
     ),
-) -> HttpResponse {
+) -> Result<HttpResponse, HttpError> {
     create(
-        &name.into_inner(),
+        &path.namespace,
+        &path.name,
         &config.into_inner(),
         &state.registry,
-        &state.config,
+        &state.config.read(),
     )
 }
 
 pub fn create_without_name(
-    (config, state): (Json<types::TopicCreateConfig>, State<HttpState>),
-) -> HttpResponse {
+    (path, config, state): (
+        Path<types::NamespacePath>,
+        Json<types::TopicCreateConfig>,
+        State<HttpState>,
+    ),
+) -> Result<HttpResponse, HttpError> {
     create(
+        &path.namespace,
         &Uuid::new_v4().to_string(),
         &config.into_inner(),
         &state.registry,
-        &state.config,
+        &state.config.read(),
     )
 }
 
 pub fn update(
-    (name, config, state): (
-        Path<String>,
+    (path, config, state): (
+        Path<types::ResourcePath>,
         Json<types::TopicUpdateConfig>,
         State<HttpState>,
     ),
-) -> Option<Json<TopicMeta>> {
+) -> Result<Json<TopicMeta>, HttpError> {
     let config = config.into_inner();
     let message_ttl = config
         .message_ttl
         .map(|message_ttl| Duration::seconds(i64::from(message_ttl)));
     let ttl = config.ttl.map(|ttl| Duration::seconds(i64::from(ttl)));
-    state
-        .registry
-        .update_topic(&name, message_ttl, ttl)
-        .map(Json)
+    let topic = state.registry.update_topic(
+        &path.namespace,
+        &path.name,
+        config.labels,
+        config.description,
+        message_ttl,
+        ttl,
+    )?;
+    Ok(Json(topic))
 }
 
-pub fn delete((name, state): (Path<String>, State<HttpState>)) -> HttpResponseBuilder {
-    if state.registry.delete_topic(&name) {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::NotFound()
-    }
+pub fn delete(
+    (path, state): (Path<types::ResourcePath>, State<HttpState>),
+) -> Result<HttpResponse, HttpError> {
+    state.registry.delete_topic(&path.namespace, &path.name)?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub fn get((name, state): (Path<String>, State<HttpState>)) -> Option<Json<TopicMeta>> {
-    state.registry.get_topic(&name).map(Json)
+pub fn get(
+    (path, state): (Path<types::ResourcePath>, State<HttpState>),
+) -> Result<Json<TopicMeta>, HttpError> {
+    Ok(Json(state.registry.get_topic(&path.namespace, &path.name)?))
 }
 
-pub fn list(state: State<HttpState>) -> Json<types::TopicList> {
-    Json(types::TopicList::new(state.registry.list_topics()))
+pub fn list(
+    (path, options, state): (
+        Path<types::NamespacePath>,
+        Query<ListOptions>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::TopicList>, HttpError> {
+    let page = state.registry.list_topics(&path.namespace, &options)?;
+    Ok(Json(types::TopicList::new(
+        page.items,
+        page.next_page_token,
+    )))
 }
 
 pub fn subscriptions(
-    (name, state): (Path<String>, State<HttpState>),
-) -> Option<Json<types::SubscriptionNameList>> {
+    (path, options, state): (
+        Path<types::ResourcePath>,
+        Query<ListOptions>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::SubscriptionNameList>, HttpError> {
     let reg = &state.registry;
-    reg.list_topic_subscriptions(&name)
-        .map(|l| Json(types::SubscriptionNameList::new(l)))
+    let page = reg.list_topic_subscriptions(&path.namespace, &path.name, &options)?;
+    Ok(Json(types::SubscriptionNameList::new(
+        page.items,
+        page.next_page_token,
+    )))
 }
 
 pub fn publish(
-    (name, messages, state): (Path<String>, Json<types::RawMessageList>, State<HttpState>),
-) -> Option<Json<types::MessageIdList>> {
+    (path, messages, state): (
+        Path<types::ResourcePath>,
+        Json<types::RawMessageList>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::PublishedMessageList>, HttpError> {
+    let reg = &state.registry;
+    let data = messages.into_inner().raw_messages;
+    let published = reg.publish(&path.namespace, &path.name, data)?;
+    Ok(Json(types::PublishedMessageList::new(published)))
+}
+
+pub fn purge(
+    (path, config, state): (
+        Path<types::ResourcePath>,
+        Json<types::TopicPurgeConfig>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::PurgeResult>, HttpError> {
+    let reg = &state.registry;
+    let purged = reg.purge_topic(&path.namespace, &path.name, config.before)?;
+    Ok(Json(types::PurgeResult::new(purged)))
+}
+
+pub fn message(
+    (path, state): (Path<types::TopicMessagePath>, State<HttpState>),
+) -> Result<Json<Message>, HttpError> {
+    let reg = &state.registry;
+    Ok(Json(reg.get_message(
+        &path.namespace,
+        &path.name,
+        path.id,
+    )?))
+}
+
+pub fn messages(
+    (path, query, state): (
+        Path<types::ResourcePath>,
+        Query<types::ReadQuery>,
+        State<HttpState>,
+    ),
+) -> Result<Json<types::OffsetMessageList>, HttpError> {
     let reg = &state.registry;
-    let data = messages
-        .into_inner()
-        .raw_messages
-        .into_iter()
-        .map(|m| m.data)
-        .collect();
-    reg.publish(&name, data)
-        .map(|m| Json(types::MessageIdList::new(m)))
+    let max = query
+        .limit
+        .unwrap_or(state.config.read().default_max_messages);
+    let (messages, next_offset) = reg.read(
+        &path.namespace,
+        &path.name,
+        query.partition.unwrap_or(0),
+        query.from_offset,
+        max,
+    )?;
+    Ok(Json(types::OffsetMessageList::new(messages, next_offset)))
 }
@@ -1,39 +1,112 @@
-use courier::{Message, SubscriptionMeta, TopicMeta};
+use chrono::prelude::*;
+use courier::bridge::BridgeMetrics;
+pub use courier::RawMessage;
+use courier::{Consumer, Message, NamespaceMeta, SubscriptionMeta, TopicMeta, DEFAULT_NAMESPACE};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+fn default_namespace() -> String {
+    String::from(DEFAULT_NAMESPACE)
+}
+
+/// Path of a route which is optionally nested under a namespace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NamespacePath {
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+}
+
+/// Path of a named resource which is optionally nested under a namespace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ResourcePath {
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub name: String,
+}
+
+/// Path of a message which is optionally nested under a namespace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MessagePath {
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub id: Uuid,
+}
+
+/// Path of a message of a topic which is optionally nested under a namespace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TopicMessagePath {
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub name: String,
+    pub id: Uuid,
+}
+
+/// Path of a consumer of a subscription which is optionally nested under a namespace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConsumerPath {
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub name: String,
+    pub consumer: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NamespaceCreateConfig {
+    pub default_message_ttl: Option<u32>,
+    pub default_topic_ttl: Option<u32>,
+    pub default_subscription_ttl: Option<u32>,
+    pub shared_with: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NamespaceUpdateConfig {
+    pub default_message_ttl: Option<u32>,
+    pub default_topic_ttl: Option<u32>,
+    pub default_subscription_ttl: Option<u32>,
+    pub shared_with: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NamespaceList {
+    pub namespaces: Vec<NamespaceMeta>,
+}
+
+impl NamespaceList {
+    pub fn new(namespaces: Vec<NamespaceMeta>) -> Self {
+        Self { namespaces }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct TopicCreateConfig {
     pub message_ttl: Option<u32>,
     pub ttl: Option<u32>,
+    pub partitions: Option<u32>,
+    pub labels: Option<HashMap<String, String>>,
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct TopicUpdateConfig {
     pub message_ttl: Option<u32>,
     pub ttl: Option<u32>,
+    pub labels: Option<HashMap<String, String>>,
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct TopicList {
     pub topics: Vec<TopicMeta>,
+    pub next_page_token: Option<String>,
 }
 
 impl TopicList {
-    pub fn new(topics: Vec<TopicMeta>) -> Self {
-        Self { topics }
-    }
-}
-
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub struct RawMessage {
-    pub data: String,
-}
-
-impl RawMessage {
-    #[allow(dead_code)]
-    pub fn new(data: String) -> Self {
-        Self { data }
+    pub fn new(topics: Vec<TopicMeta>, next_page_token: Option<String>) -> Self {
+        Self {
+            topics,
+            next_page_token,
+        }
     }
 }
 
@@ -60,39 +133,93 @@ impl MessageIdList {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PublishedMessageList {
+    pub message_ids: Vec<Uuid>,
+    pub partitions: Vec<u32>,
+    pub offsets: Vec<u64>,
+}
+
+impl PublishedMessageList {
+    pub fn new(published: Vec<(Uuid, u32, u64)>) -> Self {
+        let mut list = Self {
+            message_ids: Vec::with_capacity(published.len()),
+            partitions: Vec::with_capacity(published.len()),
+            offsets: Vec::with_capacity(published.len()),
+        };
+        for (id, partition, offset) in published {
+            list.message_ids.push(id);
+            list.partitions.push(partition);
+            list.offsets.push(offset);
+        }
+        list
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TopicPurgeConfig {
+    pub before: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PurgeResult {
+    pub purged: usize,
+}
+
+impl PurgeResult {
+    pub fn new(purged: usize) -> Self {
+        Self { purged }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SubscriptionNameList {
     pub subscription_names: Vec<String>,
+    pub next_page_token: Option<String>,
 }
 
 impl SubscriptionNameList {
-    pub fn new(subscription_names: Vec<String>) -> Self {
-        Self { subscription_names }
+    pub fn new(subscription_names: Vec<String>, next_page_token: Option<String>) -> Self {
+        Self {
+            subscription_names,
+            next_page_token,
+        }
     }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SubscriptionCreateConfig {
     pub topic: String,
+    pub topic_namespace: Option<String>,
     pub ack_deadline: Option<u32>,
     pub ttl: Option<u32>,
+    pub consumer_timeout: Option<u32>,
     pub historical: Option<bool>,
+    pub labels: Option<HashMap<String, String>>,
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SubscriptionUpdateConfig {
     pub ack_deadline: Option<u32>,
     pub ttl: Option<u32>,
+    pub consumer_timeout: Option<u32>,
+    pub labels: Option<HashMap<String, String>>,
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SubscriptionList {
     pub subscriptions: Vec<SubscriptionMeta>,
+    pub next_page_token: Option<String>,
 }
 
 impl SubscriptionList {
-    pub fn new(subscriptions: Vec<SubscriptionMeta>) -> Self {
-        Self { subscriptions }
+    pub fn new(subscriptions: Vec<SubscriptionMeta>, next_page_token: Option<String>) -> Self {
+        Self {
+            subscriptions,
+            next_page_token,
+        }
     }
 }
 
@@ -107,9 +234,33 @@ impl MessageList {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OffsetMessageList {
+    pub messages: Vec<Message>,
+    pub next_offset: u64,
+}
+
+impl OffsetMessageList {
+    pub fn new(messages: Vec<Message>, next_offset: u64) -> Self {
+        Self {
+            messages,
+            next_offset,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ReadQuery {
+    pub partition: Option<u32>,
+    pub from_offset: Option<u64>,
+    pub limit: Option<usize>,
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct PullConfig {
     pub max_messages: Option<usize>,
+    pub partitions: Option<Vec<u32>>,
+    pub consumer_id: Option<String>,
 }
 
 impl PullConfig {
@@ -117,6 +268,63 @@ impl PullConfig {
     pub fn new(max_messages: usize) -> Self {
         Self {
             max_messages: Some(max_messages),
+            partitions: None,
+            consumer_id: None,
         }
     }
 }
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConsumerList {
+    pub consumers: Vec<Consumer>,
+}
+
+impl ConsumerList {
+    pub fn new(consumers: Vec<Consumer>) -> Self {
+        Self { consumers }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ReleaseResult {
+    pub released: usize,
+}
+
+impl ReleaseResult {
+    pub fn new(released: usize) -> Self {
+        Self { released }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Origin {
+    pub origin: String,
+}
+
+impl Origin {
+    pub fn new(origin: String) -> Self {
+        Self { origin }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Identity {
+    pub identity: Option<String>,
+}
+
+impl Identity {
+    pub fn new(identity: Option<String>) -> Self {
+        Self { identity }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BridgeList {
+    pub bridges: Vec<BridgeMetrics>,
+}
+
+impl BridgeList {
+    pub fn new(bridges: Vec<BridgeMetrics>) -> Self {
+        Self { bridges }
+    }
+}
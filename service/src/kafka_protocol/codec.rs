@@ -0,0 +1,389 @@
+//! Encoding and decoding of the primitive types and record batches of the Kafka protocol.
+
+use std::io;
+
+/// The magic byte of the only record batch format supported, the one of Kafka 0.11 and later.
+const MAGIC: i8 = 2;
+
+/// The bits of the attributes of a record batch holding its compression codec.
+const COMPRESSION_MASK: i16 = 0x07;
+
+// The CRC-32C (Castagnoli) lookup table record batches are checked with.
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Compute the CRC-32C of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        CRC32C_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Reads the primitive types of the Kafka protocol from a buffer.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    /// The bytes which have not been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.buf.len() {
+            return Err(invalid("unexpected end of the buffer"));
+        }
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn array_of<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    pub fn i8(&mut self) -> io::Result<i8> {
+        Ok(i8::from_be_bytes(self.array_of()?))
+    }
+
+    pub fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(self.array_of()?))
+    }
+
+    pub fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.array_of()?))
+    }
+
+    pub fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_be_bytes(self.array_of()?))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.array_of()?))
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.i8()? != 0)
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        self.nullable_string()?
+            .ok_or_else(|| invalid("unexpected null string"))
+    }
+
+    pub fn nullable_string(&mut self) -> io::Result<Option<String>> {
+        match self.i16()? {
+            -1 => Ok(None),
+            len if len < 0 => Err(invalid("invalid string length")),
+            len => utf8(self.take(len as usize)?).map(Some),
+        }
+    }
+
+    pub fn bytes(&mut self) -> io::Result<Option<&'a [u8]>> {
+        match self.i32()? {
+            -1 => Ok(None),
+            len if len < 0 => Err(invalid("invalid bytes length")),
+            len => self.take(len as usize).map(Some),
+        }
+    }
+
+    /// Read an array with `read` reading each element, returns None for a null array.
+    pub fn array<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Option<Vec<T>>> {
+        match self.i32()? {
+            -1 => Ok(None),
+            len if len < 0 || len as usize > self.buf.len() => Err(invalid("invalid array length")),
+            len => (0..len)
+                .map(|_| read(self))
+                .collect::<io::Result<_>>()
+                .map(Some),
+        }
+    }
+
+    pub fn varlong(&mut self) -> io::Result<i64> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.array_of::<1>()?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                // Zig zag decoding
+                return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    pub fn varint(&mut self) -> io::Result<i32> {
+        let value = self.varlong()?;
+        if value < i64::from(i32::MIN) || value > i64::from(i32::MAX) {
+            return Err(invalid("varint is out of range"));
+        }
+        Ok(value as i32)
+    }
+
+    // Bytes prefixed with their varint length, -1 is null.
+    fn var_bytes(&mut self) -> io::Result<Option<&'a [u8]>> {
+        match self.varint()? {
+            -1 => Ok(None),
+            len if len < 0 => Err(invalid("invalid bytes length")),
+            len => self.take(len as usize).map(Some),
+        }
+    }
+}
+
+/// Writes the primitive types of the Kafka protocol to a buffer.
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer::default()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn i8(&mut self, value: i8) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn i16(&mut self, value: i16) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn i64(&mut self, value: i64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.i8(value as i8)
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.i16(value.len() as i16);
+        self.buf.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    pub fn nullable_string(&mut self, value: Option<&str>) -> &mut Self {
+        match value {
+            Some(value) => self.string(value),
+            None => self.i16(-1),
+        }
+    }
+
+    pub fn bytes(&mut self, value: Option<&[u8]>) -> &mut Self {
+        match value {
+            Some(value) => {
+                self.i32(value.len() as i32);
+                self.buf.extend_from_slice(value);
+                self
+            }
+            None => self.i32(-1),
+        }
+    }
+
+    /// Write an array with `write` writing each element.
+    pub fn array<T>(&mut self, items: &[T], mut write: impl FnMut(&mut Self, &T)) -> &mut Self {
+        self.i32(items.len() as i32);
+        for item in items {
+            write(self, item);
+        }
+        self
+    }
+
+    pub fn varlong(&mut self, value: i64) -> &mut Self {
+        // Zig zag encoding
+        let mut value = ((value << 1) ^ (value >> 63)) as u64;
+        while value >= 0x80 {
+            self.buf.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+        self
+    }
+
+    pub fn varint(&mut self, value: i32) -> &mut Self {
+        self.varlong(i64::from(value))
+    }
+
+    fn var_bytes(&mut self, value: Option<&[u8]>) -> &mut Self {
+        match value {
+            Some(value) => {
+                self.varint(value.len() as i32);
+                self.buf.extend_from_slice(value);
+                self
+            }
+            None => self.varint(-1),
+        }
+    }
+}
+
+/// A record of a record batch, headers are not kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub offset: i64,
+    pub timestamp: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Why the record batches of a request could not be read.
+#[derive(Debug, PartialEq)]
+pub enum RecordsError {
+    /// The batches are malformed, in an older format, or fail their CRC.
+    Corrupt(&'static str),
+    /// The batches are compressed.
+    Compressed,
+}
+
+/// Read the records of a sequence of record batches.
+pub fn read_batches(buf: &[u8]) -> Result<Vec<Record>, RecordsError> {
+    let corrupt = |_| RecordsError::Corrupt("record batch is truncated");
+    let mut records = Vec::new();
+    let mut reader = Reader::new(buf);
+    while !reader.remaining().is_empty() {
+        let base_offset = reader.i64().map_err(corrupt)?;
+        let len = reader.i32().map_err(corrupt)?;
+        if len < 0 {
+            return Err(RecordsError::Corrupt("invalid record batch length"));
+        }
+        let mut batch = Reader::new(reader.take(len as usize).map_err(corrupt)?);
+        batch.i32().map_err(corrupt)?;
+        if batch.i8().map_err(corrupt)? != MAGIC {
+            return Err(RecordsError::Corrupt("unsupported record batch format"));
+        }
+        let crc = batch.u32().map_err(corrupt)?;
+        if crc32c(batch.remaining()) != crc {
+            return Err(RecordsError::Corrupt("record batch fails its CRC"));
+        }
+        let attributes = batch.i16().map_err(corrupt)?;
+        if attributes & COMPRESSION_MASK != 0 {
+            return Err(RecordsError::Compressed);
+        }
+        batch.i32().map_err(corrupt)?;
+        let base_timestamp = batch.i64().map_err(corrupt)?;
+        // The max timestamp, producer id, producer epoch, and base sequence
+        batch.take(8 + 8 + 2 + 4).map_err(corrupt)?;
+        let count = batch.i32().map_err(corrupt)?;
+        for _ in 0..count {
+            let len = batch.varint().map_err(corrupt)?;
+            if len < 0 {
+                return Err(RecordsError::Corrupt("invalid record length"));
+            }
+            let mut record = Reader::new(batch.take(len as usize).map_err(corrupt)?);
+            record.i8().map_err(corrupt)?;
+            let timestamp_delta = record.varlong().map_err(corrupt)?;
+            let offset_delta = record.varint().map_err(corrupt)?;
+            let key = record.var_bytes().map_err(corrupt)?;
+            let value = record.var_bytes().map_err(corrupt)?;
+            records.push(Record {
+                offset: base_offset + i64::from(offset_delta),
+                timestamp: base_timestamp + timestamp_delta,
+                key: key.map(Vec::from),
+                value: value.map(Vec::from),
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// Write `records` as a single uncompressed record batch, the offsets of the records must be
+/// consecutive.
+pub fn write_batch(records: &[Record]) -> Vec<u8> {
+    let base_offset = records.first().map(|r| r.offset).unwrap_or(0);
+    let base_timestamp = records.first().map(|r| r.timestamp).unwrap_or(-1);
+    let max_timestamp = records.iter().map(|r| r.timestamp).max().unwrap_or(-1);
+
+    // The part of the batch covered by its CRC
+    let mut body = Writer::new();
+    body.i16(0)
+        .i32(records.len() as i32 - 1)
+        .i64(base_timestamp)
+        .i64(max_timestamp)
+        // No producer id, epoch, or base sequence
+        .i64(-1)
+        .i16(-1)
+        .i32(-1)
+        .i32(records.len() as i32);
+    for record in records {
+        let mut encoded = Writer::new();
+        encoded
+            .i8(0)
+            .varlong(record.timestamp - base_timestamp)
+            .varint((record.offset - base_offset) as i32)
+            .var_bytes(record.key.as_deref())
+            .var_bytes(record.value.as_deref())
+            .varint(0);
+        let encoded = encoded.into_inner();
+        body.varint(encoded.len() as i32);
+        body.buf.extend_from_slice(&encoded);
+    }
+    let body = body.into_inner();
+
+    let mut batch = Writer::new();
+    batch
+        .i64(base_offset)
+        // The partition leader epoch, magic, and CRC follow the length
+        .i32((4 + 1 + 4 + body.len()) as i32)
+        .i32(0)
+        .i8(MAGIC)
+        .u32(crc32c(&body));
+    batch.buf.extend_from_slice(&body);
+    batch.into_inner()
+}
+
+fn utf8(bytes: &[u8]) -> io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| invalid("the string is not UTF-8"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Protocol error: {}", message),
+    )
+}
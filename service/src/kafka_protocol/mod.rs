@@ -0,0 +1,923 @@
+//! A Kafka listener serving a subset of the Kafka protocol backed by the registry.
+//!
+//! Kafka topics are the topics of the default namespace and Kafka offsets are the offsets of
+//! their partitions, Courier acts as a single broker leading every partition.
+//!
+//! * `ApiVersions` advertises the supported APIs, each of them only in its versions without
+//!   tagged fields.
+//! * `Metadata` lists topics, missing topics are created with the default settings unless the
+//!   client disables auto creation.
+//! * `Produce` publishes the records of uncompressed record batches to their partition. Record
+//!   values are the data of the messages and keys their partition keys, both must be UTF-8,
+//!   headers are dropped.
+//! * `Fetch` reads messages from an offset of a partition as records stamped with their publish
+//!   time, it waits up to the maximum wait time of the request for new messages.
+//! * `ListOffsets` returns the offset of the oldest retained message, the next offset, or the
+//!   offset of the first message published at or after a timestamp.
+//! * `FindCoordinator` returns the broker itself. `OffsetCommit` stores the offsets a consumer
+//!   group commits for a topic as the cursors of the subscription `kafka/<group>/<topic>`, which
+//!   is created by the first commit, and `OffsetFetch` returns them.
+//! * `InitProducerId` hands out producer ids so idempotent producers can connect, sequence numbers
+//!   are not checked.
+//!
+//! Group membership (`JoinGroup`, `SyncGroup`, `Heartbeat`) and transactions are not supported,
+//! the consumers of a group assign their partitions themselves.
+
+mod codec;
+#[cfg(test)]
+mod tests;
+
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use self::codec::{Reader, Record, RecordsError, Writer};
+use crate::http_protocol::subscription_handlers::create_subscription;
+use crate::http_protocol::topic_handlers::ensure_topic;
+use crate::http_protocol::types::SubscriptionCreateConfig;
+use crate::http_protocol::SharedConfig;
+use courier::{
+    Error, ErrorCode, ListOptions, Message, RawMessage, SharedRegistry, DEFAULT_NAMESPACE,
+};
+
+/// How long a Fetch waiting for messages waits before reading again.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// The maximum size of a request.
+const MAX_REQUEST_LEN: usize = 100 * 1024 * 1024;
+
+/// The maximum number of messages read from a partition at once.
+const MAX_READ_MESSAGES: usize = 1000;
+
+/// The size of a record besides its value counted against the byte limits of a Fetch.
+const RECORD_OVERHEAD: usize = 24;
+
+/// The node id of the broker.
+const NODE_ID: i32 = 0;
+
+/// The id of the cluster reported by Metadata.
+const CLUSTER_ID: &str = "courier";
+
+/// The label of a subscription holding the consumer group whose offsets it stores.
+const GROUP_LABEL: &str = "kafka-group";
+
+// The keys of the supported APIs.
+const PRODUCE: i16 = 0;
+const FETCH: i16 = 1;
+const LIST_OFFSETS: i16 = 2;
+const METADATA: i16 = 3;
+const OFFSET_COMMIT: i16 = 8;
+const OFFSET_FETCH: i16 = 9;
+const FIND_COORDINATOR: i16 = 10;
+const API_VERSIONS: i16 = 18;
+const INIT_PRODUCER_ID: i16 = 22;
+
+/// The key, minimum version, and maximum version of the supported APIs.
+const APIS: [(i16, i16, i16); 9] = [
+    (PRODUCE, 3, 8),
+    (FETCH, 4, 11),
+    (LIST_OFFSETS, 1, 5),
+    (METADATA, 0, 8),
+    (OFFSET_COMMIT, 2, 7),
+    (OFFSET_FETCH, 1, 5),
+    (FIND_COORDINATOR, 0, 2),
+    (API_VERSIONS, 0, 2),
+    (INIT_PRODUCER_ID, 0, 1),
+];
+
+// The error codes of responses.
+const UNKNOWN_SERVER_ERROR: i16 = -1;
+const NO_ERROR: i16 = 0;
+const OFFSET_OUT_OF_RANGE: i16 = 1;
+const CORRUPT_MESSAGE: i16 = 2;
+const UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+const UNSUPPORTED_VERSION: i16 = 35;
+const UNSUPPORTED_COMPRESSION_TYPE: i16 = 76;
+
+// The timestamps of ListOffsets asking for the next and the oldest offset.
+const LATEST_TIMESTAMP: i64 = -1;
+const EARLIEST_TIMESTAMP: i64 = -2;
+
+/// The state shared by the connections of the Kafka listener.
+struct KafkaState {
+    registry: SharedRegistry,
+    config: SharedConfig,
+    next_producer_id: AtomicI64,
+}
+
+/// Start the Kafka listener on `address` in the background.
+pub fn start(address: &str, registry: &SharedRegistry, config: &SharedConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving Kafka on '{}'.", listener.local_addr()?);
+    let state = Arc::new(KafkaState {
+        registry: Arc::clone(registry),
+        config: Arc::clone(config),
+        next_producer_id: AtomicI64::new(0),
+    });
+    thread::Builder::new()
+        .name(String::from("kafka"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            if let Err(e) = serve(&state, stream) {
+                                debug!("Kafka connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Can not accept a Kafka connection: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// The header of a request.
+struct Header {
+    api_key: i16,
+    version: i16,
+    correlation_id: i32,
+}
+
+// Serve a connection until it is closed.
+fn serve(state: &KafkaState, stream: TcpStream) -> io::Result<()> {
+    // The broker is advertised at the address the client reached it at
+    let address = stream.local_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let len = i32::from_be_bytes(len);
+        if len < 0 || len as usize > MAX_REQUEST_LEN {
+            return Err(invalid(format!("invalid request length {}", len)));
+        }
+        let mut request = vec![0; len as usize];
+        reader.read_exact(&mut request)?;
+        let mut request = Reader::new(&request);
+        let header = Header {
+            api_key: request.i16()?,
+            version: request.i16()?,
+            correlation_id: request.i32()?,
+        };
+        // The client id
+        request.nullable_string()?;
+        if let Some(body) = state.handle(&header, &mut request, &address)? {
+            writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+            writer.write_all(&header.correlation_id.to_be_bytes())?;
+            writer.write_all(&body)?;
+            writer.flush()?;
+        }
+    }
+}
+
+/// A partition of a Fetch request.
+struct FetchPartition {
+    partition: i32,
+    offset: i64,
+    max_bytes: usize,
+}
+
+/// A partition of a Fetch response.
+struct FetchedPartition {
+    partition: i32,
+    error: i16,
+    high_watermark: i64,
+    log_start_offset: i64,
+    records: Vec<u8>,
+}
+
+impl FetchedPartition {
+    fn error(partition: i32, error: i16) -> Self {
+        FetchedPartition {
+            partition,
+            error,
+            high_watermark: -1,
+            log_start_offset: -1,
+            records: Vec::new(),
+        }
+    }
+}
+
+impl KafkaState {
+    // Handle a request returning the body of its response, None if it has no response.
+    fn handle(
+        &self,
+        header: &Header,
+        request: &mut Reader<'_>,
+        address: &SocketAddr,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let version = header.version;
+        let supported = APIS
+            .iter()
+            .any(|(key, min, max)| *key == header.api_key && *min <= version && version <= *max);
+        let mut response = Writer::new();
+        if !supported {
+            if header.api_key != API_VERSIONS {
+                return Err(invalid(format!(
+                    "unsupported version {} of API {}",
+                    version, header.api_key
+                )));
+            }
+            // The client retries with a version of the list
+            self.api_versions(0, UNSUPPORTED_VERSION, &mut response);
+            return Ok(Some(response.into_inner()));
+        }
+        match header.api_key {
+            PRODUCE => {
+                if !self.produce(version, request, &mut response)? {
+                    return Ok(None);
+                }
+            }
+            FETCH => self.fetch(version, request, &mut response)?,
+            LIST_OFFSETS => self.list_offsets(version, request, &mut response)?,
+            METADATA => self.metadata(version, request, &mut response, address)?,
+            OFFSET_COMMIT => self.offset_commit(version, request, &mut response)?,
+            OFFSET_FETCH => self.offset_fetch(version, request, &mut response)?,
+            FIND_COORDINATOR => self.find_coordinator(version, request, &mut response, address)?,
+            API_VERSIONS => self.api_versions(version, NO_ERROR, &mut response),
+            _ => self.init_producer_id(request, &mut response)?,
+        }
+        Ok(Some(response.into_inner()))
+    }
+
+    fn api_versions(&self, version: i16, error: i16, w: &mut Writer) {
+        w.i16(error).array(&APIS, |w, (key, min, max)| {
+            w.i16(*key).i16(*min).i16(*max);
+        });
+        if version >= 1 {
+            w.i32(0);
+        }
+    }
+
+    fn metadata(
+        &self,
+        version: i16,
+        r: &mut Reader<'_>,
+        w: &mut Writer,
+        address: &SocketAddr,
+    ) -> io::Result<()> {
+        let names = r.array(Reader::string)?;
+        let auto_create = version < 4 || r.bool()?;
+        let names = match names {
+            Some(names) if version >= 1 || !names.is_empty() => names,
+            // A null array or before version 1 an empty one lists every topic
+            _ => self
+                .registry
+                .list_topics(DEFAULT_NAMESPACE, &ListOptions::default())
+                .map(|page| page.items.into_iter().map(|t| t.name).collect())
+                .unwrap_or_default(),
+        };
+        let topics: Vec<(String, i16, u32)> = names
+            .into_iter()
+            .map(|name| {
+                let created = if auto_create {
+                    ensure_topic(
+                        DEFAULT_NAMESPACE,
+                        &name,
+                        &self.registry,
+                        &self.config.read(),
+                    )
+                } else {
+                    Ok(())
+                };
+                match created.and_then(|()| self.registry.get_topic(DEFAULT_NAMESPACE, &name)) {
+                    Ok(topic) => (name, NO_ERROR, topic.partitions),
+                    Err(e) => (name, error_code(&e), 0),
+                }
+            })
+            .collect();
+
+        if version >= 3 {
+            w.i32(0);
+        }
+        w.i32(1);
+        broker(w, address);
+        if version >= 1 {
+            // No rack
+            w.nullable_string(None);
+        }
+        if version >= 2 {
+            w.nullable_string(Some(CLUSTER_ID));
+        }
+        if version >= 1 {
+            w.i32(NODE_ID);
+        }
+        w.array(&topics, |w, (name, error, partitions)| {
+            w.i16(*error).string(name);
+            if version >= 1 {
+                // Not internal
+                w.bool(false);
+            }
+            let partitions: Vec<i32> = (0..*partitions as i32).collect();
+            w.array(&partitions, |w, partition| {
+                w.i16(NO_ERROR).i32(*partition).i32(NODE_ID);
+                if version >= 7 {
+                    // The leader epoch
+                    w.i32(0);
+                }
+                // The replicas and in sync replicas
+                w.array(&[NODE_ID], |w, node| {
+                    w.i32(*node);
+                })
+                .array(&[NODE_ID], |w, node| {
+                    w.i32(*node);
+                });
+                if version >= 5 {
+                    // No offline replicas
+                    w.i32(0);
+                }
+            });
+            if version >= 8 {
+                // The authorized operations were not requested
+                w.i32(i32::MIN);
+            }
+        });
+        if version >= 8 {
+            w.i32(i32::MIN);
+        }
+        Ok(())
+    }
+
+    // Publish the records of a Produce request, returns if a response is expected.
+    fn produce(&self, version: i16, r: &mut Reader<'_>, w: &mut Writer) -> io::Result<bool> {
+        // The transactional id
+        r.nullable_string()?;
+        let acks = r.i16()?;
+        // The timeout
+        r.i32()?;
+        let topics = r
+            .array(|r| {
+                let name = r.string()?;
+                let partitions = r
+                    .array(|r| {
+                        let partition = r.i32()?;
+                        let records = r.bytes()?.unwrap_or_default();
+                        Ok((partition, self.produce_partition(&name, partition, records)))
+                    })?
+                    .unwrap_or_default();
+                Ok((name, partitions))
+            })?
+            .unwrap_or_default();
+        if acks == 0 {
+            return Ok(false);
+        }
+
+        w.array(&topics, |w, (name, partitions)| {
+            w.string(name).array(
+                partitions,
+                |w, (partition, (error, base_offset, log_start_offset))| {
+                    // The log append time is not used
+                    w.i32(*partition).i16(*error).i64(*base_offset).i64(-1);
+                    if version >= 5 {
+                        w.i64(*log_start_offset);
+                    }
+                    if version >= 8 {
+                        // No record errors or error message
+                        w.i32(0).nullable_string(None);
+                    }
+                },
+            );
+        });
+        w.i32(0);
+        Ok(true)
+    }
+
+    // Publish records to a partition, returns the error code, the offset of the first record, and
+    // the oldest offset of the partition.
+    fn produce_partition(&self, topic: &str, partition: i32, records: &[u8]) -> (i16, i64, i64) {
+        let records = match codec::read_batches(records) {
+            Ok(records) => records,
+            Err(RecordsError::Compressed) => return (UNSUPPORTED_COMPRESSION_TYPE, -1, -1),
+            Err(RecordsError::Corrupt(e)) => {
+                debug!("Can not read the records produced to '{}': {}", topic, e);
+                return (CORRUPT_MESSAGE, -1, -1);
+            }
+        };
+        let partition = match u32::try_from(partition) {
+            Ok(partition) => partition,
+            Err(_) => return (UNKNOWN_TOPIC_OR_PARTITION, -1, -1),
+        };
+        let messages = records
+            .into_iter()
+            .map(|record| {
+                let data = String::from_utf8(record.value.unwrap_or_default()).ok()?;
+                let partition_key = match record.key {
+                    Some(key) => Some(String::from_utf8(key).ok()?),
+                    None => None,
+                };
+                Some(RawMessage {
+                    data,
+                    partition_key,
+                    partition: Some(partition),
+                    ..RawMessage::default()
+                })
+            })
+            .collect::<Option<Vec<_>>>();
+        let messages = match messages {
+            Some(messages) => messages,
+            None => return (CORRUPT_MESSAGE, -1, -1),
+        };
+        match self.registry.publish(DEFAULT_NAMESPACE, topic, messages) {
+            Ok(published) => {
+                let base_offset = published
+                    .first()
+                    .map_or(-1, |(_, _, offset)| *offset as i64);
+                let log_start_offset = self
+                    .offsets(topic, partition as i32)
+                    .map_or(-1, |(head, _)| head as i64);
+                (NO_ERROR, base_offset, log_start_offset)
+            }
+            Err(e) => (error_code(&e), -1, -1),
+        }
+    }
+
+    fn fetch(&self, version: i16, r: &mut Reader<'_>, w: &mut Writer) -> io::Result<()> {
+        // The replica id
+        r.i32()?;
+        let max_wait = time::Duration::from_millis(r.i32()?.max(0) as u64);
+        let min_bytes = r.i32()?.max(0) as usize;
+        let max_bytes = r.i32()?.max(0) as usize;
+        // The isolation level
+        r.i8()?;
+        if version >= 7 {
+            // Fetch sessions are not supported, the session id 0 of the response tells the
+            // client to send full requests
+            r.i32()?;
+            r.i32()?;
+        }
+        let topics = r
+            .array(|r| {
+                let name = r.string()?;
+                let partitions = r
+                    .array(|r| {
+                        let partition = r.i32()?;
+                        if version >= 9 {
+                            // The current leader epoch
+                            r.i32()?;
+                        }
+                        let offset = r.i64()?;
+                        if version >= 5 {
+                            // The log start offset of a follower
+                            r.i64()?;
+                        }
+                        let max_bytes = r.i32()?.max(0) as usize;
+                        Ok(FetchPartition {
+                            partition,
+                            offset,
+                            max_bytes,
+                        })
+                    })?
+                    .unwrap_or_default();
+                Ok((name, partitions))
+            })?
+            .unwrap_or_default();
+
+        // Read until there are enough messages or the wait time is over
+        let deadline = time::Instant::now() + max_wait;
+        let fetched = loop {
+            let mut remaining = max_bytes;
+            let fetched: Vec<(&String, Vec<FetchedPartition>)> = topics
+                .iter()
+                .map(|(name, partitions)| {
+                    let partitions = partitions
+                        .iter()
+                        .map(|p| {
+                            let fetched = self.fetch_partition(name, p, remaining);
+                            remaining = remaining.saturating_sub(fetched.records.len());
+                            fetched
+                        })
+                        .collect();
+                    (name, partitions)
+                })
+                .collect();
+            let now = time::Instant::now();
+            if max_bytes - remaining >= min_bytes || now >= deadline {
+                break fetched;
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline - now));
+        };
+
+        w.i32(0);
+        if version >= 7 {
+            w.i16(NO_ERROR).i32(0);
+        }
+        w.array(&fetched, |w, (name, partitions)| {
+            w.string(name).array(partitions, |w, p| {
+                // The last stable offset is the high watermark without transactions
+                w.i32(p.partition)
+                    .i16(p.error)
+                    .i64(p.high_watermark)
+                    .i64(p.high_watermark);
+                if version >= 5 {
+                    w.i64(p.log_start_offset);
+                }
+                // No aborted transactions
+                w.i32(-1);
+                if version >= 11 {
+                    // No preferred read replica
+                    w.i32(-1);
+                }
+                w.bytes(Some(&p.records));
+            });
+        });
+        Ok(())
+    }
+
+    // Read messages of a partition as a record batch of at most `remaining` bytes.
+    fn fetch_partition(
+        &self,
+        topic: &str,
+        p: &FetchPartition,
+        remaining: usize,
+    ) -> FetchedPartition {
+        let (head, next) = match self.offsets(topic, p.partition) {
+            Ok(offsets) => offsets,
+            Err(error) => return FetchedPartition::error(p.partition, error),
+        };
+        let mut fetched = FetchedPartition {
+            high_watermark: next as i64,
+            log_start_offset: head as i64,
+            ..FetchedPartition::error(p.partition, NO_ERROR)
+        };
+        if p.offset < head as i64 || p.offset > next as i64 {
+            fetched.error = OFFSET_OUT_OF_RANGE;
+            return fetched;
+        }
+        let limit = p.max_bytes.min(remaining);
+        if p.offset == next as i64 || limit == 0 {
+            return fetched;
+        }
+        let messages = match self.registry.read(
+            DEFAULT_NAMESPACE,
+            topic,
+            p.partition as u32,
+            Some(p.offset as u64),
+            MAX_READ_MESSAGES,
+        ) {
+            Ok((messages, _)) => messages,
+            Err(e) => return FetchedPartition::error(p.partition, error_code(&e)),
+        };
+        // A message larger than the limit is returned on its own so the consumer is not stuck
+        let mut size = 0;
+        let mut records = Vec::new();
+        for message in messages {
+            size += message.data.len() + RECORD_OVERHEAD;
+            if size > limit && !records.is_empty() {
+                break;
+            }
+            records.push(record(message));
+        }
+        fetched.records = codec::write_batch(&records);
+        fetched
+    }
+
+    fn list_offsets(&self, version: i16, r: &mut Reader<'_>, w: &mut Writer) -> io::Result<()> {
+        // The replica id
+        r.i32()?;
+        if version >= 2 {
+            // The isolation level
+            r.i8()?;
+        }
+        let topics = r
+            .array(|r| {
+                let name = r.string()?;
+                let partitions = r
+                    .array(|r| {
+                        let partition = r.i32()?;
+                        if version >= 4 {
+                            // The current leader epoch
+                            r.i32()?;
+                        }
+                        let timestamp = r.i64()?;
+                        Ok((partition, self.list_offset(&name, partition, timestamp)))
+                    })?
+                    .unwrap_or_default();
+                Ok((name, partitions))
+            })?
+            .unwrap_or_default();
+
+        if version >= 2 {
+            w.i32(0);
+        }
+        w.array(&topics, |w, (name, partitions)| {
+            w.string(name)
+                .array(partitions, |w, (partition, (error, timestamp, offset))| {
+                    w.i32(*partition).i16(*error).i64(*timestamp).i64(*offset);
+                    if version >= 4 {
+                        // The leader epoch
+                        w.i32(0);
+                    }
+                });
+        });
+        Ok(())
+    }
+
+    // Find an offset of a partition by timestamp, returns the error code, the timestamp, and the
+    // offset.
+    fn list_offset(&self, topic: &str, partition: i32, timestamp: i64) -> (i16, i64, i64) {
+        let (head, next) = match self.offsets(topic, partition) {
+            Ok(offsets) => offsets,
+            Err(error) => return (error, -1, -1),
+        };
+        match timestamp {
+            LATEST_TIMESTAMP => (NO_ERROR, -1, next as i64),
+            EARLIEST_TIMESTAMP => (NO_ERROR, -1, head as i64),
+            timestamp => {
+                // The first message published at or after the timestamp if any
+                let mut from_offset = None;
+                loop {
+                    let read = self.registry.read(
+                        DEFAULT_NAMESPACE,
+                        topic,
+                        partition as u32,
+                        from_offset,
+                        MAX_READ_MESSAGES,
+                    );
+                    let (messages, next_offset) = match read {
+                        Ok(read) => read,
+                        Err(e) => return (error_code(&e), -1, -1),
+                    };
+                    if messages.is_empty() {
+                        return (NO_ERROR, -1, -1);
+                    }
+                    let found = messages
+                        .iter()
+                        .find(|m| m.time.timestamp_millis() >= timestamp);
+                    if let Some(m) = found {
+                        return (NO_ERROR, m.time.timestamp_millis(), m.offset as i64);
+                    }
+                    from_offset = Some(next_offset);
+                }
+            }
+        }
+    }
+
+    fn find_coordinator(
+        &self,
+        version: i16,
+        r: &mut Reader<'_>,
+        w: &mut Writer,
+        address: &SocketAddr,
+    ) -> io::Result<()> {
+        // The group id, the broker coordinates every group
+        r.string()?;
+        if version >= 1 {
+            // The key type
+            r.i8()?;
+            w.i32(0);
+        }
+        w.i16(NO_ERROR);
+        if version >= 1 {
+            // No error message
+            w.nullable_string(None);
+        }
+        broker(w, address);
+        Ok(())
+    }
+
+    fn offset_commit(&self, version: i16, r: &mut Reader<'_>, w: &mut Writer) -> io::Result<()> {
+        let group = r.string()?;
+        // The generation and member id, group membership is not checked
+        r.i32()?;
+        r.string()?;
+        if version <= 4 {
+            // The retention time
+            r.i64()?;
+        }
+        if version >= 7 {
+            // The group instance id
+            r.nullable_string()?;
+        }
+        let topics = r
+            .array(|r| {
+                let name = r.string()?;
+                let partitions = r
+                    .array(|r| {
+                        let partition = r.i32()?;
+                        let offset = r.i64()?;
+                        if version >= 6 {
+                            // The leader epoch
+                            r.i32()?;
+                        }
+                        // The metadata
+                        r.nullable_string()?;
+                        Ok((partition, self.commit(&group, &name, partition, offset)))
+                    })?
+                    .unwrap_or_default();
+                Ok((name, partitions))
+            })?
+            .unwrap_or_default();
+
+        if version >= 3 {
+            w.i32(0);
+        }
+        w.array(&topics, |w, (name, partitions)| {
+            w.string(name).array(partitions, |w, (partition, error)| {
+                w.i32(*partition).i16(*error);
+            });
+        });
+        Ok(())
+    }
+
+    // Store the offset a group commits for a partition of a topic as the cursor of the
+    // subscription of the group, returns the error code.
+    fn commit(&self, group: &str, topic: &str, partition: i32, offset: i64) -> i16 {
+        let partition = match u32::try_from(partition) {
+            Ok(partition) => partition,
+            Err(_) => return UNKNOWN_TOPIC_OR_PARTITION,
+        };
+        let name = subscription_name(group, topic);
+        let offset = offset.max(0) as u64;
+        let seek = || {
+            self.registry
+                .seek(DEFAULT_NAMESPACE, &name, partition, offset)
+        };
+        let result = match seek() {
+            Err(ref e) if e.code == ErrorCode::SubscriptionNotFound => self
+                .create_group_subscription(group, topic, &name)
+                .and_then(|()| seek()),
+            result => result,
+        };
+        match result {
+            Ok(()) => NO_ERROR,
+            Err(e) => error_code(&e),
+        }
+    }
+
+    fn create_group_subscription(&self, group: &str, topic: &str, name: &str) -> Result<(), Error> {
+        let mut labels = HashMap::new();
+        labels.insert(String::from(GROUP_LABEL), String::from(group));
+        let config = SubscriptionCreateConfig {
+            topic: String::from(topic),
+            topic_namespace: None,
+            ack_deadline: None,
+            ttl: None,
+            consumer_timeout: None,
+            historical: Some(true),
+            labels: Some(labels),
+            description: None,
+        };
+        match create_subscription(
+            DEFAULT_NAMESPACE,
+            name,
+            &config,
+            &self.registry,
+            &self.config.read(),
+        ) {
+            // Another member of the group committed first
+            Err(ref e) if e.code == ErrorCode::SubscriptionAlreadyExists => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn offset_fetch(&self, version: i16, r: &mut Reader<'_>, w: &mut Writer) -> io::Result<()> {
+        let group = r.string()?;
+        let topics =
+            match r.array(|r| Ok((r.string()?, r.array(Reader::i32)?.unwrap_or_default())))? {
+                Some(topics) => topics,
+                // A null array fetches every topic the group committed offsets for
+                None => self.group_topics(&group),
+            };
+        let topics: Vec<_> = topics
+            .into_iter()
+            .map(|(name, partitions)| {
+                let committed = self
+                    .registry
+                    .subscription_offsets(DEFAULT_NAMESPACE, &subscription_name(&group, &name));
+                let partitions: Vec<_> = partitions
+                    .into_iter()
+                    .map(|partition| match &committed {
+                        Ok(offsets) => {
+                            let offset = usize::try_from(partition)
+                                .ok()
+                                .and_then(|p| offsets.get(p))
+                                .map_or(-1, |offset| *offset as i64);
+                            (partition, offset, NO_ERROR)
+                        }
+                        // The group did not commit offsets for the topic yet
+                        Err(e) if e.code == ErrorCode::SubscriptionNotFound => {
+                            (partition, -1, NO_ERROR)
+                        }
+                        Err(e) => (partition, -1, error_code(e)),
+                    })
+                    .collect();
+                (name, partitions)
+            })
+            .collect();
+
+        if version >= 3 {
+            w.i32(0);
+        }
+        w.array(&topics, |w, (name, partitions)| {
+            w.string(name)
+                .array(partitions, |w, (partition, offset, error)| {
+                    w.i32(*partition).i64(*offset);
+                    if version >= 5 {
+                        // No leader epoch
+                        w.i32(-1);
+                    }
+                    w.nullable_string(Some("")).i16(*error);
+                });
+        });
+        if version >= 2 {
+            w.i16(NO_ERROR);
+        }
+        Ok(())
+    }
+
+    // The topics a group committed offsets for with all of their partitions.
+    fn group_topics(&self, group: &str) -> Vec<(String, Vec<i32>)> {
+        let options = ListOptions {
+            prefix: Some(format!("kafka/{}/", group)),
+            ..ListOptions::default()
+        };
+        let subscriptions = match self
+            .registry
+            .list_subscriptions(DEFAULT_NAMESPACE, &options)
+        {
+            Ok(page) => page.items,
+            Err(_) => return Vec::new(),
+        };
+        subscriptions
+            .into_iter()
+            .filter(|s| s.labels.get(GROUP_LABEL).map(String::as_str) == Some(group))
+            .filter_map(|s| {
+                let offsets = self
+                    .registry
+                    .subscription_offsets(DEFAULT_NAMESPACE, &s.name)
+                    .ok()?;
+                Some((s.topic, (0..offsets.len() as i32).collect()))
+            })
+            .collect()
+    }
+
+    fn init_producer_id(&self, r: &mut Reader<'_>, w: &mut Writer) -> io::Result<()> {
+        // The transactional id and transaction timeout
+        r.nullable_string()?;
+        r.i32()?;
+        let producer_id = self.next_producer_id.fetch_add(1, Ordering::Relaxed);
+        w.i32(0).i16(NO_ERROR).i64(producer_id).i16(0);
+        Ok(())
+    }
+
+    // The oldest retained and the next offset of a partition or the error code if it does not
+    // exist.
+    fn offsets(&self, topic: &str, partition: i32) -> Result<(u64, u64), i16> {
+        let offsets = self
+            .registry
+            .topic_offsets(DEFAULT_NAMESPACE, topic)
+            .map_err(|e| error_code(&e))?;
+        usize::try_from(partition)
+            .ok()
+            .and_then(|p| offsets.get(p).cloned())
+            .ok_or(UNKNOWN_TOPIC_OR_PARTITION)
+    }
+}
+
+// The subscription storing the offsets a group committed for a topic.
+fn subscription_name(group: &str, topic: &str) -> String {
+    format!("kafka/{}/{}", group, topic)
+}
+
+// Write the node id, host, and port of the broker.
+fn broker(w: &mut Writer, address: &SocketAddr) {
+    w.i32(NODE_ID)
+        .string(&address.ip().to_string())
+        .i32(i32::from(address.port()));
+}
+
+fn record(message: Message) -> Record {
+    Record {
+        offset: message.offset as i64,
+        timestamp: message.time.timestamp_millis(),
+        key: message.partition_key.map(|key| key.as_bytes().to_vec()),
+        value: Some(message.data.as_bytes().to_vec()),
+    }
+}
+
+fn error_code(error: &Error) -> i16 {
+    match error.code {
+        ErrorCode::TopicNotFound | ErrorCode::PartitionNotFound => UNKNOWN_TOPIC_OR_PARTITION,
+        ErrorCode::OffsetExpired => OFFSET_OUT_OF_RANGE,
+        _ => UNKNOWN_SERVER_ERROR,
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Protocol error: {}", message),
+    )
+}
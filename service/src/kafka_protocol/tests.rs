@@ -0,0 +1,508 @@
+use super::*;
+use crate::testing::{config, unused_port};
+use courier::Registry;
+
+// A Kafka client sending requests and reading the body of their responses.
+struct Client {
+    stream: TcpStream,
+    correlation_id: i32,
+}
+
+impl Client {
+    fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            stream,
+            correlation_id: 0,
+        }
+    }
+
+    fn send(&mut self, api_key: i16, version: i16, body: Writer) {
+        self.correlation_id += 1;
+        let mut request = Writer::new();
+        request
+            .i16(api_key)
+            .i16(version)
+            .i32(self.correlation_id)
+            .nullable_string(Some("test"));
+        let mut request = request.into_inner();
+        request.extend_from_slice(&body.into_inner());
+        self.stream
+            .write_all(&(request.len() as i32).to_be_bytes())
+            .unwrap();
+        self.stream.write_all(&request).unwrap();
+    }
+
+    fn receive(&mut self) -> (i32, Vec<u8>) {
+        let mut len = [0; 4];
+        self.stream.read_exact(&mut len).unwrap();
+        let mut response = vec![0; i32::from_be_bytes(len) as usize];
+        self.stream.read_exact(&mut response).unwrap();
+        let correlation_id = Reader::new(&response).i32().unwrap();
+        (correlation_id, response.split_off(4))
+    }
+
+    fn request(&mut self, api_key: i16, version: i16, body: Writer) -> Vec<u8> {
+        self.send(api_key, version, body);
+        let (correlation_id, response) = self.receive();
+        assert_eq!(self.correlation_id, correlation_id);
+        response
+    }
+}
+
+fn record(offset: i64, value: &str) -> Record {
+    Record {
+        offset,
+        timestamp: 1_000 + offset,
+        key: Some(b"key".to_vec()),
+        value: Some(value.as_bytes().to_vec()),
+    }
+}
+
+fn produce(client: &mut Client, acks: i16, topic: &str, batch: &[u8]) -> Option<(i16, i64)> {
+    let mut w = Writer::new();
+    w.nullable_string(None).i16(acks).i32(1_000);
+    w.i32(1).string(topic).i32(1).i32(0).bytes(Some(batch));
+    client.send(PRODUCE, 8, w);
+    if acks == 0 {
+        return None;
+    }
+    let (_, response) = client.receive();
+    let mut r = Reader::new(&response);
+    assert_eq!(
+        (1, String::from(topic), 1, 0),
+        (
+            r.i32().unwrap(),
+            r.string().unwrap(),
+            r.i32().unwrap(),
+            r.i32().unwrap()
+        )
+    );
+    Some((r.i16().unwrap(), r.i64().unwrap()))
+}
+
+// Fetch a partition returning the error code, the high watermark, and the records.
+fn fetch(client: &mut Client, topic: &str, offset: i64, max_wait: i32) -> (i16, i64, Vec<Record>) {
+    let mut w = Writer::new();
+    w.i32(-1)
+        .i32(max_wait)
+        .i32(1)
+        .i32(1024 * 1024)
+        .i8(0)
+        .i32(0)
+        .i32(-1);
+    w.i32(1).string(topic).i32(1);
+    w.i32(0).i32(0).i64(offset).i64(-1).i32(1024 * 1024);
+    w.i32(0).string("");
+    let response = client.request(FETCH, 11, w);
+    let mut r = Reader::new(&response);
+    // The throttle time, error code, and session id
+    assert_eq!(
+        (0, 0, 0),
+        (r.i32().unwrap(), r.i16().unwrap(), r.i32().unwrap())
+    );
+    assert_eq!(
+        (1, String::from(topic), 1, 0),
+        (
+            r.i32().unwrap(),
+            r.string().unwrap(),
+            r.i32().unwrap(),
+            r.i32().unwrap()
+        )
+    );
+    let error = r.i16().unwrap();
+    let high_watermark = r.i64().unwrap();
+    assert_eq!(high_watermark, r.i64().unwrap());
+    r.i64().unwrap();
+    assert_eq!((-1, -1), (r.i32().unwrap(), r.i32().unwrap()));
+    let records = codec::read_batches(r.bytes().unwrap().unwrap()).unwrap();
+    (error, high_watermark, records)
+}
+
+fn list_offset(client: &mut Client, topic: &str, timestamp: i64) -> (i16, i64) {
+    let mut w = Writer::new();
+    w.i32(-1).i8(0);
+    w.i32(1).string(topic).i32(1).i32(0).i32(0).i64(timestamp);
+    let response = client.request(LIST_OFFSETS, 5, w);
+    let mut r = Reader::new(&response);
+    assert_eq!(0, r.i32().unwrap());
+    assert_eq!(
+        (1, String::from(topic), 1, 0),
+        (
+            r.i32().unwrap(),
+            r.string().unwrap(),
+            r.i32().unwrap(),
+            r.i32().unwrap()
+        )
+    );
+    let error = r.i16().unwrap();
+    r.i64().unwrap();
+    let offset = r.i64().unwrap();
+    assert_eq!(0, r.i32().unwrap());
+    (error, offset)
+}
+
+fn commit(client: &mut Client, topic: &str, partition: i32, offset: i64) -> i16 {
+    let mut w = Writer::new();
+    w.string("group").i32(-1).string("").nullable_string(None);
+    w.i32(1).string(topic).i32(1);
+    w.i32(partition).i64(offset).i32(-1).nullable_string(None);
+    let response = client.request(OFFSET_COMMIT, 7, w);
+    let mut r = Reader::new(&response);
+    assert_eq!(0, r.i32().unwrap());
+    assert_eq!(
+        (1, String::from(topic), 1, partition),
+        (
+            r.i32().unwrap(),
+            r.string().unwrap(),
+            r.i32().unwrap(),
+            r.i32().unwrap()
+        )
+    );
+    r.i16().unwrap()
+}
+
+// Fetch the committed offsets of `group`, of every topic if `topic` is None.
+fn committed(client: &mut Client, group: &str, topic: Option<&str>) -> Vec<(String, i32, i64)> {
+    let mut w = Writer::new();
+    w.string(group);
+    match topic {
+        Some(topic) => {
+            w.i32(1).string(topic).array(&[0, 1], |w, p| {
+                w.i32(*p);
+            });
+        }
+        None => {
+            w.i32(-1);
+        }
+    }
+    let response = client.request(OFFSET_FETCH, 5, w);
+    let mut r = Reader::new(&response);
+    assert_eq!(0, r.i32().unwrap());
+    let topics = r
+        .array(|r| {
+            let name = r.string()?;
+            let partitions = r
+                .array(|r| {
+                    let partition = r.i32()?;
+                    let offset = r.i64()?;
+                    assert_eq!(-1, r.i32()?);
+                    r.nullable_string()?;
+                    assert_eq!(0, r.i16()?);
+                    Ok((partition, offset))
+                })?
+                .unwrap();
+            Ok((name, partitions))
+        })
+        .unwrap()
+        .unwrap();
+    assert_eq!(0, r.i16().unwrap());
+    topics
+        .into_iter()
+        .flat_map(|(name, partitions)| {
+            partitions
+                .into_iter()
+                .map(move |(partition, offset)| (name.clone(), partition, offset))
+        })
+        .collect()
+}
+
+#[test]
+fn kafka_codec() {
+    assert_eq!(0xe306_9283, codec::crc32c(b"123456789"));
+
+    let mut w = Writer::new();
+    w.i8(-1)
+        .i16(-2)
+        .i32(-3)
+        .i64(-4)
+        .bool(true)
+        .string("a")
+        .nullable_string(None)
+        .bytes(Some(b"b"))
+        .bytes(None)
+        .array(&[1, 2], |w, i| {
+            w.i32(*i);
+        })
+        .i32(-1);
+    for value in &[0, -1, 1, 63, -64, 64, i64::MAX, i64::MIN] {
+        w.varlong(*value);
+    }
+    let buf = w.into_inner();
+    let mut r = Reader::new(&buf);
+    assert_eq!(-1, r.i8().unwrap());
+    assert_eq!(-2, r.i16().unwrap());
+    assert_eq!(-3, r.i32().unwrap());
+    assert_eq!(-4, r.i64().unwrap());
+    assert!(r.bool().unwrap());
+    assert_eq!("a", r.string().unwrap());
+    assert_eq!(None, r.nullable_string().unwrap());
+    assert_eq!(Some(&b"b"[..]), r.bytes().unwrap());
+    assert_eq!(None, r.bytes().unwrap());
+    assert_eq!(Some(vec![1, 2]), r.array(Reader::i32).unwrap());
+    assert_eq!(None, r.array(Reader::i32).unwrap());
+    for value in &[0, -1, 1, 63, -64, 64, i64::MAX, i64::MIN] {
+        assert_eq!(*value, r.varlong().unwrap());
+    }
+    assert!(r.remaining().is_empty());
+    assert!(r.i8().is_err());
+    assert!(Reader::new(&[0, 2, b'a']).string().is_err());
+    assert!(Reader::new(&[0x7f, 0xff, 0xff, 0xff])
+        .array(Reader::i8)
+        .is_err());
+
+    // Record batches
+    let records = vec![record(5, "a"), record(6, "b")];
+    let mut batches = codec::write_batch(&records);
+    batches.extend(codec::write_batch(&[record(7, "c")]));
+    let mut read = codec::read_batches(&batches).unwrap();
+    assert_eq!(3, read.len());
+    assert_eq!(record(7, "c"), read.pop().unwrap());
+    assert_eq!(records, read);
+
+    let mut corrupt = codec::write_batch(&records);
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 1;
+    assert_eq!(
+        Err(RecordsError::Corrupt("record batch fails its CRC")),
+        codec::read_batches(&corrupt)
+    );
+    assert!(codec::read_batches(&corrupt[..20]).is_err());
+    // Set the compression to gzip and fix the CRC
+    let mut compressed = codec::write_batch(&records);
+    compressed[22] = 1;
+    let crc = codec::crc32c(&compressed[21..]);
+    compressed[17..21].copy_from_slice(&crc.to_be_bytes());
+    assert_eq!(
+        Err(RecordsError::Compressed),
+        codec::read_batches(&compressed)
+    );
+}
+
+#[test]
+fn kafka_protocol() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+    let mut client = Client::connect(port);
+
+    // A newer ApiVersions gets the supported versions in the oldest format
+    let response = client.request(API_VERSIONS, 3, Writer::new());
+    let mut r = Reader::new(&response);
+    assert_eq!(UNSUPPORTED_VERSION, r.i16().unwrap());
+    let apis = r
+        .array(|r| Ok((r.i16()?, r.i16()?, r.i16()?)))
+        .unwrap()
+        .unwrap();
+    assert_eq!(APIS.to_vec(), apis);
+    assert!(r.remaining().is_empty());
+    let response = client.request(API_VERSIONS, 2, Writer::new());
+    assert_eq!(&response[response.len() - 4..], &[0, 0, 0, 0]);
+
+    // Metadata creates topics and lists the broker
+    let mut w = Writer::new();
+    w.i32(1).string("temps").bool(true).bool(false).bool(false);
+    let response = client.request(METADATA, 8, w);
+    let mut r = Reader::new(&response);
+    assert_eq!(0, r.i32().unwrap());
+    let brokers = r
+        .array(|r| Ok((r.i32()?, r.string()?, r.i32()?, r.nullable_string()?)))
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        vec![(0, String::from("127.0.0.1"), i32::from(port), None)],
+        brokers
+    );
+    assert_eq!(Some(String::from(CLUSTER_ID)), r.nullable_string().unwrap());
+    assert_eq!(0, r.i32().unwrap());
+    assert_eq!(1, r.i32().unwrap());
+    assert_eq!(
+        (0, String::from("temps"), false),
+        (r.i16().unwrap(), r.string().unwrap(), r.bool().unwrap())
+    );
+    assert_eq!(1, r.i32().unwrap());
+    assert!(registry.get_topic(DEFAULT_NAMESPACE, "temps").is_ok());
+    let mut w = Writer::new();
+    w.i32(1).string("other").bool(false).bool(false).bool(false);
+    let response = client.request(METADATA, 8, w);
+    let mut r = Reader::new(&response[4..]);
+    r.array(|r| Ok((r.i32()?, r.string()?, r.i32()?, r.nullable_string()?)))
+        .unwrap();
+    r.nullable_string().unwrap();
+    r.i32().unwrap();
+    assert_eq!(1, r.i32().unwrap());
+    assert_eq!(UNKNOWN_TOPIC_OR_PARTITION, r.i16().unwrap());
+
+    // Records are published to the partition
+    let batch = codec::write_batch(&[record(0, "21"), record(1, "22")]);
+    assert_eq!(
+        Some((NO_ERROR, 0)),
+        produce(&mut client, 1, "temps", &batch)
+    );
+    assert_eq!(
+        Some((UNKNOWN_TOPIC_OR_PARTITION, -1)),
+        produce(&mut client, 1, "other", &batch)
+    );
+    let invalid = codec::write_batch(&[Record {
+        value: Some(vec![0xff]),
+        ..record(0, "")
+    }]);
+    assert_eq!(
+        Some((CORRUPT_MESSAGE, -1)),
+        produce(&mut client, -1, "temps", &invalid)
+    );
+    let invalid = codec::write_batch(&[Record {
+        key: Some(vec![0xff]),
+        ..record(0, "23")
+    }]);
+    assert_eq!(
+        Some((CORRUPT_MESSAGE, -1)),
+        produce(&mut client, -1, "temps", &invalid)
+    );
+    let (messages, _) = registry
+        .read(DEFAULT_NAMESPACE, "temps", 0, None, 10)
+        .unwrap();
+    let data: Vec<_> = messages.iter().map(|m| m.data.to_string()).collect();
+    assert_eq!(vec!["21", "22"], data);
+    assert_eq!(Some("key"), messages[0].partition_key.as_deref());
+
+    // Messages are fetched as records
+    let (error, high_watermark, records) = fetch(&mut client, "temps", 1, 0);
+    assert_eq!((NO_ERROR, 2), (error, high_watermark));
+    assert_eq!(1, records.len());
+    assert_eq!(
+        (1, Some(b"key".to_vec()), Some(b"22".to_vec())),
+        (
+            records[0].offset,
+            records[0].key.clone(),
+            records[0].value.clone()
+        )
+    );
+    assert_eq!(messages[1].time.timestamp_millis(), records[0].timestamp);
+    let (error, _, records) = fetch(&mut client, "temps", 5, 0);
+    assert_eq!(OFFSET_OUT_OF_RANGE, error);
+    assert!(records.is_empty());
+
+    // A fetch waits for new messages, a produce without acks has no response
+    let started = time::Instant::now();
+    let (error, _, records) = fetch(&mut client, "temps", 2, 300);
+    assert_eq!(NO_ERROR, error);
+    assert!(records.is_empty());
+    assert!(started.elapsed() >= time::Duration::from_millis(300));
+    let publisher = thread::spawn(move || {
+        thread::sleep(POLL_INTERVAL * 2);
+        let mut client = Client::connect(port);
+        let batch = codec::write_batch(&[record(0, "23")]);
+        assert_eq!(None, produce(&mut client, 0, "temps", &batch));
+        client.request(API_VERSIONS, 0, Writer::new());
+    });
+    let (_, high_watermark, records) = fetch(&mut client, "temps", 2, 5_000);
+    publisher.join().unwrap();
+    assert_eq!(3, high_watermark);
+    assert_eq!(Some(b"23".to_vec()), records[0].value);
+
+    // Offsets are listed by timestamp
+    assert_eq!(
+        (NO_ERROR, 3),
+        list_offset(&mut client, "temps", LATEST_TIMESTAMP)
+    );
+    assert_eq!(
+        (NO_ERROR, 0),
+        list_offset(&mut client, "temps", EARLIEST_TIMESTAMP)
+    );
+    // Messages published within the same millisecond share a timestamp
+    let time = messages[1].time.timestamp_millis();
+    let offset = messages
+        .iter()
+        .position(|m| m.time.timestamp_millis() >= time)
+        .unwrap();
+    assert_eq!(
+        (NO_ERROR, offset as i64),
+        list_offset(&mut client, "temps", time)
+    );
+    assert_eq!(
+        (NO_ERROR, -1),
+        list_offset(&mut client, "temps", time + 3_600_000)
+    );
+
+    // The broker coordinates every group
+    let mut w = Writer::new();
+    w.string("group").i8(0);
+    let response = client.request(FIND_COORDINATOR, 2, w);
+    let mut r = Reader::new(&response);
+    assert_eq!(
+        (0, NO_ERROR, None),
+        (
+            r.i32().unwrap(),
+            r.i16().unwrap(),
+            r.nullable_string().unwrap()
+        )
+    );
+    assert_eq!(
+        (0, String::from("127.0.0.1"), i32::from(port)),
+        (r.i32().unwrap(), r.string().unwrap(), r.i32().unwrap())
+    );
+
+    // Committed offsets are the cursors of the subscription of the group
+    assert_eq!(
+        vec![
+            (String::from("temps"), 0, -1),
+            (String::from("temps"), 1, -1)
+        ],
+        committed(&mut client, "group", Some("temps"))
+    );
+    assert_eq!(NO_ERROR, commit(&mut client, "temps", 0, 2));
+    assert_eq!(
+        UNKNOWN_TOPIC_OR_PARTITION,
+        commit(&mut client, "temps", 1, 0)
+    );
+    assert_eq!(
+        UNKNOWN_TOPIC_OR_PARTITION,
+        commit(&mut client, "other", 0, 0)
+    );
+    let subscription = registry
+        .get_subscription(DEFAULT_NAMESPACE, "kafka/group/temps")
+        .unwrap();
+    assert_eq!("group", subscription.labels[GROUP_LABEL]);
+    assert_eq!(
+        vec![2],
+        registry
+            .subscription_offsets(DEFAULT_NAMESPACE, "kafka/group/temps")
+            .unwrap()
+    );
+    assert_eq!(
+        vec![
+            (String::from("temps"), 0, 2),
+            (String::from("temps"), 1, -1)
+        ],
+        committed(&mut client, "group", Some("temps"))
+    );
+    assert_eq!(
+        vec![(String::from("temps"), 0, 2)],
+        committed(&mut client, "group", None)
+    );
+    assert!(committed(&mut client, "others", None).is_empty());
+
+    // Idempotent producers get an id
+    let mut w = Writer::new();
+    w.nullable_string(None).i32(60_000);
+    let response = client.request(INIT_PRODUCER_ID, 1, w);
+    let mut r = Reader::new(&response);
+    assert_eq!(
+        (0, NO_ERROR, 0, 0),
+        (
+            r.i32().unwrap(),
+            r.i16().unwrap(),
+            r.i64().unwrap(),
+            r.i16().unwrap()
+        )
+    );
+
+    // An unsupported version closes the connection
+    client.send(FETCH, 3, Writer::new());
+    let mut buf = [0; 1];
+    assert_eq!(0, client.stream.read(&mut buf).unwrap());
+}
@@ -0,0 +1,363 @@
+//! Bridges mirroring the messages of a local subscription to a topic of a remote Courier.
+//!
+//! A bridge pulls batches of messages from a subscription of the local
+//! [Registry](../struct.Registry.html) and publishes them to a topic of another Courier instance
+//! through its HTTP API. A batch is only acked once the remote publish succeeded, after a failed
+//! publish the batch is nacked and pulled again once the retry interval passed so no message is
+//! lost while the remote is unreachable.
+//! Messages may be published to the remote more than once if the local instance stops between
+//! publishing and acking.
+//!
+//! Every bridged message carries the name of the instance it was first published to as its
+//! `origin`. A bridge does not forward messages which originated at the remote instance or at the
+//! local instance, so bridges in both directions or in a cycle do not loop messages back.
+
+#[cfg(test)]
+mod tests;
+
+use chrono::prelude::*;
+use courier_client::Client;
+use log::{info, warn};
+use parking_lot::{Condvar, Mutex};
+use serde_derive::{Deserialize, Serialize};
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time;
+
+use crate::{Message, Registry, SharedRegistry, DEFAULT_NAMESPACE};
+
+/// The consumer id a bridge pulls from its subscription with.
+const CONSUMER: &str = "bridge";
+
+/// The configuration of a bridge.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BridgeConfig {
+    /// Namespace of the local subscription.
+    pub namespace: String,
+    /// Name of the local subscription the messages are pulled from.
+    pub subscription: String,
+    /// Base url of the remote Courier.
+    pub url: String,
+    /// Namespace of the remote topic.
+    pub remote_namespace: String,
+    /// Name of the remote topic the messages are published to.
+    pub remote_topic: String,
+    /// The maximum number of messages forwarded at once.
+    pub max_messages: usize,
+    /// How long to wait for new messages when the subscription has none.
+    pub poll_interval: time::Duration,
+    /// How long to wait before retrying a failed pull or publish.
+    pub retry_interval: time::Duration,
+}
+
+impl BridgeConfig {
+    /// Create a new configuration with the default batch size and intervals.
+    pub fn new(
+        namespace: &str,
+        subscription: &str,
+        url: &str,
+        remote_namespace: &str,
+        remote_topic: &str,
+    ) -> Self {
+        BridgeConfig {
+            namespace: String::from(namespace),
+            subscription: String::from(subscription),
+            url: String::from(url),
+            remote_namespace: String::from(remote_namespace),
+            remote_topic: String::from(remote_topic),
+            max_messages: 100,
+            poll_interval: time::Duration::from_millis(100),
+            retry_interval: time::Duration::from_secs(1),
+        }
+    }
+}
+
+// Split `<namespace>/<name>` into its parts, a name without a namespace is in the default
+// namespace.
+fn qualified(name: &str) -> Option<(&str, &str)> {
+    let (namespace, name) = match name.find('/') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (DEFAULT_NAMESPACE, name),
+    };
+    if namespace.is_empty() || name.is_empty() {
+        None
+    } else {
+        Some((namespace, name))
+    }
+}
+
+impl FromStr for BridgeConfig {
+    type Err = String;
+
+    /// Parse `[<namespace>/]<subscription>=[<namespace>/]<topic>@<url>`.
+    fn from_str(bridge: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Expected '[<namespace>/]<subscription>=[<namespace>/]<topic>@<url>' but got '{}'",
+                bridge
+            )
+        };
+        let mut parts = bridge.splitn(2, '=');
+        let (local, remote) = match (parts.next(), parts.next()) {
+            (Some(local), Some(remote)) => (local, remote),
+            _ => return Err(invalid()),
+        };
+        let mut parts = remote.splitn(2, '@');
+        let (topic, url) = match (parts.next(), parts.next()) {
+            (Some(topic), Some(url)) if !url.is_empty() => (topic, url),
+            _ => return Err(invalid()),
+        };
+        match (qualified(local), qualified(topic)) {
+            (Some((namespace, subscription)), Some((remote_namespace, remote_topic))) => Ok(
+                BridgeConfig::new(namespace, subscription, url, remote_namespace, remote_topic),
+            ),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Metrics on a bridge.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BridgeMetrics {
+    /// Namespace of the local subscription.
+    pub namespace: String,
+    /// Name of the local subscription.
+    pub subscription: String,
+    /// Base url of the remote Courier.
+    pub url: String,
+    /// Namespace of the remote topic.
+    pub remote_namespace: String,
+    /// Name of the remote topic.
+    pub remote_topic: String,
+    /// Name of the remote instance once it is known.
+    pub remote_origin: Option<String>,
+    /// Number of messages published to the remote topic all time.
+    pub forwarded_all_time: u64,
+    /// Number of messages not forwarded because they originated at the remote or local instance
+    /// all time.
+    pub skipped_all_time: u64,
+    /// Number of failed pulls, publishes, and acks all time.
+    pub failures_all_time: u64,
+    /// Number of pulled messages waiting to be forwarded.
+    pub in_flight: usize,
+    /// Number of messages of the subscription which were not pulled yet.
+    pub backlog: usize,
+    /// Milliseconds since the oldest message waiting to be forwarded or not pulled yet was
+    /// published, 0 if no message is waiting.
+    pub lag: i64,
+    /// When a message was last forwarded.
+    pub last_forwarded: Option<DateTime<Utc>>,
+    /// The error of the last attempt, None once an attempt succeeds.
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    remote_origin: Option<String>,
+    forwarded_all_time: u64,
+    skipped_all_time: u64,
+    failures_all_time: u64,
+    in_flight: usize,
+    oldest: Option<DateTime<Utc>>,
+    last_forwarded: Option<DateTime<Utc>>,
+    error: Option<String>,
+}
+
+/// A bridge forwarding the messages of a local subscription to a remote topic.
+pub struct Bridge {
+    config: BridgeConfig,
+    origin: String,
+    registry: Weak<Registry>,
+    state: Mutex<State>,
+    // Notified when the bridge is stopped.
+    wake: Condvar,
+    stopped: AtomicBool,
+}
+
+impl Bridge {
+    /// Start a bridge of `registry` in the background.
+    ///
+    /// `origin` is the name of the local instance, it is attached to the forwarded messages which
+    /// were published to the local instance.
+    pub fn start(
+        config: BridgeConfig,
+        origin: &str,
+        registry: &SharedRegistry,
+    ) -> Result<Arc<Self>, Box<dyn Error>> {
+        let client = Client::new(&config.url)?.with_namespace(&config.remote_namespace);
+        let bridge = Arc::new(Bridge {
+            config,
+            origin: String::from(origin),
+            registry: Arc::downgrade(registry),
+            state: Mutex::new(State::default()),
+            wake: Condvar::new(),
+            stopped: AtomicBool::new(false),
+        });
+        info!(
+            "Bridging '{}/{}' to '{}/{}' at '{}'.",
+            bridge.config.namespace,
+            bridge.config.subscription,
+            bridge.config.remote_namespace,
+            bridge.config.remote_topic,
+            bridge.config.url
+        );
+        let b = Arc::clone(&bridge);
+        thread::spawn(move || b.run(&client));
+        Ok(bridge)
+    }
+
+    /// Stop the bridge, messages pulled but not yet forwarded are resent to the subscription once
+    /// their ack deadline is reached.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let _state = self.state.lock();
+        self.wake.notify_all();
+    }
+
+    fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Get a snapshot of the metrics of the bridge.
+    pub fn metrics(&self) -> BridgeMetrics {
+        let (backlog, oldest_unpulled) = self
+            .registry
+            .upgrade()
+            .and_then(|registry| {
+                registry
+                    .subscription_backlog(&self.config.namespace, &self.config.subscription)
+                    .ok()
+            })
+            .unwrap_or((0, None));
+        let state = self.state.lock();
+        let oldest = match (state.oldest, oldest_unpulled) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        BridgeMetrics {
+            namespace: self.config.namespace.clone(),
+            subscription: self.config.subscription.clone(),
+            url: self.config.url.clone(),
+            remote_namespace: self.config.remote_namespace.clone(),
+            remote_topic: self.config.remote_topic.clone(),
+            remote_origin: state.remote_origin.clone(),
+            forwarded_all_time: state.forwarded_all_time,
+            skipped_all_time: state.skipped_all_time,
+            failures_all_time: state.failures_all_time,
+            in_flight: state.in_flight,
+            backlog,
+            lag: oldest.map_or(0, |oldest| (Utc::now() - oldest).num_milliseconds().max(0)),
+            last_forwarded: state.last_forwarded,
+            error: state.error.clone(),
+        }
+    }
+
+    fn run(&self, client: &Client) {
+        while !self.stopped() {
+            let wait = match self.registry.upgrade() {
+                Some(registry) => self.step(&registry, client),
+                None => return,
+            };
+            if let Some(wait) = wait {
+                let mut state = self.state.lock();
+                if !self.stopped() {
+                    self.wake.wait_for(&mut state, wait);
+                }
+            }
+        }
+    }
+
+    // Pull a batch and forward it. Returns how long to wait before the next step.
+    fn step(&self, registry: &Registry, client: &Client) -> Option<time::Duration> {
+        let config = &self.config;
+        if self.state.lock().remote_origin.is_none() {
+            // Forward without knowing the remote's name until it can be reached
+            if let Ok(origin) = client.get_origin() {
+                self.state.lock().remote_origin = Some(origin.origin);
+            }
+        }
+
+        let batch = match registry.pull(
+            &config.namespace,
+            &config.subscription,
+            config.max_messages,
+            None,
+            Some(CONSUMER),
+        ) {
+            Ok(batch) => batch,
+            Err(error) => {
+                self.failed(error.to_string());
+                return Some(config.retry_interval);
+            }
+        };
+        {
+            let mut state = self.state.lock();
+            state.in_flight = batch.len();
+            state.oldest = batch.iter().map(|m| m.time).min();
+        }
+        if batch.is_empty() {
+            return Some(config.poll_interval);
+        }
+        let ids: Vec<_> = batch.iter().map(|m| m.id).collect();
+
+        let remote_origin = self.state.lock().remote_origin.clone();
+        let (skipped, forwarded): (Vec<&Message>, Vec<&Message>) =
+            batch.iter().partition(|m| match m.origin.as_deref() {
+                Some(origin) => origin == self.origin || Some(origin) == remote_origin.as_deref(),
+                None => false,
+            });
+        if !forwarded.is_empty() {
+            let raw_messages = forwarded
+                .iter()
+                .map(|m| courier_client::RawMessage {
+                    data: m.data.to_string(),
+                    partition_key: None,
+                    partition: None,
+                    origin: Some(m.origin.as_deref().unwrap_or(&self.origin).to_string()),
+                })
+                .collect();
+            if let Err(error) = client.publish_raw(&config.remote_topic, raw_messages) {
+                self.failed(error.to_string());
+                // Messages which could not be nacked are resent once their ack deadline is reached
+                let _ = registry.nack(&config.namespace, &config.subscription, &ids);
+                return Some(config.retry_interval);
+            }
+        }
+        let (skipped, forwarded) = (skipped.len() as u64, forwarded.len() as u64);
+
+        let acked = registry.ack(&config.namespace, &config.subscription, &ids);
+        let mut state = self.state.lock();
+        state.forwarded_all_time += forwarded;
+        state.skipped_all_time += skipped;
+        state.in_flight = 0;
+        state.oldest = None;
+        if forwarded > 0 {
+            state.last_forwarded = Some(Utc::now());
+        }
+        match acked {
+            Ok(_) => state.error = None,
+            // The messages were forwarded but are resent once their ack deadline is reached
+            Err(error) => {
+                drop(state);
+                self.failed(error.to_string());
+            }
+        }
+        None
+    }
+
+    // Record a failed attempt, the error is logged when it differs from the previous one.
+    fn failed(&self, error: String) {
+        let mut state = self.state.lock();
+        state.failures_all_time += 1;
+        if state.error.as_ref() != Some(&error) {
+            warn!(
+                "Bridge of '{}/{}' failed: {}",
+                self.config.namespace, self.config.subscription, error
+            );
+            state.error = Some(error);
+        }
+    }
+}
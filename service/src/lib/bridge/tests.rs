@@ -0,0 +1,37 @@
+use super::*;
+
+#[test]
+fn bridge_config_parse() {
+    let config: BridgeConfig = "sub=topic@http://central:3140".parse().unwrap();
+    assert_eq!(
+        config,
+        BridgeConfig::new(
+            DEFAULT_NAMESPACE,
+            "sub",
+            "http://central:3140",
+            DEFAULT_NAMESPACE,
+            "topic"
+        )
+    );
+
+    let config: BridgeConfig = "site/sub=central/topic@https://central:3140/"
+        .parse()
+        .unwrap();
+    assert_eq!(config.namespace, "site");
+    assert_eq!(config.subscription, "sub");
+    assert_eq!(config.url, "https://central:3140/");
+    assert_eq!(config.remote_namespace, "central");
+    assert_eq!(config.remote_topic, "topic");
+
+    for invalid in &[
+        "sub",
+        "sub=topic",
+        "sub=topic@",
+        "=topic@http://central:3140",
+        "sub=@http://central:3140",
+        "site/=topic@http://central:3140",
+        "/sub=topic@http://central:3140",
+    ] {
+        assert!(invalid.parse::<BridgeConfig>().is_err(), "{}", invalid);
+    }
+}
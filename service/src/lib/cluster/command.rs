@@ -0,0 +1,239 @@
+//! The mutations of a [Registry](../struct.Registry.html) which are replicated through the log.
+
+use chrono::prelude::*;
+use chrono::Duration;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{Error, Message, NamespaceMeta, RawMessage, Result, SubscriptionMeta, TopicMeta};
+
+/// A mutation of a [Registry](../struct.Registry.html).
+///
+/// Every node of a cluster applies the same commands in the same order, at the time the leader
+/// appended them to the log. Values which would otherwise be generated while applying a command,
+/// like the ids of published messages, are part of the command so every node ends up with the
+/// same state.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    CreateNamespace {
+        namespace: String,
+        #[serde(with = "crate::millis::option")]
+        default_message_ttl: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        default_topic_ttl: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        default_subscription_ttl: Option<Duration>,
+        shared_with: Vec<String>,
+    },
+    UpdateNamespace {
+        namespace: String,
+        #[serde(with = "crate::millis::option")]
+        default_message_ttl: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        default_topic_ttl: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        default_subscription_ttl: Option<Duration>,
+        shared_with: Option<Vec<String>>,
+    },
+    DeleteNamespace {
+        namespace: String,
+    },
+    CreateTopic {
+        namespace: String,
+        topic: String,
+        labels: HashMap<String, String>,
+        description: String,
+        #[serde(with = "crate::millis")]
+        message_ttl: Duration,
+        #[serde(with = "crate::millis")]
+        ttl: Duration,
+        partitions: u32,
+    },
+    UpdateTopic {
+        namespace: String,
+        topic: String,
+        labels: Option<HashMap<String, String>>,
+        description: Option<String>,
+        #[serde(with = "crate::millis::option")]
+        message_ttl: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        ttl: Option<Duration>,
+    },
+    DeleteTopic {
+        namespace: String,
+        topic: String,
+    },
+    Publish {
+        namespace: String,
+        topic: String,
+        messages: Vec<RawMessage>,
+        /// The id of each message.
+        ids: Vec<Uuid>,
+    },
+    PurgeTopic {
+        namespace: String,
+        topic: String,
+        before: Option<DateTime<Utc>>,
+    },
+    CreateSubscription {
+        namespace: String,
+        subscription: String,
+        topic_namespace: String,
+        topic: String,
+        labels: HashMap<String, String>,
+        description: String,
+        #[serde(with = "crate::millis")]
+        ack_deadline: Duration,
+        #[serde(with = "crate::millis")]
+        ttl: Duration,
+        #[serde(with = "crate::millis")]
+        consumer_timeout: Duration,
+        historical: bool,
+    },
+    UpdateSubscription {
+        namespace: String,
+        subscription: String,
+        labels: Option<HashMap<String, String>>,
+        description: Option<String>,
+        #[serde(with = "crate::millis::option")]
+        ack_deadline: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        ttl: Option<Duration>,
+        #[serde(with = "crate::millis::option")]
+        consumer_timeout: Option<Duration>,
+    },
+    DeleteSubscription {
+        namespace: String,
+        subscription: String,
+    },
+    Pull {
+        namespace: String,
+        subscription: String,
+        max_messages: usize,
+        partitions: Option<Vec<u32>>,
+        consumer: Option<String>,
+    },
+    Ack {
+        namespace: String,
+        subscription: String,
+        ids: Vec<Uuid>,
+    },
+    Nack {
+        namespace: String,
+        subscription: String,
+        ids: Vec<Uuid>,
+    },
+    ReleaseConsumer {
+        namespace: String,
+        subscription: String,
+        consumer: String,
+    },
+    PurgeSubscription {
+        namespace: String,
+        subscription: String,
+    },
+    Seek {
+        namespace: String,
+        subscription: String,
+        partition: u32,
+        offset: u64,
+    },
+    /// Remove the topic if it reached its ttl.
+    ExpireTopic {
+        namespace: String,
+        topic: String,
+    },
+    /// Remove the subscription if it reached its ttl.
+    ExpireSubscription {
+        namespace: String,
+        subscription: String,
+    },
+    /// Cleanup the messages of the topic which reached their ttl.
+    ExpireMessages {
+        namespace: String,
+        topic: String,
+    },
+    /// Release the messages of the consumers of the subscription which reached their timeout.
+    ReleaseExpiredConsumers {
+        namespace: String,
+        subscription: String,
+    },
+}
+
+/// The result of applying a [Command](enum.Command.html).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Applied {
+    Namespace(NamespaceMeta),
+    Topic(TopicMeta),
+    Subscription(SubscriptionMeta),
+    Published(Vec<(Uuid, u32, u64)>),
+    Messages(Vec<Message>),
+    Acked(Vec<Uuid>),
+    Count(usize),
+    Done,
+}
+
+fn unexpected(applied: &Applied) -> Error {
+    Error::internal(format!("Unexpected result of a command '{:?}'", applied))
+}
+
+impl Applied {
+    pub fn into_namespace(self) -> Result<NamespaceMeta> {
+        match self {
+            Applied::Namespace(meta) => Ok(meta),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_topic(self) -> Result<TopicMeta> {
+        match self {
+            Applied::Topic(meta) => Ok(meta),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_subscription(self) -> Result<SubscriptionMeta> {
+        match self {
+            Applied::Subscription(meta) => Ok(meta),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_published(self) -> Result<Vec<(Uuid, u32, u64)>> {
+        match self {
+            Applied::Published(published) => Ok(published),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_messages(self) -> Result<Vec<Message>> {
+        match self {
+            Applied::Messages(messages) => Ok(messages),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_acked(self) -> Result<Vec<Uuid>> {
+        match self {
+            Applied::Acked(ids) => Ok(ids),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_count(self) -> Result<usize> {
+        match self {
+            Applied::Count(count) => Ok(count),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+
+    pub fn into_done(self) -> Result<()> {
+        match self {
+            Applied::Done => Ok(()),
+            applied => Err(unexpected(&applied)),
+        }
+    }
+}
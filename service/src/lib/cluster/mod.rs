@@ -0,0 +1,1010 @@
+//! Replication of a [Registry](../struct.Registry.html) across a cluster of nodes.
+//!
+//! The nodes of a cluster replicate every mutation of their registry through a log using the
+//! Raft consensus algorithm. One node is elected leader, it appends each
+//! [Command](enum.Command.html) to its log and replicates it to the other nodes. Once a majority
+//! of the nodes stored a command it is committed and every node applies it to its registry in
+//! log order. Commands submitted to a follower are forwarded to the leader.
+//!
+//! The leader timestamps every entry it appends and the commands are applied at that time, so
+//! the state of a node never depends on its own clock. The expiration of topics, subscriptions,
+//! messages, and consumers is scheduled by the leader which appends a command for each
+//! expiration once it is due.
+//!
+//! The term, vote, and log of a node are stored in its data directory. Once enough entries were
+//! applied a node writes a snapshot of its registry and removes the entries it includes from the
+//! log. A node which restarts restores its snapshot, replays the rest of its log, and then
+//! catches up with the leader. A node which is behind the entries the leader removed, for
+//! example because it joins or lost its data directory, receives the snapshot of the leader.
+//!
+//! Reads are served from the local registry of any node and may lag behind the leader.
+
+mod command;
+mod rpc;
+mod storage;
+#[cfg(test)]
+mod tests;
+
+pub use self::command::{Applied, Command};
+
+use chrono::prelude::*;
+use log::{debug, error, info, warn};
+use parking_lot::{Condvar, Mutex};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{self, Instant};
+
+use self::rpc::{Peer, Request, Response};
+use self::storage::{Entry, SnapshotMeta, Storage};
+use crate::{Error, Registry, Result, SharedRegistry};
+
+/// The maximum number of entries sent to a follower at once.
+const MAX_BATCH: usize = 256;
+
+/// How long a command may take to commit before the submitter gives up.
+const COMMIT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// How long a command waits for a leader to be elected.
+const LEADER_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// The maximum number of bytes of a snapshot sent to a follower at once.
+const SNAPSHOT_CHUNK: usize = 1 << 20;
+
+/// The configuration of a cluster node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterConfig {
+    /// The id of the node, unique within the cluster.
+    pub id: u64,
+    /// The address the node listens on for the other nodes.
+    pub listen: String,
+    /// The ids and addresses of the other nodes.
+    pub peers: Vec<(u64, String)>,
+    /// The directory the term, vote, and log of the node are stored in.
+    pub data_dir: PathBuf,
+    /// The interval the leader sends heartbeats at.
+    pub heartbeat_interval: time::Duration,
+    /// The minimum time without a heartbeat after which a follower starts an election, the
+    /// actual timeout is randomized between it and twice it.
+    pub election_timeout: time::Duration,
+    /// The number of entries applied since the last snapshot after which a new snapshot is
+    /// taken and the log is compacted, 0 never compacts the log.
+    pub snapshot_entries: u64,
+}
+
+impl ClusterConfig {
+    /// Create a new configuration with the default heartbeat interval, election timeout, and
+    /// snapshot entries.
+    pub fn new(id: u64, listen: &str, peers: Vec<(u64, String)>, data_dir: PathBuf) -> Self {
+        ClusterConfig {
+            id,
+            listen: String::from(listen),
+            peers,
+            data_dir,
+            heartbeat_interval: time::Duration::from_millis(50),
+            election_timeout: time::Duration::from_millis(300),
+            snapshot_entries: 10_000,
+        }
+    }
+}
+
+/// The role of a cluster node.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// The state of a cluster node.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ClusterStatus {
+    /// The id of the node.
+    pub id: u64,
+    /// The role of the node.
+    pub role: Role,
+    /// The current term.
+    pub term: u64,
+    /// The id of the current leader if known.
+    pub leader: Option<u64>,
+    /// The ids of the other nodes.
+    pub peers: Vec<u64>,
+    /// The index of the last entry of the log.
+    pub last_index: u64,
+    /// The index of the last committed entry.
+    pub commit_index: u64,
+    /// The index of the last entry applied to the registry.
+    pub last_applied: u64,
+    /// The index of the last entry included in the snapshot, 0 if there is none.
+    pub snapshot_index: u64,
+}
+
+type Waiter = (u64, mpsc::Sender<Result<Applied>>);
+
+struct State {
+    role: Role,
+    leader: Option<u64>,
+    storage: Storage,
+    commit_index: u64,
+    last_applied: u64,
+    election_deadline: Instant,
+    // Votes received as a candidate in the current term.
+    votes: HashSet<u64>,
+    // Leader state, the next index to send to and the last index replicated on each follower.
+    next_index: HashMap<u64, u64>,
+    match_index: HashMap<u64, u64>,
+    // Submitters waiting for the command at an index, with the term it was appended in.
+    waiters: HashMap<u64, Waiter>,
+    // The last entry included in the snapshot being received from the leader and the number of
+    // bytes received so far.
+    receiving: Option<(u64, u64)>,
+}
+
+/// A node of a cluster replicating a [Registry](../struct.Registry.html).
+pub struct Cluster {
+    config: ClusterConfig,
+    peers: Vec<Peer>,
+    registry: Weak<Registry>,
+    state: Mutex<State>,
+    // Notified when entries are appended and when the role changes.
+    changed: Condvar,
+    // Notified when entries are committed.
+    committed: Condvar,
+    // Notified when entries are applied.
+    applied: Condvar,
+    stopped: AtomicBool,
+}
+
+// A random duration between `min` and twice `min`.
+fn randomized(min: time::Duration) -> time::Duration {
+    min + min * rand::thread_rng().gen_range(0, 1000) / 1000
+}
+
+impl Cluster {
+    /// Start a node replicating `registry`.
+    ///
+    /// Restores the snapshot and replays the log stored in the data directory and starts the
+    /// threads of the node. Every mutation of the registry is replicated through the cluster from
+    /// then on.
+    pub fn start(config: ClusterConfig, registry: &SharedRegistry) -> io::Result<Arc<Self>> {
+        let storage = Storage::open(&config.data_dir)?;
+        let listener = TcpListener::bind(&config.listen)?;
+        info!(
+            "Starting cluster node '{}' on '{}' at term {} with {} log entries",
+            config.id,
+            config.listen,
+            storage.term(),
+            storage.last_index() - storage.snapshot_index()
+        );
+        let peers = config
+            .peers
+            .iter()
+            .map(|(id, address)| Peer::new(*id, address))
+            .collect();
+        let election_deadline = Instant::now() + randomized(config.election_timeout);
+        let cluster = Arc::new(Cluster {
+            config,
+            peers,
+            registry: Arc::downgrade(registry),
+            state: Mutex::new(State {
+                role: Role::Follower,
+                leader: None,
+                storage,
+                commit_index: 0,
+                last_applied: 0,
+                election_deadline,
+                votes: HashSet::new(),
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                waiters: HashMap::new(),
+                receiving: None,
+            }),
+            changed: Condvar::new(),
+            committed: Condvar::new(),
+            applied: Condvar::new(),
+            stopped: AtomicBool::new(false),
+        });
+        registry.set_cluster(Arc::clone(&cluster));
+        if cluster.state.lock().storage.snapshot().is_some() {
+            cluster.restore(registry)?;
+        }
+
+        let node = Arc::clone(&cluster);
+        thread::spawn(move || {
+            let handler_node = Arc::clone(&node);
+            rpc::serve(
+                listener,
+                move |request| handler_node.handle(request),
+                || node.stopped(),
+            )
+        });
+        let node = Arc::clone(&cluster);
+        thread::spawn(move || node.run_elections());
+        let node = Arc::clone(&cluster);
+        thread::spawn(move || node.run_applier());
+        for peer in 0..cluster.peers.len() {
+            let node = Arc::clone(&cluster);
+            thread::spawn(move || node.run_replication(peer));
+        }
+
+        Ok(cluster)
+    }
+
+    /// Stop the threads of the node.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.changed.notify_all();
+        self.committed.notify_all();
+        self.applied.notify_all();
+        // Wake the listener so it notices the node stopped
+        let _ = TcpStream::connect(&self.config.listen);
+    }
+
+    fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the node is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.state.lock().role == Role::Leader
+    }
+
+    /// Get the state of the node.
+    pub fn status(&self) -> ClusterStatus {
+        let state = self.state.lock();
+        ClusterStatus {
+            id: self.config.id,
+            role: state.role,
+            term: state.storage.term(),
+            leader: state.leader,
+            peers: self.peers.iter().map(|p| p.id).collect(),
+            last_index: state.storage.last_index(),
+            commit_index: state.commit_index,
+            last_applied: state.last_applied,
+            snapshot_index: state.storage.snapshot_index(),
+        }
+    }
+
+    /// Replicate a command and return the result of applying it.
+    ///
+    /// The command is appended to the log if the node is the leader, otherwise it is forwarded to
+    /// the leader. Waits for a leader to be elected if there is none.
+    pub fn replicate(&self, command: Command) -> Result<Applied> {
+        let started = Instant::now();
+        loop {
+            let leader = {
+                let state = self.state.lock();
+                if state.role == Role::Leader {
+                    drop(state);
+                    return self.propose(command);
+                }
+                state
+                    .leader
+                    .and_then(|id| self.peers.iter().find(|p| p.id == id))
+            };
+            if let Some(leader) = leader {
+                let request = Request::Forward {
+                    command: command.clone(),
+                };
+                match leader.call(&request, COMMIT_TIMEOUT) {
+                    Ok(Response::Forward { result, applied }) => {
+                        self.wait_applied(applied);
+                        return result;
+                    }
+                    Ok(response) => warn!("Unexpected forward response '{:?}'", response),
+                    Err(e) => debug!("Failed to forward to node '{}': {}", leader.id, e),
+                }
+            }
+            if started.elapsed() > LEADER_TIMEOUT || self.stopped() {
+                return Err(Error::unavailable(String::from(
+                    "The cluster has no leader",
+                )));
+            }
+            thread::sleep(self.config.heartbeat_interval);
+        }
+    }
+
+    // Wait until the entries up to `index` are applied, gives up after the commit timeout.
+    fn wait_applied(&self, index: u64) {
+        let deadline = Instant::now() + COMMIT_TIMEOUT;
+        let mut state = self.state.lock();
+        while state.last_applied < index && !self.stopped() {
+            if self.applied.wait_until(&mut state, deadline).timed_out() {
+                break;
+            }
+        }
+    }
+
+    // Append a command to the log of the leader and wait until it is applied.
+    fn propose(&self, command: Command) -> Result<Applied> {
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut state = self.state.lock();
+            if state.role != Role::Leader {
+                return Err(Error::unavailable(String::from(
+                    "The node is no longer the leader",
+                )));
+            }
+            let term = state.storage.term();
+            let index = self
+                .append_command(&mut state, Some(command))
+                .map_err(|e| Error::internal(format!("Failed to append to the log: {}", e)))?;
+            state.waiters.insert(index, (term, tx));
+            self.advance_commit(&mut state);
+        }
+        self.changed.notify_all();
+        rx.recv_timeout(COMMIT_TIMEOUT).unwrap_or_else(|_| {
+            Err(Error::unavailable(String::from(
+                "The command was not committed in time",
+            )))
+        })
+    }
+
+    // Append an entry to the log of the leader. Its time never goes back even if the clock of
+    // the leader does or the clock of a previous leader was ahead.
+    fn append_command(&self, state: &mut State, command: Option<Command>) -> io::Result<u64> {
+        let now = Utc::now();
+        let entry = Entry {
+            index: state.storage.last_index() + 1,
+            term: state.storage.term(),
+            time: state.storage.last_time().map_or(now, |last| last.max(now)),
+            command,
+        };
+        self.append(state, vec![entry])
+    }
+
+    fn append(&self, state: &mut State, entries: Vec<Entry>) -> io::Result<u64> {
+        let result = state.storage.append(entries);
+        if let Err(ref e) = result {
+            error!("Failed to append to the log: {}", e);
+        }
+        result
+    }
+
+    fn set_term(&self, state: &mut State, term: u64, voted_for: Option<u64>) {
+        if let Err(e) = state.storage.set_term(term, voted_for) {
+            // Without its vote persisted the node could vote twice in a term after a restart
+            error!("Failed to store term {}: {}", term, e);
+        }
+    }
+
+    fn reset_election_deadline(&self, state: &mut State) {
+        state.election_deadline = Instant::now() + randomized(self.config.election_timeout);
+    }
+
+    // Become a follower of a newer term.
+    fn step_down(&self, state: &mut State, term: u64) {
+        if term > state.storage.term() {
+            self.set_term(state, term, None);
+            state.leader = None;
+        }
+        if state.role != Role::Follower {
+            info!(
+                "Cluster node '{}' is a follower in term {}",
+                self.config.id, term
+            );
+            state.role = Role::Follower;
+            self.reset_election_deadline(state);
+            self.changed.notify_all();
+        }
+    }
+
+    // The number of nodes which form a majority.
+    fn quorum(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    // Start elections while there is no leader.
+    fn run_elections(self: Arc<Self>) {
+        while !self.stopped() {
+            thread::sleep(self.config.heartbeat_interval / 5);
+            let request = {
+                let mut state = self.state.lock();
+                if state.role == Role::Leader || Instant::now() < state.election_deadline {
+                    continue;
+                }
+                let term = state.storage.term() + 1;
+                info!(
+                    "Cluster node '{}' starts an election in term {}",
+                    self.config.id, term
+                );
+                self.set_term(&mut state, term, Some(self.config.id));
+                state.role = Role::Candidate;
+                state.leader = None;
+                state.votes = HashSet::new();
+                state.votes.insert(self.config.id);
+                self.reset_election_deadline(&mut state);
+                if state.votes.len() >= self.quorum() {
+                    self.become_leader(&mut state);
+                    continue;
+                }
+                Arc::new(Request::Vote {
+                    term,
+                    candidate: self.config.id,
+                    last_log_index: state.storage.last_index(),
+                    last_log_term: state.storage.last_term(),
+                })
+            };
+            for peer in 0..self.peers.len() {
+                let node = Arc::clone(&self);
+                let request = Arc::clone(&request);
+                thread::spawn(move || node.request_vote(peer, &request));
+            }
+        }
+    }
+
+    fn request_vote(&self, peer: usize, request: &Request) {
+        let term = match request {
+            Request::Vote { term, .. } => *term,
+            _ => return,
+        };
+        let peer = &self.peers[peer];
+        let response = match peer.call(request, self.config.election_timeout / 2) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Failed to request a vote of node '{}': {}", peer.id, e);
+                return;
+            }
+        };
+        let mut state = self.state.lock();
+        if let Response::Vote {
+            term: peer_term,
+            granted,
+        } = response
+        {
+            if peer_term > state.storage.term() {
+                self.step_down(&mut state, peer_term);
+            } else if granted && state.role == Role::Candidate && state.storage.term() == term {
+                state.votes.insert(peer.id);
+                if state.votes.len() >= self.quorum() {
+                    self.become_leader(&mut state);
+                }
+            }
+        }
+    }
+
+    fn become_leader(&self, state: &mut State) {
+        let term = state.storage.term();
+        info!(
+            "Cluster node '{}' is the leader in term {}",
+            self.config.id, term
+        );
+        state.role = Role::Leader;
+        state.leader = Some(self.config.id);
+        let next_index = state.storage.last_index() + 1;
+        for peer in &self.peers {
+            state.next_index.insert(peer.id, next_index);
+            state.match_index.insert(peer.id, 0);
+        }
+        // Entries of previous terms are only committed along with an entry of the current term
+        let _ = self.append_command(state, None);
+        self.advance_commit(state);
+        self.changed.notify_all();
+    }
+
+    // Commit the entries of the current term stored by a majority of the nodes.
+    fn advance_commit(&self, state: &mut State) {
+        let mut indexes: Vec<u64> = self
+            .peers
+            .iter()
+            .map(|p| state.match_index.get(&p.id).cloned().unwrap_or(0))
+            .collect();
+        indexes.push(state.storage.last_index());
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+        let index = indexes[self.quorum() - 1];
+        if index > state.commit_index && state.storage.term_at(index) == Some(state.storage.term())
+        {
+            state.commit_index = index;
+            self.committed.notify_all();
+        }
+    }
+
+    // Replicate the log to a follower while the node is the leader.
+    fn run_replication(&self, peer: usize) {
+        let peer = &self.peers[peer];
+        let mut failed = false;
+        // The snapshot being sent to the follower and the offset to continue at
+        let mut sending: Option<(u64, u64)> = None;
+        while !self.stopped() {
+            let (term, request) = {
+                let mut state = self.state.lock();
+                let next_index = state.next_index.get(&peer.id).cloned().unwrap_or(1);
+                // Send entries right away to a reachable follower which is behind, otherwise
+                // wait for new entries or the next heartbeat
+                let behind = state.role == Role::Leader && next_index <= state.storage.last_index();
+                if failed || !behind {
+                    self.changed
+                        .wait_for(&mut state, self.config.heartbeat_interval);
+                }
+                if state.role != Role::Leader {
+                    continue;
+                }
+                let term = state.storage.term();
+                let next_index = state.next_index.get(&peer.id).cloned().unwrap_or(1);
+                let snapshot_index = state.storage.snapshot_index();
+                if next_index <= snapshot_index {
+                    // The entries the follower needs were compacted, send the snapshot instead
+                    let file = File::open(state.storage.snapshot_path());
+                    drop(state);
+                    let offset = match sending {
+                        Some((index, offset)) if index == snapshot_index => offset,
+                        _ => 0,
+                    };
+                    match file.and_then(|file| read_chunk(file, offset)) {
+                        Ok((data, done)) => (
+                            term,
+                            Request::InstallSnapshot {
+                                term,
+                                leader: self.config.id,
+                                snapshot_index,
+                                offset,
+                                data,
+                                done,
+                            },
+                        ),
+                        Err(e) => {
+                            error!("Failed to read the snapshot: {}", e);
+                            failed = true;
+                            continue;
+                        }
+                    }
+                } else {
+                    let prev_log_index = next_index - 1;
+                    (
+                        term,
+                        Request::Append {
+                            term,
+                            leader: self.config.id,
+                            prev_log_index,
+                            prev_log_term: state.storage.term_at(prev_log_index).unwrap_or(0),
+                            entries: state.storage.entries(next_index, MAX_BATCH),
+                            leader_commit: state.commit_index,
+                        },
+                    )
+                }
+            };
+            let response = match peer.call(&request, self.config.election_timeout) {
+                Ok(response) => response,
+                Err(e) => {
+                    debug!("Failed to replicate to node '{}': {}", peer.id, e);
+                    failed = true;
+                    continue;
+                }
+            };
+            failed = false;
+            let mut state = self.state.lock();
+            let peer_term = match response {
+                Response::Append { term, .. } | Response::InstallSnapshot { term, .. } => term,
+                response => {
+                    warn!("Unexpected replication response '{:?}'", response);
+                    continue;
+                }
+            };
+            if peer_term > state.storage.term() {
+                self.step_down(&mut state, peer_term);
+                continue;
+            }
+            if state.role != Role::Leader || state.storage.term() != term {
+                continue;
+            }
+            match (request, response) {
+                (
+                    _,
+                    Response::Append {
+                        success: true,
+                        match_index,
+                        ..
+                    },
+                ) => {
+                    state.match_index.insert(peer.id, match_index);
+                    state.next_index.insert(peer.id, match_index + 1);
+                    self.advance_commit(&mut state);
+                }
+                (_, Response::Append { match_index, .. }) => {
+                    let next_index = state.next_index.get(&peer.id).cloned().unwrap_or(1);
+                    let retry = (match_index + 1).min(next_index - 1).max(1);
+                    state.next_index.insert(peer.id, retry);
+                }
+                (
+                    Request::InstallSnapshot {
+                        snapshot_index,
+                        offset,
+                        data,
+                        done,
+                        ..
+                    },
+                    Response::InstallSnapshot { received, .. },
+                ) => {
+                    if done && received == offset + data.len() as u64 {
+                        sending = None;
+                        state.match_index.insert(peer.id, snapshot_index);
+                        state.next_index.insert(peer.id, snapshot_index + 1);
+                        self.advance_commit(&mut state);
+                    } else {
+                        sending = Some((snapshot_index, received));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Apply the committed entries to the registry in log order.
+    fn run_applier(&self) {
+        loop {
+            let (entries, leader_term, restore) = {
+                let mut state = self.state.lock();
+                while state.last_applied >= state.commit_index && !self.stopped() {
+                    self.committed.wait(&mut state);
+                }
+                if self.stopped() {
+                    return;
+                }
+                let restore = state.last_applied < state.storage.snapshot_index();
+                let first = state.last_applied + 1;
+                let count = (state.commit_index - state.last_applied) as usize;
+                let leader_term = Some(state.storage.term()).filter(|_| state.role == Role::Leader);
+                if restore {
+                    (Vec::new(), leader_term, restore)
+                } else {
+                    (state.storage.entries(first, count), leader_term, restore)
+                }
+            };
+            let registry = match self.registry.upgrade() {
+                Some(registry) => registry,
+                None => return,
+            };
+            if restore {
+                // The entries were replaced by the snapshot of the leader
+                if let Err(e) = self.restore(&registry) {
+                    error!("Failed to restore the snapshot: {}", e);
+                    thread::sleep(self.config.election_timeout);
+                }
+                continue;
+            }
+            let mut results = Vec::with_capacity(entries.len());
+            for entry in entries {
+                let result = match entry.command {
+                    Some(command) => registry.apply(command, entry.time),
+                    None => {
+                        // The registry is up to date once the empty entry of a new leader is
+                        // applied, from then on the leader takes care of the expirations
+                        if Some(entry.term) == leader_term {
+                            registry.schedule_all();
+                        }
+                        Ok(Applied::Done)
+                    }
+                };
+                let meta = SnapshotMeta {
+                    index: entry.index,
+                    term: entry.term,
+                    time: entry.time,
+                };
+                results.push((meta, result));
+            }
+            let mut snapshot = None;
+            {
+                let mut state = self.state.lock();
+                for (meta, result) in results {
+                    if let Some((waiter_term, waiter)) = state.waiters.remove(&meta.index) {
+                        let _ = if waiter_term == meta.term {
+                            waiter.send(result)
+                        } else {
+                            waiter.send(Err(Error::unavailable(String::from(
+                                "The command was replaced by a new leader",
+                            ))))
+                        };
+                    }
+                    state.last_applied = meta.index;
+                    snapshot = Some(meta);
+                }
+                self.applied.notify_all();
+                let applied = state.last_applied - state.storage.snapshot_index();
+                if self.config.snapshot_entries == 0 || applied < self.config.snapshot_entries {
+                    snapshot = None;
+                }
+            }
+            if let Some(meta) = snapshot {
+                if let Err(e) = self.take_snapshot(&registry, &meta) {
+                    error!("Failed to take a snapshot: {}", e);
+                }
+            }
+        }
+    }
+
+    // Replace the registry with the snapshot of the node.
+    fn restore(&self, registry: &Registry) -> io::Result<()> {
+        let (meta, reader) = storage::read_snapshot(&self.state.lock().storage.snapshot_path())?;
+        registry.restore(reader)?;
+        let mut state = self.state.lock();
+        state.last_applied = state.last_applied.max(meta.index);
+        state.commit_index = state.commit_index.max(meta.index);
+        self.applied.notify_all();
+        info!(
+            "Cluster node '{}' restored the snapshot up to {}",
+            self.config.id, meta.index
+        );
+        Ok(())
+    }
+
+    // Write a snapshot of the registry, which applied the entries up to `meta`, and remove the
+    // entries it includes from the log. Only the applier mutates the registry so it does not
+    // change while the snapshot is written.
+    fn take_snapshot(&self, registry: &Registry, meta: &SnapshotMeta) -> io::Result<()> {
+        let path = self.state.lock().storage.partial_snapshot_path("taking");
+        storage::write_snapshot(&path, meta, |writer| registry.write_snapshot(writer))?;
+        self.state.lock().storage.install_snapshot(&path)?;
+        info!(
+            "Cluster node '{}' compacted its log up to {}",
+            self.config.id, meta.index
+        );
+        Ok(())
+    }
+
+    // Handle a request of another node.
+    fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Vote {
+                term,
+                candidate,
+                last_log_index,
+                last_log_term,
+            } => self.handle_vote(term, candidate, last_log_index, last_log_term),
+            Request::Append {
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => self.handle_append(
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            ),
+            Request::Forward { command } => {
+                let result = self.propose(command);
+                Response::Forward {
+                    result,
+                    applied: self.state.lock().last_applied,
+                }
+            }
+            Request::InstallSnapshot {
+                term,
+                leader,
+                snapshot_index,
+                offset,
+                data,
+                done,
+            } => self.handle_install_snapshot(term, leader, snapshot_index, offset, &data, done),
+        }
+    }
+
+    fn handle_vote(
+        &self,
+        term: u64,
+        candidate: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> Response {
+        let mut state = self.state.lock();
+        if term > state.storage.term() {
+            self.step_down(&mut state, term);
+        }
+        let up_to_date = (last_log_term, last_log_index)
+            >= (state.storage.last_term(), state.storage.last_index());
+        let granted = term == state.storage.term()
+            && state.storage.voted_for().is_none_or(|c| c == candidate)
+            && up_to_date;
+        if granted {
+            self.set_term(&mut state, term, Some(candidate));
+            self.reset_election_deadline(&mut state);
+        }
+        Response::Vote {
+            term: state.storage.term(),
+            granted,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_append(
+        &self,
+        term: u64,
+        leader: u64,
+        mut prev_log_index: u64,
+        mut prev_log_term: u64,
+        mut entries: Vec<Entry>,
+        leader_commit: u64,
+    ) -> Response {
+        let mut state = self.state.lock();
+        if term < state.storage.term() {
+            return Response::Append {
+                term: state.storage.term(),
+                success: false,
+                match_index: 0,
+            };
+        }
+        self.follow(&mut state, term, leader);
+
+        // The entries included in the snapshot are committed so they match the ones of the leader
+        let snapshot_index = state.storage.snapshot_index();
+        if prev_log_index < snapshot_index {
+            let skipped = (snapshot_index - prev_log_index) as usize;
+            entries.drain(..skipped.min(entries.len()));
+            prev_log_index = snapshot_index;
+            prev_log_term = state.storage.term_at(snapshot_index).unwrap_or(0);
+        }
+
+        if state.storage.term_at(prev_log_index) != Some(prev_log_term) {
+            let match_index = state
+                .storage
+                .last_index()
+                .min(prev_log_index.saturating_sub(1));
+            return Response::Append {
+                term,
+                success: false,
+                match_index,
+            };
+        }
+
+        // Skip the entries already stored and remove conflicting ones
+        let mut index = prev_log_index;
+        let mut new_entries = Vec::new();
+        for entry in entries {
+            index += 1;
+            if !new_entries.is_empty() {
+                new_entries.push(entry);
+                continue;
+            }
+            match state.storage.term_at(index) {
+                Some(t) if t == entry.term => {}
+                Some(_) => {
+                    if let Err(e) = state.storage.truncate(index) {
+                        error!("Failed to truncate the log: {}", e);
+                        return Response::Append {
+                            term,
+                            success: false,
+                            match_index: 0,
+                        };
+                    }
+                    new_entries.push(entry);
+                }
+                None => new_entries.push(entry),
+            }
+        }
+        if !new_entries.is_empty() && self.append(&mut state, new_entries).is_err() {
+            return Response::Append {
+                term,
+                success: false,
+                match_index: 0,
+            };
+        }
+
+        let commit_index = leader_commit.min(index);
+        if commit_index > state.commit_index {
+            state.commit_index = commit_index;
+            self.committed.notify_all();
+        }
+        Response::Append {
+            term,
+            success: true,
+            match_index: index,
+        }
+    }
+
+    fn handle_install_snapshot(
+        &self,
+        term: u64,
+        leader: u64,
+        snapshot_index: u64,
+        offset: u64,
+        data: &str,
+        done: bool,
+    ) -> Response {
+        let mut state = self.state.lock();
+        if term < state.storage.term() {
+            return Response::InstallSnapshot {
+                term: state.storage.term(),
+                received: 0,
+            };
+        }
+        self.follow(&mut state, term, leader);
+
+        // Continue where the previous chunk of the same snapshot ended
+        let received = match state.receiving {
+            Some((index, received)) if index == snapshot_index => received,
+            _ => 0,
+        };
+        if offset != received {
+            return Response::InstallSnapshot { term, received };
+        }
+        let path = state.storage.partial_snapshot_path("receiving");
+        let written = if offset == 0 {
+            File::create(&path)
+        } else {
+            OpenOptions::new().append(true).open(&path)
+        }
+        .and_then(|mut file| {
+            file.write_all(data.as_bytes())?;
+            if done {
+                file.sync_all()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            error!("Failed to write the snapshot: {}", e);
+            state.receiving = None;
+            return Response::InstallSnapshot { term, received: 0 };
+        }
+        let received = offset + data.len() as u64;
+        state.receiving = Some((snapshot_index, received));
+        if !done {
+            return Response::InstallSnapshot { term, received };
+        }
+
+        // The applier restores the registry from the snapshot once it is installed
+        state.receiving = None;
+        match state.storage.install_snapshot(&path) {
+            Ok(meta) => {
+                info!(
+                    "Cluster node '{}' installed the snapshot of node '{}' up to {}",
+                    self.config.id, leader, meta.index
+                );
+                if meta.index > state.commit_index {
+                    state.commit_index = meta.index;
+                    self.committed.notify_all();
+                }
+                Response::InstallSnapshot { term, received }
+            }
+            Err(e) => {
+                error!("Failed to install the snapshot: {}", e);
+                Response::InstallSnapshot { term, received: 0 }
+            }
+        }
+    }
+
+    // Follow the leader of the current term which is at least `term`.
+    fn follow(&self, state: &mut State, term: u64, leader: u64) {
+        self.step_down(state, term);
+        if state.leader != Some(leader) {
+            info!(
+                "Cluster node '{}' follows node '{}' in term {}",
+                self.config.id, leader, term
+            );
+            state.leader = Some(leader);
+        }
+        self.reset_election_deadline(state);
+    }
+}
+
+// Read up to `SNAPSHOT_CHUNK` bytes of a snapshot starting at `offset`, cut at the last complete
+// character. Returns the chunk and whether it is the last one.
+fn read_chunk(mut file: File, offset: u64) -> io::Result<(String, bool)> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = Vec::with_capacity(SNAPSHOT_CHUNK);
+    file.take(SNAPSHOT_CHUNK as u64).read_to_end(&mut buffer)?;
+    let done = buffer.len() < SNAPSHOT_CHUNK;
+    let data = match String::from_utf8(buffer) {
+        Ok(data) => data,
+        Err(e) => {
+            let valid = e.utf8_error().valid_up_to();
+            let mut buffer = e.into_bytes();
+            buffer.truncate(valid);
+            String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        }
+    };
+    Ok((data, done))
+}
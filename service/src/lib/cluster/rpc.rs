@@ -0,0 +1,191 @@
+//! The messages exchanged by the nodes of a cluster and their transport.
+//!
+//! Nodes connect to each other over TCP and send one JSON encoded
+//! [Request](enum.Request.html) per line, each answered by one JSON encoded
+//! [Response](enum.Response.html) line. Connections are kept open and reused.
+
+use log::debug;
+use parking_lot::Mutex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time;
+
+use super::command::{Applied, Command};
+use super::storage::Entry;
+use crate::Result;
+
+/// A request sent from one node to another.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// A candidate asks for a vote.
+    Vote {
+        term: u64,
+        candidate: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    /// The leader replicates entries, an empty list of entries is a heartbeat.
+    Append {
+        term: u64,
+        leader: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: u64,
+    },
+    /// A follower forwards a command to the leader.
+    Forward { command: Command },
+    /// The leader sends a chunk of its snapshot, starting at byte `offset`, to a follower which
+    /// is behind the entries the leader compacted.
+    InstallSnapshot {
+        term: u64,
+        leader: u64,
+        /// The index of the last entry included in the snapshot.
+        snapshot_index: u64,
+        offset: u64,
+        data: String,
+        /// Whether the chunk is the last one of the snapshot.
+        done: bool,
+    },
+}
+
+/// The response to a [Request](enum.Request.html).
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    /// On success `match_index` is the index of the last replicated entry, otherwise the index
+    /// up to which the leader should retry.
+    Append {
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+    /// `applied` is the index up to which the leader applied the log, the forwarding node waits
+    /// until it applied the same entries so it reads its own writes.
+    Forward {
+        result: Result<Applied>,
+        applied: u64,
+    },
+    /// `received` is the number of bytes of the snapshot the follower stored, the leader
+    /// continues from there.
+    InstallSnapshot {
+        term: u64,
+        received: u64,
+    },
+}
+
+fn read_line<T: serde::de::DeserializeOwned>(reader: &mut BufReader<TcpStream>) -> io::Result<T> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_line<T: serde::Serialize>(stream: &mut TcpStream, value: &T) -> io::Result<()> {
+    let mut buffer =
+        serde_json::to_vec(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    buffer.push(b'\n');
+    stream.write_all(&buffer)
+}
+
+/// Accept connections on `listener` answering each request with `handler`.
+///
+/// Every connection is served by its own thread. Returns once `stopped` returns true after a
+/// connection was accepted.
+pub fn serve<H, S>(listener: TcpListener, handler: H, stopped: S)
+where
+    H: Fn(Request) -> Response + Clone + Send + 'static,
+    S: Fn() -> bool,
+{
+    for stream in listener.incoming() {
+        if stopped() {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Failed to accept a cluster connection: {}", e);
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        thread::spawn(move || {
+            let _ = stream.set_nodelay(true);
+            let mut writer = match stream.try_clone() {
+                Ok(writer) => writer,
+                Err(_) => return,
+            };
+            let mut reader = BufReader::new(stream);
+            while let Ok(request) = read_line(&mut reader) {
+                if write_line(&mut writer, &handler(request)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// Another node of the cluster.
+pub struct Peer {
+    pub id: u64,
+    pub address: String,
+    // Idle connections, a call takes one or opens a new one so calls do not wait on each other.
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Peer {
+    pub fn new(id: u64, address: &str) -> Self {
+        Peer {
+            id,
+            address: String::from(address),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn connect(&self, timeout: time::Duration) -> io::Result<Connection> {
+        let address = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unresolved address"))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Send a request and wait up to `timeout` for its response.
+    pub fn call(&self, request: &Request, timeout: time::Duration) -> io::Result<Response> {
+        let idle = self.idle.lock().pop();
+        let mut connection = match idle {
+            Some(connection) => connection,
+            None => self.connect(timeout)?,
+        };
+        connection.writer.set_write_timeout(Some(timeout))?;
+        connection.writer.set_read_timeout(Some(timeout))?;
+        write_line(&mut connection.writer, request)?;
+        // A failed connection is dropped, a late response would be read by the next call
+        let response = read_line(&mut connection.reader)?;
+        self.idle.lock().push(connection);
+        Ok(response)
+    }
+}
@@ -0,0 +1,299 @@
+//! The persistent state of a cluster node.
+//!
+//! The current term and vote are kept in `state.json` which is replaced atomically on every
+//! change. The log is kept in `log.jsonl` with one JSON encoded [Entry](struct.Entry.html) per
+//! line, entries are appended to it and the file is only rewritten when conflicting entries are
+//! removed or the log is compacted. A partially written last line, left by a crash, is dropped
+//! when the log is opened.
+//!
+//! The log is compacted by replacing its prefix with a snapshot of the registry in
+//! `snapshot.jsonl`. Its first line is the JSON encoded [SnapshotMeta](struct.SnapshotMeta.html)
+//! of the last entry it includes, the rest is written by the registry. Snapshots are written to a
+//! temporary file which replaces the snapshot once it is complete.
+
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use super::command::Command;
+
+const STATE_FILE: &str = "state.json";
+const LOG_FILE: &str = "log.jsonl";
+const SNAPSHOT_FILE: &str = "snapshot.jsonl";
+
+/// An entry of the replicated log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    /// The index of the entry in the log.
+    pub index: u64,
+    /// The term in which the entry was created by the leader.
+    pub term: u64,
+    /// The time the leader created the entry at, the command is applied at this time.
+    pub time: DateTime<Utc>,
+    /// The command to apply or None for the empty entry a new leader creates.
+    pub command: Option<Command>,
+}
+
+/// The last entry included in a snapshot.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SnapshotMeta {
+    pub index: u64,
+    pub term: u64,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct HardState {
+    term: u64,
+    voted_for: Option<u64>,
+}
+
+/// The term, vote, snapshot, and log of a node.
+///
+/// Log indexes start at 1, index 0 is the empty log with term 0. The entries in memory start
+/// right after the last entry of the snapshot.
+pub struct Storage {
+    dir: PathBuf,
+    state: HardState,
+    snapshot: Option<SnapshotMeta>,
+    entries: Vec<Entry>,
+    log: File,
+}
+
+fn invalid_data(error: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Write a snapshot to `path`, its meta data followed by the lines written by `write`.
+pub fn write_snapshot(
+    path: &Path,
+    meta: &SnapshotMeta,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut file, meta).map_err(invalid_data)?;
+    file.write_all(b"\n")?;
+    write(&mut file)?;
+    file.into_inner()?.sync_all()
+}
+
+/// Open the snapshot at `path` returning its meta data and a reader of the lines after it.
+pub fn read_snapshot(path: &Path) -> io::Result<(SnapshotMeta, BufReader<File>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let meta = serde_json::from_str(&line).map_err(invalid_data)?;
+    Ok((meta, reader))
+}
+
+impl Storage {
+    /// Open the storage in `dir` creating the directory if it does not exist.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let state = match fs::read(dir.join(STATE_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(invalid_data)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HardState::default(),
+            Err(e) => return Err(e),
+        };
+        let snapshot = match read_snapshot(&dir.join(SNAPSHOT_FILE)) {
+            Ok((meta, _)) => Some(meta),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut torn = false;
+        if let Ok(file) = File::open(dir.join(LOG_FILE)) {
+            let mut lines = BufReader::new(file).lines().peekable();
+            while let Some(line) = lines.next() {
+                match serde_json::from_str(&line?) {
+                    Ok(entry) => entries.push(entry),
+                    // Only the last line may be incomplete
+                    Err(_) if lines.peek().is_none() => torn = true,
+                    Err(e) => return Err(invalid_data(e)),
+                }
+            }
+        }
+        // The log is only rewritten after the snapshot replaced it, a crash in between leaves
+        // the entries the snapshot includes in it
+        let compacted = match snapshot {
+            Some(meta) if entries.first().is_some_and(|e| e.index <= meta.index) => {
+                let kept = entries
+                    .iter()
+                    .position(|e| e.index == meta.index && e.term == meta.term)
+                    .map_or(entries.len(), |i| i + 1);
+                entries.drain(..kept);
+                true
+            }
+            _ => false,
+        };
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        let mut storage = Storage {
+            dir: dir.to_path_buf(),
+            state,
+            snapshot,
+            entries,
+            log,
+        };
+        if torn || compacted {
+            storage.rewrite()?;
+        }
+        Ok(storage)
+    }
+
+    /// Get the current term.
+    pub fn term(&self) -> u64 {
+        self.state.term
+    }
+
+    /// Get the candidate voted for in the current term.
+    pub fn voted_for(&self) -> Option<u64> {
+        self.state.voted_for
+    }
+
+    /// Durably set the current term and vote.
+    pub fn set_term(&mut self, term: u64, voted_for: Option<u64>) -> io::Result<()> {
+        self.state = HardState { term, voted_for };
+        let path = self.dir.join(STATE_FILE);
+        let tmp_path = self.dir.join(format!("{}.tmp", STATE_FILE));
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, &self.state).map_err(invalid_data)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Get the meta data of the snapshot or None if the log was never compacted.
+    pub fn snapshot(&self) -> Option<SnapshotMeta> {
+        self.snapshot
+    }
+
+    /// Get the path of the snapshot.
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(SNAPSHOT_FILE)
+    }
+
+    /// Get the path a snapshot is written to before it is installed, `name` tells apart the
+    /// snapshots taken by the node from the ones received from the leader.
+    pub fn partial_snapshot_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", SNAPSHOT_FILE, name))
+    }
+
+    /// Get the index of the last entry included in the snapshot or 0 if there is none.
+    pub fn snapshot_index(&self) -> u64 {
+        self.snapshot.map_or(0, |s| s.index)
+    }
+
+    /// Get the index of the last entry.
+    pub fn last_index(&self) -> u64 {
+        self.snapshot_index() + self.entries.len() as u64
+    }
+
+    /// Get the term of the last entry.
+    pub fn last_term(&self) -> u64 {
+        self.term_at(self.last_index()).unwrap_or(0)
+    }
+
+    /// Get the time of the last entry or None if the log is empty.
+    pub fn last_time(&self) -> Option<DateTime<Utc>> {
+        match self.entries.last() {
+            Some(entry) => Some(entry.time),
+            None => self.snapshot.map(|s| s.time),
+        }
+    }
+
+    /// Get the term of the entry at `index` or None if there is no such entry or it was
+    /// compacted.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        let snapshot_index = self.snapshot_index();
+        if index < snapshot_index {
+            return None;
+        }
+        if index == snapshot_index {
+            return Some(self.snapshot.map_or(0, |s| s.term));
+        }
+        self.entries
+            .get((index - snapshot_index) as usize - 1)
+            .map(|e| e.term)
+    }
+
+    /// Get up to `max` entries starting at `index`, entries which were compacted are skipped.
+    pub fn entries(&self, index: u64, max: usize) -> Vec<Entry> {
+        let start = index.saturating_sub(self.snapshot_index() + 1) as usize;
+        self.entries.iter().skip(start).take(max).cloned().collect()
+    }
+
+    /// Durably append entries to the log returning the index of the last entry.
+    ///
+    /// Each entry must have the index following the last entry.
+    pub fn append(&mut self, entries: Vec<Entry>) -> io::Result<u64> {
+        let mut buffer = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.index != self.last_index() + 1 + i as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("entry {} does not follow the last entry", entry.index),
+                ));
+            }
+            serde_json::to_writer(&mut buffer, entry).map_err(invalid_data)?;
+            buffer.push(b'\n');
+        }
+        self.log.write_all(&buffer)?;
+        self.log.sync_data()?;
+        self.entries.extend(entries);
+        Ok(self.last_index())
+    }
+
+    /// Durably remove the entries starting at `index`, the snapshot is never removed.
+    pub fn truncate(&mut self, index: u64) -> io::Result<()> {
+        let kept = index.saturating_sub(self.snapshot_index() + 1) as usize;
+        self.entries.truncate(kept);
+        self.rewrite()
+    }
+
+    /// Replace the snapshot with the one written to `path` and remove the entries it includes.
+    ///
+    /// The entries after the snapshot are kept if the log contains its last entry, otherwise the
+    /// whole log is replaced by the snapshot. A snapshot which does not include more entries than
+    /// the current one is discarded. Returns the meta data of the installed snapshot.
+    pub fn install_snapshot(&mut self, path: &Path) -> io::Result<SnapshotMeta> {
+        let (meta, _) = read_snapshot(path)?;
+        if let Some(current) = self.snapshot.filter(|s| s.index >= meta.index) {
+            fs::remove_file(path)?;
+            return Ok(current);
+        }
+        fs::rename(path, self.snapshot_path())?;
+        if self.term_at(meta.index) == Some(meta.term) {
+            let compacted = (meta.index - self.snapshot_index()) as usize;
+            self.entries.drain(..compacted);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot = Some(meta);
+        self.rewrite()?;
+        Ok(meta)
+    }
+
+    // Replace the log file with the entries in memory.
+    fn rewrite(&mut self) -> io::Result<()> {
+        let path = self.dir.join(LOG_FILE);
+        let tmp_path = self.dir.join(format!("{}.tmp", LOG_FILE));
+        {
+            let mut file = io::BufWriter::new(File::create(&tmp_path)?);
+            for entry in &self.entries {
+                serde_json::to_writer(&mut file, entry).map_err(invalid_data)?;
+                file.write_all(b"\n")?;
+            }
+            file.into_inner()?.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        self.log = OpenOptions::new().append(true).open(path)?;
+        Ok(())
+    }
+}
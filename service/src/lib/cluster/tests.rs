@@ -0,0 +1,635 @@
+use super::*;
+use crate::{ErrorCode, RawMessage, DEFAULT_NAMESPACE};
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("courier-cluster-{}", uuid::Uuid::new_v4()))
+}
+
+fn entry(index: u64, term: u64, topic: &str) -> Entry {
+    Entry {
+        index,
+        term,
+        time: Utc.timestamp_opt(index as i64, 0).unwrap(),
+        command: Some(Command::DeleteTopic {
+            namespace: String::from(DEFAULT_NAMESPACE),
+            topic: String::from(topic),
+        }),
+    }
+}
+
+#[test]
+fn cluster_storage() {
+    let dir = temp_dir();
+    {
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.term(), 0);
+        assert_eq!(storage.voted_for(), None);
+        assert_eq!(storage.last_index(), 0);
+        assert_eq!(storage.last_term(), 0);
+        assert_eq!(storage.term_at(0), Some(0));
+        assert_eq!(storage.term_at(1), None);
+
+        storage.set_term(2, Some(3)).unwrap();
+        assert_eq!(
+            storage
+                .append(vec![entry(1, 1, "a"), entry(2, 2, "b")])
+                .unwrap(),
+            2
+        );
+        assert_eq!(storage.append(vec![entry(3, 2, "c")]).unwrap(), 3);
+        assert_eq!(
+            storage.entries(2, 10),
+            vec![entry(2, 2, "b"), entry(3, 2, "c")]
+        );
+        assert_eq!(storage.entries(1, 1), vec![entry(1, 1, "a")]);
+        assert_eq!(storage.entries(4, 10), Vec::new());
+        assert_eq!(storage.last_time(), Some(entry(3, 2, "c").time));
+        // Entries must follow the last entry
+        assert!(storage.append(vec![entry(5, 2, "e")]).is_err());
+    }
+
+    // The term, vote, and log survive reopening
+    {
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.term(), 2);
+        assert_eq!(storage.voted_for(), Some(3));
+        assert_eq!(storage.last_index(), 3);
+        assert_eq!(storage.last_term(), 2);
+        assert_eq!(storage.term_at(1), Some(1));
+
+        storage.truncate(2).unwrap();
+        assert_eq!(storage.last_index(), 1);
+        storage.append(vec![entry(2, 3, "d")]).unwrap();
+    }
+    {
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(
+            storage.entries(1, 10),
+            vec![entry(1, 1, "a"), entry(2, 3, "d")]
+        );
+    }
+
+    // A partially written last entry is dropped
+    {
+        let mut log = fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("log.jsonl"))
+            .unwrap();
+        log.write_all(b"{\"term\":3,\"comm").unwrap();
+    }
+    {
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.last_index(), 2);
+        storage.append(vec![entry(3, 3, "e")]).unwrap();
+    }
+    assert_eq!(Storage::open(&dir).unwrap().last_index(), 3);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn snapshot(storage: &Storage, name: &str, index: u64, term: u64) -> PathBuf {
+    let path = storage.partial_snapshot_path(name);
+    let meta = SnapshotMeta {
+        index,
+        term,
+        time: Utc.timestamp_opt(index as i64, 0).unwrap(),
+    };
+    storage::write_snapshot(&path, &meta, |writer| writer.write_all(b"registry\n")).unwrap();
+    path
+}
+
+#[test]
+fn cluster_storage_snapshot() {
+    let dir = temp_dir();
+    {
+        let mut storage = Storage::open(&dir).unwrap();
+        let entries = (1..=4).map(|i| entry(i, 1, "t")).collect();
+        storage.append(entries).unwrap();
+
+        // The entries included in the snapshot are removed from the log
+        let path = snapshot(&storage, "taking", 2, 1);
+        storage.install_snapshot(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(storage.snapshot_index(), 2);
+        assert_eq!(storage.last_index(), 4);
+        assert_eq!(storage.term_at(1), None);
+        assert_eq!(storage.term_at(2), Some(1));
+        assert_eq!(
+            storage.entries(1, 10),
+            vec![entry(3, 1, "t"), entry(4, 1, "t")]
+        );
+
+        // An older snapshot is discarded
+        let path = snapshot(&storage, "receiving", 1, 1);
+        assert_eq!(storage.install_snapshot(&path).unwrap().index, 2);
+        assert!(!path.exists());
+        assert_eq!(storage.snapshot_index(), 2);
+
+        // The entries after the snapshot are kept, they can be truncated but the snapshot not
+        storage.truncate(1).unwrap();
+        assert_eq!(storage.last_index(), 2);
+        assert_eq!(storage.last_term(), 1);
+        assert_eq!(storage.last_time(), Some(entry(2, 1, "t").time));
+        storage.append(vec![entry(3, 2, "u")]).unwrap();
+    }
+
+    // The snapshot and the entries after it survive reopening
+    {
+        let mut storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.snapshot_index(), 2);
+        assert_eq!(storage.last_index(), 3);
+        assert_eq!(storage.entries(3, 10), vec![entry(3, 2, "u")]);
+        let (meta, reader) = storage::read_snapshot(&storage.snapshot_path()).unwrap();
+        assert_eq!(meta.index, 2);
+        assert_eq!(
+            reader.lines().map(|l| l.unwrap()).collect::<Vec<_>>(),
+            vec!["registry"]
+        );
+
+        // A snapshot of a log the node does not have replaces the whole log
+        let path = snapshot(&storage, "receiving", 5, 4);
+        storage.install_snapshot(&path).unwrap();
+        assert_eq!(storage.last_index(), 5);
+        assert_eq!(storage.last_term(), 4);
+        assert_eq!(storage.entries(1, 10), Vec::new());
+        storage.append(vec![entry(6, 4, "v")]).unwrap();
+    }
+
+    // The entries the snapshot includes are dropped if the node stopped before the log was
+    // rewritten
+    {
+        let mut log = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(dir.join("log.jsonl"))
+            .unwrap();
+        for e in &[entry(4, 4, "t"), entry(5, 4, "t"), entry(6, 4, "v")] {
+            serde_json::to_writer(&mut log, e).unwrap();
+            log.write_all(b"\n").unwrap();
+        }
+    }
+    let storage = Storage::open(&dir).unwrap();
+    assert_eq!(storage.last_index(), 6);
+    assert_eq!(storage.entries(1, 10), vec![entry(6, 4, "v")]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn cluster_command_serialization() {
+    let command = Command::CreateSubscription {
+        namespace: String::from("ns"),
+        subscription: String::from("s"),
+        topic_namespace: String::from("ns"),
+        topic: String::from("t"),
+        labels: HashMap::new(),
+        description: String::new(),
+        ack_deadline: Duration::milliseconds(1500),
+        ttl: Duration::seconds(0),
+        consumer_timeout: Duration::seconds(30),
+        historical: true,
+    };
+    let json = serde_json::to_string(&command).unwrap();
+    assert!(json.contains("\"ack_deadline\":1500"));
+    assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+
+    let command = Command::Publish {
+        namespace: String::from("ns"),
+        topic: String::from("t"),
+        messages: vec![RawMessage::with_partition_key(
+            String::from("data"),
+            String::from("key"),
+        )],
+        ids: vec![uuid::Uuid::new_v4()],
+    };
+    let json = serde_json::to_string(&command).unwrap();
+    assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+
+    let command = Command::UpdateTopic {
+        namespace: String::from("ns"),
+        topic: String::from("t"),
+        labels: None,
+        description: None,
+        message_ttl: Some(Duration::seconds(5)),
+        ttl: None,
+    };
+    let json = serde_json::to_string(&command).unwrap();
+    assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+}
+
+fn unused_address() -> String {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string()
+}
+
+fn cluster_configs(dir: &Path, nodes: u64) -> Vec<ClusterConfig> {
+    let addresses: Vec<(u64, String)> = (1..=nodes).map(|id| (id, unused_address())).collect();
+    addresses
+        .iter()
+        .map(|(id, address)| {
+            let peers = addresses.iter().filter(|(p, _)| p != id).cloned().collect();
+            ClusterConfig::new(*id, address, peers, dir.join(id.to_string()))
+        })
+        .collect()
+}
+
+fn start_node(config: ClusterConfig) -> SharedRegistry {
+    let registry = Registry::new();
+    Cluster::start(config, &registry).unwrap();
+    registry
+}
+
+fn start_cluster(dir: &Path, nodes: u64) -> Vec<SharedRegistry> {
+    cluster_configs(dir, nodes)
+        .into_iter()
+        .map(start_node)
+        .collect()
+}
+
+// Wait until `f` returns true or fail after 10 seconds.
+fn wait_for(f: impl Fn() -> bool) {
+    for _ in 0..200 {
+        if f() {
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(50));
+    }
+    panic!("timed out");
+}
+
+fn status(registry: &Registry) -> ClusterStatus {
+    registry.cluster_status().unwrap()
+}
+
+#[test]
+fn cluster_single_node() {
+    let dir = temp_dir();
+    let registries = start_cluster(&dir, 1);
+    let registry = &registries[0];
+    wait_for(|| status(registry).role == Role::Leader);
+
+    let topic = registry
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            1,
+        )
+        .unwrap();
+    assert_eq!(topic.name, "t");
+    let error = registry
+        .delete_topic(DEFAULT_NAMESPACE, "missing")
+        .unwrap_err();
+    assert_eq!(error.code, ErrorCode::TopicNotFound);
+
+    let status = status(registry);
+    assert_eq!(status.id, 1);
+    assert_eq!(status.leader, Some(1));
+    assert_eq!(status.peers, Vec::<u64>::new());
+    // The empty entry of the leader and both commands
+    assert_eq!(status.commit_index, 3);
+    assert_eq!(status.last_applied, 3);
+
+    drop(registries);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn cluster_empty_pulls() {
+    let dir = temp_dir();
+    let registries = start_cluster(&dir, 1);
+    let registry = &registries[0];
+    wait_for(|| status(registry).role == Role::Leader);
+
+    registry
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            1,
+        )
+        .unwrap();
+    registry
+        .create_subscription(
+            DEFAULT_NAMESPACE,
+            "s",
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            Duration::seconds(0),
+            false,
+        )
+        .unwrap();
+    let last_index = status(registry).last_index;
+
+    // Polls with nothing to deliver are not replicated
+    for _ in 0..3 {
+        let pulled = registry
+            .pull(DEFAULT_NAMESPACE, "s", 10, None, None)
+            .unwrap();
+        assert!(pulled.is_empty());
+    }
+    assert_eq!(status(registry).last_index, last_index);
+
+    registry
+        .publish(
+            DEFAULT_NAMESPACE,
+            "t",
+            vec![RawMessage::new(String::from("a"))],
+        )
+        .unwrap();
+    let pulled = registry
+        .pull(DEFAULT_NAMESPACE, "s", 10, None, None)
+        .unwrap();
+    assert_eq!(pulled.len(), 1);
+    assert_eq!(status(registry).last_index, last_index + 2);
+
+    drop(registries);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn cluster_replication() {
+    let dir = temp_dir();
+    let registries = start_cluster(&dir, 3);
+    wait_for(|| registries.iter().any(|r| status(r).role == Role::Leader));
+    let leader = registries
+        .iter()
+        .map(|r| status(r))
+        .find(|s| s.role == Role::Leader)
+        .unwrap()
+        .id;
+    let followers: Vec<&SharedRegistry> = registries
+        .iter()
+        .filter(|r| status(r).id != leader)
+        .collect();
+    wait_for(|| followers.iter().all(|r| status(r).leader == Some(leader)));
+
+    // Mutations of followers are forwarded to the leader
+    followers[0]
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            2,
+        )
+        .unwrap();
+    followers[1]
+        .create_subscription(
+            DEFAULT_NAMESPACE,
+            "s",
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            Duration::seconds(0),
+            false,
+        )
+        .unwrap();
+    let published = followers[0]
+        .publish(
+            DEFAULT_NAMESPACE,
+            "t",
+            vec![
+                RawMessage::new(String::from("a")),
+                RawMessage::with_partition_key(String::from("b"), String::from("key")),
+            ],
+        )
+        .unwrap();
+    assert_eq!(published.len(), 2);
+    let error = followers[1]
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            1,
+        )
+        .unwrap_err();
+    assert_eq!(error.code, ErrorCode::TopicAlreadyExists);
+
+    // Every node applies the same commands
+    let last_index = registries
+        .iter()
+        .map(|r| status(r).last_index)
+        .max()
+        .unwrap();
+    wait_for(|| {
+        registries
+            .iter()
+            .all(|r| status(r).last_applied == last_index)
+    });
+    for registry in &registries {
+        for (id, partition, offset) in &published {
+            let message = registry.get_message(DEFAULT_NAMESPACE, "t", *id).unwrap();
+            assert_eq!(message.partition, *partition);
+            assert_eq!(message.offset, *offset);
+        }
+    }
+
+    // Pulls and acks are replicated as well
+    let messages = followers[1]
+        .pull(DEFAULT_NAMESPACE, "s", 10, None, Some("consumer"))
+        .unwrap();
+    assert_eq!(messages.len(), 2);
+    let acked = followers[0]
+        .ack(DEFAULT_NAMESPACE, "s", &[messages[0].id])
+        .unwrap();
+    assert_eq!(acked, vec![messages[0].id]);
+    let last_index = status(&registries[0])
+        .last_index
+        .max(status(&registries[1]).last_index);
+    wait_for(|| {
+        registries
+            .iter()
+            .all(|r| status(r).last_applied >= last_index)
+    });
+    for registry in &registries {
+        let consumers = registry.consumers(DEFAULT_NAMESPACE, "s").unwrap();
+        assert_eq!(consumers.len(), 1);
+        assert_eq!(consumers[0].in_flight, 1);
+    }
+
+    drop(followers);
+    drop(registries);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn create_topic(registry: &Registry, name: &str, ttl: Duration) {
+    registry
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            name,
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(0),
+            ttl,
+            1,
+        )
+        .unwrap();
+}
+
+fn publish(registry: &Registry, topic: &str, count: usize) -> Vec<uuid::Uuid> {
+    (0..count)
+        .map(|i| {
+            registry
+                .publish(
+                    DEFAULT_NAMESPACE,
+                    topic,
+                    vec![RawMessage::new(i.to_string())],
+                )
+                .unwrap()[0]
+                .0
+        })
+        .collect()
+}
+
+#[test]
+fn cluster_snapshot_restore() {
+    let dir = temp_dir();
+    let mut config = cluster_configs(&dir, 1).remove(0);
+    config.snapshot_entries = 5;
+    let registry = start_node(config.clone());
+    wait_for(|| status(&registry).role == Role::Leader);
+
+    create_topic(&registry, "t", Duration::seconds(0));
+    registry
+        .create_subscription(
+            DEFAULT_NAMESPACE,
+            "s",
+            DEFAULT_NAMESPACE,
+            "t",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            Duration::seconds(0),
+            true,
+        )
+        .unwrap();
+    let ids = publish(&registry, "t", 10);
+    let pulled = registry
+        .pull(DEFAULT_NAMESPACE, "s", 3, None, Some("consumer"))
+        .unwrap();
+    registry
+        .ack(DEFAULT_NAMESPACE, "s", &[pulled[0].id])
+        .unwrap();
+    wait_for(|| status(&registry).snapshot_index > 0);
+    let topic = registry.get_topic(DEFAULT_NAMESPACE, "t").unwrap();
+    let subscription = registry.get_subscription(DEFAULT_NAMESPACE, "s").unwrap();
+    let last_applied = status(&registry).last_applied;
+    drop(registry);
+
+    // The restarted node restores the snapshot and replays the entries after it
+    config.listen = unused_address();
+    let registry = start_node(config);
+    assert!(status(&registry).snapshot_index > 0);
+    wait_for(|| status(&registry).last_applied >= last_applied);
+    assert_eq!(registry.get_topic(DEFAULT_NAMESPACE, "t").unwrap(), topic);
+    assert_eq!(
+        registry.get_subscription(DEFAULT_NAMESPACE, "s").unwrap(),
+        subscription
+    );
+    for id in &ids {
+        registry.get_message(DEFAULT_NAMESPACE, "t", *id).unwrap();
+    }
+    let consumers = registry.consumers(DEFAULT_NAMESPACE, "s").unwrap();
+    assert_eq!(consumers.len(), 1);
+    assert_eq!(consumers[0].in_flight, 2);
+    wait_for(|| status(&registry).role == Role::Leader);
+    let messages = registry
+        .pull(DEFAULT_NAMESPACE, "s", 10, None, None)
+        .unwrap();
+    let pulled: Vec<_> = messages.iter().map(|m| m.id).collect();
+    assert_eq!(pulled, ids[3..].to_vec());
+
+    drop(registry);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn cluster_install_snapshot() {
+    let dir = temp_dir();
+    let mut configs = cluster_configs(&dir, 3);
+    for config in &mut configs {
+        config.snapshot_entries = 5;
+    }
+    let late = configs.pop().unwrap();
+    let registries: Vec<SharedRegistry> = configs.into_iter().map(start_node).collect();
+    wait_for(|| registries.iter().any(|r| status(r).role == Role::Leader));
+
+    create_topic(&registries[0], "t", Duration::seconds(0));
+    let ids = publish(&registries[0], "t", 10);
+    wait_for(|| registries.iter().all(|r| status(r).snapshot_index > 0));
+
+    // The entries the late node needs were compacted so it receives the snapshot of the leader
+    let late = start_node(late);
+    let last_index = registries
+        .iter()
+        .map(|r| status(r).commit_index)
+        .max()
+        .unwrap();
+    wait_for(|| status(&late).last_applied >= last_index);
+    assert!(status(&late).snapshot_index > 0);
+    for id in &ids {
+        late.get_message(DEFAULT_NAMESPACE, "t", *id).unwrap();
+    }
+    assert_eq!(
+        late.get_topic(DEFAULT_NAMESPACE, "t").unwrap(),
+        registries[0].get_topic(DEFAULT_NAMESPACE, "t").unwrap()
+    );
+
+    drop(late);
+    drop(registries);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn cluster_expiration() {
+    let dir = temp_dir();
+    let registries = start_cluster(&dir, 3);
+    wait_for(|| registries.iter().any(|r| status(r).role == Role::Leader));
+
+    create_topic(&registries[0], "t", Duration::milliseconds(200));
+    create_topic(&registries[0], "u", Duration::seconds(0));
+
+    // The leader appends the expiration to the log and every node applies it
+    wait_for(|| {
+        registries
+            .iter()
+            .all(|r| r.get_topic(DEFAULT_NAMESPACE, "t").is_err())
+    });
+    let log = fs::read_to_string(dir.join("1").join("log.jsonl")).unwrap();
+    assert_eq!(log.matches("\"expire_topic\"").count(), 1);
+    for registry in &registries {
+        registry.get_topic(DEFAULT_NAMESPACE, "u").unwrap();
+    }
+
+    drop(registries);
+    let _ = fs::remove_dir_all(&dir);
+}
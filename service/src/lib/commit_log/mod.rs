@@ -1,276 +1,263 @@
 //! A commit log like data structure.
 //!
-//! The [CommitLog](struct.CommitLog.html) is essentially a singly linked list. It allows appending
-//! elements to its tail and then running a cleanup function that removes elements from its head.
-//! This module provides a [Cursor](struct.Cursor.html)s which can be used to walk along the
-//! elements of the commit log as well as an [Index](struct.Index.html) which points to a single
-//! element of the [CommitLog](struct.CommitLog.html).
+//! The [CommitLog](struct.CommitLog.html) stores its elements in fixed size segments addressed by
+//! absolute indexes. It allows appending elements to its tail and then running a cleanup function
+//! that removes elements from its head, indexes are never reused as elements are cleaned up. This
+//! module provides a [Cursor](struct.Cursor.html)s which can be used to walk along the elements of
+//! the commit log as well as an [Index](struct.Index.html) which points to a single element of the
+//! [CommitLog](struct.CommitLog.html).
 
 #[cfg(test)]
 mod tests;
 
-use parking_lot::RwLock;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fmt;
 use std::fmt::Debug;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Weak};
 
-type Shared<T> = Arc<RwLock<T>>;
+/// The number of elements in a segment.
+const SEGMENT_SIZE: usize = 1024;
 
-type WeakShared<T> = Weak<RwLock<T>>;
-
-struct Element<T> {
-    value: T,
-    next: Node<T>,
+// The elements of a commit log.
+//
+// Segments are only removed once all of their elements have been cleaned up, so the first
+// segment starts at the index `head_index` rounded down to a multiple of `SEGMENT_SIZE`. Cleaned
+// up elements of the first segment are set to None so they are dropped immediately.
+struct Segments<T> {
+    segments: VecDeque<Vec<Option<T>>>,
+    head_index: usize,
+    next_index: usize,
 }
 
-impl<T> Element<T> {
-    fn new(value: T) -> Shared<Self> {
-        Arc::new(RwLock::new(Self { value, next: None }))
+impl<T> Segments<T> {
+    fn get(&self, index: usize) -> Option<&T> {
+        if index < self.head_index || index >= self.next_index {
+            return None;
+        }
+        let segment = index / SEGMENT_SIZE - self.head_index / SEGMENT_SIZE;
+        self.segments[segment][index % SEGMENT_SIZE].as_ref()
     }
-}
-
-type Link<T> = Shared<Element<T>>;
 
-type Pointer<T> = WeakShared<Element<T>>;
-
-type Node<T> = Option<Link<T>>;
+    fn head(&self) -> Option<&T> {
+        self.get(self.head_index)
+    }
+}
 
 /// An index into an element of a [CommitLog](struct.CommitLog.html).
-pub struct Index<T> {
-    index: Pointer<T>,
+///
+/// The index is a plain offset, its element is read through the commit log it was created from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Index {
+    index: Option<usize>,
 }
 
-impl<T> Index<T> {
-    /// Create a new index at the element the [Cursor](struct.Cursor.html) is currently pointing.
-    pub fn new(cursor: &Cursor<T>) -> Self {
+impl Index {
+    /// Create a new index at the element the [Cursor](struct.Cursor.html) retrieved last.
+    pub fn new(cursor: &Cursor) -> Self {
         Self {
-            index: Weak::clone(&cursor.cursor),
+            index: cursor.next_index.checked_sub(1),
         }
     }
-}
 
-impl<T: Clone> Index<T> {
     /// Try and get the value of the element at the index.
     ///
-    /// If it returns None, it means the element has been cleaned up by the underlying
+    /// If it returns None, it means the element has been cleaned up by the
     /// [CommitLog](struct.CommitLog.html).
-    pub fn get(&self) -> Option<T> {
-        self.index.upgrade().map(|i| i.read().value.clone())
-    }
-}
-
-impl<T: Clone + Debug> Debug for Index<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Index {{ {:?} }}", self.get())
+    pub fn get<T: Clone>(&self, log: &CommitLog<T>) -> Option<T> {
+        log.get(self.index?)
     }
 }
 
 /// A cursor which can be used to walk the elements of a [CommitLog](struct.CommitLog.html).
-pub struct Cursor<T> {
-    cursor: Pointer<T>,
+///
+/// The cursor is a plain offset, its elements are read through the commit log it was created from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Cursor {
     next_index: usize,
-    to_head: Pointer<T>,
-    to_head_index: Arc<AtomicUsize>,
 }
 
-impl<T> Cursor<T> {
+impl Cursor {
     /// Create a new cursor at the head (beginning) of the [CommitLog](struct.CommitLog.html).
-    pub fn new_head(log: &CommitLog<T>) -> Self {
+    pub fn new_head<T>(log: &CommitLog<T>) -> Self {
         Cursor {
-            cursor: Arc::downgrade(&log.to_head),
-            next_index: log.to_head_index.load(Ordering::SeqCst),
-            to_head: Arc::downgrade(&log.to_head),
-            to_head_index: Arc::clone(&log.to_head_index),
+            next_index: log.head_index(),
         }
     }
 
     /// Create a new cursor at the tail (end) of the [CommitLog](struct.CommitLog.html).
-    pub fn new_tail(log: &CommitLog<T>) -> Self {
-        match log.tail.as_ref() {
-            Some(tail) => Cursor {
-                cursor: Arc::downgrade(tail),
-                next_index: log.to_head_index.load(Ordering::SeqCst) + log.len(),
-                to_head: Arc::downgrade(&log.to_head),
-                to_head_index: Arc::clone(&log.to_head_index),
-            },
-            // The commit log does not have a tail element so simply use its head.
-            None => Cursor::new_head(log),
+    pub fn new_tail<T>(log: &CommitLog<T>) -> Self {
+        Cursor {
+            next_index: log.next_index(),
         }
     }
 
+    /// Create a new cursor which will retrieve the element at `index` with next.
+    ///
+    /// Returns None if the element at `index` has already been cleaned up. If `index` is past the
+    /// tail of the [CommitLog](struct.CommitLog.html) the cursor is created at the tail.
+    pub fn new_at<T>(log: &CommitLog<T>, index: usize) -> Option<Self> {
+        if index < log.head_index() {
+            return None;
+        }
+        Some(Cursor {
+            next_index: index.min(log.next_index()),
+        })
+    }
+
     /// Get the index of the element the cursor will retrieve with next.
     ///
-    /// This assumes the cursor is pointing to a valid element if it is not the index may be much
-    /// larger as the cursor is moved to the [CommitLog](struct.CommitLog.html)'s head.
+    /// If the element has already been cleaned up the cursor moves to the
+    /// [CommitLog](struct.CommitLog.html)'s head with the next call to next.
     pub fn next_index(&self) -> usize {
         self.next_index
     }
-}
 
-impl<T: Clone> Cursor<T> {
+    /// Move the cursor so it retrieves the element at `index` with next.
+    ///
+    /// If `index` is past the tail of the [CommitLog](struct.CommitLog.html) the cursor is moved
+    /// to the tail, if the element has already been cleaned up the cursor moves to the head with
+    /// the next call to next.
+    pub fn seek<T>(&mut self, log: &CommitLog<T>, index: usize) {
+        self.next_index = index.min(log.next_index());
+    }
+
+    /// Move the cursor to the tail of the [CommitLog](struct.CommitLog.html) skipping all of the
+    /// remaining elements.
+    ///
+    /// Returns the number of elements skipped.
+    pub fn skip_to_tail<T>(&mut self, log: &CommitLog<T>) -> usize {
+        // Elements which have been cleaned up are not counted as skipped.
+        let start = self.next_index.max(log.head_index());
+        self.next_index = log.next_index();
+        self.next_index.saturating_sub(start)
+    }
+
     /// Get the value of the next element of the cursor.
     ///
     /// If it returns None it means the cursor has reached the tail of the
     /// [CommitLog](struct.CommitLog.html).
-    pub fn next(&mut self) -> Option<T> {
-        match self.cursor.upgrade() {
-            // If the cursor is pointing to a valid element, see if it has a next element and if it
-            // does return its value and increment the cursor. If it does not have a next element,
-            // it means we have reached the tail of the commit log.
-            Some(cursor) => cursor.read().next.as_ref().map(|next| {
-                self.next_index += 1;
-                self.cursor = Arc::downgrade(next);
-                next.read().value.clone()
-            }),
-            // If the cursor has expired it means we are pointing to a cleaned up element.
-            // Promote the commit log's head to be the cursor and try calling next again.
-            None => {
-                if self.to_head.upgrade().is_some() {
-                    self.next_index = self.to_head_index.load(Ordering::SeqCst);
-                    self.cursor = Weak::clone(&self.to_head);
-                    self.next()
-                } else {
-                    // This should be an unreachable state as the commit log should always have a
-                    // valid to head, but for now simply return None.
-                    None
-                }
-            }
+    pub fn next<T: Clone>(&mut self, log: &CommitLog<T>) -> Option<T> {
+        // If the cursor is pointing to a cleaned up element move it to the commit log's head.
+        self.next_index = self.next_index.max(log.head_index());
+        let value = log.get(self.next_index);
+        if value.is_some() {
+            self.next_index += 1;
         }
+        value
     }
 
     /// Peek at the value of the next element of the cursor without progressing the cursor.
     ///
     /// If it returns None it means the cursor has reached the tail of the
-    /// [CommitLog](struct.CommitLog.html).
-    pub fn peek(&self) -> Option<T> {
-        match self.cursor.upgrade() {
-            Some(cursor) => cursor
-                .read()
-                .next
-                .as_ref()
-                .map(|next| next.read().value.clone()),
-            None => None,
-        }
-    }
-}
-
-impl<T: Clone + Debug> Debug for Cursor<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Cursor {{ {:?} }}", self.peek())
+    /// [CommitLog](struct.CommitLog.html) or is pointing to a cleaned up element.
+    pub fn peek<T: Clone>(&self, log: &CommitLog<T>) -> Option<T> {
+        log.get(self.next_index)
     }
 }
 
 /// A commit log like data structure.
 ///
-/// Allows pushing elements to its head and cleaning up elements from its tail.
+/// Allows pushing elements to its tail and cleaning up elements from its head. Accessing an
+/// element by its index is constant time.
 pub struct CommitLog<T> {
-    to_head: Link<T>,
-    tail: Node<T>,
-    length: usize,
-    to_head_index: Arc<AtomicUsize>,
+    segments: Segments<T>,
 }
 
-impl<T: Default> CommitLog<T> {
+impl<T> Default for CommitLog<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> CommitLog<T> {
     /// Create a new commit log
     pub fn new() -> Self {
+        Self::starting_at(0)
+    }
+
+    /// Create a new empty commit log whose first element will have `index`
+    pub fn starting_at(index: usize) -> Self {
+        let mut segments = VecDeque::new();
+        // The first segment starts at the index rounded down to a multiple of the segment size
+        if !index.is_multiple_of(SEGMENT_SIZE) {
+            let mut segment = Vec::with_capacity(SEGMENT_SIZE);
+            segment.resize_with(index % SEGMENT_SIZE, || None);
+            segments.push_back(segment);
+        }
         Self {
-            to_head: Element::new(Default::default()),
-            tail: None,
-            length: 0,
-            to_head_index: Arc::new(AtomicUsize::new(0)),
+            segments: Segments {
+                segments,
+                head_index: index,
+                next_index: index,
+            },
         }
     }
-}
 
-impl<T> CommitLog<T> {
     /// Returns `true` if there are no elements
     pub fn empty(&self) -> bool {
-        self.length == 0
+        self.len() == 0
     }
 
     /// Get the length
     pub fn len(&self) -> usize {
-        self.length
+        self.segments.next_index - self.segments.head_index
+    }
+
+    /// Get the index of the first element, indexes are never reused as elements are cleaned up
+    pub fn head_index(&self) -> usize {
+        self.segments.head_index
+    }
+
+    /// Get the index the next appended element will have
+    pub fn next_index(&self) -> usize {
+        self.segments.next_index
     }
 
     /// Add an element to the tail
     pub fn append(&mut self, value: T) {
-        let element = Element::new(value);
-        let new_tail = Some(Arc::clone(&element));
-        match self.tail.take() {
-            // Point the current tails next to the new tail
-            Some(old_tail) => {
-                let mut old_tail = old_tail.write();
-                old_tail.next = new_tail;
-            }
-            // There is not tail so point head to the new tail
-            None => {
-                let mut to_head = self.to_head.write();
-                to_head.next = new_tail;
-            }
-        };
-        self.tail = Some(element);
-        self.length += 1;
+        let segments = &mut self.segments;
+        if segments.next_index.is_multiple_of(SEGMENT_SIZE) {
+            segments
+                .segments
+                .push_back(Vec::with_capacity(SEGMENT_SIZE));
+        }
+        segments
+            .segments
+            .back_mut()
+            .expect("a segment with free space")
+            .push(Some(value));
+        segments.next_index += 1;
     }
 
     /// Remove elements from the head
     ///
-    /// Given a function expired, elements will be removed from the tail as long as expired returns
+    /// Given a function expired, elements will be removed from the head as long as expired returns
     /// true once expired returns false cleanup will exit.
     pub fn cleanup(&mut self, expired: &dyn Fn(&T) -> bool) -> usize {
+        let segments = &mut self.segments;
         let mut count = 0;
-        loop {
-            // Check if the head has expired
-            let did_expire;
-            match self.to_head.read().next.as_ref() {
-                Some(next) => {
-                    did_expire = expired(&next.read().value);
-                    if !did_expire {
-                        return count;
-                    }
-                }
-                None => {
-                    return count;
-                }
+        while segments.head().is_some_and(expired) {
+            let head_index = segments.head_index;
+            segments.segments[0][head_index % SEGMENT_SIZE] = None;
+            segments.head_index += 1;
+            // Drop the first segment once all of its elements have been cleaned up
+            if segments.head_index.is_multiple_of(SEGMENT_SIZE) {
+                segments.segments.pop_front();
             }
-            if did_expire {
-                count += 1;
-                self.remove_head();
-            }
-        }
-    }
-
-    fn remove_head(&mut self) {
-        let mut to_head = self.to_head.write();
-        // Take ownership of head element. This means it will be dropped at the end of its lifetime.
-        match to_head.next.take() {
-            // Take ownership of the head element's next element
-            Some(old_head) => match old_head.write().next.take() {
-                Some(new_head) => {
-                    to_head.next = Some(new_head);
-                }
-                // The last element of the commit log was removed
-                None => {
-                    self.tail = None;
-                }
-            },
-            // The commit log is empty
-            None => return,
+            count += 1;
         }
-        self.to_head_index.fetch_add(1, Ordering::SeqCst);
-        self.length -= 1;
+        count
     }
 }
 
-impl<T> Drop for CommitLog<T> {
-    // The generated drop method is a recursive algorithm which makes it easy to blow the stack
-    // this overrides with a iterative algorithm.
-    fn drop(&mut self) {
-        let mut node = self.to_head.write().next.take();
-        while let Some(link) = node {
-            node = link.write().next.take();
-        }
+impl<T: Clone> CommitLog<T> {
+    /// Get the value of the element at `index`
+    ///
+    /// Returns None if the element has been cleaned up or has not been appended yet.
+    pub fn get(&self, index: usize) -> Option<T> {
+        self.segments.get(index).cloned()
     }
 }
 
@@ -278,9 +265,48 @@ impl<T: Clone + Debug> Debug for CommitLog<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut cursor = Cursor::new_head(self);
         write!(f, "CommitLog {{ ")?;
-        while let Some(value) = cursor.next() {
+        while let Some(value) = cursor.next(self) {
             write!(f, "{:?} -> ", value)?;
         }
-        write!(f, " : {:?} }}", self.length)
+        write!(f, " : {:?} }}", self.len())
+    }
+}
+
+// The elements of a commit log which have not been cleaned up.
+struct Elements<'a, T>(&'a Segments<T>);
+
+impl<'a, T: Serialize> Serialize for Elements<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let segments = self.0;
+        serializer
+            .collect_seq((segments.head_index..segments.next_index).filter_map(|i| segments.get(i)))
+    }
+}
+
+/// A commit log is stored as the index of its first element and the elements which have not
+/// been cleaned up.
+impl<T: Serialize> Serialize for CommitLog<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CommitLog", 2)?;
+        state.serialize_field("head_index", &self.segments.head_index)?;
+        state.serialize_field("elements", &Elements(&self.segments))?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct StoredCommitLog<T> {
+    head_index: usize,
+    elements: Vec<T>,
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for CommitLog<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let stored = StoredCommitLog::deserialize(deserializer)?;
+        let mut log = CommitLog::starting_at(stored.head_index);
+        for element in stored.elements {
+            log.append(element);
+        }
+        Ok(log)
     }
 }
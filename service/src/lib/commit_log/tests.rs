@@ -18,46 +18,46 @@ fn commit_log_basic() {
     assert_eq!(log.len(), 4);
 
     println!("{:?}", cursor0);
-    assert_eq!(Some(1), cursor0.peek());
-    assert_eq!(Some(1), cursor0.next());
-    assert_eq!(Some(2), cursor0.peek());
-    assert_eq!(Some(2), cursor0.next());
-    assert_eq!(Some(3), cursor0.peek());
-    assert_eq!(Some(3), cursor0.next());
+    assert_eq!(Some(1), cursor0.peek(&log));
+    assert_eq!(Some(1), cursor0.next(&log));
+    assert_eq!(Some(2), cursor0.peek(&log));
+    assert_eq!(Some(2), cursor0.next(&log));
+    assert_eq!(Some(3), cursor0.peek(&log));
+    assert_eq!(Some(3), cursor0.next(&log));
     let index0 = Index::new(&cursor0);
     println!("{:?}", index0);
-    assert_eq!(Some(3), index0.get());
-    assert_eq!(Some(3), index0.get());
-    assert_eq!(Some(4), cursor0.next());
-    assert_eq!(None, cursor0.next());
-    assert_eq!(None, cursor0.peek());
-    assert_eq!(None, cursor0.next());
+    assert_eq!(Some(3), index0.get(&log));
+    assert_eq!(Some(3), index0.get(&log));
+    assert_eq!(Some(4), cursor0.next(&log));
+    assert_eq!(None, cursor0.next(&log));
+    assert_eq!(None, cursor0.peek(&log));
+    assert_eq!(None, cursor0.next(&log));
 
     let mut cursor1 = Cursor::new_head(&log);
-    assert_eq!(Some(1), cursor1.next());
+    assert_eq!(Some(1), cursor1.next(&log));
 
     log.append(5);
-    assert_eq!(Some(5), cursor0.next());
+    assert_eq!(Some(5), cursor0.next(&log));
 
     log.cleanup(&|t: &u32| t <= &5);
-    assert_eq!(None, index0.get());
-    assert_eq!(None, cursor1.peek());
-    assert_eq!(None, cursor1.next());
+    assert_eq!(None, index0.get(&log));
+    assert_eq!(None, cursor1.peek(&log));
+    assert_eq!(None, cursor1.next(&log));
     log.append(6);
-    assert_eq!(Some(6), cursor0.next());
-    assert_eq!(Some(6), cursor1.next());
+    assert_eq!(Some(6), cursor0.next(&log));
+    assert_eq!(Some(6), cursor1.next(&log));
     assert_eq!(log.len(), 1);
 
     log.append(7);
     let mut cursor2 = Cursor::new_tail(&log);
-    assert_eq!(None, cursor2.next());
+    assert_eq!(None, cursor2.next(&log));
     log.append(8);
-    assert_eq!(Some(8), cursor2.next());
-    assert_eq!(None, cursor2.next());
+    assert_eq!(Some(8), cursor2.next(&log));
+    assert_eq!(None, cursor2.next(&log));
 
     log.cleanup(&|t: &u32| t < &8);
-    assert_eq!(Some(8), cursor0.next());
-    assert_eq!(Some(8), cursor1.next());
+    assert_eq!(Some(8), cursor0.next(&log));
+    assert_eq!(Some(8), cursor1.next(&log));
 }
 
 #[test]
@@ -78,7 +78,7 @@ fn commit_log_random() {
             }
             let num_nexts = rng.gen_range(0, r2 - r1);
             for i in 0..num_nexts {
-                assert_eq!(Some(expected[i]), cursor.next());
+                assert_eq!(Some(expected[i]), cursor.next(&log));
             }
             expected = expected.split_off(num_nexts);
             let cleanup_threshold = rng.gen_range(r1, r2);
@@ -87,3 +87,141 @@ fn commit_log_random() {
         }
     }
 }
+
+#[test]
+fn commit_log_indexes() {
+    let mut log = CommitLog::new();
+    assert_eq!(0, log.head_index());
+    assert_eq!(0, log.next_index());
+    for i in 0..10 {
+        log.append(i);
+    }
+    assert_eq!(0, log.head_index());
+    assert_eq!(10, log.next_index());
+
+    let mut cursor = Cursor::new_at(&log, 4).unwrap();
+    assert_eq!(4, cursor.next_index());
+    assert_eq!(Some(4), cursor.next(&log));
+    let mut cursor = Cursor::new_at(&log, 20).unwrap();
+    assert_eq!(10, cursor.next_index());
+    assert_eq!(None, cursor.next(&log));
+
+    log.cleanup(&|t: &u32| t < &6);
+    assert_eq!(6, log.head_index());
+    assert_eq!(10, log.next_index());
+    assert!(Cursor::new_at(&log, 5).is_none());
+    let mut cursor = Cursor::new_at(&log, 6).unwrap();
+    assert_eq!(Some(6), cursor.next(&log));
+    log.append(10);
+    let mut cursor = Cursor::new_at(&log, 10).unwrap();
+    assert_eq!(Some(10), cursor.next(&log));
+}
+
+#[test]
+fn commit_log_skip_to_tail() {
+    let mut log = CommitLog::new();
+    let mut cursor = Cursor::new_head(&log);
+    assert_eq!(0, cursor.skip_to_tail(&log));
+    for i in 0..5 {
+        log.append(i);
+    }
+    assert_eq!(Some(0), cursor.next(&log));
+    assert_eq!(4, cursor.skip_to_tail(&log));
+    assert_eq!(5, cursor.next_index());
+    assert_eq!(None, cursor.next(&log));
+    log.append(5);
+    assert_eq!(Some(5), cursor.next(&log));
+
+    // Skip from a cleaned up element
+    let mut cursor = Cursor::new_head(&log);
+    assert_eq!(Some(0), cursor.next(&log));
+    log.cleanup(&|t: &u32| t < &3);
+    assert_eq!(3, cursor.skip_to_tail(&log));
+    assert_eq!(None, cursor.next(&log));
+}
+
+#[test]
+fn commit_log_seek() {
+    let mut log = CommitLog::new();
+    for i in 0..5 {
+        log.append(i);
+    }
+    let mut cursor = Cursor::new_tail(&log);
+    cursor.seek(&log, 2);
+    assert_eq!(Some(2), cursor.next(&log));
+    cursor.seek(&log, 10);
+    assert_eq!(5, cursor.next_index());
+
+    // Seeking to a cleaned up element moves to the head
+    log.cleanup(&|t: &u32| t < &3);
+    cursor.seek(&log, 1);
+    assert_eq!(1, cursor.next_index());
+    assert_eq!(Some(3), cursor.next(&log));
+}
+
+#[test]
+fn commit_log_segments() {
+    let mut log = CommitLog::new();
+    let count = 3 * SEGMENT_SIZE + 10;
+    for i in 0..count {
+        log.append(i);
+    }
+    let mut cursor = Cursor::new_at(&log, SEGMENT_SIZE - 1).unwrap();
+    let index = Index::new(&cursor);
+    assert_eq!(Some(SEGMENT_SIZE - 2), index.get(&log));
+    assert_eq!(Some(SEGMENT_SIZE - 1), cursor.next(&log));
+    assert_eq!(Some(SEGMENT_SIZE), cursor.next(&log));
+    assert_eq!(Some(2 * SEGMENT_SIZE + 5), log.get(2 * SEGMENT_SIZE + 5));
+    assert_eq!(None, log.get(count));
+
+    // Cleanup across segment boundaries
+    assert_eq!(
+        2 * SEGMENT_SIZE + 1,
+        log.cleanup(&|t: &usize| t <= &(2 * SEGMENT_SIZE))
+    );
+    assert_eq!(2 * SEGMENT_SIZE + 1, log.head_index());
+    assert_eq!(count - 2 * SEGMENT_SIZE - 1, log.len());
+    assert_eq!(None, index.get(&log));
+    assert_eq!(None, log.get(2 * SEGMENT_SIZE));
+    assert_eq!(Some(2 * SEGMENT_SIZE + 1), cursor.next(&log));
+
+    // Empty the log at a segment boundary and keep appending
+    let mut log = CommitLog::new();
+    for i in 0..SEGMENT_SIZE {
+        log.append(i);
+    }
+    assert_eq!(SEGMENT_SIZE, log.cleanup(&|_| true));
+    assert!(log.empty());
+    log.append(SEGMENT_SIZE);
+    assert_eq!(Some(SEGMENT_SIZE), log.get(SEGMENT_SIZE));
+    assert_eq!(Some(SEGMENT_SIZE), Cursor::new_head(&log).next(&log));
+}
+
+#[test]
+fn commit_log_serialization() {
+    let mut log = CommitLog::new();
+    for i in 0..2 * SEGMENT_SIZE {
+        log.append(i);
+    }
+    log.cleanup(&|t: &usize| t < &(SEGMENT_SIZE + 3));
+    let json = serde_json::to_string(&log).unwrap();
+    assert!(json.starts_with(&format!("{{\"head_index\":{},", SEGMENT_SIZE + 3)));
+
+    let mut restored: CommitLog<usize> = serde_json::from_str(&json).unwrap();
+    assert_eq!(log.head_index(), restored.head_index());
+    assert_eq!(log.next_index(), restored.next_index());
+    assert_eq!(None, restored.get(SEGMENT_SIZE + 2));
+    assert_eq!(Some(SEGMENT_SIZE + 3), restored.get(SEGMENT_SIZE + 3));
+    let mut cursor = Cursor::new_head(&restored);
+    assert_eq!(Some(SEGMENT_SIZE + 3), cursor.next(&restored));
+
+    // The restored log keeps appending and cleaning up across segments
+    restored.append(2 * SEGMENT_SIZE);
+    assert_eq!(Some(2 * SEGMENT_SIZE), restored.get(2 * SEGMENT_SIZE));
+    assert_eq!(
+        SEGMENT_SIZE - 2,
+        restored.cleanup(&|t: &usize| t < &(2 * SEGMENT_SIZE + 1))
+    );
+    assert_eq!(2 * SEGMENT_SIZE + 1, restored.head_index());
+    assert!(restored.empty());
+}
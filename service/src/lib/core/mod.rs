@@ -1,65 +1,65 @@
 //! The core pubsub module containing [Message](struct.Message.html), [Topic](struct.Topic.html),
-//! and [Subscription](struct.Subscription.html).
+//! [Subscription](struct.Subscription.html), and [Namespace](struct.Namespace.html).
 
+use self::pending::Pending;
 use crate::commit_log::{CommitLog, Cursor, Index};
 use chrono::prelude::*;
 use chrono::Duration;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use uuid::Uuid;
 
+mod pending;
 #[cfg(test)]
 mod tests;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct InternalMessage {
     id: Uuid,
+    partition: u32,
+    offset: u64,
     time: DateTime<Utc>,
-    data: String,
+    // The data is shared by every clone of the message so pulling does not copy it.
+    data: Arc<str>,
+    origin: Option<Arc<str>>,
+    #[serde(default)]
+    partition_key: Option<Arc<str>>,
 }
 
 impl InternalMessage {
-    fn new(data: String) -> Self {
+    fn new(
+        id: Uuid,
+        time: DateTime<Utc>,
+        data: String,
+        origin: Option<String>,
+        partition_key: Option<String>,
+        partition: u32,
+        offset: u64,
+    ) -> Self {
         InternalMessage {
-            id: Uuid::new_v4(),
-            time: Utc::now(),
-            data,
+            id,
+            partition,
+            offset,
+            time,
+            data: Arc::from(data),
+            origin: origin.map(Arc::from),
+            partition_key: partition_key.map(Arc::from),
         }
     }
-}
-
-impl From<String> for InternalMessage {
-    fn from(data: String) -> Self {
-        Self::new(data)
-    }
-}
-
-impl Default for InternalMessage {
-    fn default() -> Self {
-        InternalMessage {
-            id: Default::default(),
-            time: Utc::now(),
-            data: Default::default(),
-        }
-    }
-}
-
-#[derive(Debug)]
-struct PendingMessage {
-    time_sent: DateTime<Utc>,
-    message_id: Uuid,
-    tries: u32,
-    index: Index<InternalMessage>,
-}
 
-impl PendingMessage {
-    fn new(message_id: Uuid, tries: u32, index: Index<InternalMessage>) -> Self {
-        Self {
-            time_sent: Utc::now(),
-            message_id,
+    fn into_message(self, tries: u32) -> Message {
+        Message {
+            id: self.id,
+            partition: self.partition,
+            offset: self.offset,
+            time: self.time,
             tries,
-            index,
+            data: self.data,
+            origin: self.origin,
+            partition_key: self.partition_key,
         }
     }
 }
@@ -69,12 +69,25 @@ impl PendingMessage {
 pub struct Message {
     /// Unique identifier for this message.
     pub id: Uuid,
+    /// Partition of its [Topic](struct.Topic.html) the message was published to.
+    pub partition: u32,
+    /// Position of the message in its partition, offsets increase monotonically and are never
+    /// reused.
+    pub offset: u64,
     /// Time the message was published.
     pub time: DateTime<Utc>,
     /// Number of times the message has been tried (pulled).
     pub tries: u32,
-    /// Actual message data.
-    pub data: String,
+    /// Actual message data, shared with every other copy of the message.
+    pub data: Arc<str>,
+    /// Name of the Courier instance the message was first published to if it was bridged from
+    /// another instance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Arc<str>>,
+    /// Key the message was published with, every message with the same key is in the same
+    /// partition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_key: Option<Arc<str>>,
 }
 
 impl Message {
@@ -82,187 +95,669 @@ impl Message {
     pub fn new(data: String) -> Self {
         Message {
             id: Uuid::new_v4(),
+            partition: 0,
+            offset: 0,
             time: Utc::now(),
             tries: 0,
+            data: Arc::from(data),
+            origin: None,
+            partition_key: None,
+        }
+    }
+}
+
+/// The data of a [Message](struct.Message.html) to publish.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RawMessage {
+    /// Actual message data.
+    pub data: String,
+    /// Messages with the same key are published to the same partition, messages without a key
+    /// are distributed round robin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_key: Option<String>,
+    /// Partition to publish the message to, takes precedence over the partition key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<u32>,
+    /// Name of the Courier instance the message was first published to, set when bridging
+    /// messages between instances.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+impl RawMessage {
+    /// Create a new [RawMessage](struct.RawMessage.html) without a partition key.
+    pub fn new(data: String) -> Self {
+        Self {
             data,
+            partition_key: None,
+            partition: None,
+            origin: None,
         }
     }
+
+    /// Create a new [RawMessage](struct.RawMessage.html) with a partition key.
+    pub fn with_partition_key(data: String, partition_key: String) -> Self {
+        Self {
+            data,
+            partition_key: Some(partition_key),
+            partition: None,
+            origin: None,
+        }
+    }
+}
+
+/// The delivery status of a [Message](struct.Message.html) for a
+/// [Subscription](struct.Subscription.html).
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum DeliveryStatus {
+    /// The message has not been pulled yet.
+    NotDelivered,
+    /// The message was pulled and is waiting to be acked before its deadline.
+    Pending {
+        /// Number of times the message has been tried (pulled).
+        tries: u32,
+        /// Time the message will be resent if it is not acked.
+        deadline: DateTime<Utc>,
+    },
+    /// The message was pulled but not acked before its deadline, it will be resent.
+    Expired {
+        /// Number of times the message has been tried (pulled).
+        tries: u32,
+        /// Time the ack deadline was reached.
+        deadline: DateTime<Utc>,
+    },
+    /// The message was acked.
+    Acked,
+}
+
+/// The [DeliveryStatus](enum.DeliveryStatus.html) of a message for a single subscription.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubscriptionDeliveryStatus {
+    /// Namespace of the subscription.
+    pub namespace: String,
+    /// Name of the subscription.
+    pub name: String,
+    /// Delivery status of the message.
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+}
+
+/// A retained [Message](struct.Message.html) and its delivery status for every subscription of
+/// its topic.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MessageStatus {
+    /// Namespace of the topic the message was published to.
+    pub topic_namespace: String,
+    /// Topic name the message was published to.
+    pub topic: String,
+    /// The message.
+    pub message: Message,
+    /// Delivery status of the message for each subscription of the topic.
+    pub subscriptions: Vec<SubscriptionDeliveryStatus>,
+}
+
+/// A consumer of a [Subscription](struct.Subscription.html) which identified itself when
+/// pulling.
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Consumer {
+    /// Identifier chosen by the consumer.
+    pub id: String,
+    /// Time the consumer last pulled.
+    pub last_seen: DateTime<Utc>,
+    /// Number of messages pulled by the consumer which are waiting to be acked.
+    pub in_flight: usize,
+}
+
+/// A message of a [Subscription](struct.Subscription.html) which was pulled and is waiting to
+/// be acked.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PendingDelivery {
+    /// Unique identifier of the message.
+    pub id: Uuid,
+    /// Partition of the topic the message was published to.
+    pub partition: u32,
+    /// Position of the message in its partition.
+    pub offset: u64,
+    /// The consumer holding the message if it identified itself when pulling.
+    pub consumer: Option<String>,
+    /// Number of times the message has been tried (pulled).
+    pub tries: u32,
+    /// Time the message will be resent if it is not acked.
+    pub deadline: DateTime<Utc>,
 }
 
 /// A subscription used to subscribe to a [Topic](struct.Topic.html).
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Subscription {
-    /// Unique name for this subscription.
+    /// Namespace of the subscription.
+    pub namespace: String,
+    /// Unique name for this subscription within its namespace.
     pub name: String,
+    /// Namespace of the topic the subscription is subscribed to.
+    pub topic_namespace: String,
     /// Topic name the subscription is subscribed to.
     pub topic: String,
+    /// Labels used to organize and select subscriptions.
+    pub labels: HashMap<String, String>,
+    /// Free form description of the subscription.
+    pub description: String,
     /// Amount of time given to ack a message.
+    #[serde(with = "crate::millis")]
     pub ack_deadline: Duration,
     /// Time to live of the subscription.
+    #[serde(with = "crate::millis")]
     pub ttl: Duration,
+    /// Amount of time after which the messages of a consumer which stopped pulling are released.
+    #[serde(with = "crate::millis")]
+    pub consumer_timeout: Duration,
     /// Time the subscription was created.
     pub created: DateTime<Utc>,
     /// Time the subscription was last updated.
     pub updated: DateTime<Utc>,
-    cursor: Cursor<InternalMessage>,
-    pending: VecDeque<PendingMessage>,
-    pending_ids: HashSet<Uuid>,
-    acked: HashSet<Uuid>,
+    // The time each identified consumer last pulled.
+    consumers: HashMap<String, DateTime<Utc>>,
+    // A cursor, the offset the subscription started at, and the pending messages of each
+    // partition of the topic.
+    cursors: Vec<Cursor>,
+    start_offsets: Vec<u64>,
+    pending: Vec<Pending>,
+    // The partition the next pull starts at so all partitions are pulled from evenly.
+    next_partition: usize,
 }
 
 impl Subscription {
     /// Create a new subscription at the head (beginning) of the [Topic](struct.Topic.html).
-    pub fn new_head(name: &str, topic: &Topic, ack_deadline: Duration, ttl: Duration) -> Self {
+    pub fn new_head(
+        namespace: &str,
+        name: &str,
+        topic: &Topic,
+        ack_deadline: Duration,
+        ttl: Duration,
+    ) -> Self {
         let now = Utc::now();
         Self {
+            namespace: String::from(namespace),
             name: String::from(name),
+            topic_namespace: topic.namespace.clone(),
             topic: topic.name.clone(),
+            labels: HashMap::new(),
+            description: String::new(),
             ack_deadline,
             ttl,
+            consumer_timeout: Duration::seconds(0),
             created: now,
             updated: now,
-            cursor: Cursor::new_head(&topic.log),
-            pending: VecDeque::new(),
-            pending_ids: HashSet::new(),
-            acked: HashSet::new(),
+            consumers: HashMap::new(),
+            cursors: topic
+                .partitions
+                .iter()
+                .map(|p| Cursor::new_head(&p.log))
+                .collect(),
+            start_offsets: topic
+                .partitions
+                .iter()
+                .map(Partition::head_offset)
+                .collect(),
+            pending: topic
+                .partitions
+                .iter()
+                .map(|_| Pending::default())
+                .collect(),
+            next_partition: 0,
         }
     }
 
     /// Create a new subscription at the tail (end) of the [Topic](struct.Topic.html).
-    pub fn new_tail(name: &str, topic: &Topic, ack_deadline: Duration, ttl: Duration) -> Self {
+    pub fn new_tail(
+        namespace: &str,
+        name: &str,
+        topic: &Topic,
+        ack_deadline: Duration,
+        ttl: Duration,
+    ) -> Self {
         let now = Utc::now();
         Self {
+            namespace: String::from(namespace),
             name: String::from(name),
+            topic_namespace: topic.namespace.clone(),
             topic: topic.name.clone(),
+            labels: HashMap::new(),
+            description: String::new(),
             ack_deadline,
             ttl,
+            consumer_timeout: Duration::seconds(0),
             created: now,
             updated: now,
-            cursor: Cursor::new_tail(&topic.log),
-            pending: VecDeque::new(),
-            pending_ids: HashSet::new(),
-            acked: HashSet::new(),
+            consumers: HashMap::new(),
+            cursors: topic
+                .partitions
+                .iter()
+                .map(|p| Cursor::new_tail(&p.log))
+                .collect(),
+            start_offsets: topic
+                .partitions
+                .iter()
+                .map(Partition::next_offset)
+                .collect(),
+            pending: topic
+                .partitions
+                .iter()
+                .map(|_| Pending::default())
+                .collect(),
+            next_partition: 0,
         }
     }
 
-    /// Pull a message.
+    /// Pull a message of the subscribed `topic`.
     ///
     /// If `pull` returns `None` there are no [Message](struct.Message.html)s to pull. This will
     /// try and return [Message](struct.Message.html)s that have reached there ack deadline, but
     /// have not been acked before pulling new messages.
-    pub fn pull(&mut self) -> Option<Message> {
-        self.update();
+    pub fn pull(&mut self, topic: &Topic) -> Option<Message> {
+        self.pull_with(topic, None, None, Utc::now())
+    }
 
+    /// Pull a message on behalf of `consumer` from one of `partitions` or from any partition if
+    /// `partitions` is None.
+    ///
+    /// Messages of a partition are pulled in order, partitions are pulled from round robin.
+    /// Partitions which do not exist are ignored. The pulled message is held by the consumer
+    /// until it is acked, its ack deadline after `now` is reached, or the consumer is released.
+    pub fn pull_with(
+        &mut self,
+        topic: &Topic,
+        consumer: Option<&str>,
+        partitions: Option<&[u32]>,
+        now: DateTime<Utc>,
+    ) -> Option<Message> {
+        self.update(now);
+        if let Some(consumer) = consumer {
+            self.consumer_seen(consumer, now);
+        }
+
+        let count = self.cursors.len();
+        for i in 0..count {
+            let partition = (self.next_partition + i) % count;
+            if partitions.is_some_and(|ps| !ps.contains(&(partition as u32))) {
+                continue;
+            }
+            let log = match topic.partitions.get(partition) {
+                Some(p) => &p.log,
+                None => continue,
+            };
+            if let Some(message) = self.pull_partition(log, partition, consumer, now) {
+                self.next_partition = partition + 1;
+                return Some(message);
+            }
+        }
+        None
+    }
+
+    /// Check if a pull at `now` would not return any message and only refresh the update time
+    /// and the time `consumer` was last seen while they are less than half their timeout old.
+    ///
+    /// Such a pull can be skipped without the subscription expiring or the consumer being
+    /// released.
+    pub fn pull_is_idle(
+        &self,
+        topic: &Topic,
+        consumer: Option<&str>,
+        partitions: Option<&[u32]>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if self.ttl > Duration::seconds(0) && now - self.updated >= self.ttl / 2 {
+            return false;
+        }
+        if let Some(consumer) = consumer {
+            match self.consumers.get(consumer) {
+                Some(&last_seen) if now - last_seen < self.consumer_timeout / 2 => {}
+                Some(_) if self.consumer_timeout == Duration::seconds(0) => {}
+                _ => return false,
+            }
+        }
+        self.cursors
+            .iter()
+            .zip(&self.pending)
+            .zip(&topic.partitions)
+            .enumerate()
+            .filter(|(partition, _)| partitions.is_none_or(|ps| ps.contains(&(*partition as u32))))
+            .all(|(_, ((cursor, pending), p))| {
+                !pending.has_expired(now) && cursor.peek(&p.log).is_none()
+            })
+    }
+
+    fn pull_partition(
+        &mut self,
+        log: &CommitLog<InternalMessage>,
+        partition: usize,
+        consumer: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Option<Message> {
         // Check if there are any pending messages. If not, try and pull a new one from the cursor.
-        let (internal_message, index, tries) = self
-            .check_pending()
-            .unwrap_or_else(|| (self.cursor.next(), Index::new(&self.cursor), 1));
+        let (internal_message, index, tries) =
+            self.check_pending(log, partition, now).unwrap_or_else(|| {
+                let cursor = &mut self.cursors[partition];
+                (cursor.next(log), Index::new(cursor), 1)
+            });
 
         // If there is a message to send add it as a pending message
         if let Some(m) = internal_message.as_ref() {
-            self.pending_ids.insert(m.id);
-            self.pending
-                .push_back(PendingMessage::new(m.id, tries, index));
+            let deadline = now + self.ack_deadline;
+            self.pending[partition].insert(m.id, tries, index, deadline, consumer);
         }
 
-        internal_message.map(|m| Message {
-            id: m.id,
-            time: m.time,
-            tries,
-            data: m.data,
-        })
+        internal_message.map(|m| m.into_message(tries))
     }
 
-    /// Ack the message with `id`.
+    /// Ack the message with `id` at `now`.
     ///
     /// Return true if the ack was successful.
-    pub fn ack(&mut self, id: Uuid) -> bool {
-        self.update();
+    pub fn ack(&mut self, id: Uuid, now: DateTime<Utc>) -> bool {
+        self.update(now);
 
         // Check if the id is a pending id. If it is not, the message either reached its ack
         // deadline or it is an invalid id.
-        if self.pending_ids.remove(&id) {
-            self.acked.insert(id);
-            return true;
-        }
-        false
+        self.pending.iter_mut().any(|pending| pending.remove(&id))
     }
 
-    /// Ack many message `id`s.
+    /// Ack many message `id`s at `now`.
     ///
     /// Returns all ids which were successfully acked.
-    pub fn ack_many(&mut self, ids: &[Uuid]) -> Vec<Uuid> {
+    pub fn ack_many(&mut self, ids: &[Uuid], now: DateTime<Utc>) -> Vec<Uuid> {
         let mut acked = Vec::with_capacity(ids.len());
         for id in ids {
-            if self.ack(*id) {
+            if self.ack(*id, now) {
                 acked.push(*id);
             }
         }
         acked
     }
 
+    /// Nack many message `id`s at `now` so they are resent with the next pull instead of at
+    /// their ack deadline.
+    ///
+    /// Returns all ids which were pending.
+    pub fn nack_many(&mut self, ids: &[Uuid], now: DateTime<Utc>) -> Vec<Uuid> {
+        self.update(now);
+        ids.iter()
+            .filter(|id| {
+                self.pending
+                    .iter_mut()
+                    .any(|pending| pending.release_message(id, now))
+            })
+            .cloned()
+            .collect()
+    }
+
     /// Set the ack deadline.
+    ///
+    /// The deadline of a message is set when it is pulled so this only applies to messages pulled
+    /// afterwards.
     pub fn set_ack_deadline(&mut self, ack_deadline: Duration) {
-        self.update();
-
         self.ack_deadline = ack_deadline;
     }
 
     /// Set the time to live.
     pub fn set_ttl(&mut self, ttl: Duration) {
-        self.update();
-
         self.ttl = ttl;
     }
 
-    /// Set the updated time to now.
-    pub fn update(&mut self) {
-        self.updated = Utc::now();
+    /// Set the consumer timeout, use 0 to never release the messages of inactive consumers.
+    pub fn set_consumer_timeout(&mut self, consumer_timeout: Duration) {
+        self.consumer_timeout = consumer_timeout;
+    }
+
+    /// Set the labels.
+    pub fn set_labels(&mut self, labels: HashMap<String, String>) {
+        self.labels = labels;
+    }
+
+    /// Set the description.
+    pub fn set_description(&mut self, description: String) {
+        self.description = description;
+    }
+
+    /// Set the updated time, the setters leave it to their caller.
+    pub fn update(&mut self, now: DateTime<Utc>) {
+        self.updated = now;
     }
 
-    /// Get the index of the element a pull with no pending elements will retrieve with next.
+    /// Get the time the subscription will expire if it is not updated or None if it never
+    /// expires.
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        if self.ttl == Duration::seconds(0) {
+            return None;
+        }
+        Some(self.updated + self.ttl)
+    }
+
+    /// Get the identified consumers ordered by id.
+    pub fn consumers(&self) -> Vec<Consumer> {
+        let mut in_flight: HashMap<&str, usize> = HashMap::new();
+        for m in self.pending.iter().flat_map(Pending::iter) {
+            if let Some(consumer) = m.consumer.as_deref() {
+                *in_flight.entry(consumer).or_insert(0) += 1;
+            }
+        }
+        let mut consumers: Vec<Consumer> = self
+            .consumers
+            .iter()
+            .map(|(id, &last_seen)| Consumer {
+                id: id.clone(),
+                last_seen,
+                in_flight: in_flight.get(&**id).cloned().unwrap_or(0),
+            })
+            .collect();
+        consumers.sort();
+        consumers
+    }
+
+    /// Get the pending messages ordered by partition and offset, messages which have been cleaned
+    /// up by the subscribed `topic` are left out.
+    pub fn pending_deliveries(&self, topic: &Topic) -> Vec<PendingDelivery> {
+        let mut deliveries: Vec<PendingDelivery> = self
+            .pending
+            .iter()
+            .zip(&topic.partitions)
+            .flat_map(|(pending, p)| pending.iter().map(move |m| (m, &p.log)))
+            .filter_map(|(m, log)| {
+                let message = m.index.get(log)?;
+                Some(PendingDelivery {
+                    id: message.id,
+                    partition: message.partition,
+                    offset: message.offset,
+                    consumer: m.consumer.clone(),
+                    tries: m.tries,
+                    deadline: m.deadline,
+                })
+            })
+            .collect();
+        deliveries.sort_by_key(|d| (d.partition, d.offset));
+        deliveries
+    }
+
+    /// Release all messages held by `consumer` at `now` so they are resent with the next pull
+    /// and forget the consumer.
     ///
-    /// This assumes the cursor is pointing to a valid element if it is not the index may be much
-    /// larger as the cursor is moved to the [Topic](struct.Topic.html)'s last valid
-    /// [Message](struct.Message.html).
+    /// Returns the number of messages released or None if the consumer is not known.
+    pub fn release_consumer(&mut self, consumer: &str, now: DateTime<Utc>) -> Option<usize> {
+        self.consumers.remove(consumer)?;
+        Some(
+            self.pending
+                .iter_mut()
+                .map(|pending| pending.release(consumer, now))
+                .sum(),
+        )
+    }
+
+    /// Release the consumers which have not pulled within the consumer timeout before `now`.
+    ///
+    /// Returns the number of messages released.
+    pub fn release_inactive(&mut self, now: DateTime<Utc>) -> usize {
+        if self.consumer_timeout == Duration::seconds(0) {
+            return 0;
+        }
+        let inactive_since = now - self.consumer_timeout;
+        let mut inactive: Vec<String> = self
+            .consumers
+            .iter()
+            .filter(|(_, &last_seen)| last_seen <= inactive_since)
+            .map(|(id, _)| id.clone())
+            .collect();
+        // Release in a stable order so every replica requeues the messages in the same order
+        inactive.sort();
+        inactive
+            .iter()
+            .filter_map(|consumer| self.release_consumer(consumer, now))
+            .sum()
+    }
+
+    /// Get the time the least recently seen consumer will be inactive or None if there are no
+    /// identified consumers or they are never released.
+    pub fn consumers_expire(&self) -> Option<DateTime<Utc>> {
+        if self.consumer_timeout == Duration::seconds(0) {
+            return None;
+        }
+        self.consumers
+            .values()
+            .min()
+            .map(|&last_seen| last_seen + self.consumer_timeout)
+    }
+
+    fn consumer_seen(&mut self, consumer: &str, now: DateTime<Utc>) {
+        match self.consumers.get_mut(consumer) {
+            Some(last_seen) => *last_seen = now,
+            None => {
+                self.consumers.insert(String::from(consumer), now);
+            }
+        }
+    }
+
+    /// Get the sum over all partitions of the offset a pull with no pending messages will
+    /// retrieve next.
+    ///
+    /// This assumes the cursors are pointing to valid messages if they are not the index may be
+    /// smaller as the cursors are moved to the [Topic](struct.Topic.html)'s oldest retained
+    /// [Message](struct.Message.html)s.
     pub fn next_index(&self) -> usize {
-        self.cursor.next_index()
+        self.cursors.iter().map(Cursor::next_index).sum()
+    }
+
+    /// Get the number of partitions of the subscribed [Topic](struct.Topic.html).
+    pub fn partitions(&self) -> u32 {
+        self.cursors.len() as u32
+    }
+
+    /// Get the offset a pull with no pending messages will retrieve next for each partition.
+    pub fn offsets(&self) -> Vec<u64> {
+        self.cursors
+            .iter()
+            .map(|cursor| cursor.next_index() as u64)
+            .collect()
+    }
+
+    /// Get the number of [Message](struct.Message.html)s of the subscribed `topic` which were not
+    /// pulled yet and the time the oldest of them was published.
+    pub fn backlog(&self, topic: &Topic) -> (usize, Option<DateTime<Utc>>) {
+        let mut messages = 0;
+        let mut oldest: Option<DateTime<Utc>> = None;
+        for (cursor, p) in self.cursors.iter().zip(&topic.partitions) {
+            // Messages the cursor points at may have been cleaned up
+            let next = cursor.next_index().max(p.log.head_index());
+            messages += p.log.next_index().saturating_sub(next);
+            if let Some(m) = p.log.get(next) {
+                oldest = Some(oldest.map_or(m.time, |t| t.min(m.time)));
+            }
+        }
+        (messages, oldest)
+    }
+
+    /// Move the cursor of `partition` of the subscribed `topic` so the next pull from it
+    /// retrieves the message at `offset`, dropping the pending messages of the partition.
+    ///
+    /// Returns false if the partition does not exist.
+    pub fn seek(&mut self, topic: &Topic, partition: u32, offset: u64, now: DateTime<Utc>) -> bool {
+        self.update(now);
+
+        let partition = partition as usize;
+        match (
+            self.cursors.get_mut(partition),
+            topic.partitions.get(partition),
+        ) {
+            (Some(cursor), Some(p)) => {
+                cursor.seek(&p.log, offset as usize);
+                self.pending[partition].clear();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Purge the backlog by moving to the tail of the subscribed `topic` and dropping all pending
+    /// messages as if every message was acked.
+    ///
+    /// Returns the number of messages purged.
+    pub fn purge(&mut self, topic: &Topic, now: DateTime<Utc>) -> usize {
+        self.update(now);
+
+        let skipped: usize = self
+            .cursors
+            .iter_mut()
+            .zip(&topic.partitions)
+            .map(|(cursor, p)| cursor.skip_to_tail(&p.log))
+            .sum();
+        let pending = self.num_pending();
+        self.pending.iter_mut().for_each(Pending::clear);
+        skipped + pending
     }
 
     /// Get the number of pending messages
     pub fn num_pending(&self) -> usize {
-        self.pending_ids.len()
-    }
-
-    fn check_pending(&mut self) -> Option<(Option<InternalMessage>, Index<InternalMessage>, u32)> {
-        while let Some(pending) = self.pending.pop_front() {
-            match pending.index.get() {
-                Some(message) => {
-                    // Check to see if this message was acked
-                    if self.acked.remove(&message.id) {
-                        continue;
-                    }
-                    if Utc::now().signed_duration_since(pending.time_sent) < self.ack_deadline {
-                        // If the message has not yet reached its ack deadline put the message back
-                        // on the front of the queue and return None as all other messages will have
-                        // not timed out either.
-                        self.pending.push_front(pending);
-                        return None;
-                    }
-                    // The message ack deadline has been reached so return the message to be resent
-                    // increment the number of times the message has been tried
-                    return Some((Some(message), pending.index, pending.tries + 1));
-                }
-                // The message has timed out in the topic
-                None => {
-                    // Cleanup the message from pending and acked
-                    self.pending_ids.remove(&pending.message_id);
-                    self.acked.remove(&pending.message_id);
-                    continue;
-                }
+        self.pending.iter().map(Pending::len).sum()
+    }
+
+    /// Get the delivery status of the message with `id` at `offset` of `partition` of the
+    /// subscribed [Topic](struct.Topic.html).
+    ///
+    /// Returns None if the message was published before the subscription was created at the tail
+    /// of the topic, so it will never be delivered to the subscription.
+    pub fn delivery_status(&self, id: Uuid, partition: u32, offset: u64) -> Option<DeliveryStatus> {
+        let partition = partition as usize;
+        if offset < *self.start_offsets.get(partition)? {
+            return None;
+        }
+        if let Some(pending) = self.pending[partition].get(&id) {
+            let tries = pending.tries;
+            let deadline = pending.deadline;
+            return Some(if Utc::now() < deadline {
+                DeliveryStatus::Pending { tries, deadline }
+            } else {
+                DeliveryStatus::Expired { tries, deadline }
+            });
+        }
+        if offset < self.cursors[partition].next_index() as u64 {
+            Some(DeliveryStatus::Acked)
+        } else {
+            Some(DeliveryStatus::NotDelivered)
+        }
+    }
+
+    fn check_pending(
+        &mut self,
+        log: &CommitLog<InternalMessage>,
+        partition: usize,
+        now: DateTime<Utc>,
+    ) -> Option<(Option<InternalMessage>, Index, u32)> {
+        // Resend the message with the earliest deadline if its ack deadline has been reached
+        // incrementing the number of times the message has been tried. Messages which have timed
+        // out in the topic are dropped.
+        while let Some(pending) = self.pending[partition].pop_expired(now) {
+            if let Some(message) = pending.index.get(log) {
+                return Some((Some(message), pending.index, pending.tries + 1));
             }
         }
-        // There are no pending messages
+        // There are no pending messages which reached their ack deadline
         None
     }
 }
@@ -270,14 +765,24 @@ impl Subscription {
 /// A subscription meta type used for serialization.
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct SubscriptionMeta {
-    /// Unique name for this subscription.
+    /// Namespace of the subscription.
+    pub namespace: String,
+    /// Unique name for this subscription within its namespace.
     pub name: String,
+    /// Namespace of the topic the subscription is subscribed to.
+    pub topic_namespace: String,
     /// Topic name the subscription is subscribed to.
     pub topic: String,
+    /// Labels used to organize and select subscriptions.
+    pub labels: BTreeMap<String, String>,
+    /// Free form description of the subscription.
+    pub description: String,
     /// Amount of time given to ack a message in seconds.
     pub ack_deadline: i64,
     /// Time to live of the subscription in seconds.
     pub ttl: i64,
+    /// Time after which the messages of an inactive consumer are released in seconds.
+    pub consumer_timeout: i64,
     /// Time the subscription was created.
     pub created: DateTime<Utc>,
     /// Time the subscription was last updated.
@@ -287,115 +792,395 @@ pub struct SubscriptionMeta {
 impl<'a> From<&'a Subscription> for SubscriptionMeta {
     fn from(subscription: &'a Subscription) -> Self {
         Self {
+            namespace: subscription.namespace.clone(),
             name: subscription.name.clone(),
+            topic_namespace: subscription.topic_namespace.clone(),
             topic: subscription.topic.clone(),
+            labels: subscription
+                .labels
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            description: subscription.description.clone(),
             ack_deadline: subscription.ack_deadline.num_seconds(),
             ttl: subscription.ttl.num_seconds(),
+            consumer_timeout: subscription.consumer_timeout.num_seconds(),
             created: subscription.created,
             updated: subscription.updated,
         }
     }
 }
 
+// A partition of a topic, messages of a partition are ordered by their offset.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(from = "StoredPartition")]
+struct Partition {
+    log: CommitLog<InternalMessage>,
+    // Index of the retained messages by id and the ids in the order they were published so the
+    // index can be cleaned up along with the log. It is rebuilt from the log when deserialized.
+    #[serde(skip)]
+    index: HashMap<Uuid, usize>,
+    #[serde(skip)]
+    index_order: VecDeque<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct StoredPartition {
+    log: CommitLog<InternalMessage>,
+}
+
+impl From<StoredPartition> for Partition {
+    fn from(stored: StoredPartition) -> Self {
+        let log = stored.log;
+        let index_order: VecDeque<Uuid> = (log.head_index()..log.next_index())
+            .filter_map(|i| log.get(i))
+            .map(|m| m.id)
+            .collect();
+        let index = index_order
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, log.head_index() + i))
+            .collect();
+        Partition {
+            log,
+            index,
+            index_order,
+        }
+    }
+}
+
+impl Partition {
+    fn head_offset(&self) -> u64 {
+        self.log.head_index() as u64
+    }
+
+    fn next_offset(&self) -> u64 {
+        self.log.next_index() as u64
+    }
+
+    fn publish(
+        &mut self,
+        id: Uuid,
+        time: DateTime<Utc>,
+        data: String,
+        origin: Option<String>,
+        partition_key: Option<String>,
+        partition: u32,
+    ) -> u64 {
+        let offset = self.next_offset();
+        self.log.append(InternalMessage::new(
+            id,
+            time,
+            data,
+            origin,
+            partition_key,
+            partition,
+            offset,
+        ));
+        self.index.insert(id, offset as usize);
+        self.index_order.push_back(id);
+        offset
+    }
+
+    // Remove messages from the head of the log while they match and remove them from the index.
+    fn remove(&mut self, expired: &dyn Fn(&InternalMessage) -> bool) -> usize {
+        let count = self.log.cleanup(expired);
+        for id in self.index_order.drain(..count) {
+            self.index.remove(&id);
+        }
+        count
+    }
+}
+
+// Hash a partition key with FNV-1a, it is stable across processes and releases unlike the
+// standard library's hasher.
+fn hash_key(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 /// A topic which [Message](struct.Message.html)s can be published and
 /// [Subscription](struct.Subscription.html) can subscribe to.
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Topic {
-    /// Unique name of the topic.
+    /// Namespace of the topic.
+    pub namespace: String,
+    /// Unique name of the topic within its namespace.
     pub name: String,
+    /// Labels used to organize and select topics.
+    pub labels: HashMap<String, String>,
+    /// Free form description of the topic.
+    pub description: String,
     /// Message time to live.
+    #[serde(with = "crate::millis")]
     pub message_ttl: Duration,
     /// Time to live of the topic.
+    #[serde(with = "crate::millis")]
     pub ttl: Duration,
     /// Time the topic was created.
     pub created: DateTime<Utc>,
     /// Time the topic was updated.
     pub updated: DateTime<Utc>,
-    log: CommitLog<InternalMessage>,
+    partitions: Vec<Partition>,
+    // The partition the next message without a partition key is published to.
+    next_partition: usize,
 }
 
 impl Topic {
-    /// Create a new topic.
-    pub fn new(name: &str, message_ttl: Duration, ttl: Duration) -> Topic {
+    /// Create a new topic with a single partition.
+    pub fn new(namespace: &str, name: &str, message_ttl: Duration, ttl: Duration) -> Topic {
+        Self::with_partitions(namespace, name, message_ttl, ttl, 1)
+    }
+
+    /// Create a new topic with `partitions` partitions.
+    ///
+    /// A topic always has at least one partition.
+    pub fn with_partitions(
+        namespace: &str,
+        name: &str,
+        message_ttl: Duration,
+        ttl: Duration,
+        partitions: u32,
+    ) -> Topic {
         let now = Utc::now();
         Topic {
+            namespace: String::from(namespace),
             name: String::from(name),
+            labels: HashMap::new(),
+            description: String::new(),
             message_ttl,
             ttl,
             created: now,
             updated: now,
-            log: CommitLog::new(),
+            partitions: (0..partitions.max(1))
+                .map(|_| Partition::default())
+                .collect(),
+            next_partition: 0,
         }
     }
 
+    /// Get the number of partitions.
+    pub fn partitions(&self) -> u32 {
+        self.partitions.len() as u32
+    }
+
     /// Returns `true` if there are no [Message](struct.Message.html)s.
     pub fn empty(&self) -> bool {
-        self.log.empty()
+        self.partitions.iter().all(|p| p.log.empty())
     }
 
     /// Get the number of [Message](struct.Message.html)s.
     pub fn len(&self) -> usize {
-        self.log.len()
+        self.partitions.iter().map(|p| p.log.len()).sum()
+    }
+
+    /// Get the offset of the oldest [Message](struct.Message.html) of `partition` which has not
+    /// been cleaned up.
+    ///
+    /// Returns None if the partition does not exist.
+    pub fn head_offset(&self, partition: u32) -> Option<u64> {
+        self.partitions
+            .get(partition as usize)
+            .map(Partition::head_offset)
+    }
+
+    /// Get the offset the next [Message](struct.Message.html) published to `partition` will have.
+    ///
+    /// Returns None if the partition does not exist.
+    pub fn next_offset(&self, partition: u32) -> Option<u64> {
+        self.partitions
+            .get(partition as usize)
+            .map(Partition::next_offset)
     }
 
     /// Publish the provided data.
     ///
-    /// Data is converted to a [Message](struct.Message.html) and its id is returned.
-    pub fn publish(&mut self, data: String) -> Uuid {
+    /// Data is converted to a [Message](struct.Message.html) and its id and offset are returned.
+    /// Messages are published to the partitions round robin.
+    pub fn publish(&mut self, data: String) -> (Uuid, u64) {
+        let (id, _, offset) = self.publish_with_key(data, None);
+        (id, offset)
+    }
+
+    /// Publish the provided data to the partition selected by `partition_key`.
+    ///
+    /// Messages with the same key are published to the same partition, messages without a key
+    /// are published to the partitions round robin. Returns the id, partition, and offset of the
+    /// [Message](struct.Message.html).
+    pub fn publish_with_key(
+        &mut self,
+        data: String,
+        partition_key: Option<&str>,
+    ) -> (Uuid, u32, u64) {
+        let raw_message = RawMessage {
+            data,
+            partition_key: partition_key.map(String::from),
+            ..RawMessage::default()
+        };
+        self.publish_at(Uuid::new_v4(), Utc::now(), raw_message)
+    }
+
+    /// Publish a [RawMessage](struct.RawMessage.html) as a [Message](struct.Message.html) with
+    /// the given id and publish time.
+    ///
+    /// Used to publish the same message to every replica of a topic, otherwise the same as
+    /// [publish_with_key](#method.publish_with_key). A message with a partition is published to
+    /// that partition, wrapped around the number of partitions.
+    pub fn publish_at(
+        &mut self,
+        id: Uuid,
+        time: DateTime<Utc>,
+        raw_message: RawMessage,
+    ) -> (Uuid, u32, u64) {
         // Update updated time
-        self.update();
+        self.update(time);
 
-        let internal_message = InternalMessage::new(data);
-        let id = internal_message.id;
-        self.log.append(internal_message);
-        id
+        let count = self.partitions.len();
+        let partition = match (raw_message.partition, raw_message.partition_key.as_deref()) {
+            (Some(partition), _) => partition as usize % count,
+            (None, Some(key)) => (hash_key(key) % count as u64) as usize,
+            (None, None) => {
+                let partition = self.next_partition % count;
+                self.next_partition = (partition + 1) % count;
+                partition
+            }
+        };
+        let offset = self.partitions[partition].publish(
+            id,
+            time,
+            raw_message.data,
+            raw_message.origin,
+            raw_message.partition_key,
+            partition as u32,
+        );
+        (id, partition as u32, offset)
     }
 
-    /// Cleanup expired messages.
+    /// Get the retained [Message](struct.Message.html) with `id`.
+    pub fn get(&self, id: Uuid) -> Option<Message> {
+        self.partitions
+            .iter()
+            .find_map(|p| p.index.get(&id).and_then(|&index| p.log.get(index)))
+            .map(|m| m.into_message(0))
+    }
+
+    /// Read up to `max_messages` [Message](struct.Message.html)s of `partition` starting at
+    /// `from_offset` without the bookkeeping of a [Subscription](struct.Subscription.html).
+    ///
+    /// Returns None if the partition does not exist or the message at `from_offset` has already
+    /// been cleaned up.
+    pub fn read(
+        &self,
+        partition: u32,
+        from_offset: u64,
+        max_messages: usize,
+    ) -> Option<Vec<Message>> {
+        let log = &self.partitions.get(partition as usize)?.log;
+        let mut cursor = Cursor::new_at(log, from_offset as usize)?;
+        let mut messages = Vec::with_capacity(max_messages.min(log.len()));
+        while messages.len() < max_messages {
+            match cursor.next(log) {
+                Some(m) => messages.push(m.into_message(0)),
+                None => break,
+            }
+        }
+        Some(messages)
+    }
+
+    /// Cleanup the messages which are expired at `now`.
     ///
     /// Returns the number of messages cleaned up
-    pub fn cleanup(&mut self) -> usize {
+    pub fn cleanup(&mut self, now: DateTime<Utc>) -> usize {
         let ttl = self.message_ttl;
-        if ttl != Duration::seconds(0) {
-            self.log
-                .cleanup(&|m| Utc::now().signed_duration_since(m.time) > ttl)
-        } else {
-            0
+        if ttl == Duration::seconds(0) {
+            return 0;
         }
+        self.remove(&|m| now.signed_duration_since(m.time) > ttl)
     }
 
-    /// Set the message time to live
-    pub fn set_message_ttl(&mut self, message_ttl: Duration) {
+    /// Get the time the oldest message will expire or None if the topic is empty or messages
+    /// never expire.
+    pub fn head_expires(&self) -> Option<DateTime<Utc>> {
+        if self.message_ttl == Duration::seconds(0) {
+            return None;
+        }
+        self.partitions
+            .iter()
+            .filter_map(|p| p.log.get(p.log.head_index()))
+            .map(|m| m.time + self.message_ttl)
+            .min()
+    }
+
+    /// Get the time the topic will expire if it is not updated or None if it never expires.
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        if self.ttl == Duration::seconds(0) {
+            return None;
+        }
+        Some(self.updated + self.ttl)
+    }
+
+    /// Purge all messages or only the messages published before `before` at `now`.
+    ///
+    /// Returns the number of messages purged
+    pub fn purge(&mut self, before: Option<DateTime<Utc>>, now: DateTime<Utc>) -> usize {
         // Update updated time
-        self.update();
+        self.update(now);
+
+        match before {
+            Some(before) => self.remove(&|m| m.time < before),
+            None => self.remove(&|_| true),
+        }
+    }
+
+    // Remove messages from the head of every partition while they match.
+    fn remove(&mut self, expired: &dyn Fn(&InternalMessage) -> bool) -> usize {
+        self.partitions.iter_mut().map(|p| p.remove(expired)).sum()
+    }
 
+    /// Set the message time to live
+    pub fn set_message_ttl(&mut self, message_ttl: Duration) {
         self.message_ttl = message_ttl;
     }
 
     /// Set the topic time to live
     pub fn set_ttl(&mut self, ttl: Duration) {
-        // Update updated time
-        self.update();
-
         self.ttl = ttl;
     }
 
-    /// Update the updated time to now
-    pub fn update(&mut self) {
-        // Update updated time
-        self.updated = Utc::now();
+    /// Set the topic labels
+    pub fn set_labels(&mut self, labels: HashMap<String, String>) {
+        self.labels = labels;
+    }
+
+    /// Set the topic description
+    pub fn set_description(&mut self, description: String) {
+        self.description = description;
+    }
+
+    /// Set the updated time, the setters leave it to their caller.
+    pub fn update(&mut self, now: DateTime<Utc>) {
+        self.updated = now;
     }
 }
 
 /// A topic meta type used for serialization.
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct TopicMeta {
-    /// Unique name of the topic.
+    /// Namespace of the topic.
+    pub namespace: String,
+    /// Unique name of the topic within its namespace.
     pub name: String,
+    /// Labels used to organize and select topics.
+    pub labels: BTreeMap<String, String>,
+    /// Free form description of the topic.
+    pub description: String,
     /// Message time to live in seconds.
     pub message_ttl: i64,
     /// Time to live of the topic in seconds.
     pub ttl: i64,
+    /// Number of partitions of the topic.
+    pub partitions: u32,
     /// Time the topic was created.
     pub created: DateTime<Utc>,
     /// Time the topic was updated.
@@ -405,11 +1190,104 @@ pub struct TopicMeta {
 impl<'a> From<&'a Topic> for TopicMeta {
     fn from(topic: &'a Topic) -> Self {
         Self {
+            namespace: topic.namespace.clone(),
             name: topic.name.clone(),
+            labels: topic
+                .labels
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            description: topic.description.clone(),
             message_ttl: topic.message_ttl.num_seconds(),
             ttl: topic.ttl.num_seconds(),
+            partitions: topic.partitions(),
             created: topic.created,
             updated: topic.updated,
         }
     }
 }
+
+/// A namespace isolating a group of [Topic](struct.Topic.html)s and
+/// [Subscription](struct.Subscription.html)s with its own defaults.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Namespace {
+    /// Unique name of the namespace.
+    pub name: String,
+    /// Default message time to live of topics created in the namespace.
+    #[serde(with = "crate::millis::option")]
+    pub default_message_ttl: Option<Duration>,
+    /// Default time to live of topics created in the namespace.
+    #[serde(with = "crate::millis::option")]
+    pub default_topic_ttl: Option<Duration>,
+    /// Default time to live of subscriptions created in the namespace.
+    #[serde(with = "crate::millis::option")]
+    pub default_subscription_ttl: Option<Duration>,
+    /// Other namespaces which are allowed to subscribe to topics of this namespace.
+    pub shared_with: HashSet<String>,
+    /// Time the namespace was created.
+    pub created: DateTime<Utc>,
+    /// Time the namespace was updated.
+    pub updated: DateTime<Utc>,
+}
+
+impl Namespace {
+    /// Create a new namespace without defaults which is not shared with other namespaces.
+    pub fn new(name: &str) -> Self {
+        let now = Utc::now();
+        Namespace {
+            name: String::from(name),
+            default_message_ttl: None,
+            default_topic_ttl: None,
+            default_subscription_ttl: None,
+            shared_with: HashSet::new(),
+            created: now,
+            updated: now,
+        }
+    }
+
+    /// Returns `true` if subscriptions of the `namespace` can subscribe to topics of this
+    /// namespace.
+    pub fn allows_subscriptions_from(&self, namespace: &str) -> bool {
+        self.name == namespace || self.shared_with.contains(namespace)
+    }
+
+    /// Set the updated time.
+    pub fn update(&mut self, now: DateTime<Utc>) {
+        self.updated = now;
+    }
+}
+
+/// A namespace meta type used for serialization.
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct NamespaceMeta {
+    /// Unique name of the namespace.
+    pub name: String,
+    /// Default message time to live of topics created in the namespace in seconds.
+    pub default_message_ttl: Option<i64>,
+    /// Default time to live of topics created in the namespace in seconds.
+    pub default_topic_ttl: Option<i64>,
+    /// Default time to live of subscriptions created in the namespace in seconds.
+    pub default_subscription_ttl: Option<i64>,
+    /// Other namespaces which are allowed to subscribe to topics of this namespace.
+    pub shared_with: Vec<String>,
+    /// Time the namespace was created.
+    pub created: DateTime<Utc>,
+    /// Time the namespace was updated.
+    pub updated: DateTime<Utc>,
+}
+
+impl<'a> From<&'a Namespace> for NamespaceMeta {
+    fn from(namespace: &'a Namespace) -> Self {
+        let mut shared_with: Vec<String> = namespace.shared_with.iter().cloned().collect();
+        shared_with.sort();
+        Self {
+            name: namespace.name.clone(),
+            default_message_ttl: namespace.default_message_ttl.map(|d| d.num_seconds()),
+            default_topic_ttl: namespace.default_topic_ttl.map(|d| d.num_seconds()),
+            default_subscription_ttl: namespace.default_subscription_ttl.map(|d| d.num_seconds()),
+            shared_with,
+            created: namespace.created,
+            updated: namespace.updated,
+        }
+    }
+}
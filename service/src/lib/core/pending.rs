@@ -0,0 +1,201 @@
+//! Tracking of the pulled but not yet acked messages of a subscription.
+
+use crate::commit_log::Index;
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use uuid::Uuid;
+
+/// Compact the deadline queue once it holds this many more entries than there are pending
+/// messages.
+const MAX_STALE: usize = 64;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingMessage {
+    pub deadline: DateTime<Utc>,
+    pub tries: u32,
+    pub index: Index,
+    /// The consumer which pulled the message if it identified itself.
+    pub consumer: Option<String>,
+    seq: u64,
+}
+
+/// Pending messages ordered by their ack deadline.
+///
+/// Messages are looked up and removed by id in constant time. The deadline queue is a min heap
+/// which is not updated on removal, instead entries of messages which are no longer pending (or
+/// were pulled again) are skipped when they reach the front. To keep memory bounded the queue is
+/// rebuilt from the pending messages when the skipped entries outnumber them, or when they are
+/// deserialized.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(from = "StoredPending")]
+pub struct Pending {
+    messages: HashMap<Uuid, PendingMessage>,
+    #[serde(skip)]
+    deadlines: BinaryHeap<Reverse<(DateTime<Utc>, u64, Uuid)>>,
+    next_seq: u64,
+}
+
+#[derive(Deserialize)]
+struct StoredPending {
+    messages: HashMap<Uuid, PendingMessage>,
+    next_seq: u64,
+}
+
+impl From<StoredPending> for Pending {
+    fn from(stored: StoredPending) -> Self {
+        let mut pending = Pending {
+            messages: stored.messages,
+            deadlines: BinaryHeap::new(),
+            next_seq: stored.next_seq,
+        };
+        pending.rebuild();
+        pending
+    }
+}
+
+impl Pending {
+    /// Add a message which must be acked before `deadline`.
+    pub fn insert(
+        &mut self,
+        id: Uuid,
+        tries: u32,
+        index: Index,
+        deadline: DateTime<Utc>,
+        consumer: Option<&str>,
+    ) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.messages.insert(
+            id,
+            PendingMessage {
+                deadline,
+                tries,
+                index,
+                consumer: consumer.map(String::from),
+                seq,
+            },
+        );
+        self.deadlines.push(Reverse((deadline, seq, id)));
+        self.compact();
+    }
+
+    /// Remove a message returning true if it was pending.
+    pub fn remove(&mut self, id: &Uuid) -> bool {
+        let removed = self.messages.remove(id).is_some();
+        if removed {
+            self.compact();
+        }
+        removed
+    }
+
+    /// Get a pending message.
+    pub fn get(&self, id: &Uuid) -> Option<&PendingMessage> {
+        self.messages.get(id)
+    }
+
+    /// Remove and return the pending message with the earliest deadline if its deadline is before
+    /// `now`.
+    pub fn pop_expired(&mut self, now: DateTime<Utc>) -> Option<PendingMessage> {
+        while let Some(&Reverse((deadline, seq, id))) = self.deadlines.peek() {
+            if self.messages.get(&id).map(|m| m.seq) != Some(seq) {
+                // The message is no longer pending or was pulled again
+                self.deadlines.pop();
+                continue;
+            }
+            if deadline > now {
+                return None;
+            }
+            self.deadlines.pop();
+            return self.messages.remove(&id);
+        }
+        None
+    }
+
+    /// Check if a message reached its deadline before `now` without removing it.
+    pub fn has_expired(&self, now: DateTime<Utc>) -> bool {
+        self.messages.values().any(|m| m.deadline <= now)
+    }
+
+    /// Move the deadline of every message pulled by `consumer` to `now` so the messages are
+    /// resent with the next pull.
+    ///
+    /// Returns the number of messages released.
+    pub fn release(&mut self, consumer: &str, now: DateTime<Utc>) -> usize {
+        // Keep the order the messages were pulled in
+        let mut released: Vec<(u64, Uuid)> = self
+            .messages
+            .iter()
+            .filter(|(_, m)| m.consumer.as_deref() == Some(consumer))
+            .map(|(id, m)| (m.seq, *id))
+            .collect();
+        released.sort();
+        for (_, id) in &released {
+            self.requeue(id, now);
+        }
+        self.compact();
+        released.len()
+    }
+
+    /// Move the deadline of a message to `now` so it is resent with the next pull.
+    ///
+    /// Returns true if the message was pending.
+    pub fn release_message(&mut self, id: &Uuid, now: DateTime<Utc>) -> bool {
+        let released = self.requeue(id, now);
+        if released {
+            self.compact();
+        }
+        released
+    }
+
+    fn requeue(&mut self, id: &Uuid, now: DateTime<Utc>) -> bool {
+        let m = match self.messages.get_mut(id) {
+            Some(m) => m,
+            None => return false,
+        };
+        m.deadline = now;
+        m.seq = self.next_seq;
+        m.consumer = None;
+        self.next_seq += 1;
+        self.deadlines.push(Reverse((now, m.seq, *id)));
+        true
+    }
+
+    /// Iterate over the pending messages in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &PendingMessage> {
+        self.messages.values()
+    }
+
+    /// Get the number of pending messages.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Get the number of entries in the deadline queue including skipped entries.
+    #[cfg(test)]
+    pub fn queued(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    /// Remove all pending messages.
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.deadlines.clear();
+    }
+
+    fn compact(&mut self) {
+        if self.deadlines.len() <= 2 * self.messages.len() + MAX_STALE {
+            return;
+        }
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        self.deadlines = self
+            .messages
+            .iter()
+            .map(|(id, m)| Reverse((m.deadline, m.seq, *id)))
+            .collect();
+    }
+}
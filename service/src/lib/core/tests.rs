@@ -5,13 +5,19 @@ use std::time;
 #[test]
 fn pub_sub_create() {
     let message = Message::new(String::from(""));
-    assert_eq!(message.data, String::from(""));
-    let mut topic = Topic::new("topic", Duration::seconds(60), Duration::seconds(0));
+    assert_eq!(&*message.data, String::from(""));
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
     topic.set_message_ttl(Duration::seconds(120));
     let topic_meta = TopicMeta::from(&topic);
     assert_eq!(topic_meta.name, String::from("topic"));
     assert_eq!(topic_meta.message_ttl, 120);
     let mut subscription = Subscription::new_head(
+        "default",
         "subscription",
         &topic,
         Duration::seconds(60),
@@ -26,8 +32,14 @@ fn pub_sub_create() {
 
 #[test]
 fn pub_sub_basic() {
-    let mut topic = Topic::new("topic", Duration::seconds(60), Duration::seconds(0));
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
     let mut subscription = Subscription::new_head(
+        "default",
         "subscription",
         &topic,
         Duration::milliseconds(10),
@@ -36,38 +48,568 @@ fn pub_sub_basic() {
     topic.publish(String::from("a"));
     topic.publish(String::from("b"));
     assert_eq!(2, topic.len());
-    let message = subscription.pull().unwrap();
-    assert_eq!(String::from("a"), message.data);
-    subscription.ack(message.id);
-    let message = subscription.pull().unwrap();
-    assert_eq!(None, subscription.pull());
-    subscription.ack_many(&[message.id]);
-    assert_eq!(String::from("b"), message.data);
-    assert_eq!(None, subscription.pull());
+    let message = subscription.pull(&topic).unwrap();
+    assert_eq!(String::from("a"), &*message.data);
+    subscription.ack(message.id, Utc::now());
+    let message = subscription.pull(&topic).unwrap();
+    assert_eq!(None, subscription.pull(&topic));
+    subscription.ack_many(&[message.id], Utc::now());
+    assert_eq!(String::from("b"), &*message.data);
+    assert_eq!(None, subscription.pull(&topic));
     thread::sleep(time::Duration::from_millis(20));
-    assert_eq!(None, subscription.pull());
+    assert_eq!(None, subscription.pull(&topic));
 
     let mut subscription = Subscription::new_tail(
+        "default",
         "subscription",
         &topic,
         Duration::milliseconds(10),
         Duration::seconds(0),
     );
-    assert_eq!(None, subscription.pull());
+    assert_eq!(None, subscription.pull(&topic));
 
     topic.publish(String::from("c"));
-    let message = subscription.pull().unwrap();
-    assert_eq!(String::from("c"), message.data);
+    let message = subscription.pull(&topic).unwrap();
+    assert_eq!(String::from("c"), &*message.data);
     thread::sleep(time::Duration::from_millis(20));
-    let message = subscription.pull().unwrap();
-    assert_eq!(String::from("c"), message.data);
-    subscription.ack_many(&[message.id]);
+    let message = subscription.pull(&topic).unwrap();
+    assert_eq!(String::from("c"), &*message.data);
+    subscription.ack_many(&[message.id], Utc::now());
     thread::sleep(time::Duration::from_millis(20));
-    assert_eq!(None, subscription.pull());
+    assert_eq!(None, subscription.pull(&topic));
 
     topic.publish(String::from("d"));
     topic.set_message_ttl(Duration::milliseconds(10));
     thread::sleep(time::Duration::from_millis(20));
-    topic.cleanup();
-    assert_eq!(None, subscription.pull());
+    topic.cleanup(Utc::now());
+    assert_eq!(None, subscription.pull(&topic));
+}
+
+#[test]
+fn namespace_sharing() {
+    let mut namespace = Namespace::new("payments");
+    assert!(namespace.allows_subscriptions_from("payments"));
+    assert!(!namespace.allows_subscriptions_from("billing"));
+    namespace.shared_with.insert(String::from("billing"));
+    assert!(namespace.allows_subscriptions_from("billing"));
+    let namespace_meta = NamespaceMeta::from(&namespace);
+    assert_eq!(namespace_meta.shared_with, vec![String::from("billing")]);
+    assert_eq!(namespace_meta.default_message_ttl, None);
+
+    let topic = Topic::new(
+        "payments",
+        "topic",
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    let subscription = Subscription::new_head(
+        "billing",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    let subscription_meta = SubscriptionMeta::from(&subscription);
+    assert_eq!(subscription_meta.namespace, String::from("billing"));
+    assert_eq!(subscription_meta.topic_namespace, String::from("payments"));
+}
+
+#[test]
+fn pub_sub_offsets() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::milliseconds(10),
+        Duration::seconds(0),
+    );
+    assert_eq!(
+        (Some(0), Some(0)),
+        (topic.head_offset(0), topic.next_offset(0))
+    );
+    let (_, offset) = topic.publish(String::from("a"));
+    assert_eq!(0, offset);
+    let (id, offset) = topic.publish(String::from("b"));
+    assert_eq!(1, offset);
+
+    let messages = topic.read(0, 1, 10).unwrap();
+    assert_eq!(1, messages.len());
+    assert_eq!(id, messages[0].id);
+    assert_eq!(1, messages[0].offset);
+    assert_eq!(0, messages[0].tries);
+    assert_eq!(2, topic.read(0, 0, 10).unwrap().len());
+    assert_eq!(1, topic.read(0, 0, 1).unwrap().len());
+    assert!(topic.read(0, 5, 10).unwrap().is_empty());
+
+    thread::sleep(time::Duration::from_millis(20));
+    topic.cleanup(Utc::now());
+    let (_, offset) = topic.publish(String::from("c"));
+    assert_eq!(2, offset);
+    assert_eq!(
+        (Some(2), Some(3)),
+        (topic.head_offset(0), topic.next_offset(0))
+    );
+    assert_eq!(None, topic.read(0, 1, 10));
+    assert_eq!("c", &*topic.read(0, 2, 10).unwrap()[0].data);
+}
+
+#[test]
+fn pub_sub_delivery_status() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::milliseconds(50),
+        Duration::seconds(0),
+    );
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::milliseconds(10),
+        Duration::seconds(0),
+    );
+    let (id0, offset0) = topic.publish(String::from("a"));
+    let (id1, offset1) = topic.publish(String::from("b"));
+    let late = Subscription::new_tail(
+        "default",
+        "late",
+        &topic,
+        Duration::milliseconds(10),
+        Duration::seconds(0),
+    );
+    assert_eq!("a", &*topic.get(id0).unwrap().data);
+    assert_eq!(None, topic.get(Uuid::new_v4()));
+    assert_eq!(None, late.delivery_status(id0, 0, offset0));
+
+    assert_eq!(
+        Some(DeliveryStatus::NotDelivered),
+        subscription.delivery_status(id0, 0, offset0)
+    );
+    subscription.pull(&topic).unwrap();
+    match subscription.delivery_status(id0, 0, offset0) {
+        Some(DeliveryStatus::Pending { tries: 1, .. }) => (),
+        status => panic!("unexpected status {:?}", status),
+    }
+    thread::sleep(time::Duration::from_millis(20));
+    match subscription.delivery_status(id0, 0, offset0) {
+        Some(DeliveryStatus::Expired { tries: 1, .. }) => (),
+        status => panic!("unexpected status {:?}", status),
+    }
+    subscription.ack(id0, Utc::now());
+    assert_eq!(
+        Some(DeliveryStatus::Acked),
+        subscription.delivery_status(id0, 0, offset0)
+    );
+    assert_eq!(
+        Some(DeliveryStatus::NotDelivered),
+        subscription.delivery_status(id1, 0, offset1)
+    );
+
+    thread::sleep(time::Duration::from_millis(60));
+    assert_eq!(2, topic.cleanup(Utc::now()));
+    assert_eq!(None, topic.get(id0));
+    assert_eq!(None, topic.get(id1));
+}
+
+#[test]
+fn pub_sub_purge() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(0),
+        Duration::seconds(0),
+    );
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    let (id0, _) = topic.publish(String::from("a"));
+    thread::sleep(time::Duration::from_millis(10));
+    let before = Utc::now();
+    let (id1, _) = topic.publish(String::from("b"));
+    topic.publish(String::from("c"));
+
+    // Purge a subscription's pending messages and backlog
+    assert_eq!(id0, subscription.pull(&topic).unwrap().id);
+    assert_eq!(3, subscription.purge(&topic, Utc::now()));
+    assert_eq!(0, subscription.num_pending());
+    assert_eq!(None, subscription.pull(&topic));
+    topic.publish(String::from("d"));
+    assert_eq!("d", &*subscription.pull(&topic).unwrap().data);
+
+    // Purge the messages of a topic before a time
+    assert_eq!(1, topic.purge(Some(before), Utc::now()));
+    assert_eq!(3, topic.len());
+    assert_eq!(None, topic.get(id0));
+    assert_eq!("b", &*topic.get(id1).unwrap().data);
+
+    // Purge all messages of a topic
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    assert_eq!(3, topic.purge(None, Utc::now()));
+    assert!(topic.empty());
+    assert_eq!(None, topic.get(id1));
+    assert_eq!(None, subscription.pull(&topic));
+    assert_eq!(0, subscription.purge(&topic, Utc::now()));
+}
+
+#[test]
+fn pub_sub_shared_data() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(0),
+        Duration::seconds(0),
+    );
+    let mut subscription0 = Subscription::new_head(
+        "default",
+        "subscription0",
+        &topic,
+        Duration::milliseconds(10),
+        Duration::seconds(0),
+    );
+    let mut subscription1 = Subscription::new_head(
+        "default",
+        "subscription1",
+        &topic,
+        Duration::milliseconds(10),
+        Duration::seconds(0),
+    );
+    let (id, _) = topic.publish(String::from("a"));
+
+    // Every copy of the message shares the same data
+    let message0 = subscription0.pull(&topic).unwrap();
+    let message1 = subscription1.pull(&topic).unwrap();
+    assert!(Arc::ptr_eq(&message0.data, &message1.data));
+    assert!(Arc::ptr_eq(&message0.data, &topic.get(id).unwrap().data));
+    thread::sleep(time::Duration::from_millis(20));
+    let retried = subscription0.pull(&topic).unwrap();
+    assert_eq!(2, retried.tries);
+    assert!(Arc::ptr_eq(&message0.data, &retried.data));
+}
+
+#[test]
+fn pub_sub_pending_bounded() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(0),
+        Duration::seconds(0),
+    );
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+
+    // A slow message at the front of the queue which is never acked
+    topic.publish(String::from("slow"));
+    let slow = subscription.pull(&topic).unwrap();
+
+    // Ack in order, out of order, twice, and unknown ids
+    let mut held = Vec::new();
+    for i in 0..20_000 {
+        topic.publish(i.to_string());
+        let message = subscription.pull(&topic).unwrap();
+        match i % 4 {
+            0 => assert!(subscription.ack(message.id, Utc::now())),
+            1 => {
+                assert!(subscription.ack(message.id, Utc::now()));
+                assert!(!subscription.ack(message.id, Utc::now()));
+            }
+            2 => held.push(message.id),
+            _ => {
+                assert!(subscription.ack(message.id, Utc::now()));
+                assert!(!subscription.ack(Uuid::new_v4(), Utc::now()));
+            }
+        }
+        if held.len() == 10 {
+            for id in held.drain(..).rev() {
+                assert!(subscription.ack(id, Utc::now()));
+            }
+        }
+        assert!(subscription.num_pending() <= 12);
+        assert!(subscription.pending[0].queued() <= 2 * subscription.num_pending() + 64);
+    }
+    assert!(subscription.pending[0].get(&slow.id).is_some());
+
+    // Shrinking the ack deadline applies to newly pulled messages which are resent first
+    subscription.set_ack_deadline(Duration::milliseconds(1));
+    topic.publish(String::from("fast"));
+    let fast = subscription.pull(&topic).unwrap();
+    thread::sleep(time::Duration::from_millis(5));
+    assert_eq!(fast.id, subscription.pull(&topic).unwrap().id);
+}
+
+#[test]
+fn pub_sub_partitions() {
+    let mut topic = Topic::with_partitions(
+        "default",
+        "topic",
+        Duration::seconds(0),
+        Duration::seconds(0),
+        3,
+    );
+    assert_eq!(3, topic.partitions());
+    assert_eq!(None, topic.head_offset(3));
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+
+    // Messages without a key are published round robin
+    let partitions: Vec<u32> = (0..6)
+        .map(|i| topic.publish_with_key(i.to_string(), None).1)
+        .collect();
+    assert_eq!(vec![0, 1, 2, 0, 1, 2], partitions);
+    assert_eq!(Some(2), topic.next_offset(1));
+
+    // Messages with the same key are published to the same partition in order
+    let (id0, partition, offset0) = topic.publish_with_key(String::from("a"), Some("key"));
+    let (_, same, offset1) = topic.publish_with_key(String::from("b"), Some("key"));
+    assert_eq!(partition, same);
+    assert_eq!(offset0 + 1, offset1);
+    assert_eq!(partition, topic.get(id0).unwrap().partition);
+    let read = topic.read(partition, offset0, 10).unwrap();
+    assert_eq!(
+        vec!["a", "b"],
+        read.iter().map(|m| &*m.data).collect::<Vec<_>>()
+    );
+    assert_eq!(None, topic.read(3, 0, 10));
+
+    // Pulls can be restricted to a subset of partitions
+    let other = (partition + 1) % 3;
+    let mut pulled = Vec::new();
+    while let Some(m) = subscription.pull_with(&topic, None, Some(&[other]), Utc::now()) {
+        assert_eq!(other, m.partition);
+        pulled.push(m);
+    }
+    assert_eq!(2, pulled.len());
+    assert!(pulled[0].offset < pulled[1].offset);
+    assert_eq!(
+        Some(DeliveryStatus::NotDelivered),
+        subscription.delivery_status(id0, partition, offset0)
+    );
+
+    // The remaining messages are pulled from all partitions
+    let mut count = 0;
+    while let Some(m) = subscription.pull(&topic) {
+        assert_ne!(other, m.partition);
+        assert!(subscription.ack(m.id, Utc::now()));
+        count += 1;
+    }
+    assert_eq!(6, count);
+    assert_eq!(
+        Some(DeliveryStatus::Acked),
+        subscription.delivery_status(id0, partition, offset0)
+    );
+    assert_eq!(2, subscription.num_pending());
+    assert_eq!(8, subscription.next_index());
+
+    // Messages can be published to a given partition
+    let raw_message = RawMessage {
+        data: String::from("c"),
+        partition: Some(other),
+        ..RawMessage::default()
+    };
+    let (_, published, offset2) = topic.publish_at(Uuid::new_v4(), Utc::now(), raw_message);
+    assert_eq!((other, 2), (published, offset2));
+    let offsets = subscription.offsets();
+    assert_eq!(
+        (4, 2),
+        (offsets[partition as usize], offsets[other as usize])
+    );
+
+    // Seeking a partition drops its pending messages
+    assert!(subscription.seek(&topic, other, 1, Utc::now()));
+    assert!(!subscription.seek(&topic, 3, 0, Utc::now()));
+    assert_eq!(0, subscription.num_pending());
+    let m = subscription
+        .pull_with(&topic, None, Some(&[other]), Utc::now())
+        .unwrap();
+    assert_eq!((other, 1), (m.partition, m.offset));
+}
+
+#[test]
+fn pub_sub_consumers() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(0),
+        Duration::seconds(0),
+    );
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    for i in 0..4 {
+        topic.publish(i.to_string());
+    }
+
+    // Pulled messages are held by the consumer which pulled them
+    let m0 = subscription
+        .pull_with(&topic, Some("worker0"), None, Utc::now())
+        .unwrap();
+    let m1 = subscription
+        .pull_with(&topic, Some("worker0"), None, Utc::now())
+        .unwrap();
+    let m2 = subscription
+        .pull_with(&topic, Some("worker1"), None, Utc::now())
+        .unwrap();
+    let anonymous = subscription.pull(&topic).unwrap();
+    assert!(subscription.ack(m1.id, Utc::now()));
+    let consumers = subscription.consumers();
+    assert_eq!(
+        vec![("worker0", 1), ("worker1", 1)],
+        consumers
+            .iter()
+            .map(|c| (&*c.id, c.in_flight))
+            .collect::<Vec<_>>()
+    );
+
+    // Releasing a consumer resends its messages immediately
+    assert_eq!(None, subscription.release_consumer("unknown", Utc::now()));
+    assert_eq!(
+        Some(1),
+        subscription.release_consumer("worker0", Utc::now())
+    );
+    assert_eq!(1, subscription.consumers().len());
+    let resent = subscription
+        .pull_with(&topic, Some("worker1"), None, Utc::now())
+        .unwrap();
+    assert_eq!(m0.id, resent.id);
+    assert_eq!(2, resent.tries);
+    assert_eq!(2, subscription.consumers()[0].in_flight);
+    assert_ne!(m2.id, anonymous.id);
+
+    // Pending messages are listed in the order of their offsets
+    let pending = subscription.pending_deliveries(&topic);
+    assert_eq!(
+        vec![
+            (m0.id, Some("worker1")),
+            (m2.id, Some("worker1")),
+            (anonymous.id, None)
+        ],
+        pending
+            .iter()
+            .map(|d| (d.id, d.consumer.as_deref()))
+            .collect::<Vec<_>>()
+    );
+
+    // Nacked messages are resent immediately
+    assert_eq!(
+        vec![m2.id],
+        subscription.nack_many(&[m2.id, m1.id], Utc::now())
+    );
+    let resent = subscription
+        .pull_with(&topic, Some("worker0"), None, Utc::now())
+        .unwrap();
+    assert_eq!(m2.id, resent.id);
+    assert_eq!(2, resent.tries);
+
+    // Inactive consumers are only released with a consumer timeout
+    assert_eq!(0, subscription.release_inactive(Utc::now()));
+    assert_eq!(None, subscription.consumers_expire());
+    subscription.set_consumer_timeout(Duration::milliseconds(50));
+    assert!(subscription.consumers_expire().is_some());
+    thread::sleep(time::Duration::from_millis(100));
+    assert_eq!(2, subscription.release_inactive(Utc::now()));
+    assert!(subscription.consumers().is_empty());
+    assert_eq!(3, subscription.num_pending());
+}
+
+#[test]
+fn pub_sub_idle_pull() {
+    let mut topic = Topic::new(
+        "default",
+        "topic",
+        Duration::seconds(0),
+        Duration::seconds(0),
+    );
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    let now = Utc::now();
+    assert!(subscription.pull_is_idle(&topic, None, None, now));
+
+    // New and resendable messages make a pull deliver
+    topic.publish(String::from("message"));
+    assert!(!subscription.pull_is_idle(&topic, None, None, now));
+    let m = subscription.pull_with(&topic, None, None, now).unwrap();
+    assert!(subscription.pull_is_idle(&topic, None, None, now));
+    assert_eq!(vec![m.id], subscription.nack_many(&[m.id], now));
+    assert!(!subscription.pull_is_idle(&topic, None, None, now));
+    subscription.pull_with(&topic, None, None, now).unwrap();
+
+    // Consumers are seen and the subscription updated before their timeouts run out
+    assert!(!subscription.pull_is_idle(&topic, Some("worker"), None, now));
+    assert!(subscription
+        .pull_with(&topic, Some("worker"), None, now)
+        .is_none());
+    assert!(subscription.pull_is_idle(&topic, Some("worker"), None, now));
+    subscription.set_consumer_timeout(Duration::seconds(10));
+    subscription.set_ttl(Duration::seconds(60));
+    assert!(subscription.pull_is_idle(&topic, Some("worker"), None, now));
+    let later = now + Duration::seconds(5);
+    assert!(!subscription.pull_is_idle(&topic, Some("worker"), None, later));
+    assert!(subscription.pull_is_idle(&topic, None, None, later));
+    assert!(!subscription.pull_is_idle(&topic, None, None, now + Duration::seconds(30)));
+}
+
+#[test]
+fn pub_sub_backlog() {
+    let mut topic = Topic::with_partitions(
+        "default",
+        "topic",
+        Duration::milliseconds(50),
+        Duration::seconds(0),
+        2,
+    );
+    let mut subscription = Subscription::new_head(
+        "default",
+        "subscription",
+        &topic,
+        Duration::seconds(60),
+        Duration::seconds(0),
+    );
+    assert_eq!((0, None), subscription.backlog(&topic));
+
+    // The oldest message of any partition which was not pulled
+    topic.publish(String::from("a"));
+    thread::sleep(time::Duration::from_millis(30));
+    for data in &["b", "c"] {
+        topic.publish(data.to_string());
+    }
+    let (messages, oldest) = subscription.backlog(&topic);
+    assert_eq!(3, messages);
+    let a = subscription.pull(&topic).unwrap();
+    assert_eq!("a", &*a.data);
+    assert_eq!(Some(a.time), oldest);
+    let (messages, oldest) = subscription.backlog(&topic);
+    assert_eq!(2, messages);
+    assert!(oldest.unwrap() > a.time);
+
+    // Cleaned up messages are no longer part of the backlog
+    thread::sleep(time::Duration::from_millis(60));
+    topic.cleanup(Utc::now());
+    assert_eq!((0, None), subscription.backlog(&topic));
 }
@@ -0,0 +1,210 @@
+use serde_derive::{Deserialize, Serialize};
+use std::error;
+use std::fmt;
+use std::result;
+use uuid::Uuid;
+
+/// A stable code identifying the kind of an [Error](struct.Error.html).
+///
+/// Codes are serialized in screaming snake case, for example `TOPIC_NOT_FOUND`, and are never
+/// renamed so clients can rely on them.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The namespace does not exist.
+    NamespaceNotFound,
+    /// A namespace with the same name already exists.
+    NamespaceAlreadyExists,
+    /// The topic's namespace is not shared with the subscription's namespace.
+    NamespaceNotShared,
+    /// The topic does not exist.
+    TopicNotFound,
+    /// A topic with the same name already exists in the namespace.
+    TopicAlreadyExists,
+    /// The subscription does not exist.
+    SubscriptionNotFound,
+    /// A subscription with the same name already exists in the namespace.
+    SubscriptionAlreadyExists,
+    /// The message does not exist or is no longer retained.
+    MessageNotFound,
+    /// The requested offset of a topic has already been cleaned up.
+    OffsetExpired,
+    /// The topic does not have the requested partition.
+    PartitionNotFound,
+    /// The consumer has not pulled from the subscription or was already released.
+    ConsumerNotFound,
+    /// The request could not be parsed or is otherwise invalid.
+    InvalidRequest,
+    /// No route matches the request.
+    RouteNotFound,
+    /// The cluster has no leader or lost it while handling the request, the request may be
+    /// retried.
+    Unavailable,
+    /// An unexpected internal error.
+    Internal,
+}
+
+/// A courier error.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Error {
+    /// Code identifying the kind of error.
+    pub code: ErrorCode,
+    /// Human readable description of the error.
+    pub message: String,
+    /// The resource the error refers to if any, topics and subscriptions are qualified by their
+    /// namespace as `<namespace>/<name>`.
+    pub resource: Option<String>,
+}
+
+/// A result with a courier [Error](struct.Error.html).
+pub type Result<T> = result::Result<T, Error>;
+
+fn qualified(namespace: &str, name: &str) -> String {
+    format!("{}/{}", namespace, name)
+}
+
+impl Error {
+    /// Create a new error.
+    pub fn new(code: ErrorCode, message: String, resource: Option<String>) -> Self {
+        Self {
+            code,
+            message,
+            resource,
+        }
+    }
+
+    pub fn namespace_not_found(namespace: &str) -> Self {
+        Self::new(
+            ErrorCode::NamespaceNotFound,
+            format!("Namespace '{}' does not exist", namespace),
+            Some(String::from(namespace)),
+        )
+    }
+
+    pub fn namespace_already_exists(namespace: &str) -> Self {
+        Self::new(
+            ErrorCode::NamespaceAlreadyExists,
+            format!("Namespace '{}' already exists", namespace),
+            Some(String::from(namespace)),
+        )
+    }
+
+    pub fn namespace_not_shared(namespace: &str, topic_namespace: &str) -> Self {
+        Self::new(
+            ErrorCode::NamespaceNotShared,
+            format!(
+                "Namespace '{}' is not shared with namespace '{}'",
+                topic_namespace, namespace
+            ),
+            Some(String::from(topic_namespace)),
+        )
+    }
+
+    pub fn topic_not_found(namespace: &str, topic: &str) -> Self {
+        let resource = qualified(namespace, topic);
+        Self::new(
+            ErrorCode::TopicNotFound,
+            format!("Topic '{}' does not exist", resource),
+            Some(resource),
+        )
+    }
+
+    pub fn topic_already_exists(namespace: &str, topic: &str) -> Self {
+        let resource = qualified(namespace, topic);
+        Self::new(
+            ErrorCode::TopicAlreadyExists,
+            format!("Topic '{}' already exists", resource),
+            Some(resource),
+        )
+    }
+
+    pub fn subscription_not_found(namespace: &str, subscription: &str) -> Self {
+        let resource = qualified(namespace, subscription);
+        Self::new(
+            ErrorCode::SubscriptionNotFound,
+            format!("Subscription '{}' does not exist", resource),
+            Some(resource),
+        )
+    }
+
+    pub fn subscription_already_exists(namespace: &str, subscription: &str) -> Self {
+        let resource = qualified(namespace, subscription);
+        Self::new(
+            ErrorCode::SubscriptionAlreadyExists,
+            format!("Subscription '{}' already exists", resource),
+            Some(resource),
+        )
+    }
+
+    pub fn message_not_found(id: Uuid) -> Self {
+        Self::new(
+            ErrorCode::MessageNotFound,
+            format!("Message '{}' does not exist", id),
+            Some(id.to_string()),
+        )
+    }
+
+    pub fn offset_expired(namespace: &str, topic: &str, offset: u64, head_offset: u64) -> Self {
+        let resource = qualified(namespace, topic);
+        Self::new(
+            ErrorCode::OffsetExpired,
+            format!(
+                "Offset {} of topic '{}' has expired, the oldest available offset is {}",
+                offset, resource, head_offset
+            ),
+            Some(resource),
+        )
+    }
+
+    pub fn partition_not_found(namespace: &str, topic: &str, partition: u32) -> Self {
+        let resource = qualified(namespace, topic);
+        Self::new(
+            ErrorCode::PartitionNotFound,
+            format!(
+                "Partition {} of topic '{}' does not exist",
+                partition, resource
+            ),
+            Some(resource),
+        )
+    }
+
+    pub fn consumer_not_found(namespace: &str, subscription: &str, consumer: &str) -> Self {
+        let resource = qualified(namespace, subscription);
+        Self::new(
+            ErrorCode::ConsumerNotFound,
+            format!(
+                "Consumer '{}' of subscription '{}' does not exist",
+                consumer, resource
+            ),
+            Some(resource),
+        )
+    }
+
+    pub fn invalid_request(message: String) -> Self {
+        Self::new(ErrorCode::InvalidRequest, message, None)
+    }
+
+    pub fn route_not_found(path: &str) -> Self {
+        Self::new(
+            ErrorCode::RouteNotFound,
+            format!("No route matches '{}'", path),
+            Some(String::from(path)),
+        )
+    }
+
+    pub fn unavailable(message: String) -> Self {
+        Self::new(ErrorCode::Unavailable, message, None)
+    }
+
+    pub fn internal(message: String) -> Self {
+        Self::new(ErrorCode::Internal, message, None)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Error {}
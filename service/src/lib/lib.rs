@@ -1,30 +1,164 @@
-pub use crate::core::{Message, Subscription, SubscriptionMeta, Topic, TopicMeta};
+use crate::cluster::{Applied, Cluster, ClusterStatus, Command};
+pub use crate::core::{
+    Consumer, DeliveryStatus, Message, MessageStatus, Namespace, NamespaceMeta, PendingDelivery,
+    RawMessage, Subscription, SubscriptionDeliveryStatus, SubscriptionMeta, Topic, TopicMeta,
+};
+pub use crate::error::{Error, ErrorCode, Result};
+pub use crate::list::{ListOptions, OrderBy, Page};
+use crate::scheduler::{Expiration, Scheduler};
+use crate::signal::{Signal, Signals};
 use chrono::prelude::*;
 use chrono::Duration;
-use parking_lot::RwLock;
+use log::debug;
+use parking_lot::{Mutex, RwLock};
 use psutil;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
 use uuid::Uuid;
 
+pub mod bridge;
+pub mod cluster;
 pub mod commit_log;
 mod core;
+mod error;
+mod list;
+mod millis;
+mod scheduler;
+pub mod signal;
+#[cfg(test)]
+mod tests;
+
+/// The namespace used when a namespace is not specified.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// A (namespace, name) pair uniquely identifying a topic or subscription.
+type Key = (String, String);
+
+fn key(namespace: &str, name: &str) -> Key {
+    (String::from(namespace), String::from(name))
+}
+
+fn invalid_data(error: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Counters of a namespace.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct NamespaceCounters {
+    topics_all_time: AtomicU64,
+    subscriptions_all_time: AtomicU64,
+}
+
+struct NamespaceStore {
+    namespace: Namespace,
+    counters: NamespaceCounters,
+}
+
+/// Counters of a topic which are updated without locking the topic.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct TopicCounters {
+    messages_all_time: AtomicU64,
+    expired_all_time: AtomicU64,
+    purged_all_time: AtomicU64,
+}
 
 struct TopicStore {
-    topic: Topic,
-    subscriptions: HashSet<String>,
+    topic: RwLock<Topic>,
+    // Keys of the subscriptions subscribed to the topic. Once the topic is deleted no more
+    // subscriptions can be added, both are guarded by the same lock.
+    subscriptions: RwLock<HashSet<Key>>,
+    deleted: AtomicBool,
+    counters: TopicCounters,
 }
 
 impl TopicStore {
-    fn new(name: &str, message_ttl: Duration, ttl: Duration) -> Self {
+    fn new(topic: Topic) -> Self {
         TopicStore {
-            topic: Topic::new(name, message_ttl, ttl),
-            subscriptions: HashSet::new(),
+            topic: RwLock::new(topic),
+            subscriptions: RwLock::new(HashSet::new()),
+            deleted: AtomicBool::new(false),
+            counters: Default::default(),
         }
     }
 }
 
+/// Counters of a subscription which are updated without locking the subscription.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SubscriptionCounters {
+    pulled_all_time: AtomicU64,
+    pulled_retries_all_time: AtomicU64,
+    acks_all_time: AtomicU64,
+    acked_all_time: AtomicU64,
+    purged_all_time: AtomicU64,
+}
+
+struct SubscriptionStore {
+    subscription: Mutex<Subscription>,
+    // The subscribed topic, its lock guards the messages the subscription's cursors point to. It
+    // is gone once the topic expired.
+    topic: Weak<TopicStore>,
+    counters: SubscriptionCounters,
+}
+
+impl SubscriptionStore {
+    fn new(subscription: Subscription, topic: &Arc<TopicStore>) -> Self {
+        SubscriptionStore {
+            subscription: Mutex::new(subscription),
+            topic: Arc::downgrade(topic),
+            counters: Default::default(),
+        }
+    }
+}
+
+/// A record of a snapshot of a [Registry](struct.Registry.html).
+#[derive(Serialize)]
+enum SnapshotRecord<'a> {
+    Counters {
+        topics_all_time: u64,
+        subscriptions_all_time: u64,
+    },
+    Namespace {
+        namespace: &'a Namespace,
+        counters: &'a NamespaceCounters,
+    },
+    Topic {
+        topic: &'a Topic,
+        subscriptions: &'a HashSet<Key>,
+        counters: &'a TopicCounters,
+    },
+    Subscription {
+        subscription: &'a Subscription,
+        counters: &'a SubscriptionCounters,
+    },
+}
+
+/// A [SnapshotRecord](enum.SnapshotRecord.html) read back from a snapshot.
+#[derive(Deserialize)]
+enum StoredRecord {
+    Counters {
+        topics_all_time: u64,
+        subscriptions_all_time: u64,
+    },
+    Namespace {
+        namespace: Namespace,
+        counters: NamespaceCounters,
+    },
+    Topic {
+        topic: Topic,
+        subscriptions: HashSet<Key>,
+        counters: TopicCounters,
+    },
+    Subscription {
+        subscription: Subscription,
+        counters: SubscriptionCounters,
+    },
+}
+
 /// Metrics on a topic.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TopicMetrics {
@@ -34,6 +168,10 @@ pub struct TopicMetrics {
     pub messages_all_time: u64,
     /// Number of messages expired all time.
     pub expired_all_time: u64,
+    /// Number of messages purged all time.
+    pub purged_all_time: u64,
+    /// Labels of the topic.
+    pub labels: HashMap<String, String>,
     /// Message time to live.
     pub message_ttl: i64,
     /// Time to live.
@@ -45,12 +183,13 @@ pub struct TopicMetrics {
 }
 
 impl TopicMetrics {
-    /// Create a new topic metrics.
-    pub fn new(topic: &Topic) -> Self {
+    fn new(topic: &Topic, counters: &TopicCounters) -> Self {
         Self {
-            messages: 0,
-            messages_all_time: 0,
-            expired_all_time: 0,
+            messages: topic.len(),
+            messages_all_time: counters.messages_all_time.load(Ordering::Relaxed),
+            expired_all_time: counters.expired_all_time.load(Ordering::Relaxed),
+            purged_all_time: counters.purged_all_time.load(Ordering::Relaxed),
+            labels: topic.labels.clone(),
             message_ttl: topic.message_ttl.num_seconds(),
             ttl: topic.ttl.num_seconds(),
             created: topic.created,
@@ -72,8 +211,14 @@ pub struct SubscriptionMetrics {
     pub acks_all_time: u64,
     /// Number of messages successfully acked all time.
     pub acked_all_time: u64,
+    /// Number of messages purged all time.
+    pub purged_all_time: u64,
+    /// Topic namespace.
+    pub topic_namespace: String,
     /// Topic name.
     pub topic: String,
+    /// Labels of the subscription.
+    pub labels: HashMap<String, String>,
     /// Index into a topic.
     pub message_index: usize,
     /// Ack deadline.
@@ -87,15 +232,17 @@ pub struct SubscriptionMetrics {
 }
 
 impl SubscriptionMetrics {
-    /// Create a new subscription metrics.
-    pub fn new(subscription: &Subscription) -> Self {
+    fn new(subscription: &Subscription, counters: &SubscriptionCounters) -> Self {
         Self {
-            pending: 0,
-            pulled_all_time: 0,
-            pulled_retries_all_time: 0,
-            acks_all_time: 0,
-            acked_all_time: 0,
+            pending: subscription.num_pending(),
+            pulled_all_time: counters.pulled_all_time.load(Ordering::Relaxed),
+            pulled_retries_all_time: counters.pulled_retries_all_time.load(Ordering::Relaxed),
+            acks_all_time: counters.acks_all_time.load(Ordering::Relaxed),
+            acked_all_time: counters.acked_all_time.load(Ordering::Relaxed),
+            purged_all_time: counters.purged_all_time.load(Ordering::Relaxed),
+            topic_namespace: subscription.topic_namespace.clone(),
             topic: subscription.topic.clone(),
+            labels: subscription.labels.clone(),
             message_index: subscription.next_index(),
             ack_deadline: subscription.ack_deadline.num_seconds(),
             ttl: subscription.ttl.num_seconds(),
@@ -105,314 +252,1885 @@ impl SubscriptionMetrics {
     }
 }
 
-/// Courier metrics.
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Metrics {
-    /// Number of topics all time.
+/// Metrics on a namespace.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct NamespaceMetrics {
+    /// Number of topics in the namespace all time.
     pub topics_all_time: u64,
-    /// Number of subscriptions all time.
+    /// Number of subscriptions in the namespace all time.
     pub subscriptions_all_time: u64,
-    /// The memory resident set size usage.
-    pub memory_resident_set_size: i64,
-    /// When the service was started.
-    pub start_time: DateTime<Utc>,
     /// Topic metrics.
     pub topics: HashMap<String, TopicMetrics>,
     /// Subscription metrics.
     pub subscriptions: HashMap<String, SubscriptionMetrics>,
 }
 
-impl Metrics {
-    /// Create a new metrics.
-    pub fn new() -> Self {
+impl NamespaceMetrics {
+    fn new(counters: &NamespaceCounters) -> Self {
         Self {
-            topics_all_time: 0,
-            subscriptions_all_time: 0,
-            memory_resident_set_size: 0,
-            start_time: Utc::now(),
+            topics_all_time: counters.topics_all_time.load(Ordering::Relaxed),
+            subscriptions_all_time: counters.subscriptions_all_time.load(Ordering::Relaxed),
             topics: HashMap::new(),
             subscriptions: HashMap::new(),
         }
     }
 }
 
-/// A registry mapping names to topics and subscriptions and the relevant metrics.
+/// Courier metrics.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Metrics {
+    /// Number of topics all time.
+    pub topics_all_time: u64,
+    /// Number of subscriptions all time.
+    pub subscriptions_all_time: u64,
+    /// The memory resident set size usage.
+    pub memory_resident_set_size: i64,
+    /// When the service was started.
+    pub start_time: DateTime<Utc>,
+    /// Metrics grouped by namespace.
+    pub namespaces: HashMap<String, NamespaceMetrics>,
+}
+
+/// A registry mapping names to namespaces, topics, and subscriptions and the relevant metrics.
+///
+/// Topics and subscriptions are keyed by their (namespace, name) pair. Each topic and
+/// subscription has its own lock so operations on independent topics and subscriptions do not
+/// contend, the maps are only locked for writing to create and delete. Counters are atomics so
+/// they can be updated without any lock.
+///
+/// To avoid deadlocks a map lock is never acquired while holding the lock of a topic or
+/// subscription, except for the subscription map while holding a topic's subscriptions.
+///
+/// Topics, subscriptions, and messages are expired by a background thread which only wakes when
+/// the next one is due.
+///
+/// Every mutation is expressed as a [Command](cluster/enum.Command.html). When the registry is
+/// part of a [Cluster](cluster/struct.Cluster.html) the commands are replicated to the other
+/// nodes before they are applied, otherwise they are applied right away.
 pub struct Registry {
-    topics: RwLock<HashMap<String, TopicStore>>,
-    subscriptions: RwLock<HashMap<String, Subscription>>,
-    metrics: Arc<RwLock<Metrics>>,
+    namespaces: RwLock<HashMap<String, NamespaceStore>>,
+    topics: RwLock<HashMap<Key, Arc<TopicStore>>>,
+    subscriptions: RwLock<HashMap<Key, Arc<SubscriptionStore>>>,
+    topics_all_time: AtomicU64,
+    subscriptions_all_time: AtomicU64,
+    start_time: DateTime<Utc>,
+    scheduler: Arc<Scheduler>,
+    cluster: RwLock<Option<Arc<Cluster>>>,
+    // Raised when messages are published or released to be resent.
+    listeners: Signals,
 }
 
 /// A [Registry](struct.Registry.html) which can be shared between threads.
 pub type SharedRegistry = Arc<Registry>;
 
 impl Registry {
-    /// Create a new shared registry.
+    /// Create a new shared registry and start its expiration thread.
+    ///
+    /// The thread exits once the registry is dropped.
     pub fn new() -> SharedRegistry {
-        Arc::new(Self {
+        let scheduler = Arc::new(Scheduler::default());
+        let registry = Arc::new(Self {
+            namespaces: RwLock::new(HashMap::new()),
             topics: RwLock::new(HashMap::new()),
             subscriptions: RwLock::new(HashMap::new()),
-            metrics: Arc::new(RwLock::new(Metrics::new())),
+            topics_all_time: AtomicU64::new(0),
+            subscriptions_all_time: AtomicU64::new(0),
+            start_time: Utc::now(),
+            scheduler: Arc::clone(&scheduler),
+            cluster: RwLock::new(None),
+            listeners: Signals::default(),
+        });
+
+        let weak_registry = Arc::downgrade(&registry);
+        thread::spawn(move || {
+            while let Some(due) = scheduler.wait() {
+                let registry = match weak_registry.upgrade() {
+                    Some(registry) => registry,
+                    None => break,
+                };
+                registry.expire(due);
+            }
+        });
+
+        registry
+    }
+
+    // Schedule the expiration of a topic and its oldest message.
+    fn schedule_topic(&self, topic: &Topic) {
+        if let Some(at) = topic.expires() {
+            let topic_key = key(&topic.namespace, &topic.name);
+            self.scheduler.schedule(Expiration::Topic(topic_key), at);
+        }
+        self.schedule_messages(topic);
+    }
+
+    // Schedule the expiration of the oldest message of a topic.
+    fn schedule_messages(&self, topic: &Topic) {
+        if let Some(at) = topic.head_expires() {
+            let topic_key = key(&topic.namespace, &topic.name);
+            self.scheduler.schedule(Expiration::Messages(topic_key), at);
+        }
+    }
+
+    // Schedule the expiration of a subscription and its least recently seen consumer.
+    fn schedule_subscription(&self, subscription: &Subscription) {
+        if let Some(at) = subscription.expires() {
+            let subscription_key = key(&subscription.namespace, &subscription.name);
+            self.scheduler
+                .schedule(Expiration::Subscription(subscription_key), at);
+        }
+        self.schedule_consumers(subscription);
+    }
+
+    // Schedule the release of the least recently seen consumer of a subscription.
+    fn schedule_consumers(&self, subscription: &Subscription) {
+        if let Some(at) = subscription.consumers_expire() {
+            let subscription_key = key(&subscription.namespace, &subscription.name);
+            self.scheduler
+                .schedule(Expiration::Consumers(subscription_key), at);
+        }
+    }
+
+    // Schedule the expirations of every topic and subscription, used when a node becomes the
+    // leader of a cluster and after the registry is restored from a snapshot.
+    pub(crate) fn schedule_all(&self) {
+        for topic_store in self.topic_stores(None) {
+            self.schedule_topic(&topic_store.topic.read());
+        }
+        for subscription_store in self.subscription_stores(None) {
+            self.schedule_subscription(&subscription_store.subscription.lock());
+        }
+    }
+
+    fn topic_store(&self, namespace_name: &str, topic_name: &str) -> Result<Arc<TopicStore>> {
+        self.topics
+            .read()
+            .get(&key(namespace_name, topic_name))
+            .cloned()
+            .ok_or_else(|| Error::topic_not_found(namespace_name, topic_name))
+    }
+
+    fn subscription_store(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<Arc<SubscriptionStore>> {
+        self.subscriptions
+            .read()
+            .get(&key(namespace_name, subscription_name))
+            .cloned()
+            .ok_or_else(|| Error::subscription_not_found(namespace_name, subscription_name))
+    }
+
+    // Get the store of the topic a subscription is subscribed to or an error if it expired.
+    fn subscribed_topic_store(
+        &self,
+        subscription_store: &SubscriptionStore,
+    ) -> Result<Arc<TopicStore>> {
+        subscription_store.topic.upgrade().ok_or_else(|| {
+            let s = subscription_store.subscription.lock();
+            Error::topic_not_found(&s.topic_namespace, &s.topic)
         })
     }
 
-    /// Create a new topic returning true if the operation resulted in a new topic and the topic meta data.
+    // Get the stores of all topics, optionally only of a single namespace, without holding the
+    // map lock while the stores are used.
+    fn topic_stores(&self, namespace_name: Option<&str>) -> Vec<Arc<TopicStore>> {
+        self.topics
+            .read()
+            .iter()
+            .filter(|((namespace, _), _)| namespace_name.is_none_or(|n| n == namespace))
+            .map(|(_, ts)| Arc::clone(ts))
+            .collect()
+    }
+
+    // Get the stores of all subscriptions, optionally only of a single namespace, without holding
+    // the map lock while the stores are used.
+    fn subscription_stores(&self, namespace_name: Option<&str>) -> Vec<Arc<SubscriptionStore>> {
+        self.subscriptions
+            .read()
+            .iter()
+            .filter(|((namespace, _), _)| namespace_name.is_none_or(|n| n == namespace))
+            .map(|(_, ss)| Arc::clone(ss))
+            .collect()
+    }
+
+    // Replicate every mutation of the registry through a cluster from now on.
+    pub(crate) fn set_cluster(&self, cluster: Arc<Cluster>) {
+        *self.cluster.write() = Some(cluster);
+    }
+
+    // Apply a command to the registry or replicate it if the registry is part of a cluster.
+    fn execute(&self, command: Command) -> Result<Applied> {
+        let cluster = self.cluster.read().clone();
+        match cluster {
+            Some(cluster) => cluster.replicate(command),
+            None => self.apply(command, Utc::now()),
+        }
+    }
+
+    /// Raise `signal` whenever messages are published or released to be resent until it is
+    /// dropped.
+    pub fn listen(&self, signal: &Arc<Signal>) {
+        self.listeners.add(signal);
+    }
+
+    /// Get the state of the cluster node replicating the registry or None if the registry is not
+    /// part of a cluster.
+    pub fn cluster_status(&self) -> Option<ClusterStatus> {
+        self.cluster.read().as_ref().map(|c| c.status())
+    }
+
+    /// Create a new namespace returning the namespace meta data or an error if the namespace
+    /// already exists.
+    pub fn create_namespace(
+        &self,
+        namespace_name: &str,
+        default_message_ttl: Option<Duration>,
+        default_topic_ttl: Option<Duration>,
+        default_subscription_ttl: Option<Duration>,
+        shared_with: Vec<String>,
+    ) -> Result<NamespaceMeta> {
+        self.execute(Command::CreateNamespace {
+            namespace: String::from(namespace_name),
+            default_message_ttl,
+            default_topic_ttl,
+            default_subscription_ttl,
+            shared_with,
+        })?
+        .into_namespace()
+    }
+    /// Update a namespace and return the namespace meta or an error if the namespace does not
+    /// exist.
+    pub fn update_namespace(
+        &self,
+        namespace_name: &str,
+        default_message_ttl: Option<Duration>,
+        default_topic_ttl: Option<Duration>,
+        default_subscription_ttl: Option<Duration>,
+        shared_with: Option<Vec<String>>,
+    ) -> Result<NamespaceMeta> {
+        self.execute(Command::UpdateNamespace {
+            namespace: String::from(namespace_name),
+            default_message_ttl,
+            default_topic_ttl,
+            default_subscription_ttl,
+            shared_with,
+        })?
+        .into_namespace()
+    }
+    /// Delete a namespace and all of its topics and subscriptions return an error if the namespace
+    /// does not exist.
+    pub fn delete_namespace(&self, namespace_name: &str) -> Result<()> {
+        self.execute(Command::DeleteNamespace {
+            namespace: String::from(namespace_name),
+        })?
+        .into_done()
+    }
+    /// Get the namespace meta data or an error if the namespace does not exist.
+    pub fn get_namespace(&self, namespace_name: &str) -> Result<NamespaceMeta> {
+        let namespaces = self.namespaces.read();
+        namespaces
+            .get(namespace_name)
+            .map(|n| NamespaceMeta::from(&n.namespace))
+            .ok_or_else(|| Error::namespace_not_found(namespace_name))
+    }
+
+    /// Get a list of all namespace meta data.
+    pub fn list_namespaces(&self) -> Vec<NamespaceMeta> {
+        let namespaces = self.namespaces.read();
+        namespaces
+            .values()
+            .map(|n| NamespaceMeta::from(&n.namespace))
+            .collect()
+    }
+
+    /// Returns true if subscriptions of `namespace_name` are allowed to subscribe to topics of
+    /// `topic_namespace_name`.
+    pub fn can_subscribe(&self, namespace_name: &str, topic_namespace_name: &str) -> bool {
+        if namespace_name == topic_namespace_name {
+            return true;
+        }
+        let namespaces = self.namespaces.read();
+        namespaces
+            .get(topic_namespace_name)
+            .map(|n| n.namespace.allows_subscriptions_from(namespace_name))
+            .unwrap_or(false)
+    }
+
+    /// Create a namespace with no defaults if it does not already exist.
+    fn ensure_namespace(&self, namespace_name: &str, time: DateTime<Utc>) {
+        if self.namespaces.read().contains_key(namespace_name) {
+            return;
+        }
+        // Another thread may have created the namespace in the meantime which is fine
+        let _ = self.apply_create_namespace(namespace_name, None, None, None, Vec::new(), time);
+    }
+
+    /// Create a new topic returning the topic meta data or an error if the topic already exists.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_topic(
         &self,
+        namespace_name: &str,
         topic_name: &str,
+        labels: HashMap<String, String>,
+        description: String,
         message_ttl: Duration,
         ttl: Duration,
-    ) -> (bool, TopicMeta) {
-        let mut topics = self.topics.write();
+        partitions: u32,
+    ) -> Result<TopicMeta> {
+        self.execute(Command::CreateTopic {
+            namespace: String::from(namespace_name),
+            topic: String::from(topic_name),
+            labels,
+            description,
+            message_ttl,
+            ttl,
+            partitions,
+        })?
+        .into_topic()
+    }
+    /// Update a topic and return the topic meta or an error if the topic does not exist.
+    pub fn update_topic(
+        &self,
+        namespace_name: &str,
+        topic_name: &str,
+        labels: Option<HashMap<String, String>>,
+        description: Option<String>,
+        message_ttl: Option<Duration>,
+        ttl: Option<Duration>,
+    ) -> Result<TopicMeta> {
+        self.execute(Command::UpdateTopic {
+            namespace: String::from(namespace_name),
+            topic: String::from(topic_name),
+            labels,
+            description,
+            message_ttl,
+            ttl,
+        })?
+        .into_topic()
+    }
+    /// Delete a topic and all of its subscriptions return an error if the topic does not exist.
+    pub fn delete_topic(&self, namespace_name: &str, topic_name: &str) -> Result<()> {
+        self.execute(Command::DeleteTopic {
+            namespace: String::from(namespace_name),
+            topic: String::from(topic_name),
+        })?
+        .into_done()
+    }
+    /// Get the topic meta data or an error if the topic does not exist.
+    pub fn get_topic(&self, namespace_name: &str, topic_name: &str) -> Result<TopicMeta> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let topic = topic_store.topic.read();
+        Ok(TopicMeta::from(&*topic))
+    }
 
-        let created = !topics.contains_key(topic_name);
-        let topic_store = topics
-            .entry(String::from(topic_name))
-            .or_insert_with(|| TopicStore::new(topic_name, message_ttl, ttl));
+    /// Get a page of the topic meta data in a namespace.
+    pub fn list_topics(
+        &self,
+        namespace_name: &str,
+        options: &ListOptions,
+    ) -> Result<Page<TopicMeta>> {
+        let topics: Vec<TopicMeta> = self
+            .topic_stores(Some(namespace_name))
+            .iter()
+            .map(|ts| TopicMeta::from(&*ts.topic.read()))
+            .collect();
+        list::paginate(topics, options)
+    }
 
-        // Update metrics
-        if created {
-            let mut metrics = self.metrics.write();
-            metrics.topics_all_time += 1;
-            metrics.topics.insert(
-                String::from(topic_name),
-                TopicMetrics::new(&topic_store.topic),
-            );
+    /// Publish a list of raw messages to a topic return a list of published message ids,
+    /// partitions, and offsets or an error if the topic or the partition of a message does not
+    /// exist.
+    pub fn publish(
+        &self,
+        namespace_name: &str,
+        topic_name: &str,
+        data: Vec<RawMessage>,
+    ) -> Result<Vec<(Uuid, u32, u64)>> {
+        let partitions = self
+            .topic_store(namespace_name, topic_name)?
+            .topic
+            .read()
+            .partitions();
+        if let Some(partition) = data
+            .iter()
+            .filter_map(|m| m.partition)
+            .find(|p| *p >= partitions)
+        {
+            return Err(Error::partition_not_found(
+                namespace_name,
+                topic_name,
+                partition,
+            ));
         }
+        // The ids are part of the command so every replica uses the same ones
+        self.execute(Command::Publish {
+            namespace: String::from(namespace_name),
+            topic: String::from(topic_name),
+            ids: data.iter().map(|_| Uuid::new_v4()).collect(),
+            messages: data,
+        })?
+        .into_published()
+    }
+    /// Get a retained message of a topic or an error if the topic or message does not exist.
+    pub fn get_message(&self, namespace_name: &str, topic_name: &str, id: Uuid) -> Result<Message> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let topic = topic_store.topic.read();
+        topic.get(id).ok_or_else(|| Error::message_not_found(id))
+    }
+
+    /// Get the delivery status of a retained message for every subscription of its topic or an
+    /// error if no topic of the namespace retains the message.
+    pub fn message_status(&self, namespace_name: &str, id: Uuid) -> Result<MessageStatus> {
+        let (topic_store, topic_key, message) = self
+            .topic_stores(Some(namespace_name))
+            .into_iter()
+            .find_map(|ts| {
+                let found = {
+                    let topic = ts.topic.read();
+                    topic
+                        .get(id)
+                        .map(|m| (key(&topic.namespace, &topic.name), m))
+                };
+                found.map(|(k, m)| (ts, k, m))
+            })
+            .ok_or_else(|| Error::message_not_found(id))?;
 
-        (created, TopicMeta::from(&topic_store.topic))
+        let subscription_keys: Vec<Key> =
+            topic_store.subscriptions.read().iter().cloned().collect();
+        let subscription_stores: Vec<Arc<SubscriptionStore>> = {
+            let subscriptions = self.subscriptions.read();
+            subscription_keys
+                .iter()
+                .filter_map(|k| subscriptions.get(k).cloned())
+                .collect()
+        };
+        let mut statuses: Vec<SubscriptionDeliveryStatus> = subscription_stores
+            .iter()
+            .filter_map(|ss| {
+                let s = ss.subscription.lock();
+                s.delivery_status(id, message.partition, message.offset)
+                    .map(|status| SubscriptionDeliveryStatus {
+                        namespace: s.namespace.clone(),
+                        name: s.name.clone(),
+                        status,
+                    })
+            })
+            .collect();
+        statuses.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+
+        Ok(MessageStatus {
+            topic_namespace: topic_key.0,
+            topic: topic_key.1,
+            message,
+            subscriptions: statuses,
+        })
     }
 
-    /// Update a topic and return the topic meta or None if the topic does not exist.
-    pub fn update_topic(
+    /// Read messages from a partition of a topic starting at an offset without a subscription.
+    ///
+    /// If `from_offset` is None reading starts at the oldest message of the partition. Return the
+    /// messages and the offset to continue reading from, or an error if the topic or partition
+    /// does not exist or the offset has already been cleaned up.
+    pub fn read(
         &self,
+        namespace_name: &str,
         topic_name: &str,
-        message_ttl: Option<Duration>,
+        partition: u32,
+        from_offset: Option<u64>,
+        max_messages: usize,
+    ) -> Result<(Vec<Message>, u64)> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let topic = topic_store.topic.read();
+        let (head_offset, next_offset) =
+            match (topic.head_offset(partition), topic.next_offset(partition)) {
+                (Some(head), Some(next)) => (head, next),
+                _ => {
+                    return Err(Error::partition_not_found(
+                        namespace_name,
+                        topic_name,
+                        partition,
+                    ))
+                }
+            };
+        let from_offset = from_offset.unwrap_or(head_offset);
+        let messages = topic
+            .read(partition, from_offset, max_messages)
+            .ok_or_else(|| {
+                Error::offset_expired(namespace_name, topic_name, from_offset, head_offset)
+            })?;
+        let next_offset = messages
+            .last()
+            .map(|m| m.offset + 1)
+            .unwrap_or_else(|| from_offset.min(next_offset));
+        Ok((messages, next_offset))
+    }
+
+    /// Get the offset of the oldest retained message and the offset the next published message
+    /// gets for each partition of a topic or an error if the topic does not exist.
+    pub fn topic_offsets(&self, namespace_name: &str, topic_name: &str) -> Result<Vec<(u64, u64)>> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let topic = topic_store.topic.read();
+        let offsets = (0..topic.partitions())
+            .filter_map(|p| Some((topic.head_offset(p)?, topic.next_offset(p)?)))
+            .collect();
+        Ok(offsets)
+    }
+
+    /// Purge all messages of a topic or only the messages published before `before` return the
+    /// number of purged messages or an error if the topic does not exist.
+    pub fn purge_topic(
+        &self,
+        namespace_name: &str,
+        topic_name: &str,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<usize> {
+        self.execute(Command::PurgeTopic {
+            namespace: String::from(namespace_name),
+            topic: String::from(topic_name),
+            before,
+        })?
+        .into_count()
+    }
+    /// Return a page of the subscription names that are subscribed to this topic or an error if
+    /// the topic does not exist.
+    ///
+    /// Subscriptions from other namespaces are qualified as `<namespace>/<name>`.
+    pub fn list_topic_subscriptions(
+        &self,
+        namespace_name: &str,
+        topic_name: &str,
+        options: &ListOptions,
+    ) -> Result<Page<String>> {
+        let subscription_keys: Vec<Key> = self
+            .topic_store(namespace_name, topic_name)?
+            .subscriptions
+            .read()
+            .iter()
+            .cloned()
+            .collect();
+        let subscription_stores: Vec<Arc<SubscriptionStore>> = {
+            let subscriptions = self.subscriptions.read();
+            subscription_keys
+                .iter()
+                .filter_map(|k| subscriptions.get(k).cloned())
+                .collect()
+        };
+        let subscriptions: Vec<SubscriptionMeta> = subscription_stores
+            .iter()
+            .map(|ss| {
+                let s = ss.subscription.lock();
+                let mut meta = SubscriptionMeta::from(&*s);
+                if s.namespace != namespace_name {
+                    meta.name = format!("{}/{}", s.namespace, s.name);
+                }
+                meta
+            })
+            .collect();
+        let page = list::paginate(subscriptions, options)?;
+        Ok(Page {
+            items: page.items.into_iter().map(|s| s.name).collect(),
+            next_page_token: page.next_page_token,
+        })
+    }
+
+    /// Create a new subscription returning the subscription meta data or an error if the topic does
+    /// not exist, the subscription already exists, or the subscription's namespace is not allowed
+    /// to subscribe to the topic's namespace.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        topic_namespace_name: &str,
+        topic_name: &str,
+        labels: HashMap<String, String>,
+        description: String,
+        ack_deadline: Duration,
+        ttl: Duration,
+        consumer_timeout: Duration,
+        historical: bool,
+    ) -> Result<SubscriptionMeta> {
+        self.execute(Command::CreateSubscription {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            topic_namespace: String::from(topic_namespace_name),
+            topic: String::from(topic_name),
+            labels,
+            description,
+            ack_deadline,
+            ttl,
+            consumer_timeout,
+            historical,
+        })?
+        .into_subscription()
+    }
+    /// Update a subscription and return the subscription meta data or an error if the subscription
+    /// does not exist.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        labels: Option<HashMap<String, String>>,
+        description: Option<String>,
+        ack_deadline: Option<Duration>,
         ttl: Option<Duration>,
-    ) -> Option<TopicMeta> {
-        let mut topics = self.topics.write();
+        consumer_timeout: Option<Duration>,
+    ) -> Result<SubscriptionMeta> {
+        self.execute(Command::UpdateSubscription {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            labels,
+            description,
+            ack_deadline,
+            ttl,
+            consumer_timeout,
+        })?
+        .into_subscription()
+    }
+    /// Delete a subscription return an error if the subscription does not exist.
+    pub fn delete_subscription(&self, namespace_name: &str, subscription_name: &str) -> Result<()> {
+        self.execute(Command::DeleteSubscription {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+        })?
+        .into_done()
+    }
+    /// Get the subscription meta data or an error if the subscription does not exist.
+    pub fn get_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<SubscriptionMeta> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let subscription = subscription_store.subscription.lock();
+        Ok(SubscriptionMeta::from(&*subscription))
+    }
+
+    /// Get a page of the subscription meta data in a namespace.
+    pub fn list_subscriptions(
+        &self,
+        namespace_name: &str,
+        options: &ListOptions,
+    ) -> Result<Page<SubscriptionMeta>> {
+        let subscriptions: Vec<SubscriptionMeta> = self
+            .subscription_stores(Some(namespace_name))
+            .iter()
+            .map(|ss| SubscriptionMeta::from(&*ss.subscription.lock()))
+            .collect();
+        list::paginate(subscriptions, options)
+    }
+
+    /// Retrieve messages from a subscription return the list of messages or an error if the
+    /// subscription does not exist.
+    ///
+    /// If `partitions` is not None only messages of the listed partitions are retrieved, an error
+    /// is returned if the subscribed topic does not have one of them. If `consumer` is not None
+    /// the retrieved messages are held by the consumer until they are acked or released.
+    pub fn pull(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        max_messages: usize,
+        partitions: Option<&[u32]>,
+        consumer: Option<&str>,
+    ) -> Result<Vec<Message>> {
+        // Empty polls are answered from the local registry instead of being replicated
+        if self.cluster.read().is_some()
+            && self.pull_is_idle(namespace_name, subscription_name, partitions, consumer)
+        {
+            return Ok(Vec::new());
+        }
+        self.execute(Command::Pull {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            max_messages,
+            partitions: partitions.map(Vec::from),
+            consumer: consumer.map(String::from),
+        })?
+        .into_messages()
+    }
+
+    // Check if a pull would not change the subscription other than refreshing recent timestamps.
+    fn pull_is_idle(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        partitions: Option<&[u32]>,
+        consumer: Option<&str>,
+    ) -> bool {
+        let subscription_store = match self.subscription_store(namespace_name, subscription_name) {
+            Ok(subscription_store) => subscription_store,
+            Err(_) => return false,
+        };
+        let topic_store = match subscription_store.topic.upgrade() {
+            Some(topic_store) => topic_store,
+            None => return false,
+        };
+        let topic = topic_store.topic.read();
+        let subscription = subscription_store.subscription.lock();
+        // Unknown partitions are reported by the replicated pull
+        if partitions
+            .into_iter()
+            .flatten()
+            .any(|&p| p >= subscription.partitions())
+        {
+            return false;
+        }
+        subscription.pull_is_idle(&topic, consumer, partitions, Utc::now())
+    }
+
+    /// Ack message ids returning the list of successfully acked ids or an error if the subscription
+    /// does not exist.
+    pub fn ack(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        ids: &[Uuid],
+    ) -> Result<Vec<Uuid>> {
+        self.execute(Command::Ack {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            ids: Vec::from(ids),
+        })?
+        .into_acked()
+    }
+    /// Nack message ids so they are resent with the next pull, returning the list of ids which
+    /// were pending or an error if the subscription does not exist.
+    pub fn nack(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        ids: &[Uuid],
+    ) -> Result<Vec<Uuid>> {
+        self.execute(Command::Nack {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            ids: Vec::from(ids),
+        })?
+        .into_acked()
+    }
+    /// Get the identified consumers of a subscription or an error if the subscription does not
+    /// exist.
+    pub fn consumers(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<Vec<Consumer>> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let consumers = subscription_store.subscription.lock().consumers();
+        Ok(consumers)
+    }
 
-        topics.get_mut(topic_name).map(|topic_store| {
-            let topic = &mut topic_store.topic;
+    /// Get the messages of a subscription which were pulled and are waiting to be acked or an
+    /// error if the subscription does not exist.
+    pub fn pending(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<Vec<PendingDelivery>> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let topic_store = match subscription_store.topic.upgrade() {
+            Some(ts) => ts,
+            None => return Ok(Vec::new()),
+        };
+        let topic = topic_store.topic.read();
+        let pending = subscription_store
+            .subscription
+            .lock()
+            .pending_deliveries(&topic);
+        Ok(pending)
+    }
 
-            if let Some(v) = message_ttl {
-                topic.set_message_ttl(v);
+    /// Release all messages held by a consumer of a subscription so they are resent immediately
+    /// return the number of released messages or an error if the subscription or consumer does
+    /// not exist.
+    pub fn release_consumer(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        consumer: &str,
+    ) -> Result<usize> {
+        self.execute(Command::ReleaseConsumer {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            consumer: String::from(consumer),
+        })?
+        .into_count()
+    }
+    /// Purge the backlog of a subscription as if every message was acked return the number of
+    /// purged messages or an error if the subscription does not exist.
+    pub fn purge_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<usize> {
+        self.execute(Command::PurgeSubscription {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+        })?
+        .into_count()
+    }
+    /// Move the cursor of a partition of a subscription so the next pull from it retrieves the
+    /// message at `offset` dropping its pending messages, return an error if the subscription or
+    /// partition does not exist.
+    pub fn seek(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        partition: u32,
+        offset: u64,
+    ) -> Result<()> {
+        self.execute(Command::Seek {
+            namespace: String::from(namespace_name),
+            subscription: String::from(subscription_name),
+            partition,
+            offset,
+        })?
+        .into_done()
+    }
+    /// Get the offset the next pull of each partition of a subscription retrieves or an error if
+    /// the subscription does not exist.
+    pub fn subscription_offsets(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<Vec<u64>> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let offsets = subscription_store.subscription.lock().offsets();
+        Ok(offsets)
+    }
+    /// Get the number of messages of a subscription which were not pulled yet and when the oldest
+    /// of them was published or an error if the subscription does not exist.
+    pub fn subscription_backlog(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<(usize, Option<DateTime<Utc>>)> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let topic_store = match subscription_store.topic.upgrade() {
+            Some(ts) => ts,
+            None => return Ok((0, None)),
+        };
+        let topic = topic_store.topic.read();
+        let backlog = subscription_store.subscription.lock().backlog(&topic);
+        Ok(backlog)
+    }
+    // Collect the metrics of each namespace, optionally only of a single namespace.
+    fn namespace_metrics_map(
+        &self,
+        namespace_name: Option<&str>,
+    ) -> HashMap<String, NamespaceMetrics> {
+        let mut namespaces: HashMap<String, NamespaceMetrics> = self
+            .namespaces
+            .read()
+            .iter()
+            .filter(|(name, _)| namespace_name.is_none_or(|n| n == name.as_str()))
+            .map(|(name, n)| (name.clone(), NamespaceMetrics::new(&n.counters)))
+            .collect();
+        for topic_store in self.topic_stores(namespace_name) {
+            let topic = topic_store.topic.read();
+            if let Some(n) = namespaces.get_mut(&topic.namespace) {
+                n.topics.insert(
+                    topic.name.clone(),
+                    TopicMetrics::new(&topic, &topic_store.counters),
+                );
             }
-            if let Some(v) = ttl {
-                topic.set_ttl(v);
+        }
+        for subscription_store in self.subscription_stores(namespace_name) {
+            let subscription = subscription_store.subscription.lock();
+            if let Some(n) = namespaces.get_mut(&subscription.namespace) {
+                n.subscriptions.insert(
+                    subscription.name.clone(),
+                    SubscriptionMetrics::new(&subscription, &subscription_store.counters),
+                );
             }
+        }
+        namespaces
+    }
+
+    /// Get a snapshot of the metrics.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            topics_all_time: self.topics_all_time.load(Ordering::Relaxed),
+            subscriptions_all_time: self.subscriptions_all_time.load(Ordering::Relaxed),
+            memory_resident_set_size: psutil::process::Process::new(psutil::getpid())
+                .map(|p| p.rss)
+                .unwrap_or(0),
+            start_time: self.start_time,
+            namespaces: self.namespace_metrics_map(None),
+        }
+    }
 
-            // Ensure that updated was updated
-            topic.update();
+    /// Get a snapshot of the metrics of a namespace or an error if the namespace does not exist.
+    pub fn namespace_metrics(&self, namespace_name: &str) -> Result<NamespaceMetrics> {
+        self.namespace_metrics_map(Some(namespace_name))
+            .remove(namespace_name)
+            .ok_or_else(|| Error::namespace_not_found(namespace_name))
+    }
 
-            // Update metrics
-            let mut metrics = self.metrics.write();
-            if let Some(m) = metrics.topics.get_mut(topic_name) {
-                m.message_ttl = topic.message_ttl.num_seconds();
-                m.ttl = topic.ttl.num_seconds();
-                m.updated = topic.updated;
+    // Expire the due topics, subscriptions, messages, and consumers which have not been updated
+    // since they were scheduled, otherwise schedule them again.
+    //
+    // Every expiration is a command so the nodes of a cluster apply it at the same point of the
+    // log. Only the leader proposes them, a follower schedules everything again once it becomes
+    // the leader.
+    fn expire(&self, due: Vec<Expiration>) {
+        let cluster = self.cluster.read().clone();
+        if cluster.is_some_and(|c| !c.is_leader()) {
+            return;
+        }
+        let now = Utc::now();
+        let mut counts = [0; 4];
+        for expiration in due {
+            let (i, command) = match self.expiration_command(expiration, now) {
+                Some(command) => command,
+                None => continue,
             };
+            match self.execute(command).and_then(Applied::into_count) {
+                Ok(count) => counts[i] += count,
+                Err(e) => debug!("Failed to expire: {}", e),
+            }
+        }
+        debug!(
+            "Removed '{}' topics, '{}' subscriptions, '{}' messages, released '{}' messages",
+            counts[0], counts[1], counts[2], counts[3]
+        );
+    }
 
-            TopicMeta::from(&*topic)
-        })
+    // Get the command applying an expiration which is due at `now`, along with the index of its
+    // count, or schedule the expiration again if the item was updated.
+    fn expiration_command(
+        &self,
+        expiration: Expiration,
+        now: DateTime<Utc>,
+    ) -> Option<(usize, Command)> {
+        match expiration {
+            Expiration::Topic((namespace, topic)) => {
+                let topic_store = self.topic_store(&namespace, &topic).ok()?;
+                let topic_ = topic_store.topic.read();
+                match topic_.expires() {
+                    Some(at) if at < now => Some((0, Command::ExpireTopic { namespace, topic })),
+                    _ => {
+                        self.schedule_topic(&topic_);
+                        None
+                    }
+                }
+            }
+            Expiration::Subscription((namespace, subscription)) => {
+                let subscription_store = self.subscription_store(&namespace, &subscription).ok()?;
+                let subscription_ = subscription_store.subscription.lock();
+                match subscription_.expires() {
+                    Some(at) if at < now => Some((
+                        1,
+                        Command::ExpireSubscription {
+                            namespace,
+                            subscription,
+                        },
+                    )),
+                    _ => {
+                        self.schedule_subscription(&subscription_);
+                        None
+                    }
+                }
+            }
+            Expiration::Messages((namespace, topic)) => {
+                let topic_store = self.topic_store(&namespace, &topic).ok()?;
+                let topic_ = topic_store.topic.read();
+                match topic_.head_expires() {
+                    Some(at) if at < now => Some((2, Command::ExpireMessages { namespace, topic })),
+                    _ => {
+                        self.schedule_messages(&topic_);
+                        None
+                    }
+                }
+            }
+            Expiration::Consumers((namespace, subscription)) => {
+                let subscription_store = self.subscription_store(&namespace, &subscription).ok()?;
+                let subscription_ = subscription_store.subscription.lock();
+                match subscription_.consumers_expire() {
+                    Some(at) if at <= now => Some((
+                        3,
+                        Command::ReleaseExpiredConsumers {
+                            namespace,
+                            subscription,
+                        },
+                    )),
+                    _ => {
+                        self.schedule_consumers(&subscription_);
+                        None
+                    }
+                }
+            }
+        }
     }
 
-    /// Delete a topic return false if the topic does not exist.
-    pub fn delete_topic(&self, topic_name: &str) -> bool {
-        // Update metrics
-        {
-            let mut metrics = self.metrics.write();
-            metrics.topics.remove(topic_name);
+    /// Write a snapshot of the registry to `writer`, one JSON encoded record per line.
+    ///
+    /// Must not run concurrently with [apply](#method.apply), the
+    /// [Cluster](cluster/struct.Cluster.html) takes snapshots between applying commands.
+    pub(crate) fn write_snapshot(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let mut write_line = |record: &SnapshotRecord| -> io::Result<()> {
+            serde_json::to_writer(&mut *writer, record).map_err(invalid_data)?;
+            writer.write_all(b"\n")
+        };
+        write_line(&SnapshotRecord::Counters {
+            topics_all_time: self.topics_all_time.load(Ordering::Relaxed),
+            subscriptions_all_time: self.subscriptions_all_time.load(Ordering::Relaxed),
+        })?;
+        for store in self.namespaces.read().values() {
+            write_line(&SnapshotRecord::Namespace {
+                namespace: &store.namespace,
+                counters: &store.counters,
+            })?;
+        }
+        for topic_store in self.topic_stores(None) {
+            write_line(&SnapshotRecord::Topic {
+                topic: &topic_store.topic.read(),
+                subscriptions: &topic_store.subscriptions.read(),
+                counters: &topic_store.counters,
+            })?;
+        }
+        for subscription_store in self.subscription_stores(None) {
+            write_line(&SnapshotRecord::Subscription {
+                subscription: &subscription_store.subscription.lock(),
+                counters: &subscription_store.counters,
+            })?;
         }
+        Ok(())
+    }
+
+    /// Replace the state of the registry with a snapshot written by
+    /// [write_snapshot](#method.write_snapshot).
+    pub(crate) fn restore(&self, reader: impl BufRead) -> io::Result<()> {
+        let mut namespaces = HashMap::new();
+        let mut topics: HashMap<Key, Arc<TopicStore>> = HashMap::new();
+        let mut subscriptions = HashMap::new();
+        for line in reader.lines() {
+            match serde_json::from_str(&line?).map_err(invalid_data)? {
+                StoredRecord::Counters {
+                    topics_all_time,
+                    subscriptions_all_time,
+                } => {
+                    self.topics_all_time
+                        .store(topics_all_time, Ordering::Relaxed);
+                    self.subscriptions_all_time
+                        .store(subscriptions_all_time, Ordering::Relaxed);
+                }
+                StoredRecord::Namespace {
+                    namespace,
+                    counters,
+                } => {
+                    namespaces.insert(
+                        namespace.name.clone(),
+                        NamespaceStore {
+                            namespace,
+                            counters,
+                        },
+                    );
+                }
+                StoredRecord::Topic {
+                    topic,
+                    subscriptions,
+                    counters,
+                } => {
+                    topics.insert(
+                        key(&topic.namespace, &topic.name),
+                        Arc::new(TopicStore {
+                            topic: RwLock::new(topic),
+                            subscriptions: RwLock::new(subscriptions),
+                            deleted: AtomicBool::new(false),
+                            counters,
+                        }),
+                    );
+                }
+                StoredRecord::Subscription {
+                    subscription,
+                    counters,
+                } => {
+                    let subscription_key = key(&subscription.namespace, &subscription.name);
+                    // A topic with the same key which does not list the subscription replaced
+                    // the expired topic the subscription was subscribed to
+                    let topic = topics
+                        .get(&key(&subscription.topic_namespace, &subscription.topic))
+                        .filter(|ts| ts.subscriptions.read().contains(&subscription_key))
+                        .map(Arc::downgrade)
+                        .unwrap_or_default();
+                    subscriptions.insert(
+                        subscription_key,
+                        Arc::new(SubscriptionStore {
+                            subscription: Mutex::new(subscription),
+                            topic,
+                            counters,
+                        }),
+                    );
+                }
+            }
+        }
+        *self.namespaces.write() = namespaces;
+        *self.topics.write() = topics;
+        *self.subscriptions.write() = subscriptions;
+        self.schedule_all();
+        Ok(())
+    }
 
+    // Remove a topic if it reached its ttl at `now`. Its subscriptions are kept.
+    fn apply_expire_topic(&self, topic_key: Key, now: DateTime<Utc>) -> usize {
         let topic_store = {
             let mut topics = self.topics.write();
-            topics.remove(topic_name)
+            let expires = match topics.get(&topic_key) {
+                Some(ts) => ts.topic.read().expires(),
+                None => return 0,
+            };
+            match expires {
+                Some(at) if at < now => topics.remove(&topic_key),
+                Some(at) => {
+                    self.scheduler.schedule(Expiration::Topic(topic_key), at);
+                    return 0;
+                }
+                None => return 0,
+            }
         };
+        if let Some(topic_store) = topic_store {
+            let _subscriptions = topic_store.subscriptions.write();
+            topic_store.deleted.store(true, Ordering::SeqCst);
+        }
+        1
+    }
 
-        // Delete all subscriptions
-        if let Some(ts) = topic_store {
-            for subscription in &ts.subscriptions {
-                self.delete_subscription(&subscription);
+    // Remove a subscription if it reached its ttl at `now`.
+    fn apply_expire_subscription(&self, subscription_key: Key, now: DateTime<Utc>) -> usize {
+        let topic_key = {
+            let mut subscriptions = self.subscriptions.write();
+            let (expires, topic_key) = match subscriptions.get(&subscription_key) {
+                Some(ss) => {
+                    let s = ss.subscription.lock();
+                    (s.expires(), key(&s.topic_namespace, &s.topic))
+                }
+                None => return 0,
+            };
+            match expires {
+                Some(at) if at < now => {
+                    subscriptions.remove(&subscription_key);
+                    topic_key
+                }
+                Some(at) => {
+                    self.scheduler
+                        .schedule(Expiration::Subscription(subscription_key), at);
+                    return 0;
+                }
+                None => return 0,
             }
-            true
-        } else {
-            false
+        };
+
+        // Remove the subscription from the topic if it exists
+        let topic_store = self.topics.read().get(&topic_key).cloned();
+        if let Some(topic_store) = topic_store {
+            topic_store.subscriptions.write().remove(&subscription_key);
         }
+        1
     }
 
-    /// Get the topic meta data or None if the topic does not exist.
-    pub fn get_topic(&self, topic_name: &str) -> Option<TopicMeta> {
-        let topics = self.topics.read();
-        topics.get(topic_name).map(|ts| TopicMeta::from(&ts.topic))
+    // Cleanup the messages of a topic which expired at `now` and schedule the expiration of its
+    // new oldest message.
+    fn apply_expire_messages(&self, topic_key: &Key, now: DateTime<Utc>) -> usize {
+        let topic_store = match self.topics.read().get(topic_key) {
+            Some(ts) => Arc::clone(ts),
+            None => return 0,
+        };
+        let count = {
+            let mut topic = topic_store.topic.write();
+            let count = topic.cleanup(now);
+            self.schedule_messages(&topic);
+            count
+        };
+
+        // Update metrics
+        topic_store
+            .counters
+            .expired_all_time
+            .fetch_add(count as u64, Ordering::Relaxed);
+
+        count
     }
 
-    /// Get a list of all topic meta data.
-    pub fn list_topics(&self) -> Vec<TopicMeta> {
-        let topics = self.topics.read();
-        topics
-            .values()
-            .map(|ts| TopicMeta::from(&ts.topic))
-            .collect()
+    // Release the messages of the consumers of a subscription which are inactive at `now` and
+    // schedule the release of its new least recently seen consumer.
+    fn apply_release_expired_consumers(&self, subscription_key: &Key, now: DateTime<Utc>) -> usize {
+        let subscription_store = match self.subscriptions.read().get(subscription_key) {
+            Some(ss) => Arc::clone(ss),
+            None => return 0,
+        };
+        let mut subscription = subscription_store.subscription.lock();
+        let released = subscription.release_inactive(now);
+        self.schedule_consumers(&subscription);
+        released
     }
+}
 
-    /// Publish a list of data as messages to a topic return a list of published message ids or None
-    /// if the topic does not exist.
-    pub fn publish(&self, topic_name: &str, data: Vec<String>) -> Option<Vec<Uuid>> {
-        let mut topics = self.topics.write();
+// Applying commands to the local registry.
+impl Registry {
+    /// Apply a command to the registry at `time` without replicating it.
+    ///
+    /// Used by the [Cluster](cluster/struct.Cluster.html) to apply committed commands at the time
+    /// the leader appended them, every other mutation goes through the public methods. Applying
+    /// never reads the clock so every node ends up with the same state.
+    pub(crate) fn apply(&self, command: Command, time: DateTime<Utc>) -> Result<Applied> {
+        let deliverable = matches!(
+            command,
+            Command::Publish { .. }
+                | Command::Nack { .. }
+                | Command::ReleaseConsumer { .. }
+                | Command::Seek { .. }
+                | Command::ReleaseExpiredConsumers { .. }
+        );
+        let applied = match command {
+            Command::CreateNamespace {
+                namespace,
+                default_message_ttl,
+                default_topic_ttl,
+                default_subscription_ttl,
+                shared_with,
+            } => self
+                .apply_create_namespace(
+                    &namespace,
+                    default_message_ttl,
+                    default_topic_ttl,
+                    default_subscription_ttl,
+                    shared_with,
+                    time,
+                )
+                .map(Applied::Namespace),
+            Command::UpdateNamespace {
+                namespace,
+                default_message_ttl,
+                default_topic_ttl,
+                default_subscription_ttl,
+                shared_with,
+            } => self
+                .apply_update_namespace(
+                    &namespace,
+                    default_message_ttl,
+                    default_topic_ttl,
+                    default_subscription_ttl,
+                    shared_with,
+                    time,
+                )
+                .map(Applied::Namespace),
+            Command::DeleteNamespace { namespace } => self
+                .apply_delete_namespace(&namespace)
+                .map(|()| Applied::Done),
+            Command::CreateTopic {
+                namespace,
+                topic,
+                labels,
+                description,
+                message_ttl,
+                ttl,
+                partitions,
+            } => self
+                .apply_create_topic(
+                    &namespace,
+                    &topic,
+                    labels,
+                    description,
+                    message_ttl,
+                    ttl,
+                    partitions,
+                    time,
+                )
+                .map(Applied::Topic),
+            Command::UpdateTopic {
+                namespace,
+                topic,
+                labels,
+                description,
+                message_ttl,
+                ttl,
+            } => self
+                .apply_update_topic(
+                    &namespace,
+                    &topic,
+                    labels,
+                    description,
+                    message_ttl,
+                    ttl,
+                    time,
+                )
+                .map(Applied::Topic),
+            Command::DeleteTopic { namespace, topic } => self
+                .apply_delete_topic(&namespace, &topic)
+                .map(|()| Applied::Done),
+            Command::Publish {
+                namespace,
+                topic,
+                messages,
+                ids,
+            } => self
+                .apply_publish(&namespace, &topic, messages, ids, time)
+                .map(Applied::Published),
+            Command::PurgeTopic {
+                namespace,
+                topic,
+                before,
+            } => self
+                .apply_purge_topic(&namespace, &topic, before, time)
+                .map(Applied::Count),
+            Command::CreateSubscription {
+                namespace,
+                subscription,
+                topic_namespace,
+                topic,
+                labels,
+                description,
+                ack_deadline,
+                ttl,
+                consumer_timeout,
+                historical,
+            } => self
+                .apply_create_subscription(
+                    &namespace,
+                    &subscription,
+                    &topic_namespace,
+                    &topic,
+                    labels,
+                    description,
+                    ack_deadline,
+                    ttl,
+                    consumer_timeout,
+                    historical,
+                    time,
+                )
+                .map(Applied::Subscription),
+            Command::UpdateSubscription {
+                namespace,
+                subscription,
+                labels,
+                description,
+                ack_deadline,
+                ttl,
+                consumer_timeout,
+            } => self
+                .apply_update_subscription(
+                    &namespace,
+                    &subscription,
+                    labels,
+                    description,
+                    ack_deadline,
+                    ttl,
+                    consumer_timeout,
+                    time,
+                )
+                .map(Applied::Subscription),
+            Command::DeleteSubscription {
+                namespace,
+                subscription,
+            } => self
+                .apply_delete_subscription(&namespace, &subscription)
+                .map(|()| Applied::Done),
+            Command::Pull {
+                namespace,
+                subscription,
+                max_messages,
+                partitions,
+                consumer,
+            } => self
+                .apply_pull(
+                    &namespace,
+                    &subscription,
+                    max_messages,
+                    partitions.as_deref(),
+                    consumer.as_deref(),
+                    time,
+                )
+                .map(Applied::Messages),
+            Command::Ack {
+                namespace,
+                subscription,
+                ids,
+            } => self
+                .apply_ack(&namespace, &subscription, &ids, time)
+                .map(Applied::Acked),
+            Command::Nack {
+                namespace,
+                subscription,
+                ids,
+            } => self
+                .apply_nack(&namespace, &subscription, &ids, time)
+                .map(Applied::Acked),
+            Command::ReleaseConsumer {
+                namespace,
+                subscription,
+                consumer,
+            } => self
+                .apply_release_consumer(&namespace, &subscription, &consumer, time)
+                .map(Applied::Count),
+            Command::PurgeSubscription {
+                namespace,
+                subscription,
+            } => self
+                .apply_purge_subscription(&namespace, &subscription, time)
+                .map(Applied::Count),
+            Command::Seek {
+                namespace,
+                subscription,
+                partition,
+                offset,
+            } => self
+                .apply_seek(&namespace, &subscription, partition, offset, time)
+                .map(|()| Applied::Done),
+            Command::ExpireTopic { namespace, topic } => Ok(Applied::Count(
+                self.apply_expire_topic(key(&namespace, &topic), time),
+            )),
+            Command::ExpireSubscription {
+                namespace,
+                subscription,
+            } => Ok(Applied::Count(self.apply_expire_subscription(
+                key(&namespace, &subscription),
+                time,
+            ))),
+            Command::ExpireMessages { namespace, topic } => Ok(Applied::Count(
+                self.apply_expire_messages(&key(&namespace, &topic), time),
+            )),
+            Command::ReleaseExpiredConsumers {
+                namespace,
+                subscription,
+            } => Ok(Applied::Count(self.apply_release_expired_consumers(
+                &key(&namespace, &subscription),
+                time,
+            ))),
+        };
+        if deliverable && applied.is_ok() {
+            self.listeners.raise();
+        }
+        applied
+    }
 
-        topics.get_mut(topic_name).map(|topic_store| {
-            let topic = &mut topic_store.topic;
-            let count = data.len();
-            let mut ids = Vec::with_capacity(count);
-            for d in data {
-                ids.push(topic.publish(d));
-            }
+    fn apply_create_namespace(
+        &self,
+        namespace_name: &str,
+        default_message_ttl: Option<Duration>,
+        default_topic_ttl: Option<Duration>,
+        default_subscription_ttl: Option<Duration>,
+        shared_with: Vec<String>,
+        time: DateTime<Utc>,
+    ) -> Result<NamespaceMeta> {
+        let mut namespaces = self.namespaces.write();
 
-            // Update metrics
-            let mut metrics = self.metrics.write();
-            if let Some(m) = metrics.topics.get_mut(topic_name) {
-                m.messages = topic.len();
-                m.messages_all_time += count as u64;
-                m.updated = topic.updated;
-            }
+        if namespaces.contains_key(namespace_name) {
+            return Err(Error::namespace_already_exists(namespace_name));
+        }
+        let mut namespace = Namespace::new(namespace_name);
+        namespace.created = time;
+        namespace.updated = time;
+        namespace.default_message_ttl = default_message_ttl;
+        namespace.default_topic_ttl = default_topic_ttl;
+        namespace.default_subscription_ttl = default_subscription_ttl;
+        namespace.shared_with = shared_with.into_iter().collect();
+        let meta = NamespaceMeta::from(&namespace);
+        namespaces.insert(
+            String::from(namespace_name),
+            NamespaceStore {
+                namespace,
+                counters: Default::default(),
+            },
+        );
 
-            ids
-        })
+        Ok(meta)
     }
 
-    /// Return a list of subscription names that are subscribed to this topic or None if the topic
-    /// does not exist.
-    pub fn list_topic_subscriptions(&self, topic_name: &str) -> Option<Vec<String>> {
-        let mut topics = self.topics.write();
-        topics.get_mut(topic_name).map(|topic_store| {
-            let subscriptions = &topic_store.subscriptions;
-            subscriptions.into_iter().cloned().collect()
-        })
+    fn apply_update_namespace(
+        &self,
+        namespace_name: &str,
+        default_message_ttl: Option<Duration>,
+        default_topic_ttl: Option<Duration>,
+        default_subscription_ttl: Option<Duration>,
+        shared_with: Option<Vec<String>>,
+        time: DateTime<Utc>,
+    ) -> Result<NamespaceMeta> {
+        let mut namespaces = self.namespaces.write();
+
+        let namespace = &mut namespaces
+            .get_mut(namespace_name)
+            .ok_or_else(|| Error::namespace_not_found(namespace_name))?
+            .namespace;
+        if let Some(v) = default_message_ttl {
+            namespace.default_message_ttl = Some(v);
+        }
+        if let Some(v) = default_topic_ttl {
+            namespace.default_topic_ttl = Some(v);
+        }
+        if let Some(v) = default_subscription_ttl {
+            namespace.default_subscription_ttl = Some(v);
+        }
+        if let Some(v) = shared_with {
+            namespace.shared_with = v.into_iter().collect();
+        }
+
+        // Ensure that updated was updated
+        namespace.update(time);
+
+        Ok(NamespaceMeta::from(&*namespace))
     }
 
-    /// Create a new subscription returning true if the operation resulted in a new subscription and
-    /// the subscription meta data or None if the topic does not exist.
-    pub fn create_subscription(
+    fn apply_delete_namespace(&self, namespace_name: &str) -> Result<()> {
+        {
+            let mut namespaces = self.namespaces.write();
+            namespaces
+                .remove(namespace_name)
+                .ok_or_else(|| Error::namespace_not_found(namespace_name))?;
+        }
+
+        // Delete all topics which also deletes their subscriptions
+        let topic_names: Vec<String> = {
+            let topics = self.topics.read();
+            topics
+                .keys()
+                .filter(|(namespace, _)| namespace == namespace_name)
+                .map(|(_, name)| name.clone())
+                .collect()
+        };
+        for topic_name in &topic_names {
+            let _ = self.apply_delete_topic(namespace_name, topic_name);
+        }
+
+        // Delete the remaining subscriptions which are subscribed to topics of other namespaces
+        let subscription_names: Vec<String> = {
+            let subscriptions = self.subscriptions.read();
+            subscriptions
+                .keys()
+                .filter(|(namespace, _)| namespace == namespace_name)
+                .map(|(_, name)| name.clone())
+                .collect()
+        };
+        for subscription_name in &subscription_names {
+            let _ = self.apply_delete_subscription(namespace_name, subscription_name);
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply_create_topic(
         &self,
-        subscription_name: &str,
+        namespace_name: &str,
         topic_name: &str,
-        ack_deadline: Duration,
+        labels: HashMap<String, String>,
+        description: String,
+        message_ttl: Duration,
         ttl: Duration,
-        historical: bool,
-    ) -> Option<(bool, SubscriptionMeta)> {
-        let mut topics = self.topics.write();
-        let topic_store = topics.get_mut(topic_name)?;
-        let topic = &topic_store.topic;
-
-        let mut subscriptions = self.subscriptions.write();
-        let created = !subscriptions.contains_key(subscription_name);
-        let subscription = subscriptions
-            .entry(String::from(subscription_name))
-            .or_insert_with(|| {
-                if historical {
-                    Subscription::new_head(subscription_name, topic, ack_deadline, ttl)
-                } else {
-                    Subscription::new_tail(subscription_name, topic, ack_deadline, ttl)
-                }
-            });
+        partitions: u32,
+        time: DateTime<Utc>,
+    ) -> Result<TopicMeta> {
+        if partitions == 0 {
+            return Err(Error::invalid_request(String::from(
+                "A topic must have at least one partition",
+            )));
+        }
+        self.ensure_namespace(namespace_name, time);
 
-        topic_store
-            .subscriptions
-            .insert(String::from(subscription_name));
+        let mut topic =
+            Topic::with_partitions(namespace_name, topic_name, message_ttl, ttl, partitions);
+        topic.created = time;
+        topic.updated = time;
+        topic.labels = labels;
+        topic.description = description;
+        let meta = TopicMeta::from(&topic);
+        self.schedule_topic(&topic);
+
+        {
+            let mut topics = self.topics.write();
+            let topic_key = key(namespace_name, topic_name);
+            if topics.contains_key(&topic_key) {
+                return Err(Error::topic_already_exists(namespace_name, topic_name));
+            }
+            topics.insert(topic_key, Arc::new(TopicStore::new(topic)));
+        }
 
         // Update metrics
-        if created {
-            let mut metrics = self.metrics.write();
-            metrics.subscriptions_all_time += 1;
-            metrics.subscriptions.insert(
-                String::from(subscription_name),
-                SubscriptionMetrics::new(subscription),
-            );
+        self.topics_all_time.fetch_add(1, Ordering::Relaxed);
+        if let Some(n) = self.namespaces.read().get(namespace_name) {
+            n.counters.topics_all_time.fetch_add(1, Ordering::Relaxed);
         }
 
-        Some((created, SubscriptionMeta::from(&*subscription)))
+        Ok(meta)
     }
 
-    /// Update a subscription and return the subscription meta data or None if the subscription does
-    /// not exist.
-    pub fn update_subscription(
+    #[allow(clippy::too_many_arguments)]
+    fn apply_update_topic(
         &self,
-        subscription_name: &str,
-        ack_deadline: Option<Duration>,
+        namespace_name: &str,
+        topic_name: &str,
+        labels: Option<HashMap<String, String>>,
+        description: Option<String>,
+        message_ttl: Option<Duration>,
         ttl: Option<Duration>,
-    ) -> Option<SubscriptionMeta> {
-        let mut subscriptions = self.subscriptions.write();
-        subscriptions
-            .get_mut(subscription_name)
-            .map(|subscription| {
-                if let Some(v) = ack_deadline {
-                    subscription.set_ack_deadline(v);
-                }
-                if let Some(v) = ttl {
-                    subscription.set_ttl(v);
-                }
+        time: DateTime<Utc>,
+    ) -> Result<TopicMeta> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let mut topic = topic_store.topic.write();
 
-                // Ensure that updated was updated
-                subscription.update();
+        if let Some(v) = message_ttl {
+            topic.set_message_ttl(v);
+        }
+        if let Some(v) = ttl {
+            topic.set_ttl(v);
+        }
+        if let Some(v) = labels {
+            topic.set_labels(v);
+        }
+        if let Some(v) = description {
+            topic.set_description(v);
+        }
 
-                // Update metrics
-                let mut metrics = self.metrics.write();
-                if let Some(m) = metrics.subscriptions.get_mut(subscription_name) {
-                    m.ack_deadline = subscription.ack_deadline.num_seconds();
-                    m.ttl = subscription.ttl.num_seconds();
-                    m.updated = subscription.updated;
-                }
+        // Ensure that updated was updated
+        topic.update(time);
+        self.schedule_topic(&topic);
 
-                SubscriptionMeta::from(&*subscription)
-            })
+        Ok(TopicMeta::from(&*topic))
     }
 
-    /// Delete a subscription return false if the subscription does not exist.
-    pub fn delete_subscription(&self, subscription_name: &str) -> bool {
+    fn apply_delete_topic(&self, namespace_name: &str, topic_name: &str) -> Result<()> {
+        let topic_store = self
+            .topics
+            .write()
+            .remove(&key(namespace_name, topic_name))
+            .ok_or_else(|| Error::topic_not_found(namespace_name, topic_name))?;
+
+        // Delete all subscriptions
+        let subscription_keys = {
+            let mut subscriptions = topic_store.subscriptions.write();
+            topic_store.deleted.store(true, Ordering::SeqCst);
+            mem::take(&mut *subscriptions)
+        };
+        for (namespace, subscription) in &subscription_keys {
+            let _ = self.apply_delete_subscription(namespace, subscription);
+        }
+        Ok(())
+    }
+
+    fn apply_publish(
+        &self,
+        namespace_name: &str,
+        topic_name: &str,
+        data: Vec<RawMessage>,
+        ids: Vec<Uuid>,
+        time: DateTime<Utc>,
+    ) -> Result<Vec<(Uuid, u32, u64)>> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let published = {
+            let mut topic = topic_store.topic.write();
+            let was_empty = topic.empty();
+            let mut published = Vec::with_capacity(data.len());
+            for (d, id) in data.into_iter().zip(ids) {
+                published.push(topic.publish_at(id, time, d));
+            }
+            // The expiration of a non empty topic's oldest message is already scheduled
+            if was_empty {
+                self.schedule_messages(&topic);
+            }
+            published
+        };
+
         // Update metrics
-        let mut metrics = self.metrics.write();
-        metrics.subscriptions.remove(subscription_name);
+        topic_store
+            .counters
+            .messages_all_time
+            .fetch_add(published.len() as u64, Ordering::Relaxed);
 
-        let mut subscriptions = self.subscriptions.write();
-        let subscription = subscriptions.remove(subscription_name);
+        Ok(published)
+    }
 
-        if let Some(s) = subscription {
-            // Remove the subscription from the topic if it exists
-            let mut topics = self.topics.write();
-            if let Some(topic_store) = topics.get_mut(&s.topic) {
-                topic_store.subscriptions.remove(&s.name);
+    fn apply_purge_topic(
+        &self,
+        namespace_name: &str,
+        topic_name: &str,
+        before: Option<DateTime<Utc>>,
+        time: DateTime<Utc>,
+    ) -> Result<usize> {
+        let topic_store = self.topic_store(namespace_name, topic_name)?;
+        let count = topic_store.topic.write().purge(before, time);
+
+        // Update metrics
+        topic_store
+            .counters
+            .purged_all_time
+            .fetch_add(count as u64, Ordering::Relaxed);
+
+        Ok(count)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply_create_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        topic_namespace_name: &str,
+        topic_name: &str,
+        labels: HashMap<String, String>,
+        description: String,
+        ack_deadline: Duration,
+        ttl: Duration,
+        consumer_timeout: Duration,
+        historical: bool,
+        time: DateTime<Utc>,
+    ) -> Result<SubscriptionMeta> {
+        let topic_store = self.topic_store(topic_namespace_name, topic_name)?;
+        if !self.can_subscribe(namespace_name, topic_namespace_name) {
+            return Err(Error::namespace_not_shared(
+                namespace_name,
+                topic_namespace_name,
+            ));
+        }
+        self.ensure_namespace(namespace_name, time);
+
+        let mut subscription = {
+            let topic = topic_store.topic.read();
+            if historical {
+                Subscription::new_head(namespace_name, subscription_name, &topic, ack_deadline, ttl)
+            } else {
+                Subscription::new_tail(namespace_name, subscription_name, &topic, ack_deadline, ttl)
             }
-            true
-        } else {
-            false
+        };
+        subscription.labels = labels;
+        subscription.description = description;
+        subscription.consumer_timeout = consumer_timeout;
+        subscription.created = time;
+        subscription.updated = time;
+        let meta = SubscriptionMeta::from(&subscription);
+        self.schedule_subscription(&subscription);
+
+        // Hold the topic's subscriptions so the topic can not be deleted before the subscription
+        // is added to it
+        let mut topic_subscriptions = topic_store.subscriptions.write();
+        if topic_store.deleted.load(Ordering::SeqCst) {
+            return Err(Error::topic_not_found(topic_namespace_name, topic_name));
+        }
+        let subscription_key = key(namespace_name, subscription_name);
+        {
+            let mut subscriptions = self.subscriptions.write();
+            if subscriptions.contains_key(&subscription_key) {
+                return Err(Error::subscription_already_exists(
+                    namespace_name,
+                    subscription_name,
+                ));
+            }
+            subscriptions.insert(
+                subscription_key.clone(),
+                Arc::new(SubscriptionStore::new(subscription, &topic_store)),
+            );
         }
+        topic_subscriptions.insert(subscription_key);
+        drop(topic_subscriptions);
+
+        // Update metrics
+        self.subscriptions_all_time.fetch_add(1, Ordering::Relaxed);
+        if let Some(n) = self.namespaces.read().get(namespace_name) {
+            n.counters
+                .subscriptions_all_time
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(meta)
     }
 
-    /// Get the subscription meta data or None if the subscription does not exist.
-    pub fn get_subscription(&self, subscription_name: &str) -> Option<SubscriptionMeta> {
-        let subscriptions = self.subscriptions.read();
-        subscriptions
-            .get(subscription_name)
-            .map(SubscriptionMeta::from)
+    #[allow(clippy::too_many_arguments)]
+    fn apply_update_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        labels: Option<HashMap<String, String>>,
+        description: Option<String>,
+        ack_deadline: Option<Duration>,
+        ttl: Option<Duration>,
+        consumer_timeout: Option<Duration>,
+        time: DateTime<Utc>,
+    ) -> Result<SubscriptionMeta> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let mut subscription = subscription_store.subscription.lock();
+
+        if let Some(v) = ack_deadline {
+            subscription.set_ack_deadline(v);
+        }
+        if let Some(v) = ttl {
+            subscription.set_ttl(v);
+        }
+        if let Some(v) = consumer_timeout {
+            subscription.set_consumer_timeout(v);
+        }
+        if let Some(v) = labels {
+            subscription.set_labels(v);
+        }
+        if let Some(v) = description {
+            subscription.set_description(v);
+        }
+
+        // Ensure that updated was updated
+        subscription.update(time);
+        self.schedule_subscription(&subscription);
+
+        Ok(SubscriptionMeta::from(&*subscription))
     }
 
-    /// Get a list of all subscription meta data.
-    pub fn list_subscriptions(&self) -> Vec<SubscriptionMeta> {
-        let subscriptions = self.subscriptions.read();
-        subscriptions.values().map(SubscriptionMeta::from).collect()
+    fn apply_delete_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+    ) -> Result<()> {
+        let subscription_store = self
+            .subscriptions
+            .write()
+            .remove(&key(namespace_name, subscription_name))
+            .ok_or_else(|| Error::subscription_not_found(namespace_name, subscription_name))?;
+
+        // Remove the subscription from the topic if it exists
+        let topic_key = {
+            let s = subscription_store.subscription.lock();
+            key(&s.topic_namespace, &s.topic)
+        };
+        let topic_store = self.topics.read().get(&topic_key).cloned();
+        if let Some(topic_store) = topic_store {
+            topic_store
+                .subscriptions
+                .write()
+                .remove(&key(namespace_name, subscription_name));
+        }
+        Ok(())
     }
 
-    /// Retrieve messages from a subscription return the list of messages or None if the
-    /// subscription does not exist.
-    pub fn pull(&self, subscription_name: &str, max_messages: usize) -> Option<Vec<Message>> {
-        let mut subscriptions = self.subscriptions.write();
-        subscriptions
-            .get_mut(subscription_name)
-            .map(|subscription| {
-                let mut retry_count = 0;
-                let mut messages = Vec::with_capacity(max_messages);
-                while let Some(message) = subscription.pull() {
+    fn apply_pull(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        max_messages: usize,
+        partitions: Option<&[u32]>,
+        consumer: Option<&str>,
+        time: DateTime<Utc>,
+    ) -> Result<Vec<Message>> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let topic_store = subscription_store.topic.upgrade();
+        let mut retry_count = 0;
+        let mut messages = Vec::with_capacity(max_messages);
+        {
+            let topic = topic_store.as_ref().map(|ts| ts.topic.read());
+            let mut subscription = subscription_store.subscription.lock();
+            if let Some(&partition) = partitions
+                .into_iter()
+                .flatten()
+                .find(|&&p| p >= subscription.partitions())
+            {
+                return Err(Error::partition_not_found(
+                    &subscription.topic_namespace,
+                    &subscription.topic,
+                    partition,
+                ));
+            }
+            // There is nothing to pull once the topic expired
+            if let Some(topic) = topic.as_ref() {
+                while let Some(message) = subscription.pull_with(topic, consumer, partitions, time)
+                {
                     if message.tries > 1 {
                         retry_count += 1;
                     }
@@ -421,103 +2139,125 @@ impl Registry {
                         break;
                     }
                 }
+            }
+            if consumer.is_some() {
+                self.schedule_consumers(&subscription);
+            }
+        }
 
-                // Update metrics
-                let mut metrics = self.metrics.write();
-                if let Some(m) = metrics.subscriptions.get_mut(subscription_name) {
-                    m.pending = subscription.num_pending();
-                    m.pulled_all_time += messages.len() as u64;
-                    m.pulled_retries_all_time += retry_count;
-                    m.message_index = subscription.next_index();
-                    m.updated = subscription.updated;
-                }
+        // Update metrics
+        let counters = &subscription_store.counters;
+        counters
+            .pulled_all_time
+            .fetch_add(messages.len() as u64, Ordering::Relaxed);
+        counters
+            .pulled_retries_all_time
+            .fetch_add(retry_count, Ordering::Relaxed);
 
-                messages
-            })
+        Ok(messages)
     }
 
-    /// Ack message ids returning the list of successfully acked ids or None if the subscription
-    /// does not exist.
-    pub fn ack(&self, subscription_name: &str, ids: &[Uuid]) -> Option<Vec<Uuid>> {
-        let mut subscriptions = self.subscriptions.write();
-        subscriptions.get_mut(subscription_name).map(|s| {
-            let acked = s.ack_many(ids);
-
-            // Update metrics
-            let mut metrics = self.metrics.write();
-            if let Some(m) = metrics.subscriptions.get_mut(subscription_name) {
-                m.pending = s.num_pending();
-                m.acked_all_time += ids.len() as u64;
-                m.acks_all_time += acked.len() as u64;
-                m.updated = s.updated;
-            };
+    fn apply_ack(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        ids: &[Uuid],
+        time: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let acked = subscription_store.subscription.lock().ack_many(ids, time);
 
-            acked
-        })
-    }
+        // Update metrics
+        let counters = &subscription_store.counters;
+        counters
+            .acked_all_time
+            .fetch_add(ids.len() as u64, Ordering::Relaxed);
+        counters
+            .acks_all_time
+            .fetch_add(acked.len() as u64, Ordering::Relaxed);
 
-    /// Get a copy of the metrics.
-    pub fn metrics(&self) -> Arc<RwLock<Metrics>> {
-        Arc::clone(&self.metrics)
+        Ok(acked)
     }
 
-    /// Cleanup the registry removing messages, topics, and subscriptions that meet their ttl.
-    pub fn cleanup(&self) -> (usize, usize, usize) {
-        let mut metrics = self.metrics.write();
-
-        // Remove timed out subscriptions
-        let mut subscriptions = self.subscriptions.write();
-        let original_subscriptions_count = subscriptions.len();
-        subscriptions.retain(|_, s| {
-            if s.ttl == Duration::seconds(0) {
-                true
-            } else {
-                Utc::now().signed_duration_since(s.updated) <= s.ttl
-            }
-        });
-        let subscriptions_removed = original_subscriptions_count - subscriptions.len();
+    fn apply_nack(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        ids: &[Uuid],
+        time: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let nacked = subscription_store.subscription.lock().nack_many(ids, time);
+        Ok(nacked)
+    }
 
-        // Update metrics to match the removed subscriptions
-        metrics
-            .subscriptions
-            .retain(|name, _| subscriptions.contains_key(name));
-
-        // Remove timed out topics
-        let mut topics = self.topics.write();
-        let original_topics_count = topics.len();
-        topics.retain(|_, ts| {
-            if ts.topic.ttl == Duration::seconds(0) {
-                true
-            } else {
-                Utc::now().signed_duration_since(ts.topic.updated) <= ts.topic.ttl
-            }
-        });
-        let topics_removed = original_topics_count - topics.len();
+    fn apply_release_consumer(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        consumer: &str,
+        time: DateTime<Utc>,
+    ) -> Result<usize> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let released = subscription_store
+            .subscription
+            .lock()
+            .release_consumer(consumer, time)
+            .ok_or_else(|| {
+                Error::consumer_not_found(namespace_name, subscription_name, consumer)
+            })?;
+        Ok(released)
+    }
 
-        // Update metrics to match the removed subscriptions
-        metrics.topics.retain(|name, _| topics.contains_key(name));
+    fn apply_purge_subscription(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        time: DateTime<Utc>,
+    ) -> Result<usize> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let topic_store = self.subscribed_topic_store(&subscription_store)?;
+        let topic = topic_store.topic.read();
+        let count = subscription_store.subscription.lock().purge(&topic, time);
 
-        // Cleanup the messages of each topic
-        let mut messages_removed = 0;
-        for (topic_name, topic_store) in topics.iter_mut() {
-            let count = topic_store.topic.cleanup();
-            messages_removed += count;
+        // Update metrics
+        subscription_store
+            .counters
+            .purged_all_time
+            .fetch_add(count as u64, Ordering::Relaxed);
 
-            // Update metrics
-            if let Some(m) = metrics.topics.get_mut(topic_name) {
-                m.messages = topic_store.topic.len();
-                m.expired_all_time += count as u64;
-            }
-        }
+        Ok(count)
+    }
 
-        // Update metrics used memory
-        let process = psutil::process::Process::new(psutil::getpid());
-        metrics.memory_resident_set_size = if let Ok(process) = process {
-            process.rss
+    fn apply_seek(
+        &self,
+        namespace_name: &str,
+        subscription_name: &str,
+        partition: u32,
+        offset: u64,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        let subscription_store = self.subscription_store(namespace_name, subscription_name)?;
+        let topic_store = self.subscribed_topic_store(&subscription_store)?;
+        let topic = topic_store.topic.read();
+        let mut subscription = subscription_store.subscription.lock();
+        if subscription.seek(&topic, partition, offset, time) {
+            Ok(())
         } else {
-            0
-        };
+            Err(Error::partition_not_found(
+                &subscription.topic_namespace,
+                &subscription.topic,
+                partition,
+            ))
+        }
+    }
+}
 
-        (topics_removed, subscriptions_removed, messages_removed)
+impl Drop for Registry {
+    fn drop(&mut self) {
+        self.scheduler.stop();
+        if let Some(cluster) = self.cluster.get_mut().take() {
+            cluster.stop();
+        }
     }
 }
@@ -0,0 +1,225 @@
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::{SubscriptionMeta, TopicMeta};
+use crate::error::{Error, Result};
+
+/// The order of a listing.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBy {
+    /// Order by name.
+    #[default]
+    Name,
+    /// Order by creation time, ties are ordered by name.
+    Created,
+}
+
+/// Options to filter, order, and page a listing.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListOptions {
+    /// Only list items whose name starts with the prefix.
+    pub prefix: Option<String>,
+    /// Only list items whose labels match the selector, for example `team=payments,env!=dev`.
+    ///
+    /// A selector is a comma separated list of requirements which must all match. A requirement
+    /// is either `key=value`, `key!=value`, `key` (the label is set), or `!key` (the label is not
+    /// set).
+    pub labels: Option<String>,
+    /// The order of the items.
+    #[serde(default)]
+    pub order_by: OrderBy,
+    /// The maximum number of items in a page, all items are listed if not set.
+    pub page_size: Option<usize>,
+    /// The `next_page_token` of the previous page.
+    pub page_token: Option<String>,
+}
+
+/// A page of a listing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Page<T> {
+    /// The items of the page.
+    pub items: Vec<T>,
+    /// Token to retrieve the next page or None if this is the last page.
+    pub next_page_token: Option<String>,
+}
+
+/// An item which can be listed.
+pub(crate) trait Listed {
+    fn name(&self) -> &str;
+    fn created(&self) -> DateTime<Utc>;
+    fn label(&self, key: &str) -> Option<&str>;
+}
+
+impl Listed for TopicMeta {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    fn label(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(String::as_str)
+    }
+}
+
+impl Listed for SubscriptionMeta {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    fn label(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(String::as_str)
+    }
+}
+
+/// A single requirement of a label selector.
+#[derive(Debug, PartialEq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches<T: Listed>(&self, item: &T) -> bool {
+        match self {
+            Requirement::Equals(key, value) => item.label(key) == Some(value.as_str()),
+            Requirement::NotEquals(key, value) => item.label(key) != Some(value.as_str()),
+            Requirement::Exists(key) => item.label(key).is_some(),
+            Requirement::NotExists(key) => item.label(key).is_none(),
+        }
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Vec<Requirement>> {
+    let invalid = || Error::invalid_request(format!("Invalid label selector '{}'", selector));
+    let mut requirements = Vec::new();
+    for requirement in selector.split(',').map(str::trim) {
+        let requirement = if let Some(index) = requirement.find("!=") {
+            Requirement::NotEquals(
+                String::from(requirement[..index].trim()),
+                String::from(requirement[index + 2..].trim()),
+            )
+        } else if let Some(index) = requirement.find('=') {
+            Requirement::Equals(
+                String::from(requirement[..index].trim()),
+                String::from(requirement[index + 1..].trim()),
+            )
+        } else if let Some(key) = requirement.strip_prefix('!') {
+            Requirement::NotExists(String::from(key.trim()))
+        } else {
+            Requirement::Exists(String::from(requirement))
+        };
+        match requirement {
+            Requirement::Equals(ref key, _)
+            | Requirement::NotEquals(ref key, _)
+            | Requirement::Exists(ref key)
+            | Requirement::NotExists(ref key)
+                if key.is_empty() =>
+            {
+                return Err(invalid());
+            }
+            _ => requirements.push(requirement),
+        }
+    }
+    Ok(requirements)
+}
+
+/// The position of an item in a listing.
+type Position = (Option<DateTime<Utc>>, String);
+
+fn position<T: Listed>(item: &T, order_by: OrderBy) -> Position {
+    let created = match order_by {
+        OrderBy::Name => None,
+        OrderBy::Created => Some(item.created()),
+    };
+    (created, String::from(item.name()))
+}
+
+// Page tokens encode the position of the last item of a page. Positions are used instead of
+// offsets so pages stay consistent when items are created or deleted between requests.
+fn encode_token(position: &Position) -> String {
+    match position {
+        (Some(created), name) => format!(
+            "{}/{}",
+            created.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            name
+        ),
+        (None, name) => name.clone(),
+    }
+}
+
+fn decode_token(token: &str, order_by: OrderBy) -> Result<Position> {
+    match order_by {
+        OrderBy::Name => Ok((None, String::from(token))),
+        OrderBy::Created => {
+            let mut parts = token.splitn(2, '/');
+            let created = parts
+                .next()
+                .and_then(|c| DateTime::parse_from_rfc3339(c).ok());
+            match (created, parts.next()) {
+                (Some(created), Some(name)) => {
+                    Ok((Some(created.with_timezone(&Utc)), String::from(name)))
+                }
+                _ => Err(Error::invalid_request(format!(
+                    "Invalid page token '{}'",
+                    token
+                ))),
+            }
+        }
+    }
+}
+
+/// Filter, order, and page a list of items.
+pub(crate) fn paginate<T: Listed>(items: Vec<T>, options: &ListOptions) -> Result<Page<T>> {
+    let start = match options.page_token {
+        Some(ref token) => Some(decode_token(token, options.order_by)?),
+        None => None,
+    };
+    let requirements = match options.labels {
+        Some(ref selector) => parse_selector(selector)?,
+        None => Vec::new(),
+    };
+    let mut items: Vec<(Position, T)> = items
+        .into_iter()
+        .filter(|item| match options.prefix {
+            Some(ref prefix) => item.name().starts_with(prefix.as_str()),
+            None => true,
+        })
+        .filter(|item| requirements.iter().all(|r| r.matches(item)))
+        .map(|item| (position(&item, options.order_by), item))
+        .filter(|(position, _)| match start {
+            Some(ref start) => position > start,
+            None => true,
+        })
+        .collect();
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let page_size = match options.page_size {
+        Some(0) => {
+            return Err(Error::invalid_request(String::from(
+                "The page size must be greater than 0",
+            )))
+        }
+        Some(page_size) => page_size,
+        None => items.len(),
+    };
+    let next_page_token = if items.len() > page_size {
+        items.truncate(page_size);
+        items.last().map(|(position, _)| encode_token(position))
+    } else {
+        None
+    };
+    Ok(Page {
+        items: items.into_iter().map(|(_, item)| item).collect(),
+        next_page_token,
+    })
+}
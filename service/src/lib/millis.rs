@@ -0,0 +1,36 @@
+//! Serialization of durations as milliseconds.
+//!
+//! Used with `#[serde(with = "crate::millis")]` and, for optional durations,
+//! `#[serde(with = "crate::millis::option")]`.
+
+use chrono::Duration;
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_milliseconds())
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    i64::deserialize(deserializer).map(Duration::milliseconds)
+}
+
+pub mod option {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.num_milliseconds()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<i64>::deserialize(deserializer).map(|ms| ms.map(Duration::milliseconds))
+    }
+}
@@ -0,0 +1,120 @@
+//! Scheduling of the expiration of topics, subscriptions, messages, and consumers.
+//!
+//! The [Scheduler](struct.Scheduler.html) keeps the earliest time each topic, subscription, topic
+//! head message, and subscription consumer may expire. Expirations are only a hint, when one is
+//! due the owner of the scheduler checks the actual state of the item and schedules it again if
+//! it has not expired yet (for example because the item was updated in the meantime).
+
+use chrono::prelude::*;
+use parking_lot::{Condvar, Mutex};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time;
+
+use crate::Key;
+
+/// Something which may expire.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Expiration {
+    /// The topic reached its ttl.
+    Topic(Key),
+    /// The subscription reached its ttl.
+    Subscription(Key),
+    /// The head message of the topic reached its ttl.
+    Messages(Key),
+    /// The least recently seen consumer of the subscription reached its timeout.
+    Consumers(Key),
+}
+
+#[derive(Default)]
+struct State {
+    // The earliest scheduled time of each expiration. Entries of the queue with a later time are
+    // stale and skipped.
+    due: HashMap<Expiration, DateTime<Utc>>,
+    queue: BinaryHeap<Reverse<(DateTime<Utc>, Expiration)>>,
+    stopped: bool,
+}
+
+impl State {
+    // Drop stale entries from the front of the queue and return the time of the next expiration.
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        while let Some(Reverse((at, expiration))) = self.queue.peek() {
+            if self.due.get(expiration) == Some(at) {
+                return Some(*at);
+            }
+            self.queue.pop();
+        }
+        None
+    }
+}
+
+/// A scheduler of [Expiration](enum.Expiration.html)s.
+#[derive(Default)]
+pub struct Scheduler {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Scheduler {
+    /// Schedule `expiration` at `at` unless it is already scheduled at or before `at`.
+    pub fn schedule(&self, expiration: Expiration, at: DateTime<Utc>) {
+        let mut state = self.state.lock();
+        if state.due.get(&expiration).is_some_and(|&due| due <= at) {
+            return;
+        }
+        let next = state.next();
+        state.due.insert(expiration.clone(), at);
+        state.queue.push(Reverse((at, expiration)));
+        // Only wake the waiting thread if it now has to wake earlier
+        if next.is_none_or(|next| at < next) {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Block until at least one expiration is due and return all due expirations.
+    ///
+    /// Returns None once the scheduler is stopped.
+    pub fn wait(&self) -> Option<Vec<Expiration>> {
+        let mut state = self.state.lock();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            let due = self.pop_due(&mut state, Utc::now());
+            if !due.is_empty() {
+                return Some(due);
+            }
+            match state.next() {
+                Some(at) => {
+                    // Round up so the expiration is due once the thread wakes
+                    let timeout = (at - Utc::now()).to_std().unwrap_or_default()
+                        + time::Duration::from_millis(1);
+                    self.wake.wait_for(&mut state, timeout);
+                }
+                None => {
+                    self.wake.wait(&mut state);
+                }
+            }
+        }
+    }
+
+    /// Stop the scheduler waking a thread blocked in [wait](#method.wait).
+    pub fn stop(&self) {
+        self.state.lock().stopped = true;
+        self.wake.notify_one();
+    }
+
+    fn pop_due(&self, state: &mut State, now: DateTime<Utc>) -> Vec<Expiration> {
+        let mut due = Vec::new();
+        while let Some(at) = state.next() {
+            if at > now {
+                break;
+            }
+            if let Some(Reverse((_, expiration))) = state.queue.pop() {
+                state.due.remove(&expiration);
+                due.push(expiration);
+            }
+        }
+        due
+    }
+}
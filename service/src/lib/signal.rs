@@ -0,0 +1,66 @@
+//! Signals waking the threads which deliver messages once there may be messages to pull.
+//!
+//! A [Signal](struct.Signal.html) registered with
+//! [Registry::listen](../struct.Registry.html#method.listen) is raised whenever messages are
+//! published or released to be resent, so a delivering thread can block instead of polling its
+//! subscriptions. Messages also become deliverable when their ack deadline is reached without
+//! any event, so a waiting thread still pulls again after a timeout.
+
+use parking_lot::{Condvar, Mutex};
+use std::sync::{Arc, Weak};
+use std::time;
+
+/// A signal a thread waits on until it is raised.
+#[derive(Default)]
+pub struct Signal {
+    raised: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Signal {
+    /// Create a new signal which is not raised.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Signal::default())
+    }
+
+    /// Raise the signal waking the thread waiting on it.
+    pub fn raise(&self) {
+        *self.raised.lock() = true;
+        self.wake.notify_all();
+    }
+
+    /// Block until the signal is raised or `timeout` passed and lower it again.
+    ///
+    /// Returns true if the signal was raised.
+    pub fn wait(&self, timeout: time::Duration) -> bool {
+        let mut raised = self.raised.lock();
+        if !*raised {
+            self.wake.wait_for(&mut raised, timeout);
+        }
+        let was_raised = *raised;
+        *raised = false;
+        was_raised
+    }
+}
+
+/// The signals raised together, signals are forgotten once they are dropped.
+#[derive(Default)]
+pub(crate) struct Signals(Mutex<Vec<Weak<Signal>>>);
+
+impl Signals {
+    /// Raise `signal` with the other signals until it is dropped.
+    pub fn add(&self, signal: &Arc<Signal>) {
+        self.0.lock().push(Arc::downgrade(signal));
+    }
+
+    /// Raise every signal which was not dropped yet.
+    pub fn raise(&self) {
+        self.0.lock().retain(|signal| match signal.upgrade() {
+            Some(signal) => {
+                signal.raise();
+                true
+            }
+            None => false,
+        });
+    }
+}
@@ -0,0 +1,224 @@
+use super::*;
+use std::thread;
+
+const THREADS: usize = 8;
+const MESSAGES: usize = 200;
+
+fn create_topic(registry: &Registry, name: &str) {
+    registry
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            name,
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(0),
+            Duration::seconds(0),
+            1,
+        )
+        .unwrap();
+}
+
+fn create_subscription(registry: &Registry, name: &str, topic: &str) -> Result<SubscriptionMeta> {
+    registry.create_subscription(
+        DEFAULT_NAMESPACE,
+        name,
+        DEFAULT_NAMESPACE,
+        topic,
+        HashMap::new(),
+        String::new(),
+        Duration::seconds(60),
+        Duration::seconds(0),
+        Duration::seconds(0),
+        true,
+    )
+}
+
+#[test]
+fn registry_concurrent_topics() {
+    let registry = Registry::new();
+    let handles: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let registry = Arc::clone(&registry);
+            thread::spawn(move || {
+                let topic = format!("topic-{}", i);
+                let subscription = format!("subscription-{}", i);
+                create_topic(&registry, &topic);
+                create_subscription(&registry, &subscription, &topic).unwrap();
+                for j in 0..MESSAGES {
+                    registry
+                        .publish(
+                            DEFAULT_NAMESPACE,
+                            &topic,
+                            vec![RawMessage::new(j.to_string())],
+                        )
+                        .unwrap();
+                    let messages = registry
+                        .pull(DEFAULT_NAMESPACE, &subscription, 1, None, None)
+                        .unwrap();
+                    assert_eq!(messages.len(), 1);
+                    assert_eq!(&*messages[0].data, j.to_string());
+                    let acked = registry
+                        .ack(DEFAULT_NAMESPACE, &subscription, &[messages[0].id])
+                        .unwrap();
+                    assert_eq!(acked.len(), 1);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let metrics = registry.metrics();
+    assert_eq!(metrics.topics_all_time, THREADS as u64);
+    assert_eq!(metrics.subscriptions_all_time, THREADS as u64);
+    let namespace_metrics = registry.namespace_metrics(DEFAULT_NAMESPACE).unwrap();
+    assert_eq!(namespace_metrics.topics.len(), THREADS);
+    for i in 0..THREADS {
+        let topic = &namespace_metrics.topics[&format!("topic-{}", i)];
+        assert_eq!(topic.messages, MESSAGES);
+        assert_eq!(topic.messages_all_time, MESSAGES as u64);
+        let subscription = &namespace_metrics.subscriptions[&format!("subscription-{}", i)];
+        assert_eq!(subscription.pending, 0);
+        assert_eq!(subscription.pulled_all_time, MESSAGES as u64);
+        assert_eq!(subscription.acks_all_time, MESSAGES as u64);
+    }
+}
+
+#[test]
+fn registry_concurrent_subscribe_and_delete() {
+    let registry = Registry::new();
+    create_topic(&registry, "topic");
+
+    // Subscribe while the topic is published to and then deleted, every subscription must either
+    // fail because the topic is gone or be deleted with the topic
+    let handles: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let registry = Arc::clone(&registry);
+            thread::spawn(move || {
+                for j in 0..MESSAGES {
+                    let name = format!("subscription-{}-{}", i, j);
+                    if create_subscription(&registry, &name, "topic").is_err() {
+                        break;
+                    }
+                    let _ =
+                        registry.publish(DEFAULT_NAMESPACE, "topic", vec![RawMessage::new(name)]);
+                }
+            })
+        })
+        .collect();
+    thread::sleep(std::time::Duration::from_millis(10));
+    registry.delete_topic(DEFAULT_NAMESPACE, "topic").unwrap();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert!(registry.get_topic(DEFAULT_NAMESPACE, "topic").is_err());
+    let subscriptions = registry
+        .list_subscriptions(DEFAULT_NAMESPACE, &ListOptions::default())
+        .unwrap();
+    assert!(subscriptions.items.is_empty());
+}
+
+#[test]
+fn registry_expiration() {
+    let registry = Registry::new();
+    registry
+        .create_topic(
+            DEFAULT_NAMESPACE,
+            "topic",
+            HashMap::new(),
+            String::new(),
+            Duration::milliseconds(200),
+            Duration::milliseconds(600),
+            1,
+        )
+        .unwrap();
+    registry
+        .create_subscription(
+            DEFAULT_NAMESPACE,
+            "subscription",
+            DEFAULT_NAMESPACE,
+            "topic",
+            HashMap::new(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::milliseconds(400),
+            Duration::seconds(0),
+            true,
+        )
+        .unwrap();
+    registry
+        .publish(
+            DEFAULT_NAMESPACE,
+            "topic",
+            vec![RawMessage::new(String::from("a"))],
+        )
+        .unwrap();
+
+    // The message expires on its own
+    thread::sleep(std::time::Duration::from_millis(300));
+    let (messages, next_offset) = registry
+        .read(DEFAULT_NAMESPACE, "topic", 0, None, 10)
+        .unwrap();
+    assert!(messages.is_empty());
+    assert_eq!(1, next_offset);
+    let metrics = registry.namespace_metrics(DEFAULT_NAMESPACE).unwrap();
+    assert_eq!(1, metrics.topics["topic"].expired_all_time);
+
+    // Updating the topic postpones its expiration
+    registry
+        .publish(
+            DEFAULT_NAMESPACE,
+            "topic",
+            vec![RawMessage::new(String::from("b"))],
+        )
+        .unwrap();
+    thread::sleep(std::time::Duration::from_millis(200));
+    assert!(registry
+        .get_subscription(DEFAULT_NAMESPACE, "subscription")
+        .is_err());
+    assert!(registry.get_topic(DEFAULT_NAMESPACE, "topic").is_ok());
+    thread::sleep(std::time::Duration::from_millis(600));
+    assert!(registry.get_topic(DEFAULT_NAMESPACE, "topic").is_err());
+}
+
+#[test]
+fn registry_listen() {
+    let registry = Registry::new();
+    create_topic(&registry, "topic");
+    create_subscription(&registry, "subscription", "topic").unwrap();
+    let signal = Signal::new();
+    registry.listen(&signal);
+    let timeout = std::time::Duration::from_millis(10);
+
+    // Publishes and nacks make messages deliverable, pulls and acks do not
+    let waiter = {
+        let signal = Arc::clone(&signal);
+        thread::spawn(move || signal.wait(std::time::Duration::from_secs(10)))
+    };
+    registry
+        .publish(
+            DEFAULT_NAMESPACE,
+            "topic",
+            vec![RawMessage::new(String::from("a"))],
+        )
+        .unwrap();
+    assert!(waiter.join().unwrap());
+    let messages = registry
+        .pull(DEFAULT_NAMESPACE, "subscription", 10, None, None)
+        .unwrap();
+    assert!(!signal.wait(timeout));
+    let ids = vec![messages[0].id];
+    registry
+        .nack(DEFAULT_NAMESPACE, "subscription", &ids)
+        .unwrap();
+    assert!(signal.wait(timeout));
+    registry
+        .pull(DEFAULT_NAMESPACE, "subscription", 10, None, None)
+        .unwrap();
+    registry
+        .ack(DEFAULT_NAMESPACE, "subscription", &ids)
+        .unwrap();
+    assert!(!signal.wait(timeout));
+}
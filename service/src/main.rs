@@ -1,33 +1,40 @@
-use chrono::Duration;
+use config_file::{parse_mode, parse_peer, Settings};
+use courier::bridge::{Bridge, BridgeConfig};
+use courier::cluster::Cluster;
+use courier::Registry;
 use env_logger::fmt::WriteStyle;
 use env_logger::Builder;
 use log::LevelFilter;
-use log::{error, info};
+use log::{error, info, warn};
 use open;
+use parking_lot::RwLock;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use structopt;
 use structopt::StructOpt;
 
+mod config_file;
+mod delivery;
+mod grpc_protocol;
 mod http_protocol;
+mod kafka_protocol;
+mod mqtt_protocol;
+mod nats_protocol;
+mod redis_protocol;
+mod stomp_protocol;
+#[cfg(test)]
+mod testing;
 
 #[derive(Debug, StructOpt)]
 #[structopt()]
 struct Opt {
-    /// An IP address or host the application will listen on
-    #[structopt(
-        default_value = "0.0.0.0",
-        env = "COURIER_HOST",
-        long = "host",
-        short = "H"
-    )]
-    host: String,
-    /// A port number to listen on
-    #[structopt(
-        default_value = "3140",
-        env = "COURIER_PORT",
-        long = "port",
-        short = "P"
-    )]
-    port: u16,
+    /// An IP address or host the application will listen on (default 0.0.0.0)
+    #[structopt(env = "COURIER_HOST", long = "host", short = "H")]
+    host: Option<String>,
+    /// A port number to listen on (default 3140)
+    #[structopt(env = "COURIER_PORT", long = "port", short = "P")]
+    port: Option<u16>,
     /// Log level
     #[structopt(
         default_value = "info",
@@ -43,29 +50,162 @@ struct Opt {
 }
 
 #[derive(Debug, StructOpt)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     #[structopt()]
     /// Run the service
     #[structopt(name = "run")]
     Run {
-        /// Default time to live (ttl) of a topic (seconds)
-        #[structopt(default_value = "0", long = "default-topic-ttl")]
-        default_topic_ttl: i64,
-        /// Default time to live (ttl) of a subscription (seconds)
-        #[structopt(default_value = "0", long = "default-subscription-ttl")]
-        default_subscription_ttl: i64,
-        /// Default time to live (ttl) of a messages (seconds)
-        #[structopt(default_value = "3600", long = "default-message-ttl")]
-        default_message_ttl: i64,
-        /// Default duration a subscription has to acknowledge a message (seconds)
-        #[structopt(default_value = "60", long = "default-ack-deadline")]
-        default_ack_deadline: i64,
-        /// Default max number of messages pulled by a subscription
-        #[structopt(default_value = "1", long = "default-max-messages")]
-        default_max_messages: usize,
-        /// Duration between running the cleanup thread (seconds)
-        #[structopt(default_value = "1", long = "cleanup-interval")]
-        cleanup_interval: i64,
+        /// A TOML file with the settings of the service, flags and environment variables take
+        /// precedence over it. It is reloaded on SIGHUP and when it changes
+        #[structopt(long = "config", env = "COURIER_CONFIG", parse(from_os_str))]
+        config: Option<PathBuf>,
+        /// Default time to live (ttl) of a topic (seconds, default 0)
+        #[structopt(long = "default-topic-ttl", env = "COURIER_DEFAULT_TOPIC_TTL")]
+        default_topic_ttl: Option<i64>,
+        /// Default time to live (ttl) of a subscription (seconds, default 0)
+        #[structopt(
+            long = "default-subscription-ttl",
+            env = "COURIER_DEFAULT_SUBSCRIPTION_TTL"
+        )]
+        default_subscription_ttl: Option<i64>,
+        /// Default time to live (ttl) of a messages (seconds, default 3600)
+        #[structopt(long = "default-message-ttl", env = "COURIER_DEFAULT_MESSAGE_TTL")]
+        default_message_ttl: Option<i64>,
+        /// Default duration a subscription has to acknowledge a message (seconds, default 60)
+        #[structopt(long = "default-ack-deadline", env = "COURIER_DEFAULT_ACK_DEADLINE")]
+        default_ack_deadline: Option<i64>,
+        /// Default duration after which the messages of a consumer which stopped pulling are
+        /// released, 0 never releases them (seconds, default 0)
+        #[structopt(
+            long = "default-consumer-timeout",
+            env = "COURIER_DEFAULT_CONSUMER_TIMEOUT"
+        )]
+        default_consumer_timeout: Option<i64>,
+        /// Default max number of messages pulled by a subscription (default 1)
+        #[structopt(long = "default-max-messages", env = "COURIER_DEFAULT_MAX_MESSAGES")]
+        default_max_messages: Option<usize>,
+        /// Deprecated and ignored, expirations run when they are due
+        #[structopt(long = "cleanup-interval", raw(hidden = "true"))]
+        cleanup_interval: Option<i64>,
+        /// PEM encoded certificate chain, serves the api over https when set
+        #[structopt(
+            long = "tls-cert",
+            env = "COURIER_TLS_CERT",
+            parse(from_os_str),
+            raw(requires = r#""tls_key""#)
+        )]
+        tls_cert: Option<PathBuf>,
+        /// PEM encoded private key of the tls certificate
+        #[structopt(
+            long = "tls-key",
+            env = "COURIER_TLS_KEY",
+            parse(from_os_str),
+            raw(requires = r#""tls_cert""#)
+        )]
+        tls_key: Option<PathBuf>,
+        /// PEM encoded CA bundle, requires clients to present a certificate signed by it when set
+        #[structopt(
+            long = "tls-client-ca",
+            env = "COURIER_TLS_CLIENT_CA",
+            parse(from_os_str),
+            raw(requires = r#""tls_cert""#)
+        )]
+        tls_client_ca: Option<PathBuf>,
+        /// Client certificate common name allowed to connect, can be repeated or comma separated
+        /// (default all)
+        #[structopt(
+            long = "tls-client-identity",
+            env = "COURIER_TLS_CLIENT_IDENTITIES",
+            raw(use_delimiter = "true", requires = r#""tls_client_ca""#)
+        )]
+        tls_client_identities: Vec<String>,
+        /// A Unix domain socket path to serve the api on in addition to the TCP port
+        #[structopt(long = "unix-socket", env = "COURIER_UNIX_SOCKET", parse(from_os_str))]
+        unix_socket: Option<PathBuf>,
+        /// File permissions of the Unix domain socket as an octal mode (default 660)
+        #[structopt(
+            long = "unix-socket-mode",
+            env = "COURIER_UNIX_SOCKET_MODE",
+            parse(try_from_str = "parse_mode"),
+            raw(requires = r#""unix_socket""#)
+        )]
+        unix_socket_mode: Option<u32>,
+        /// Serve the api only on the Unix domain socket and not on the TCP port
+        #[structopt(
+            long = "unix-socket-only",
+            env = "COURIER_UNIX_SOCKET_ONLY",
+            raw(requires = r#""unix_socket""#)
+        )]
+        unix_socket_only: Option<Option<bool>>,
+        /// Id of this node, runs the service as part of a cluster when set
+        #[structopt(
+            long = "cluster-node-id",
+            env = "COURIER_CLUSTER_NODE_ID",
+            raw(requires_all = r#"&["cluster_listen", "cluster_data_dir"]"#)
+        )]
+        cluster_node_id: Option<u64>,
+        /// An address to listen on for the other nodes of the cluster
+        #[structopt(
+            long = "cluster-listen",
+            env = "COURIER_CLUSTER_LISTEN",
+            raw(requires = r#""cluster_node_id""#)
+        )]
+        cluster_listen: Option<String>,
+        /// Another node of the cluster as `<id>=<address>`, repeated or comma separated for each
+        /// node
+        #[structopt(
+            long = "cluster-peer",
+            env = "COURIER_CLUSTER_PEERS",
+            parse(try_from_str = "parse_peer"),
+            raw(use_delimiter = "true", requires = r#""cluster_node_id""#)
+        )]
+        cluster_peers: Vec<(u64, String)>,
+        /// Directory the replicated log of this node is stored in
+        #[structopt(
+            long = "cluster-data-dir",
+            env = "COURIER_CLUSTER_DATA_DIR",
+            parse(from_os_str),
+            raw(requires = r#""cluster_node_id""#)
+        )]
+        cluster_data_dir: Option<PathBuf>,
+        /// Number of applied entries after which the log is compacted into a snapshot, 0 never
+        /// compacts it (default 10000)
+        #[structopt(
+            long = "cluster-snapshot-entries",
+            env = "COURIER_CLUSTER_SNAPSHOT_ENTRIES",
+            raw(requires = r#""cluster_node_id""#)
+        )]
+        cluster_snapshot_entries: Option<u64>,
+        /// Name of this instance attached to the messages it bridges to other instances (default
+        /// `<host>:<port>`)
+        #[structopt(long = "origin", env = "COURIER_ORIGIN")]
+        origin: Option<String>,
+        /// Forward the messages of a subscription to a topic of another instance as
+        /// `[<namespace>/]<subscription>=[<namespace>/]<topic>@<url>`, can be repeated or comma
+        /// separated
+        #[structopt(long = "bridge", env = "COURIER_BRIDGES", raw(use_delimiter = "true"))]
+        bridges: Vec<BridgeConfig>,
+        /// A port number to serve the gRPC api on, it is not served when unset
+        #[structopt(long = "grpc-port", env = "COURIER_GRPC_PORT")]
+        grpc_port: Option<u16>,
+        /// A port number to serve MQTT 3.1.1 on, it is not served when unset
+        #[structopt(long = "mqtt-port", env = "COURIER_MQTT_PORT")]
+        mqtt_port: Option<u16>,
+        /// A port number to serve STOMP 1.2 on over TCP and WebSockets, it is not served when
+        /// unset
+        #[structopt(long = "stomp-port", env = "COURIER_STOMP_PORT")]
+        stomp_port: Option<u16>,
+        /// A port number to serve the Redis streams commands on over RESP, it is not served when
+        /// unset
+        #[structopt(long = "redis-port", env = "COURIER_REDIS_PORT")]
+        redis_port: Option<u16>,
+        /// A port number to serve a subset of the Kafka protocol on, it is not served when unset
+        #[structopt(long = "kafka-port", env = "COURIER_KAFKA_PORT")]
+        kafka_port: Option<u16>,
+        /// A port number to serve the core NATS protocol on, it is not served when unset
+        #[structopt(long = "nats-port", env = "COURIER_NATS_PORT")]
+        nats_port: Option<u16>,
     },
     /// Launch the web user interface
     #[structopt(name = "ui")]
@@ -92,27 +232,100 @@ pub fn main() {
 
     match opt.cmd {
         Command::Run {
+            config: config_path,
             default_topic_ttl,
             default_subscription_ttl,
             default_message_ttl,
             default_ack_deadline,
+            default_consumer_timeout,
             default_max_messages,
             cleanup_interval,
+            tls_cert,
+            tls_key,
+            tls_client_ca,
+            tls_client_identities,
+            unix_socket,
+            unix_socket_mode,
+            unix_socket_only,
+            cluster_node_id,
+            cluster_listen,
+            cluster_peers,
+            cluster_data_dir,
+            cluster_snapshot_entries,
+            origin,
+            bridges,
+            grpc_port,
+            mqtt_port,
+            stomp_port,
+            redis_port,
+            kafka_port,
+            nats_port,
         } => {
-            let config = http_protocol::Config {
-                host: opt.host.clone(),
+            if cleanup_interval.is_some() {
+                warn!(
+                    "--cleanup-interval is deprecated and ignored, expirations run when they are \
+                     due. It will be removed in a future release."
+                );
+            }
+            // The flags and environment variables which are given take precedence over the file
+            let overrides = Settings {
+                host: opt.host,
                 port: opt.port,
-                default_topic_ttl: Duration::seconds(default_topic_ttl),
-                default_subscription_ttl: Duration::seconds(default_subscription_ttl),
-                default_message_ttl: Duration::seconds(default_message_ttl),
-                default_ack_deadline: Duration::seconds(default_ack_deadline),
+                default_topic_ttl,
+                default_subscription_ttl,
+                default_message_ttl,
+                default_ack_deadline,
+                default_consumer_timeout,
                 default_max_messages,
-                cleanup_interval: Duration::seconds(cleanup_interval),
+                tls: tls_cert.map(|cert| config_file::TlsSettings {
+                    cert: Some(cert),
+                    key: tls_key,
+                    client_ca: tls_client_ca,
+                    client_identities: Some(tls_client_identities).filter(|v| !v.is_empty()),
+                }),
+                unix_socket: unix_socket.map(|path| config_file::UnixSocketSettings {
+                    path: Some(path),
+                    mode: unix_socket_mode,
+                    exclusive: unix_socket_only.map(|only| only.unwrap_or(true)),
+                }),
+                cluster: cluster_node_id.map(|id| config_file::ClusterSettings {
+                    node_id: Some(id),
+                    listen: cluster_listen,
+                    peers: Some(cluster_peers).filter(|v| !v.is_empty()),
+                    data_dir: cluster_data_dir,
+                    snapshot_entries: cluster_snapshot_entries,
+                }),
+                origin,
+                bridges: Some(bridges).filter(|v| !v.is_empty()),
+                grpc_port,
+                mqtt_port,
+                stomp_port,
+                redis_port,
+                kafka_port,
+                nats_port,
             };
-            http_protocol::start(config)
+            let config = match &config_path {
+                Some(path) => config_file::load(path, overrides.clone()),
+                None => overrides.clone().into_config(),
+            };
+            let config = Arc::new(RwLock::new(config.unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1)
+            })));
+            if let Some(path) = config_path {
+                config_file::watch(&path, overrides, &config).unwrap_or_else(|e| {
+                    error!("Can not watch '{}': {}", path.display(), e);
+                    process::exit(1)
+                });
+            }
+            serve(config)
         }
         Command::Ui {} => {
-            let url = format!("http://{}:{}/ui", &opt.host, opt.port);
+            let url = format!(
+                "http://{}:{}/ui",
+                opt.host.as_deref().unwrap_or(config_file::DEFAULT_HOST),
+                opt.port.unwrap_or(config_file::DEFAULT_PORT)
+            );
             match open::that(url.clone()) {
                 Ok(exit_status) => {
                     if exit_status.success() {
@@ -134,3 +347,54 @@ pub fn main() {
         }
     }
 }
+
+// Start the cluster node, the bridges, and the listeners of every protocol which is enabled, then
+// serve the HTTP api.
+fn serve(shared_config: http_protocol::SharedConfig) {
+    let config = shared_config.read().clone();
+    let registry = Registry::new();
+    if let Some(cluster) = config.cluster.clone() {
+        let listen = cluster.listen.clone();
+        Cluster::start(cluster, &registry)
+            .unwrap_or_else(|e| panic!("Can not start the cluster node on '{}': {}", listen, e));
+    }
+    let bridges: Vec<_> = config
+        .bridges
+        .iter()
+        .map(|bridge| {
+            Bridge::start(bridge.clone(), &config.origin, &registry)
+                .unwrap_or_else(|e| panic!("Can not start the bridge to '{}': {}", bridge.url, e))
+        })
+        .collect();
+    if let Some(port) = config.grpc_port {
+        let address = format!("{}:{}", config.host, port);
+        grpc_protocol::start(&address, &registry, &shared_config)
+            .unwrap_or_else(|e| panic!("Can not serve gRPC on '{}': {}", address, e));
+    }
+    if let Some(port) = config.mqtt_port {
+        let address = format!("{}:{}", config.host, port);
+        mqtt_protocol::start(&address, &registry, &shared_config)
+            .unwrap_or_else(|e| panic!("Can not serve MQTT on '{}': {}", address, e));
+    }
+    if let Some(port) = config.stomp_port {
+        let address = format!("{}:{}", config.host, port);
+        stomp_protocol::start(&address, &registry, &shared_config)
+            .unwrap_or_else(|e| panic!("Can not serve STOMP on '{}': {}", address, e));
+    }
+    if let Some(port) = config.redis_port {
+        let address = format!("{}:{}", config.host, port);
+        redis_protocol::start(&address, &registry, &shared_config)
+            .unwrap_or_else(|e| panic!("Can not serve RESP on '{}': {}", address, e));
+    }
+    if let Some(port) = config.kafka_port {
+        let address = format!("{}:{}", config.host, port);
+        kafka_protocol::start(&address, &registry, &shared_config)
+            .unwrap_or_else(|e| panic!("Can not serve Kafka on '{}': {}", address, e));
+    }
+    if let Some(port) = config.nats_port {
+        let address = format!("{}:{}", config.host, port);
+        nats_protocol::start(&address, &registry, &shared_config)
+            .unwrap_or_else(|e| panic!("Can not serve NATS on '{}': {}", address, e));
+    }
+    http_protocol::start(&registry, shared_config, &bridges)
+}
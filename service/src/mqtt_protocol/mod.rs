@@ -0,0 +1,560 @@
+//! An MQTT 3.1.1 listener mapped onto the topics and subscriptions of the registry.
+//!
+//! MQTT topic names are the names of topics in the default namespace, topics are created with the
+//! default settings the first time a client publishes or subscribes to them. Topic filters with
+//! wildcards are refused.
+//!
+//! A SUBSCRIBE creates the subscription `mqtt/<client id>/<topic>`, or attaches to it if it
+//! already exists. Subscriptions are historical, Courier topics retain their messages for their
+//! `message_ttl`, so a new subscriber receives the messages the topic still retains with the
+//! RETAIN flag set. The subscriptions of a clean session are deleted when it ends. The
+//! subscriptions of a persistent session outlive the connection and are attached again when the
+//! client reconnects, the messages the client did not acknowledge are released when it
+//! disconnects.
+//!
+//! Messages are delivered with at most QoS 1. A QoS 1 message is acked in the subscription once
+//! the client sends its PUBACK, unacknowledged messages are redelivered with the DUP flag once
+//! their `ack_deadline` is reached. Messages published by clients with QoS 2 are published
+//! exactly once. Payloads must be UTF-8.
+
+mod packet;
+#[cfg(test)]
+mod tests;
+
+use chrono::prelude::*;
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use uuid::Uuid;
+
+use self::packet::{Connect, Packet, Publish};
+use crate::delivery::{self, Subscriber};
+use crate::http_protocol::subscription_handlers::create_subscription;
+use crate::http_protocol::topic_handlers::ensure_topic;
+use crate::http_protocol::types::SubscriptionCreateConfig;
+use crate::http_protocol::SharedConfig;
+use courier::signal::Signal;
+use courier::{
+    Error, ErrorCode, ListOptions, Message, RawMessage, SharedRegistry, SubscriptionMeta,
+    DEFAULT_NAMESPACE,
+};
+
+/// How long a client has to send its CONNECT packet.
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// The maximum number of QoS 1 messages waiting for a PUBACK per session.
+const MAX_IN_FLIGHT: usize = 100;
+
+/// The label of a subscription holding the client id of its session.
+const CLIENT_LABEL: &str = "mqtt-client";
+
+/// The label of a subscription holding the QoS granted to its session.
+const QOS_LABEL: &str = "mqtt-qos";
+
+/// The state shared by the sessions of the MQTT listener.
+struct MqttState {
+    registry: SharedRegistry,
+    config: SharedConfig,
+    // The connection id and stream of the session of every connected client.
+    sessions: Mutex<HashMap<String, (u64, TcpStream)>>,
+    next_connection: AtomicU64,
+}
+
+/// Start the MQTT listener on `address` in the background.
+pub fn start(address: &str, registry: &SharedRegistry, config: &SharedConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving MQTT on '{}'.", listener.local_addr()?);
+    let state = Arc::new(MqttState {
+        registry: Arc::clone(registry),
+        config: Arc::clone(config),
+        sessions: Mutex::new(HashMap::new()),
+        next_connection: AtomicU64::new(0),
+    });
+    thread::Builder::new()
+        .name(String::from("mqtt"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            if let Err(e) = serve(&state, stream) {
+                                debug!("MQTT connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Can not accept an MQTT connection: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// A subscription attached to a session.
+#[derive(Clone, Debug)]
+struct Attached {
+    name: String,
+    topic: String,
+    qos: u8,
+    created: DateTime<Utc>,
+}
+
+impl Attached {
+    fn new(subscription: SubscriptionMeta) -> Self {
+        let qos = match subscription.labels.get(QOS_LABEL).map(String::as_str) {
+            Some("0") => 0,
+            _ => 1,
+        };
+        Attached {
+            name: subscription.name,
+            topic: subscription.topic,
+            qos,
+            created: subscription.created,
+        }
+    }
+}
+
+/// The session of a connected client.
+struct Session {
+    state: Arc<MqttState>,
+    connection: u64,
+    client_id: String,
+    clean_session: bool,
+    writer: Mutex<BufWriter<TcpStream>>,
+    // Attached subscriptions by topic.
+    subscriptions: Mutex<HashMap<String, Attached>>,
+    // QoS 1 messages waiting for a PUBACK by packet id.
+    in_flight: Mutex<HashMap<u16, (String, Uuid)>>,
+    next_packet_id: Mutex<u16>,
+    // Packet ids of QoS 2 messages published by the client waiting for a PUBREL.
+    received: Mutex<HashSet<u16>>,
+    closed: AtomicBool,
+    signal: Arc<Signal>,
+}
+
+// Serve a connection until it is closed.
+fn serve(state: &Arc<MqttState>, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let connect = match Packet::read(&mut reader)? {
+        Packet::Connect(connect) => connect,
+        packet => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected CONNECT but got {:?}", packet),
+            ))
+        }
+    };
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let refused = if connect.protocol_name != "MQTT" || connect.protocol_level != 4 {
+        Some(packet::UNACCEPTABLE_PROTOCOL_VERSION)
+    } else if connect.client_id.is_empty() && !connect.clean_session {
+        Some(packet::IDENTIFIER_REJECTED)
+    } else {
+        None
+    };
+    if let Some(code) = refused {
+        let connack = Packet::ConnAck {
+            session_present: false,
+            code,
+        };
+        connack.write(&mut writer)?;
+        return writer.flush();
+    }
+    let Connect {
+        client_id,
+        clean_session,
+        keep_alive,
+        will,
+        ..
+    } = connect;
+    let client_id = if client_id.is_empty() {
+        Uuid::new_v4().to_string()
+    } else {
+        client_id
+    };
+
+    // A new connection of a client takes over its session
+    let connection = state.next_connection.fetch_add(1, Ordering::SeqCst);
+    let previous = state
+        .sessions
+        .lock()
+        .insert(client_id.clone(), (connection, stream.try_clone()?));
+    if let Some((_, previous)) = previous {
+        let _ = previous.shutdown(Shutdown::Both);
+    }
+
+    let session = Arc::new(Session {
+        state: Arc::clone(state),
+        connection,
+        client_id,
+        clean_session,
+        writer: Mutex::new(writer),
+        subscriptions: Mutex::new(HashMap::new()),
+        in_flight: Mutex::new(HashMap::new()),
+        next_packet_id: Mutex::new(0),
+        received: Mutex::new(HashSet::new()),
+        closed: AtomicBool::new(false),
+        signal: Signal::new(),
+    });
+    let result = session.run(&mut reader, &stream, keep_alive);
+    let disconnected = match result {
+        Ok(()) => true,
+        Err(ref e) => {
+            debug!("MQTT session of '{}' failed: {}", session.client_id, e);
+            false
+        }
+    };
+    session.close(if disconnected { None } else { will });
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+impl Session {
+    fn registry(&self) -> &SharedRegistry {
+        &self.state.registry
+    }
+
+    fn send(&self, packet: &Packet) -> io::Result<()> {
+        let mut writer = self.writer.lock();
+        packet.write(&mut *writer)?;
+        writer.flush()
+    }
+
+    // Accept the connection and handle the packets of the client until it disconnects.
+    fn run(
+        self: &Arc<Self>,
+        reader: &mut BufReader<TcpStream>,
+        stream: &TcpStream,
+        keep_alive: u16,
+    ) -> io::Result<()> {
+        let session_present = self.attach().map_err(other)?;
+        self.send(&Packet::ConnAck {
+            session_present,
+            code: packet::CONNECTION_ACCEPTED,
+        })?;
+        let timeout = match keep_alive {
+            0 => None,
+            keep_alive => Some(time::Duration::from_millis(u64::from(keep_alive) * 1500)),
+        };
+        stream.set_read_timeout(timeout)?;
+        let session = Arc::clone(self);
+        thread::spawn(move || {
+            if let Err(e) = delivery::deliver(session.registry(), &*session) {
+                debug!("Can not deliver to '{}': {}", session.client_id, e);
+            }
+        });
+
+        loop {
+            match Packet::read(reader)? {
+                Packet::Publish(publish) => self.received(publish)?,
+                Packet::PubAck(packet_id) => self.acked(packet_id),
+                Packet::PubRel(packet_id) => {
+                    self.received.lock().remove(&packet_id);
+                    self.send(&Packet::PubComp(packet_id))?;
+                }
+                Packet::Subscribe { packet_id, filters } => {
+                    let codes = filters
+                        .iter()
+                        .map(|(filter, qos)| self.subscribe(filter, *qos))
+                        .collect();
+                    self.send(&Packet::SubAck { packet_id, codes })?;
+                }
+                Packet::Unsubscribe { packet_id, filters } => {
+                    for filter in &filters {
+                        self.unsubscribe(filter);
+                    }
+                    self.send(&Packet::UnsubAck(packet_id))?;
+                }
+                Packet::PingReq => self.send(&Packet::PingResp)?,
+                Packet::Disconnect => return Ok(()),
+                packet => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected {:?}", packet),
+                    ))
+                }
+            }
+        }
+    }
+
+    // The subscriptions of earlier sessions of the client.
+    fn stored_subscriptions(&self) -> Result<Vec<SubscriptionMeta>, Error> {
+        let options = ListOptions {
+            prefix: Some(format!("mqtt/{}/", self.client_id)),
+            ..ListOptions::default()
+        };
+        let page = self
+            .registry()
+            .list_subscriptions(DEFAULT_NAMESPACE, &options)?;
+        Ok(page
+            .items
+            .into_iter()
+            .filter(|s| s.labels.get(CLIENT_LABEL) == Some(&self.client_id))
+            .collect())
+    }
+
+    // Attach the subscriptions of an earlier persistent session or delete them for a clean
+    // session. Returns if a session was present.
+    fn attach(&self) -> Result<bool, Error> {
+        let stored = self.stored_subscriptions()?;
+        if self.clean_session {
+            for subscription in stored {
+                self.delete_subscription(&subscription.name);
+            }
+            return Ok(false);
+        }
+        let session_present = !stored.is_empty();
+        let mut subscriptions = self.subscriptions.lock();
+        for subscription in stored {
+            let attached = Attached::new(subscription);
+            subscriptions.insert(attached.topic.clone(), attached);
+        }
+        Ok(session_present)
+    }
+
+    fn delete_subscription(&self, name: &str) {
+        match self.registry().delete_subscription(DEFAULT_NAMESPACE, name) {
+            Ok(()) => {}
+            Err(ref e) if e.code == ErrorCode::SubscriptionNotFound => {}
+            Err(e) => warn!("Can not delete MQTT subscription '{}': {}", name, e),
+        }
+    }
+
+    // End the session, `will` is published if the client did not disconnect.
+    fn close(&self, will: Option<Publish>) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.signal.raise();
+        if let Some(will) = will {
+            if let Err(e) = self.publish(will) {
+                warn!("Can not publish the will of '{}': {}", self.client_id, e);
+            }
+        }
+        // Leave the subscriptions to the connection which took over the session
+        let mut sessions = self.state.sessions.lock();
+        match sessions.get(&self.client_id) {
+            Some((connection, _)) if *connection == self.connection => {
+                sessions.remove(&self.client_id);
+            }
+            _ => return,
+        }
+        drop(sessions);
+        let subscriptions = self.subscriptions.lock();
+        for attached in subscriptions.values() {
+            if self.clean_session {
+                self.delete_subscription(&attached.name);
+            } else {
+                // Resend the messages which were not acknowledged when the client reconnects
+                let _ = self.registry().release_consumer(
+                    DEFAULT_NAMESPACE,
+                    &attached.name,
+                    &self.client_id,
+                );
+            }
+        }
+    }
+
+    // Create the topic with the default settings if it does not exist.
+    fn ensure_topic(&self, topic: &str) -> Result<(), Error> {
+        ensure_topic(
+            DEFAULT_NAMESPACE,
+            topic,
+            self.registry(),
+            &self.state.config.read(),
+        )
+    }
+
+    fn publish(&self, publish: Publish) -> Result<(), Error> {
+        if publish.topic.is_empty() || publish.topic.contains(['+', '#']) {
+            return Err(Error::invalid_request(format!(
+                "'{}' is not a valid topic name",
+                publish.topic
+            )));
+        }
+        let data = String::from_utf8(publish.payload)
+            .map_err(|_| Error::invalid_request(String::from("The payload is not UTF-8")))?;
+        self.ensure_topic(&publish.topic)?;
+        let message = RawMessage {
+            data,
+            ..RawMessage::default()
+        };
+        self.registry()
+            .publish(DEFAULT_NAMESPACE, &publish.topic, vec![message])?;
+        Ok(())
+    }
+
+    // Publish a message of the client.
+    fn received(&self, publish: Publish) -> io::Result<()> {
+        let (qos, packet_id) = (publish.qos, publish.packet_id.unwrap_or_default());
+        // A QoS 2 message is only published once until it is released
+        if qos < 2 || self.received.lock().insert(packet_id) {
+            self.publish(publish).map_err(other)?;
+        }
+        match qos {
+            0 => Ok(()),
+            1 => self.send(&Packet::PubAck(packet_id)),
+            _ => self.send(&Packet::PubRec(packet_id)),
+        }
+    }
+
+    // Attach the subscription of a topic filter, returns the granted QoS.
+    fn subscribe(&self, filter: &str, qos: u8) -> u8 {
+        if filter.is_empty() || filter.contains(['+', '#']) {
+            return packet::SUBSCRIPTION_FAILURE;
+        }
+        let qos = qos.min(1);
+        let name = format!("mqtt/{}/{}", self.client_id, filter);
+        let mut labels = HashMap::new();
+        labels.insert(String::from(CLIENT_LABEL), self.client_id.clone());
+        labels.insert(String::from(QOS_LABEL), qos.to_string());
+        let config = SubscriptionCreateConfig {
+            topic: String::from(filter),
+            topic_namespace: None,
+            ack_deadline: None,
+            ttl: None,
+            consumer_timeout: None,
+            historical: Some(true),
+            labels: Some(labels.clone()),
+            description: None,
+        };
+        let registry = self.registry();
+        let subscription = self.ensure_topic(filter).and_then(|()| {
+            match create_subscription(
+                DEFAULT_NAMESPACE,
+                &name,
+                &config,
+                registry,
+                &self.state.config.read(),
+            ) {
+                // Attach to the subscription of an earlier session with the new QoS
+                Err(ref e) if e.code == ErrorCode::SubscriptionAlreadyExists => registry
+                    .update_subscription(
+                        DEFAULT_NAMESPACE,
+                        &name,
+                        Some(labels),
+                        None,
+                        None,
+                        None,
+                        None,
+                    ),
+                result => result,
+            }
+        });
+        match subscription {
+            Ok(subscription) => {
+                let attached = Attached::new(subscription);
+                self.subscriptions
+                    .lock()
+                    .insert(attached.topic.clone(), attached);
+                self.signal.raise();
+                qos
+            }
+            Err(e) => {
+                warn!(
+                    "Can not subscribe '{}' to '{}': {}",
+                    self.client_id, filter, e
+                );
+                packet::SUBSCRIPTION_FAILURE
+            }
+        }
+    }
+
+    fn unsubscribe(&self, filter: &str) {
+        if let Some(attached) = self.subscriptions.lock().remove(filter) {
+            self.in_flight
+                .lock()
+                .retain(|_, (name, _)| *name != attached.name);
+            self.delete_subscription(&attached.name);
+        }
+    }
+
+    // Ack a QoS 1 message the client acknowledged.
+    fn acked(&self, packet_id: u16) {
+        if let Some((name, id)) = self.in_flight.lock().remove(&packet_id) {
+            if let Err(e) = self.registry().ack(DEFAULT_NAMESPACE, &name, &[id]) {
+                debug!("Can not ack MQTT message '{}' of '{}': {}", id, name, e);
+            }
+            self.signal.raise();
+        }
+    }
+
+    fn send_message(&self, attached: &Attached, message: Message) -> io::Result<()> {
+        let packet_id = if attached.qos == 0 {
+            None
+        } else {
+            let mut in_flight = self.in_flight.lock();
+            // A redelivered message replaces its earlier delivery
+            in_flight.retain(|_, (_, id)| *id != message.id);
+            let mut next_packet_id = self.next_packet_id.lock();
+            loop {
+                *next_packet_id = next_packet_id.wrapping_add(1);
+                if *next_packet_id != 0 && !in_flight.contains_key(&next_packet_id) {
+                    break;
+                }
+            }
+            in_flight.insert(*next_packet_id, (attached.name.clone(), message.id));
+            Some(*next_packet_id)
+        };
+        self.send(&Packet::Publish(Publish {
+            dup: attached.qos > 0 && message.tries > 1,
+            qos: attached.qos,
+            // Messages published before the subscription existed are retained messages
+            retain: message.time < attached.created,
+            topic: attached.topic.clone(),
+            packet_id,
+            payload: message.data.as_bytes().to_vec(),
+        }))?;
+        if attached.qos == 0 {
+            let _ = self
+                .registry()
+                .ack(DEFAULT_NAMESPACE, &attached.name, &[message.id]);
+        }
+        Ok(())
+    }
+}
+
+impl Subscriber for Session {
+    type Subscription = Attached;
+
+    fn closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn signal(&self) -> &Arc<Signal> {
+        &self.signal
+    }
+
+    fn consumer(&self) -> &str {
+        &self.client_id
+    }
+
+    fn subscriptions(&self) -> Vec<Attached> {
+        self.subscriptions.lock().values().cloned().collect()
+    }
+
+    fn name(attached: &Attached) -> &str {
+        &attached.name
+    }
+
+    fn available(&self, _: &Attached) -> usize {
+        let available = MAX_IN_FLIGHT.saturating_sub(self.in_flight.lock().len());
+        available.min(self.state.config.read().default_max_messages.max(1))
+    }
+
+    fn send(&self, attached: &Attached, messages: Vec<Message>) -> io::Result<()> {
+        for message in messages {
+            self.send_message(attached, message)?;
+        }
+        Ok(())
+    }
+}
+
+fn other(error: Error) -> io::Error {
+    io::Error::other(error.to_string())
+}
@@ -0,0 +1,399 @@
+//! Encoding and decoding of MQTT 3.1.1 control packets.
+
+use std::io::{self, Read, Write};
+use std::str;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// The largest remaining length of a packet allowed by the protocol.
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// The return code of a refused subscription in a SUBACK.
+pub const SUBSCRIPTION_FAILURE: u8 = 0x80;
+
+/// The return codes of a CONNACK.
+pub const CONNECTION_ACCEPTED: u8 = 0;
+pub const UNACCEPTABLE_PROTOCOL_VERSION: u8 = 1;
+pub const IDENTIFIER_REJECTED: u8 = 2;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A CONNECT packet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Connect {
+    pub protocol_name: String,
+    pub protocol_level: u8,
+    pub clean_session: bool,
+    /// Seconds after which a silent client is disconnected, 0 disables the keep alive.
+    pub keep_alive: u16,
+    pub client_id: String,
+    /// The message published when the client disconnects without a DISCONNECT packet.
+    pub will: Option<Publish>,
+    pub username: Option<String>,
+    pub password: Option<Vec<u8>>,
+}
+
+/// A PUBLISH packet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: u8,
+    pub retain: bool,
+    pub topic: String,
+    /// The packet identifier, only set for QoS 1 and 2.
+    pub packet_id: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+/// An MQTT control packet.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    Connect(Connect),
+    ConnAck {
+        session_present: bool,
+        code: u8,
+    },
+    Publish(Publish),
+    PubAck(u16),
+    PubRec(u16),
+    PubRel(u16),
+    PubComp(u16),
+    Subscribe {
+        packet_id: u16,
+        /// Topic filters with their requested QoS.
+        filters: Vec<(String, u8)>,
+    },
+    SubAck {
+        packet_id: u16,
+        /// The granted QoS or `SUBSCRIPTION_FAILURE` of every filter.
+        codes: Vec<u8>,
+    },
+    Unsubscribe {
+        packet_id: u16,
+        filters: Vec<String>,
+    },
+    UnsubAck(u16),
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+// Reads the fields of the variable header and payload of a packet.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("packet is truncated"));
+        }
+        let (value, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    }
+
+    fn binary(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        str::from_utf8(self.binary()?)
+            .map(String::from)
+            .map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = self.bytes;
+        self.bytes = &[];
+        rest
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
+
+fn put_binary(buf: &mut Vec<u8>, value: &[u8]) {
+    put_u16(buf, value.len() as u16);
+    buf.extend_from_slice(value);
+}
+
+impl Packet {
+    /// Read a packet, blocking until it is received.
+    pub fn read(reader: &mut impl Read) -> io::Result<Packet> {
+        let mut header = [0; 1];
+        reader.read_exact(&mut header)?;
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            let mut byte = [0; 1];
+            reader.read_exact(&mut byte)?;
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            } else if shift == 21 {
+                return Err(invalid("remaining length is too long"));
+            }
+        }
+        debug_assert!(len <= MAX_REMAINING_LENGTH);
+        let mut body = Vec::new();
+        reader.take(len as u64).read_to_end(&mut body)?;
+        if body.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "packet is truncated",
+            ));
+        }
+        Packet::decode(header[0], &body)
+    }
+
+    fn decode(header: u8, body: &[u8]) -> io::Result<Packet> {
+        let flags = header & 0x0f;
+        let mut fields = Fields { bytes: body };
+        let packet = match header >> 4 {
+            CONNECT => {
+                let protocol_name = fields.string()?;
+                let protocol_level = fields.u8()?;
+                let connect_flags = fields.u8()?;
+                let keep_alive = fields.u16()?;
+                let client_id = fields.string()?;
+                let will = if connect_flags & 0x04 != 0 {
+                    let topic = fields.string()?;
+                    let payload = fields.binary()?.to_vec();
+                    Some(Publish {
+                        dup: false,
+                        qos: (connect_flags >> 3) & 0x03,
+                        retain: connect_flags & 0x20 != 0,
+                        topic,
+                        packet_id: None,
+                        payload,
+                    })
+                } else {
+                    None
+                };
+                let username = if connect_flags & 0x80 != 0 {
+                    Some(fields.string()?)
+                } else {
+                    None
+                };
+                let password = if connect_flags & 0x40 != 0 {
+                    Some(fields.binary()?.to_vec())
+                } else {
+                    None
+                };
+                Packet::Connect(Connect {
+                    protocol_name,
+                    protocol_level,
+                    clean_session: connect_flags & 0x02 != 0,
+                    keep_alive,
+                    client_id,
+                    will,
+                    username,
+                    password,
+                })
+            }
+            CONNACK => Packet::ConnAck {
+                session_present: fields.u8()? & 0x01 != 0,
+                code: fields.u8()?,
+            },
+            PUBLISH => {
+                let qos = (flags >> 1) & 0x03;
+                if qos > 2 {
+                    return Err(invalid("QoS 3 is not allowed"));
+                }
+                let topic = fields.string()?;
+                let packet_id = if qos > 0 { Some(fields.u16()?) } else { None };
+                Packet::Publish(Publish {
+                    dup: flags & 0x08 != 0,
+                    qos,
+                    retain: flags & 0x01 != 0,
+                    topic,
+                    packet_id,
+                    payload: fields.rest().to_vec(),
+                })
+            }
+            PUBACK => Packet::PubAck(fields.u16()?),
+            PUBREC => Packet::PubRec(fields.u16()?),
+            PUBREL => Packet::PubRel(fields.u16()?),
+            PUBCOMP => Packet::PubComp(fields.u16()?),
+            SUBSCRIBE => {
+                let packet_id = fields.u16()?;
+                let mut filters = Vec::new();
+                while !fields.is_empty() {
+                    let filter = fields.string()?;
+                    filters.push((filter, fields.u8()? & 0x03));
+                }
+                if filters.is_empty() {
+                    return Err(invalid("SUBSCRIBE without topic filters"));
+                }
+                Packet::Subscribe { packet_id, filters }
+            }
+            SUBACK => Packet::SubAck {
+                packet_id: fields.u16()?,
+                codes: fields.rest().to_vec(),
+            },
+            UNSUBSCRIBE => {
+                let packet_id = fields.u16()?;
+                let mut filters = Vec::new();
+                while !fields.is_empty() {
+                    filters.push(fields.string()?);
+                }
+                Packet::Unsubscribe { packet_id, filters }
+            }
+            UNSUBACK => Packet::UnsubAck(fields.u16()?),
+            PINGREQ => Packet::PingReq,
+            PINGRESP => Packet::PingResp,
+            DISCONNECT => Packet::Disconnect,
+            _ => return Err(invalid("unknown packet type")),
+        };
+        Ok(packet)
+    }
+
+    /// Write the packet.
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut body = Vec::new();
+        let header = match self {
+            Packet::Connect(connect) => {
+                put_binary(&mut body, connect.protocol_name.as_bytes());
+                body.push(connect.protocol_level);
+                let mut flags = 0;
+                if connect.clean_session {
+                    flags |= 0x02;
+                }
+                if let Some(will) = &connect.will {
+                    flags |= 0x04 | will.qos << 3;
+                    if will.retain {
+                        flags |= 0x20;
+                    }
+                }
+                if connect.password.is_some() {
+                    flags |= 0x40;
+                }
+                if connect.username.is_some() {
+                    flags |= 0x80;
+                }
+                body.push(flags);
+                put_u16(&mut body, connect.keep_alive);
+                put_binary(&mut body, connect.client_id.as_bytes());
+                if let Some(will) = &connect.will {
+                    put_binary(&mut body, will.topic.as_bytes());
+                    put_binary(&mut body, &will.payload);
+                }
+                if let Some(username) = &connect.username {
+                    put_binary(&mut body, username.as_bytes());
+                }
+                if let Some(password) = &connect.password {
+                    put_binary(&mut body, password);
+                }
+                CONNECT << 4
+            }
+            Packet::ConnAck {
+                session_present,
+                code,
+            } => {
+                body.push(*session_present as u8);
+                body.push(*code);
+                CONNACK << 4
+            }
+            Packet::Publish(publish) => {
+                put_binary(&mut body, publish.topic.as_bytes());
+                if let Some(packet_id) = publish.packet_id {
+                    put_u16(&mut body, packet_id);
+                }
+                body.extend_from_slice(&publish.payload);
+                PUBLISH << 4 | (publish.dup as u8) << 3 | publish.qos << 1 | publish.retain as u8
+            }
+            Packet::PubAck(packet_id) => {
+                put_u16(&mut body, *packet_id);
+                PUBACK << 4
+            }
+            Packet::PubRec(packet_id) => {
+                put_u16(&mut body, *packet_id);
+                PUBREC << 4
+            }
+            Packet::PubRel(packet_id) => {
+                put_u16(&mut body, *packet_id);
+                PUBREL << 4 | 0x02
+            }
+            Packet::PubComp(packet_id) => {
+                put_u16(&mut body, *packet_id);
+                PUBCOMP << 4
+            }
+            Packet::Subscribe { packet_id, filters } => {
+                put_u16(&mut body, *packet_id);
+                for (filter, qos) in filters {
+                    put_binary(&mut body, filter.as_bytes());
+                    body.push(*qos);
+                }
+                SUBSCRIBE << 4 | 0x02
+            }
+            Packet::SubAck { packet_id, codes } => {
+                put_u16(&mut body, *packet_id);
+                body.extend_from_slice(codes);
+                SUBACK << 4
+            }
+            Packet::Unsubscribe { packet_id, filters } => {
+                put_u16(&mut body, *packet_id);
+                for filter in filters {
+                    put_binary(&mut body, filter.as_bytes());
+                }
+                UNSUBSCRIBE << 4 | 0x02
+            }
+            Packet::UnsubAck(packet_id) => {
+                put_u16(&mut body, *packet_id);
+                UNSUBACK << 4
+            }
+            Packet::PingReq => PINGREQ << 4,
+            Packet::PingResp => PINGRESP << 4,
+            Packet::Disconnect => DISCONNECT << 4,
+        };
+        if body.len() > MAX_REMAINING_LENGTH {
+            return Err(invalid("packet is too large"));
+        }
+        let mut packet = Vec::with_capacity(body.len() + 5);
+        packet.push(header);
+        let mut len = body.len();
+        loop {
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            if len > 0 {
+                packet.push(byte | 0x80);
+            } else {
+                packet.push(byte);
+                break;
+            }
+        }
+        packet.extend_from_slice(&body);
+        writer.write_all(&packet)
+    }
+}
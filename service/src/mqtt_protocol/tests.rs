@@ -0,0 +1,262 @@
+use super::*;
+use crate::testing::{config, unused_port, SETTLE_TIME};
+use courier::Registry;
+
+// A connected MQTT client.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn connect(port: u16, client_id: &str, clean_session: bool) -> (Self, bool) {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        let mut client = Client { stream };
+        client.send(Packet::Connect(Connect {
+            protocol_name: String::from("MQTT"),
+            protocol_level: 4,
+            clean_session,
+            client_id: String::from(client_id),
+            ..Connect::default()
+        }));
+        match client.receive() {
+            Packet::ConnAck {
+                session_present,
+                code: 0,
+            } => (client, session_present),
+            packet => panic!("unexpected {:?}", packet),
+        }
+    }
+
+    fn send(&mut self, packet: Packet) {
+        packet.write(&mut self.stream).unwrap();
+    }
+
+    fn receive(&mut self) -> Packet {
+        Packet::read(&mut self.stream).unwrap()
+    }
+
+    fn subscribe(&mut self, filters: &[&str]) -> Vec<u8> {
+        let filters = filters.iter().map(|f| (String::from(*f), 1)).collect();
+        self.send(Packet::Subscribe {
+            packet_id: 1,
+            filters,
+        });
+        match self.receive() {
+            Packet::SubAck {
+                packet_id: 1,
+                codes,
+            } => codes,
+            packet => panic!("unexpected {:?}", packet),
+        }
+    }
+
+    fn publish(&mut self, topic: &str, payload: &str, qos: u8) {
+        self.send(Packet::Publish(Publish {
+            qos,
+            topic: String::from(topic),
+            packet_id: if qos > 0 { Some(7) } else { None },
+            payload: payload.as_bytes().to_vec(),
+            ..Publish::default()
+        }));
+    }
+
+    fn receive_publish(&mut self) -> Publish {
+        match self.receive() {
+            Packet::Publish(publish) => publish,
+            packet => panic!("unexpected {:?}", packet),
+        }
+    }
+}
+
+#[test]
+fn mqtt_packets() {
+    let packets = vec![
+        Packet::Connect(Connect {
+            protocol_name: String::from("MQTT"),
+            protocol_level: 4,
+            clean_session: true,
+            keep_alive: 30,
+            client_id: String::from("sensor"),
+            will: Some(Publish {
+                qos: 1,
+                retain: true,
+                topic: String::from("status"),
+                payload: b"offline".to_vec(),
+                ..Publish::default()
+            }),
+            username: Some(String::from("user")),
+            password: Some(b"secret".to_vec()),
+        }),
+        Packet::ConnAck {
+            session_present: true,
+            code: 0,
+        },
+        Packet::Publish(Publish {
+            dup: true,
+            qos: 1,
+            retain: true,
+            topic: String::from("temps"),
+            packet_id: Some(42),
+            payload: vec![b'x'; 200],
+        }),
+        Packet::Publish(Publish {
+            topic: String::from("temps"),
+            ..Publish::default()
+        }),
+        Packet::PubAck(1),
+        Packet::PubRec(2),
+        Packet::PubRel(3),
+        Packet::PubComp(4),
+        Packet::Subscribe {
+            packet_id: 5,
+            filters: vec![(String::from("a"), 1), (String::from("b/#"), 0)],
+        },
+        Packet::SubAck {
+            packet_id: 5,
+            codes: vec![1, packet::SUBSCRIPTION_FAILURE],
+        },
+        Packet::Unsubscribe {
+            packet_id: 6,
+            filters: vec![String::from("a")],
+        },
+        Packet::UnsubAck(6),
+        Packet::PingReq,
+        Packet::PingResp,
+        Packet::Disconnect,
+    ];
+    for packet in packets {
+        let mut buf = Vec::new();
+        packet.write(&mut buf).unwrap();
+        assert_eq!(Packet::read(&mut &buf[..]).unwrap(), packet);
+    }
+
+    // The remaining length of a packet spans several bytes
+    let mut buf = Vec::new();
+    let publish = Packet::Publish(Publish {
+        topic: String::from("t"),
+        payload: vec![0; 20_000],
+        ..Publish::default()
+    });
+    publish.write(&mut buf).unwrap();
+    assert_eq!(&buf[..4], &[0x30, 0xa3, 0x9c, 0x01]);
+    assert_eq!(Packet::read(&mut &buf[..]).unwrap(), publish);
+    assert!(Packet::read(&mut &buf[..100]).is_err());
+    assert!(Packet::read(&mut &[0xf0, 0][..]).is_err());
+    assert!(Packet::read(&mut &[0x30, 0xff, 0xff, 0xff, 0xff][..]).is_err());
+}
+
+#[test]
+fn mqtt_protocol() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+
+    let (mut sensor, session_present) = Client::connect(port, "sensor", false);
+    assert!(!session_present);
+    assert_eq!(sensor.subscribe(&["temps", "alerts/#"]), vec![1, 0x80]);
+    let subscription = registry
+        .get_subscription(DEFAULT_NAMESPACE, "mqtt/sensor/temps")
+        .unwrap();
+    assert_eq!(subscription.labels[CLIENT_LABEL], "sensor");
+
+    // Messages are published to the topic with QoS 1 and 2
+    let (mut publisher, _) = Client::connect(port, "publisher", true);
+    publisher.publish("temps", "21", 1);
+    assert_eq!(publisher.receive(), Packet::PubAck(7));
+    publisher.publish("temps", "22", 2);
+    assert_eq!(publisher.receive(), Packet::PubRec(7));
+    publisher.publish("temps", "22", 2);
+    assert_eq!(publisher.receive(), Packet::PubRec(7));
+    publisher.send(Packet::PubRel(7));
+    assert_eq!(publisher.receive(), Packet::PubComp(7));
+    let (messages, _) = registry
+        .read(DEFAULT_NAMESPACE, "temps", 0, None, 10)
+        .unwrap();
+    let data: Vec<_> = messages.iter().map(|m| m.data.to_string()).collect();
+    assert_eq!(data, vec!["21", "22"]);
+
+    // Unacknowledged messages are resent when a persistent session reconnects
+    let first = sensor.receive_publish();
+    assert_eq!((first.qos, first.dup, first.retain), (1, false, false));
+    assert_eq!(first.payload, b"21");
+    drop(sensor);
+    thread::sleep(SETTLE_TIME * 3);
+    let (mut sensor, session_present) = Client::connect(port, "sensor", false);
+    assert!(session_present);
+    let mut payloads = Vec::new();
+    for _ in 0..2 {
+        let publish = sensor.receive_publish();
+        sensor.send(Packet::PubAck(publish.packet_id.unwrap()));
+        payloads.push(publish.payload);
+    }
+    assert_eq!(payloads, vec![b"21".to_vec(), b"22".to_vec()]);
+
+    // A new subscriber receives the messages the topic retains
+    let (mut display, _) = Client::connect(port, "display", true);
+    assert_eq!(display.subscribe(&["temps"]), vec![1]);
+    let retained = display.receive_publish();
+    assert!(retained.retain);
+    assert_eq!(retained.payload, b"21");
+    display.send(Packet::PubAck(retained.packet_id.unwrap()));
+    display.send(Packet::PingReq);
+    let retained = loop {
+        match display.receive() {
+            Packet::PingResp => continue,
+            Packet::Publish(publish) => break publish,
+            packet => panic!("unexpected {:?}", packet),
+        }
+    };
+    assert_eq!(retained.payload, b"22");
+
+    // The subscriptions of a clean session are deleted when it ends
+    display.send(Packet::Disconnect);
+    thread::sleep(SETTLE_TIME * 3);
+    assert!(registry
+        .get_subscription(DEFAULT_NAMESPACE, "mqtt/display/temps")
+        .is_err());
+    assert!(registry
+        .get_subscription(DEFAULT_NAMESPACE, "mqtt/sensor/temps")
+        .is_ok());
+
+    // The will of a client is published when it disconnects without a DISCONNECT
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut client = Client { stream };
+    client.send(Packet::Connect(Connect {
+        protocol_name: String::from("MQTT"),
+        protocol_level: 4,
+        clean_session: true,
+        client_id: String::from("lamp"),
+        will: Some(Publish {
+            topic: String::from("status"),
+            payload: b"lamp offline".to_vec(),
+            ..Publish::default()
+        }),
+        ..Connect::default()
+    }));
+    client.receive();
+    drop(client);
+    thread::sleep(SETTLE_TIME * 3);
+    let (messages, _) = registry
+        .read(DEFAULT_NAMESPACE, "status", 0, None, 10)
+        .unwrap();
+    assert_eq!(&*messages[0].data, "lamp offline");
+
+    // Persistent sessions require a client id
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut client = Client { stream };
+    client.send(Packet::Connect(Connect {
+        protocol_name: String::from("MQTT"),
+        protocol_level: 4,
+        ..Connect::default()
+    }));
+    assert_eq!(
+        client.receive(),
+        Packet::ConnAck {
+            session_present: false,
+            code: packet::IDENTIFIER_REJECTED
+        }
+    );
+}
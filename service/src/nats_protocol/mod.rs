@@ -0,0 +1,513 @@
+//! A NATS listener serving the core NATS text protocol mapped onto the topics and subscriptions of
+//! the registry.
+//!
+//! Subjects name topics of the default namespace, a PUB publishes its payload to the topic of the
+//! subject, which is created with the default settings if it does not exist. Wildcard subjects
+//! are not supported and the reply subjects of published messages are dropped.
+//!
+//! * A SUB without a queue group creates the subscription `nats/<session>/<sid>` of the topic for
+//!   the connection, it is deleted by the UNSUB or when the connection ends.
+//! * A SUB with a queue group joins the shared subscription `nats/<queue>/<subject>`, which is
+//!   created if it does not exist. Its messages are spread over the members of the group and the
+//!   messages a member did not acknowledge are released when it leaves.
+//!
+//! Messages are acked as soon as they are delivered. A client connecting with the option
+//! `"explicit_ack": true` receives every message with the reply subject
+//! `$COURIER.ACK.<message id>.<subscription>` instead, publishing an empty payload, `+ACK` or
+//! `+TERM` to it acks the message and `-NAK` resends it immediately. Messages which are not
+//! acknowledged are resent once their `ack_deadline` is reached. Payloads must be UTF-8.
+
+mod op;
+#[cfg(test)]
+mod tests;
+
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use uuid::Uuid;
+
+use self::op::Op;
+use crate::delivery::{self, Subscriber};
+use crate::http_protocol::subscription_handlers::create_subscription;
+use crate::http_protocol::topic_handlers::ensure_topic;
+use crate::http_protocol::types::SubscriptionCreateConfig;
+use crate::http_protocol::SharedConfig;
+use courier::signal::Signal;
+use courier::{Error, ErrorCode, Message, RawMessage, SharedRegistry, DEFAULT_NAMESPACE};
+
+/// How long a client has to send its CONNECT operation.
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// The maximum number of messages waiting for an acknowledgement per subscription of a session.
+const MAX_IN_FLIGHT: usize = 100;
+
+/// The maximum size of a published payload in bytes.
+const MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// The label of a subscription holding the session it was created for.
+const SESSION_LABEL: &str = "nats-session";
+
+/// The label of a subscription holding the queue group it was created for.
+const QUEUE_LABEL: &str = "nats-queue";
+
+/// The prefix of the reply subjects acknowledging messages.
+const ACK_PREFIX: &str = "$COURIER.ACK.";
+
+/// The state shared by the sessions of the NATS listener.
+struct NatsState {
+    registry: SharedRegistry,
+    config: SharedConfig,
+    server_id: String,
+}
+
+/// Start the NATS listener on `address` in the background.
+pub fn start(address: &str, registry: &SharedRegistry, config: &SharedConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving NATS on '{}'.", listener.local_addr()?);
+    let state = Arc::new(NatsState {
+        registry: Arc::clone(registry),
+        config: Arc::clone(config),
+        server_id: Uuid::new_v4().to_simple().to_string(),
+    });
+    thread::Builder::new()
+        .name(String::from("nats"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            if let Err(e) = serve(&state, stream) {
+                                debug!("NATS connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Can not accept a NATS connection: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// The options a client sends with CONNECT, other options are ignored.
+#[derive(Debug, Default, Deserialize)]
+struct Options {
+    /// Confirm every operation with `+OK`.
+    #[serde(default)]
+    verbose: bool,
+    /// Acknowledge messages by publishing to their reply subject.
+    #[serde(default)]
+    explicit_ack: bool,
+}
+
+// A subscription of a session.
+#[derive(Clone, Debug)]
+struct Subscribed {
+    // The id the client chose for the subscription.
+    sid: String,
+    subject: String,
+    // The name of the subscription in the registry.
+    name: String,
+    // If the subscription was created for the session.
+    owned: bool,
+    // The number of messages to deliver before unsubscribing if any.
+    remaining: Option<u64>,
+}
+
+/// The session of a connected client.
+struct Session {
+    state: Arc<NatsState>,
+    id: String,
+    options: Options,
+    writer: Mutex<TcpStream>,
+    // Subscriptions by the id the client chose.
+    subscriptions: Mutex<HashMap<String, Subscribed>>,
+    closed: AtomicBool,
+    signal: Arc<Signal>,
+}
+
+// Serve a connection until it is closed.
+fn serve(state: &Arc<NatsState>, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream.try_clone()?;
+    let address = stream.local_addr()?;
+    let info = serde_json::json!({
+        "server_id": state.server_id,
+        "server_name": state.config.read().origin,
+        "version": env!("CARGO_PKG_VERSION"),
+        "proto": 1,
+        "host": address.ip().to_string(),
+        "port": address.port(),
+        "headers": false,
+        "max_payload": MAX_PAYLOAD,
+    });
+    writer.write_all(format!("INFO {}\r\n", info).as_bytes())?;
+
+    let options = match Op::read(&mut reader, MAX_PAYLOAD) {
+        Ok(Some(Op::Connect(options))) => serde_json::from_str::<Options>(&options),
+        Ok(Some(_)) => {
+            return writer.write_all(&op::err("Expected CONNECT"));
+        }
+        Ok(None) => return Ok(()),
+        Err(e) => return protocol_error(&mut writer, e),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(e) => {
+            return writer.write_all(&op::err(&format!("Invalid CONNECT options: {}", e)));
+        }
+    };
+    stream.set_read_timeout(None)?;
+    if options.verbose {
+        writer.write_all(b"+OK\r\n")?;
+    }
+
+    let session = Arc::new(Session {
+        state: Arc::clone(state),
+        id: Uuid::new_v4().to_string(),
+        options,
+        writer: Mutex::new(writer),
+        subscriptions: Mutex::new(HashMap::new()),
+        closed: AtomicBool::new(false),
+        signal: Signal::new(),
+    });
+    let deliverer = Arc::clone(&session);
+    thread::spawn(move || {
+        if let Err(e) = delivery::deliver(deliverer.registry(), &*deliverer) {
+            debug!("Can not deliver to NATS session '{}': {}", deliverer.id, e);
+        }
+    });
+    let result = session.run(&mut reader);
+    session.close();
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+// Send the message of a protocol violation, the connection is closed afterwards.
+fn protocol_error(writer: &mut impl Write, error: io::Error) -> io::Result<()> {
+    if error.kind() == io::ErrorKind::InvalidData {
+        writer.write_all(&op::err(&error.to_string()))?;
+    }
+    Err(error)
+}
+
+impl Session {
+    fn registry(&self) -> &SharedRegistry {
+        &self.state.registry
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock();
+        writer.write_all(buf)?;
+        writer.flush()
+    }
+
+    // Handle the operations of the client until it disconnects.
+    fn run(&self, reader: &mut BufReader<TcpStream>) -> io::Result<()> {
+        loop {
+            let op = match Op::read(reader, MAX_PAYLOAD) {
+                Ok(Some(op)) => op,
+                Ok(None) => return Ok(()),
+                Err(e) => return protocol_error(&mut *self.writer.lock(), e),
+            };
+            let confirm = !matches!(op, Op::Ping | Op::Pong);
+            match self.handle(op) {
+                // Errors of an operation do not end the connection
+                Err(e) => self.send(&op::err(&e.to_string()))?,
+                Ok(()) if confirm && self.options.verbose => self.send(b"+OK\r\n")?,
+                Ok(()) => {}
+            }
+        }
+    }
+
+    fn handle(&self, op: Op) -> Result<(), Error> {
+        match op {
+            Op::Connect(_) => Ok(()),
+            Op::Pub {
+                subject, payload, ..
+            } => match subject.strip_prefix(ACK_PREFIX) {
+                Some(ack) => self.acknowledge(ack, &payload),
+                None => self.publish(&subject, payload),
+            },
+            Op::Sub {
+                subject,
+                queue,
+                sid,
+            } => self.subscribe(subject, queue, sid),
+            Op::Unsub { sid, max_messages } => {
+                self.unsubscribe(&sid, max_messages);
+                Ok(())
+            }
+            Op::Ping => self
+                .send(b"PONG\r\n")
+                .map_err(|e| Error::invalid_request(e.to_string())),
+            Op::Pong => Ok(()),
+        }
+    }
+
+    fn publish(&self, subject: &str, payload: Vec<u8>) -> Result<(), Error> {
+        validate_subject(subject)?;
+        let data = String::from_utf8(payload)
+            .map_err(|_| Error::invalid_request(String::from("The payload is not UTF-8")))?;
+        let registry = self.registry();
+        ensure_topic(
+            DEFAULT_NAMESPACE,
+            subject,
+            registry,
+            &self.state.config.read(),
+        )?;
+        let message = RawMessage {
+            data,
+            ..RawMessage::default()
+        };
+        registry.publish(DEFAULT_NAMESPACE, subject, vec![message])?;
+        Ok(())
+    }
+
+    fn subscribe(&self, subject: String, queue: Option<String>, sid: String) -> Result<(), Error> {
+        validate_subject(&subject)?;
+        if self.subscriptions.lock().contains_key(&sid) {
+            return Err(Error::invalid_request(format!(
+                "Subscription id '{}' is already in use",
+                sid
+            )));
+        }
+        let registry = self.registry();
+        ensure_topic(
+            DEFAULT_NAMESPACE,
+            &subject,
+            registry,
+            &self.state.config.read(),
+        )?;
+        let (name, label) = match &queue {
+            Some(queue) => (
+                format!("nats/{}/{}", queue, subject),
+                (QUEUE_LABEL, queue.clone()),
+            ),
+            None => (
+                format!("nats/{}/{}", self.id, sid),
+                (SESSION_LABEL, self.id.clone()),
+            ),
+        };
+        let mut labels = HashMap::new();
+        labels.insert(String::from(label.0), label.1);
+        let config = SubscriptionCreateConfig {
+            topic: subject.clone(),
+            topic_namespace: None,
+            ack_deadline: None,
+            ttl: None,
+            consumer_timeout: None,
+            historical: None,
+            labels: Some(labels),
+            description: None,
+        };
+        match create_subscription(
+            DEFAULT_NAMESPACE,
+            &name,
+            &config,
+            registry,
+            &self.state.config.read(),
+        ) {
+            // Another member of the queue group created it first
+            Err(ref e) if queue.is_some() && e.code == ErrorCode::SubscriptionAlreadyExists => {}
+            result => {
+                result?;
+            }
+        }
+        let subscribed = Subscribed {
+            sid: sid.clone(),
+            subject,
+            name,
+            owned: queue.is_none(),
+            remaining: None,
+        };
+        self.subscriptions.lock().insert(sid, subscribed);
+        self.signal.raise();
+        Ok(())
+    }
+
+    // Unsubscribe now or once `max_messages` messages were delivered in total.
+    fn unsubscribe(&self, sid: &str, max_messages: Option<u64>) {
+        let mut subscriptions = self.subscriptions.lock();
+        if let (Some(max_messages), Some(subscribed)) = (max_messages, subscriptions.get_mut(sid)) {
+            if max_messages > 0 {
+                subscribed.remaining = Some(max_messages);
+                return;
+            }
+        }
+        if let Some(subscribed) = subscriptions.remove(sid) {
+            drop(subscriptions);
+            self.detach(&subscribed);
+        }
+    }
+
+    // Delete the subscription created for the session or hand the messages the session did not
+    // acknowledge back to the other members of the queue group.
+    fn detach(&self, subscribed: &Subscribed) {
+        let registry = self.registry();
+        let result = if subscribed.owned {
+            registry.delete_subscription(DEFAULT_NAMESPACE, &subscribed.name)
+        } else {
+            registry
+                .release_consumer(DEFAULT_NAMESPACE, &subscribed.name, &self.id)
+                .map(|_| ())
+        };
+        match result {
+            Ok(()) => {}
+            Err(ref e)
+                if e.code == ErrorCode::SubscriptionNotFound
+                    || e.code == ErrorCode::ConsumerNotFound => {}
+            Err(e) => warn!(
+                "Can not detach NATS subscription '{}': {}",
+                subscribed.name, e
+            ),
+        }
+    }
+
+    // Ack or nack the message named by the rest of an ack reply subject.
+    fn acknowledge(&self, ack: &str, payload: &[u8]) -> Result<(), Error> {
+        let invalid = || Error::invalid_request(format!("Invalid ack subject '{}'", ack));
+        let (id, name) = match ack.find('.') {
+            Some(i) => (&ack[..i], &ack[i + 1..]),
+            None => return Err(invalid()),
+        };
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+        let registry = self.registry();
+        match payload {
+            b"" | b"+ACK" | b"+TERM" => registry.ack(DEFAULT_NAMESPACE, name, &[id])?,
+            b"-NAK" => registry.nack(DEFAULT_NAMESPACE, name, &[id])?,
+            _ => {
+                return Err(Error::invalid_request(format!(
+                    "'{}' is not a supported acknowledgement",
+                    String::from_utf8_lossy(payload)
+                )))
+            }
+        };
+        self.signal.raise();
+        Ok(())
+    }
+
+    // End the session.
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.signal.raise();
+        let subscriptions: Vec<_> = self.subscriptions.lock().drain().collect();
+        for (_, subscribed) in subscriptions {
+            self.detach(&subscribed);
+        }
+    }
+
+    // Count the messages delivered to a subscription unsubscribing once it reached its maximum.
+    fn delivered(&self, sid: &str, count: u64) {
+        let mut subscriptions = self.subscriptions.lock();
+        let done = match subscriptions.get_mut(sid) {
+            Some(Subscribed {
+                remaining: Some(remaining),
+                ..
+            }) => {
+                *remaining = remaining.saturating_sub(count);
+                *remaining == 0
+            }
+            _ => false,
+        };
+        if done {
+            if let Some(subscribed) = subscriptions.remove(sid) {
+                drop(subscriptions);
+                self.detach(&subscribed);
+            }
+        }
+    }
+
+    fn send_message(&self, subscribed: &Subscribed, message: Message) -> io::Result<()> {
+        let reply_to = if self.options.explicit_ack {
+            Some(format!("{}{}.{}", ACK_PREFIX, message.id, subscribed.name))
+        } else {
+            None
+        };
+        self.send(&op::msg(
+            &subscribed.subject,
+            &subscribed.sid,
+            reply_to.as_deref(),
+            message.data.as_bytes(),
+        ))?;
+        if !self.options.explicit_ack {
+            let _ = self
+                .registry()
+                .ack(DEFAULT_NAMESPACE, &subscribed.name, &[message.id]);
+        }
+        Ok(())
+    }
+}
+
+impl Subscriber for Session {
+    type Subscription = Subscribed;
+
+    fn closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn signal(&self) -> &Arc<Signal> {
+        &self.signal
+    }
+
+    fn consumer(&self) -> &str {
+        &self.id
+    }
+
+    fn subscriptions(&self) -> Vec<Subscribed> {
+        self.subscriptions.lock().values().cloned().collect()
+    }
+
+    fn name(subscribed: &Subscribed) -> &str {
+        &subscribed.name
+    }
+
+    fn available(&self, subscribed: &Subscribed) -> usize {
+        let mut available = self.state.config.read().default_max_messages.max(1);
+        if self.options.explicit_ack {
+            let in_flight = self
+                .registry()
+                .consumers(DEFAULT_NAMESPACE, &subscribed.name)
+                .ok()
+                .and_then(|consumers| consumers.into_iter().find(|c| c.id == self.id))
+                .map_or(0, |consumer| consumer.in_flight);
+            available = available.min(MAX_IN_FLIGHT.saturating_sub(in_flight));
+        }
+        match subscribed.remaining {
+            Some(remaining) => available.min(remaining as usize),
+            None => available,
+        }
+    }
+
+    fn send(&self, subscribed: &Subscribed, messages: Vec<Message>) -> io::Result<()> {
+        let count = messages.len() as u64;
+        for message in messages {
+            self.send_message(subscribed, message)?;
+        }
+        self.delivered(&subscribed.sid, count);
+        Ok(())
+    }
+}
+
+// Subjects are dot separated tokens, wildcards are not supported.
+fn validate_subject(subject: &str) -> Result<(), Error> {
+    let valid = subject
+        .split('.')
+        .all(|token| !token.is_empty() && token != "*" && token != ">");
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::invalid_request(format!(
+            "'{}' is not a valid subject, wildcards are not supported",
+            subject
+        )))
+    }
+}
@@ -0,0 +1,123 @@
+//! The operations of the NATS text protocol.
+
+use std::io::{self, BufRead};
+
+/// An operation sent by a client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    /// The JSON options of the connection.
+    Connect(String),
+    Pub {
+        subject: String,
+        reply_to: Option<String>,
+        payload: Vec<u8>,
+    },
+    Sub {
+        subject: String,
+        queue: Option<String>,
+        sid: String,
+    },
+    Unsub {
+        sid: String,
+        max_messages: Option<u64>,
+    },
+    Ping,
+    Pong,
+}
+
+impl Op {
+    /// Read an operation, returns None if the connection was closed.
+    ///
+    /// Errors of kind `InvalidData` are protocol violations of the client, their message is sent
+    /// to the client before closing the connection.
+    pub fn read(reader: &mut impl BufRead, max_payload: usize) -> io::Result<Option<Op>> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                break;
+            }
+        }
+        let line = String::from_utf8(line).map_err(|_| invalid("Unknown Protocol Operation"))?;
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        let (name, rest) = match line.find(|c: char| c.is_ascii_whitespace()) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        let args: Vec<&str> = rest.split_ascii_whitespace().collect();
+        let op = match (name.to_ascii_uppercase().as_str(), &args[..]) {
+            ("CONNECT", _) => Op::Connect(String::from(rest)),
+            ("PUB", [subject, size]) | ("PUB", [subject, _, size]) => {
+                let size: usize = size.parse().map_err(|_| invalid("Invalid Payload Size"))?;
+                if size > max_payload {
+                    return Err(invalid("Maximum Payload Violation"));
+                }
+                let mut payload = vec![0; size + 2];
+                reader.read_exact(&mut payload)?;
+                if !payload.ends_with(b"\r\n") {
+                    return Err(invalid("Invalid Payload Size"));
+                }
+                payload.truncate(size);
+                Op::Pub {
+                    subject: String::from(*subject),
+                    reply_to: if args.len() == 3 {
+                        Some(String::from(args[1]))
+                    } else {
+                        None
+                    },
+                    payload,
+                }
+            }
+            ("SUB", [subject, sid]) => Op::Sub {
+                subject: String::from(*subject),
+                queue: None,
+                sid: String::from(*sid),
+            },
+            ("SUB", [subject, queue, sid]) => Op::Sub {
+                subject: String::from(*subject),
+                queue: Some(String::from(*queue)),
+                sid: String::from(*sid),
+            },
+            ("UNSUB", [sid]) => Op::Unsub {
+                sid: String::from(*sid),
+                max_messages: None,
+            },
+            ("UNSUB", [sid, max_messages]) => Op::Unsub {
+                sid: String::from(*sid),
+                max_messages: Some(
+                    max_messages
+                        .parse()
+                        .map_err(|_| invalid("Invalid Maximum Messages"))?,
+                ),
+            },
+            ("PING", []) => Op::Ping,
+            ("PONG", []) => Op::Pong,
+            _ => return Err(invalid("Unknown Protocol Operation")),
+        };
+        Ok(Some(op))
+    }
+}
+
+/// Serialize a message delivered to the subscription `sid`.
+pub fn msg(subject: &str, sid: &str, reply_to: Option<&str>, payload: &[u8]) -> Vec<u8> {
+    let mut buf = match reply_to {
+        Some(reply_to) => format!("MSG {} {} {} {}\r\n", subject, sid, reply_to, payload.len()),
+        None => format!("MSG {} {} {}\r\n", subject, sid, payload.len()),
+    }
+    .into_bytes();
+    buf.extend_from_slice(payload);
+    buf.extend_from_slice(b"\r\n");
+    buf
+}
+
+/// Serialize an error, single quotes of the message are replaced as they delimit it.
+pub fn err(message: &str) -> Vec<u8> {
+    format!("-ERR '{}'\r\n", message.replace('\'', "\"")).into_bytes()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,266 @@
+use super::*;
+use crate::testing::{config, unused_port, SETTLE_TIME};
+use courier::Registry;
+use std::io::{BufRead, Read};
+
+// A message delivered to a client.
+#[derive(Debug)]
+struct Msg {
+    subject: String,
+    sid: String,
+    reply_to: Option<String>,
+    payload: String,
+}
+
+// A NATS client.
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn open(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        };
+        let info = client.line();
+        assert!(info.starts_with("INFO {"), "{}", info);
+        let info: serde_json::Value = serde_json::from_str(&info[5..]).unwrap();
+        assert_eq!(info["max_payload"], MAX_PAYLOAD);
+        assert_eq!(info["headers"], false);
+        client
+    }
+
+    fn connect(port: u16, options: &str) -> Self {
+        let mut client = Client::open(port);
+        client.send(&format!("CONNECT {}\r\n", options));
+        client.ping();
+        client
+    }
+
+    fn send(&mut self, op: &str) {
+        self.writer.write_all(op.as_bytes()).unwrap();
+    }
+
+    fn line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        assert!(line.ends_with("\r\n"), "{:?}", line);
+        line.truncate(line.len() - 2);
+        line
+    }
+
+    // Wait for the server to handle the operations sent so far.
+    fn ping(&mut self) {
+        self.send("PING\r\n");
+        assert_eq!(self.line(), "PONG");
+    }
+
+    fn publish(&mut self, subject: &str, payload: &str) {
+        self.send(&format!(
+            "PUB {} {}\r\n{}\r\n",
+            subject,
+            payload.len(),
+            payload
+        ));
+    }
+
+    fn receive(&mut self) -> Msg {
+        let line = self.line();
+        let args: Vec<&str> = line.split(' ').collect();
+        let (subject, sid, reply_to, size) = match &args[..] {
+            ["MSG", subject, sid, size] => (subject, sid, None, size),
+            ["MSG", subject, sid, reply_to, size] => (subject, sid, Some(reply_to), size),
+            _ => panic!("Expected MSG but got {:?}", line),
+        };
+        let mut payload = vec![0; size.parse::<usize>().unwrap() + 2];
+        self.reader.read_exact(&mut payload).unwrap();
+        payload.truncate(payload.len() - 2);
+        Msg {
+            subject: subject.to_string(),
+            sid: sid.to_string(),
+            reply_to: reply_to.map(|reply_to| reply_to.to_string()),
+            payload: String::from_utf8(payload).unwrap(),
+        }
+    }
+}
+
+fn subscriptions(registry: &SharedRegistry) -> Vec<String> {
+    registry
+        .list_subscriptions(DEFAULT_NAMESPACE, &Default::default())
+        .unwrap()
+        .items
+        .into_iter()
+        .map(|subscription| subscription.name)
+        .collect()
+}
+
+#[test]
+fn nats_ops() {
+    let read = |input: &[u8]| Op::read(&mut &input[..], 8);
+    assert_eq!(
+        read(b"\r\nconnect {\"verbose\":true}\r\n").unwrap(),
+        Some(Op::Connect(String::from("{\"verbose\":true}")))
+    );
+    assert_eq!(
+        read(b"PUB a.b reply 5\r\nhello\r\n").unwrap(),
+        Some(Op::Pub {
+            subject: String::from("a.b"),
+            reply_to: Some(String::from("reply")),
+            payload: b"hello".to_vec(),
+        })
+    );
+    assert_eq!(
+        read(b"SUB a  workers\t7\n").unwrap(),
+        Some(Op::Sub {
+            subject: String::from("a"),
+            queue: Some(String::from("workers")),
+            sid: String::from("7"),
+        })
+    );
+    assert_eq!(
+        read(b"UNSUB 7 2\r\n").unwrap(),
+        Some(Op::Unsub {
+            sid: String::from("7"),
+            max_messages: Some(2),
+        })
+    );
+    assert_eq!(read(b"PING\r\n").unwrap(), Some(Op::Ping));
+    assert_eq!(read(b"").unwrap(), None);
+
+    for input in &[
+        &b"HPUB a 2 2\r\n\r\n\r\n"[..],
+        b"PUB a x\r\n",
+        b"PUB a 9\r\n123456789\r\n",
+        b"PUB a 2\r\nabc\r\n",
+        b"SUB a\r\n",
+        b"UNSUB 1 x\r\n",
+    ] {
+        let error = read(input).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", input);
+    }
+
+    assert_eq!(op::msg("a", "1", None, b"hi"), b"MSG a 1 2\r\nhi\r\n");
+    assert_eq!(op::msg("a", "1", Some("r"), b""), b"MSG a 1 r 0\r\n\r\n");
+    assert_eq!(op::err("Topic 'a'"), b"-ERR 'Topic \"a\"'\r\n");
+    assert!(validate_subject("a.b").is_ok());
+    for subject in &["a.*", ">", "a..b", ""] {
+        assert!(validate_subject(subject).is_err(), "{}", subject);
+    }
+}
+
+#[test]
+fn nats_protocol() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+
+    // Clients have to connect first
+    let mut client = Client::open(port);
+    client.send("PING\r\n");
+    assert_eq!(client.line(), "-ERR 'Expected CONNECT'");
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    // A subscription without a queue group is created for the session
+    let mut consumer = Client::connect(port, "{\"verbose\":false,\"lang\":\"rust\"}");
+    consumer.send("SUB temps 1\r\n");
+    consumer.ping();
+    let names = subscriptions(&registry);
+    assert_eq!(names.len(), 1);
+    assert!(names[0].starts_with("nats/") && names[0].ends_with("/1"));
+
+    let mut producer = Client::open(port);
+    producer.send("CONNECT {\"verbose\":true}\r\n");
+    assert_eq!(producer.line(), "+OK");
+    producer.publish("temps", "21");
+    assert_eq!(producer.line(), "+OK");
+
+    // Messages are acked as they are delivered
+    let msg = consumer.receive();
+    assert_eq!(
+        ("temps", "1", None, "21"),
+        (
+            &*msg.subject,
+            &*msg.sid,
+            msg.reply_to.as_deref(),
+            &*msg.payload
+        )
+    );
+    thread::sleep(SETTLE_TIME);
+    assert!(registry
+        .pending(DEFAULT_NAMESPACE, &names[0])
+        .unwrap()
+        .is_empty());
+    consumer.send("UNSUB 1\r\n");
+    consumer.ping();
+    assert!(subscriptions(&registry).is_empty());
+
+    // An unsubscription after a number of messages
+    consumer.send("SUB temps 2\r\nUNSUB 2 1\r\n");
+    consumer.ping();
+    producer.publish("temps", "22");
+    producer.publish("temps", "23");
+    assert_eq!(producer.line(), "+OK");
+    assert_eq!(producer.line(), "+OK");
+    assert_eq!(consumer.receive().payload, "22");
+    thread::sleep(SETTLE_TIME * 3);
+    consumer.ping();
+    assert!(subscriptions(&registry).is_empty());
+
+    // Errors of an operation do not end the connection
+    producer.send("SUB temps.* 1\r\n");
+    assert!(producer
+        .line()
+        .starts_with("-ERR '\"temps.*\" is not a valid subject"));
+    producer.ping();
+
+    // Queue groups share a subscription, messages are acked with their reply subject
+    let mut worker = Client::connect(port, "{\"explicit_ack\":true}");
+    worker.send("SUB jobs workers 1\r\n");
+    worker.ping();
+    assert_eq!(subscriptions(&registry), vec!["nats/workers/jobs"]);
+    producer.publish("jobs", "a");
+    assert_eq!(producer.line(), "+OK");
+    let msg = worker.receive();
+    let reply_to = msg.reply_to.unwrap();
+    assert!(reply_to.starts_with(ACK_PREFIX));
+    assert!(reply_to.ends_with(".nats/workers/jobs"));
+    worker.send(&format!("PUB {} 4\r\n-NAK\r\n", reply_to));
+    let resent = worker.receive();
+    assert_eq!(resent.payload, "a");
+    assert_eq!(resent.reply_to.as_deref(), Some(&*reply_to));
+    worker.send(&format!("PUB {} 0\r\n\r\n", reply_to));
+    worker.ping();
+    assert!(registry
+        .pending(DEFAULT_NAMESPACE, "nats/workers/jobs")
+        .unwrap()
+        .is_empty());
+    worker.send(&format!("PUB {} 5\r\n+WAIT\r\n", reply_to));
+    assert!(worker.line().starts_with("-ERR"));
+
+    // Unacknowledged messages of a member are released when it leaves, the group remains
+    producer.publish("jobs", "b");
+    assert_eq!(producer.line(), "+OK");
+    assert_eq!(worker.receive().payload, "b");
+    drop(worker);
+    thread::sleep(SETTLE_TIME * 3);
+    let messages = registry
+        .pull(DEFAULT_NAMESPACE, "nats/workers/jobs", 10, None, None)
+        .unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(&*messages[0].data, "b");
+
+    // Protocol violations end the connection
+    producer.send("FOO\r\n");
+    assert_eq!(producer.line(), "-ERR 'Unknown Protocol Operation'");
+    let mut rest = Vec::new();
+    producer.reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
@@ -0,0 +1,782 @@
+//! A RESP listener serving a subset of the Redis streams commands backed by the registry.
+//!
+//! Stream keys are the names of topics in the default namespace and consumer groups are the
+//! subscriptions of the default namespace subscribed to them, consumers are the consumers of the
+//! subscription. Entry ids are `<offset>-<partition>` of the message, reading the history from
+//! `0` or `0-0` includes the first message as in Redis.
+//!
+//! * `XADD` publishes a message, the topic is created with the default settings unless
+//!   `NOMKSTREAM` is given. An entry with the single field `data` is published as its value,
+//!   other entries as a JSON object of their fields. Only `*` ids are accepted and trimming is
+//!   left to the message TTL of the topic.
+//! * `XGROUP CREATE` creates a subscription which receives the messages the topic retains with
+//!   the id `0` or only new messages with `$`, `XGROUP DESTROY` deletes it and
+//!   `XGROUP DELCONSUMER` releases the messages of a consumer.
+//! * `XREADGROUP` pulls from the subscription with `>`, any other id lists the messages pending
+//!   for the consumer. Messages which are JSON objects of strings are read as their fields, other
+//!   messages as the single field `data`.
+//! * `XACK` acks pending messages and `XPENDING` lists them.
+//! * `XLEN` and `XINFO STREAM|GROUPS|CONSUMERS` report the metrics of topics, subscriptions and
+//!   consumers.
+
+mod resp;
+#[cfg(test)]
+mod tests;
+
+use chrono::prelude::*;
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use self::resp::Value;
+use crate::http_protocol::subscription_handlers::create_subscription;
+use crate::http_protocol::topic_handlers::ensure_topic;
+use crate::http_protocol::types::SubscriptionCreateConfig;
+use crate::http_protocol::SharedConfig;
+use courier::{
+    Error, ErrorCode, Message, PendingDelivery, RawMessage, SharedRegistry, SubscriptionMeta,
+    DEFAULT_NAMESPACE,
+};
+
+/// How long a blocking XREADGROUP waits before pulling again.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// The state shared by the connections of the RESP listener.
+struct RedisState {
+    registry: SharedRegistry,
+    config: SharedConfig,
+}
+
+/// Start the RESP listener on `address` in the background.
+pub fn start(address: &str, registry: &SharedRegistry, config: &SharedConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving RESP on '{}'.", listener.local_addr()?);
+    let state = Arc::new(RedisState {
+        registry: Arc::clone(registry),
+        config: Arc::clone(config),
+    });
+    thread::Builder::new()
+        .name(String::from("redis"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            if let Err(e) = serve(&state, stream) {
+                                debug!("RESP connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Can not accept a RESP connection: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// Serve a connection until it is closed.
+fn serve(state: &RedisState, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    while let Some(args) = resp::read_command(&mut reader)? {
+        if args.is_empty() {
+            continue;
+        }
+        let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+        let reply = if quit {
+            Ok(Value::ok())
+        } else {
+            match args
+                .into_iter()
+                .map(String::from_utf8)
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(args) => state.execute(&args),
+                Err(_) => Err(ReplyError::new("arguments must be UTF-8")),
+            }
+        };
+        reply
+            .unwrap_or_else(|e| Value::Error(e.0))
+            .write(&mut writer)?;
+        writer.flush()?;
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// An error reply, its message starts with the error code such as `ERR`.
+#[derive(Debug)]
+struct ReplyError(String);
+
+impl ReplyError {
+    fn new(message: &str) -> Self {
+        ReplyError(format!("ERR {}", message))
+    }
+
+    fn no_group(key: &str, group: &str) -> Self {
+        ReplyError(format!(
+            "NOGROUP No such key '{}' or consumer group '{}'",
+            key, group
+        ))
+    }
+
+    fn syntax() -> Self {
+        ReplyError::new("syntax error")
+    }
+}
+
+impl From<Error> for ReplyError {
+    fn from(error: Error) -> Self {
+        ReplyError::new(&error.message)
+    }
+}
+
+type Reply = Result<Value, ReplyError>;
+
+/// The id of a stream entry, entries are ordered by offset first.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct EntryId {
+    offset: u64,
+    partition: u32,
+}
+
+impl EntryId {
+    const MIN: EntryId = EntryId {
+        offset: 0,
+        partition: 0,
+    };
+    const MAX: EntryId = EntryId {
+        offset: u64::MAX,
+        partition: u32::MAX,
+    };
+
+    fn of(message: &Message) -> Self {
+        EntryId {
+            offset: message.offset,
+            partition: message.partition,
+        }
+    }
+
+    fn parse(id: &str) -> Result<Self, ReplyError> {
+        let invalid = || ReplyError::new("Invalid stream ID specified as stream command argument");
+        let mut parts = id.splitn(2, '-');
+        let offset = parts
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| invalid())?;
+        let partition = match parts.next() {
+            Some(partition) => partition.parse().map_err(|_| invalid())?,
+            None => 0,
+        };
+        Ok(EntryId { offset, partition })
+    }
+}
+
+impl From<&PendingDelivery> for EntryId {
+    fn from(delivery: &PendingDelivery) -> Self {
+        EntryId {
+            offset: delivery.offset,
+            partition: delivery.partition,
+        }
+    }
+}
+
+impl fmt::Display for EntryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.offset, self.partition)
+    }
+}
+
+// The fields of the entry of a message.
+fn fields(data: &str) -> Vec<Value> {
+    if let Ok(serde_json::Value::Object(object)) = serde_json::from_str(data) {
+        if !object.is_empty() && object.values().all(serde_json::Value::is_string) {
+            return object
+                .iter()
+                .flat_map(|(field, value)| {
+                    vec![
+                        Value::bulk(field),
+                        Value::bulk(value.as_str().unwrap_or_default()),
+                    ]
+                })
+                .collect();
+        }
+    }
+    vec![Value::bulk("data"), Value::bulk(data)]
+}
+
+fn entry(message: &Message) -> Value {
+    Value::Array(vec![
+        Value::bulk(&EntryId::of(message).to_string()),
+        Value::Array(fields(&message.data)),
+    ])
+}
+
+fn wrong_arity(command: &str) -> ReplyError {
+    ReplyError::new(&format!(
+        "wrong number of arguments for '{}' command",
+        command.to_ascii_lowercase()
+    ))
+}
+
+fn parse_integer<T: std::str::FromStr>(arg: &str) -> Result<T, ReplyError> {
+    arg.parse()
+        .map_err(|_| ReplyError::new("value is not an integer or out of range"))
+}
+
+// Milliseconds since `time`.
+fn idle(time: DateTime<Utc>) -> i64 {
+    (Utc::now() - time).num_milliseconds().max(0)
+}
+
+impl RedisState {
+    fn execute(&self, args: &[String]) -> Reply {
+        let command = args[0].to_ascii_uppercase();
+        match (command.as_str(), args.len()) {
+            ("PING", 1) => Ok(Value::Simple(String::from("PONG"))),
+            ("PING", 2) | ("ECHO", 2) => Ok(Value::bulk(&args[1])),
+            ("SELECT", 2) if args[1] == "0" => Ok(Value::ok()),
+            ("SELECT", 2) => Err(ReplyError::new("DB index is out of range")),
+            ("COMMAND", _) => Ok(Value::Array(Vec::new())),
+            ("CLIENT", len) if len > 2 => match args[1].to_ascii_uppercase().as_str() {
+                // Client names and library information are accepted and ignored
+                "SETNAME" | "SETINFO" => Ok(Value::ok()),
+                _ => Err(unknown_subcommand(&args[1], &command)),
+            },
+            ("XADD", len) if len >= 5 => self.xadd(&args[1..]),
+            ("XGROUP", len) if len >= 2 => self.xgroup(&args[1..]),
+            ("XREADGROUP", len) if len >= 7 => self.xreadgroup(&args[1..]),
+            ("XACK", len) if len >= 4 => self.xack(&args[1], &args[2], &args[3..]),
+            ("XPENDING", len) if len >= 3 => self.xpending(&args[1..]),
+            ("XLEN", 2) => self.xlen(&args[1]),
+            ("XINFO", len) if len >= 3 => self.xinfo(&args[1..]),
+            (
+                "PING" | "ECHO" | "SELECT" | "CLIENT" | "XADD" | "XGROUP" | "XREADGROUP" | "XACK"
+                | "XPENDING" | "XLEN" | "XINFO",
+                _,
+            ) => Err(wrong_arity(&command)),
+            _ => Err(ReplyError::new(&format!("unknown command '{}'", args[0]))),
+        }
+    }
+
+    fn registry(&self) -> &SharedRegistry {
+        &self.registry
+    }
+
+    // Get the subscription of the consumer group `group` of the stream `key`.
+    fn group(&self, key: &str, group: &str) -> Result<SubscriptionMeta, ReplyError> {
+        match self.registry().get_subscription(DEFAULT_NAMESPACE, group) {
+            Ok(subscription)
+                if subscription.topic_namespace == DEFAULT_NAMESPACE
+                    && subscription.topic == key =>
+            {
+                Ok(subscription)
+            }
+            Ok(_) => Err(ReplyError::no_group(key, group)),
+            Err(ref e) if e.code == ErrorCode::SubscriptionNotFound => {
+                Err(ReplyError::no_group(key, group))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn topic_exists(&self, key: &str) -> Result<bool, ReplyError> {
+        match self.registry().get_topic(DEFAULT_NAMESPACE, key) {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.code == ErrorCode::TopicNotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] * field value ...
+    fn xadd(&self, args: &[String]) -> Reply {
+        let key = &args[0];
+        let mut create = true;
+        let mut i = 1;
+        loop {
+            match args.get(i).map(|arg| arg.to_ascii_uppercase()).as_deref() {
+                Some("NOMKSTREAM") => {
+                    create = false;
+                    i += 1;
+                }
+                Some("MAXLEN") | Some("MINID") => {
+                    // Trimming is left to the message TTL of the topic
+                    i += 1;
+                    if matches!(args.get(i).map(String::as_str), Some("=") | Some("~")) {
+                        i += 1;
+                    }
+                    i += 1;
+                    if args.get(i).map(|arg| arg.eq_ignore_ascii_case("LIMIT")) == Some(true) {
+                        i += 2;
+                    }
+                }
+                _ => break,
+            }
+        }
+        match args.get(i).map(String::as_str) {
+            Some("*") => {}
+            Some(_) => {
+                return Err(ReplyError::new(
+                    "The ID of an entry is assigned by Courier, use '*'",
+                ))
+            }
+            None => return Err(ReplyError::syntax()),
+        }
+        let pairs = &args[i + 1..];
+        if pairs.is_empty() || pairs.len() % 2 == 1 {
+            return Err(wrong_arity("XADD"));
+        }
+        let data = if pairs.len() == 2 && pairs[0] == "data" {
+            pairs[1].clone()
+        } else {
+            let object: serde_json::Map<String, serde_json::Value> = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), serde_json::Value::from(pair[1].as_str())))
+                .collect();
+            serde_json::Value::Object(object).to_string()
+        };
+
+        if create {
+            ensure_topic(DEFAULT_NAMESPACE, key, self.registry(), &self.config.read())?;
+        } else if !self.topic_exists(key)? {
+            return Ok(Value::Nil);
+        }
+        let message = RawMessage {
+            data,
+            ..RawMessage::default()
+        };
+        let published = self
+            .registry()
+            .publish(DEFAULT_NAMESPACE, key, vec![message])?;
+        let (_, partition, offset) = published[0];
+        Ok(Value::bulk(&EntryId { offset, partition }.to_string()))
+    }
+
+    // XGROUP CREATE key group id [MKSTREAM] [ENTRIESREAD n] | DESTROY key group |
+    // DELCONSUMER key group consumer
+    fn xgroup(&self, args: &[String]) -> Reply {
+        let subcommand = args[0].to_ascii_uppercase();
+        match (subcommand.as_str(), args.len()) {
+            ("CREATE", len) if len >= 4 => {
+                let (key, group) = (&args[1], &args[2]);
+                let historical = match args[3].as_str() {
+                    "$" => false,
+                    "0" | "0-0" => true,
+                    _ => {
+                        return Err(ReplyError::new(
+                            "Consumer groups start at '0' or '$' in Courier",
+                        ))
+                    }
+                };
+                let make_stream = args[4..]
+                    .iter()
+                    .any(|arg| arg.eq_ignore_ascii_case("MKSTREAM"));
+                if make_stream {
+                    ensure_topic(DEFAULT_NAMESPACE, key, self.registry(), &self.config.read())?;
+                } else if !self.topic_exists(key)? {
+                    return Err(ReplyError::new(
+                        "The XGROUP subcommand requires the key to exist. Note that for CREATE \
+                         you may want to use the MKSTREAM option to create an empty stream \
+                         automatically.",
+                    ));
+                }
+                let config = SubscriptionCreateConfig {
+                    topic: key.clone(),
+                    topic_namespace: None,
+                    ack_deadline: None,
+                    ttl: None,
+                    consumer_timeout: None,
+                    historical: Some(historical),
+                    labels: None,
+                    description: None,
+                };
+                match create_subscription(
+                    DEFAULT_NAMESPACE,
+                    group,
+                    &config,
+                    self.registry(),
+                    &self.config.read(),
+                ) {
+                    Ok(_) => Ok(Value::ok()),
+                    Err(ref e) if e.code == ErrorCode::SubscriptionAlreadyExists => Err(
+                        ReplyError(String::from("BUSYGROUP Consumer Group name already exists")),
+                    ),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            ("DESTROY", 3) => {
+                let (key, group) = (&args[1], &args[2]);
+                match self.group(key, group) {
+                    Ok(_) => {
+                        self.registry()
+                            .delete_subscription(DEFAULT_NAMESPACE, group)?;
+                        Ok(Value::Integer(1))
+                    }
+                    Err(_) => Ok(Value::Integer(0)),
+                }
+            }
+            ("DELCONSUMER", 4) => {
+                let (key, group, consumer) = (&args[1], &args[2], &args[3]);
+                self.group(key, group)?;
+                match self
+                    .registry()
+                    .release_consumer(DEFAULT_NAMESPACE, group, consumer)
+                {
+                    Ok(released) => Ok(Value::Integer(released as i64)),
+                    Err(ref e) if e.code == ErrorCode::ConsumerNotFound => Ok(Value::Integer(0)),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            ("CREATE", _) | ("DESTROY", _) | ("DELCONSUMER", _) => Err(wrong_arity("XGROUP")),
+            _ => Err(unknown_subcommand(&args[0], "XGROUP")),
+        }
+    }
+
+    // XREADGROUP GROUP group consumer [COUNT count] [BLOCK ms] [NOACK] STREAMS key ... id ...
+    fn xreadgroup(&self, args: &[String]) -> Reply {
+        if !args[0].eq_ignore_ascii_case("GROUP") {
+            return Err(ReplyError::syntax());
+        }
+        let (group, consumer) = (&args[1], &args[2]);
+        let mut count = None;
+        let mut block = None;
+        let mut no_ack = false;
+        let mut i = 3;
+        let streams = loop {
+            let option = args
+                .get(i)
+                .ok_or_else(ReplyError::syntax)?
+                .to_ascii_uppercase();
+            match option.as_str() {
+                "COUNT" => {
+                    count = Some(parse_integer::<usize>(
+                        args.get(i + 1).ok_or_else(ReplyError::syntax)?,
+                    )?);
+                    i += 2;
+                }
+                "BLOCK" => {
+                    block = Some(parse_integer::<u64>(
+                        args.get(i + 1).ok_or_else(ReplyError::syntax)?,
+                    )?);
+                    i += 2;
+                }
+                "NOACK" => {
+                    no_ack = true;
+                    i += 1;
+                }
+                "STREAMS" => break &args[i + 1..],
+                _ => return Err(ReplyError::syntax()),
+            }
+        };
+        if streams.is_empty() || streams.len() % 2 != 0 {
+            return Err(ReplyError::new(
+                "Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' \
+                 must be specified.",
+            ));
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        let subscriptions = keys
+            .iter()
+            .map(|key| self.group(key, group))
+            .collect::<Result<Vec<_>, _>>()?;
+        let max = count
+            .unwrap_or(self.config.read().default_max_messages)
+            .max(1);
+
+        // Block until a stream has new messages, 0 blocks forever
+        let deadline = block
+            .filter(|ms| *ms > 0)
+            .map(|ms| time::Instant::now() + time::Duration::from_millis(ms));
+        loop {
+            let mut replies = Vec::new();
+            for ((key, id), subscription) in keys.iter().zip(ids).zip(&subscriptions) {
+                let entries = if id == ">" {
+                    let messages = self.registry().pull(
+                        DEFAULT_NAMESPACE,
+                        group,
+                        max,
+                        None,
+                        Some(consumer),
+                    )?;
+                    if no_ack && !messages.is_empty() {
+                        let ids: Vec<_> = messages.iter().map(|m| m.id).collect();
+                        self.registry().ack(DEFAULT_NAMESPACE, group, &ids)?;
+                    }
+                    if messages.is_empty() {
+                        continue;
+                    }
+                    messages.iter().map(entry).collect()
+                } else {
+                    // As in Redis `0` reads the history from its start
+                    let after = match id.as_str() {
+                        "0" | "0-0" => None,
+                        id => Some(EntryId::parse(id)?),
+                    };
+                    self.history(subscription, consumer, after, max)?
+                };
+                replies.push(Value::Array(vec![Value::bulk(key), Value::Array(entries)]));
+            }
+            if !replies.is_empty() {
+                return Ok(Value::Array(replies));
+            }
+            match (block, deadline) {
+                (None, _) => return Ok(Value::NilArray),
+                (Some(_), Some(deadline)) if time::Instant::now() >= deadline => {
+                    return Ok(Value::NilArray)
+                }
+                _ => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    // The entries pending for `consumer` after `after`, or all of them if it is None.
+    fn history(
+        &self,
+        subscription: &SubscriptionMeta,
+        consumer: &str,
+        after: Option<EntryId>,
+        max: usize,
+    ) -> Result<Vec<Value>, ReplyError> {
+        let pending = self
+            .registry()
+            .pending(DEFAULT_NAMESPACE, &subscription.name)?;
+        let mut deliveries: Vec<&PendingDelivery> = pending
+            .iter()
+            .filter(|d| d.consumer.as_deref() == Some(consumer))
+            .filter(|d| after.is_none() || Some(EntryId::from(*d)) > after)
+            .collect();
+        deliveries.sort_by_key(|d| EntryId::from(*d));
+        Ok(deliveries
+            .into_iter()
+            .filter_map(|d| {
+                self.registry()
+                    .get_message(&subscription.topic_namespace, &subscription.topic, d.id)
+                    .ok()
+            })
+            .take(max)
+            .map(|message| entry(&message))
+            .collect())
+    }
+
+    // XACK key group id ...
+    fn xack(&self, key: &str, group: &str, ids: &[String]) -> Reply {
+        let ids = ids
+            .iter()
+            .map(|id| EntryId::parse(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.group(key, group)?;
+        let pending = self.registry().pending(DEFAULT_NAMESPACE, group)?;
+        let message_ids: Vec<_> = pending
+            .iter()
+            .filter(|d| ids.contains(&EntryId::from(*d)))
+            .map(|d| d.id)
+            .collect();
+        let acked = self
+            .registry()
+            .ack(DEFAULT_NAMESPACE, group, &message_ids)?;
+        Ok(Value::Integer(acked.len() as i64))
+    }
+
+    // XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+    fn xpending(&self, args: &[String]) -> Reply {
+        let (key, group) = (&args[0], &args[1]);
+        let subscription = self.group(key, group)?;
+        let mut pending = self.registry().pending(DEFAULT_NAMESPACE, group)?;
+        pending.sort_by_key(|d| EntryId::from(d));
+        let consumer_name = |d: &PendingDelivery| d.consumer.clone().unwrap_or_default();
+
+        if args.len() == 2 {
+            if pending.is_empty() {
+                return Ok(Value::Array(vec![
+                    Value::Integer(0),
+                    Value::Nil,
+                    Value::Nil,
+                    Value::NilArray,
+                ]));
+            }
+            let mut consumers = BTreeMap::new();
+            for d in &pending {
+                *consumers.entry(consumer_name(d)).or_insert(0) += 1;
+            }
+            return Ok(Value::Array(vec![
+                Value::Integer(pending.len() as i64),
+                Value::bulk(&EntryId::from(&pending[0]).to_string()),
+                Value::bulk(&EntryId::from(&pending[pending.len() - 1]).to_string()),
+                Value::Array(
+                    consumers
+                        .into_iter()
+                        .map(|(consumer, count): (String, i64)| {
+                            Value::Array(vec![
+                                Value::bulk(&consumer),
+                                Value::bulk(&count.to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]));
+        }
+
+        let mut rest = &args[2..];
+        let mut min_idle = 0;
+        if rest[0].eq_ignore_ascii_case("IDLE") {
+            min_idle = parse_integer::<i64>(rest.get(1).ok_or_else(ReplyError::syntax)?)?;
+            rest = &rest[2..];
+        }
+        if rest.len() != 3 && rest.len() != 4 {
+            return Err(ReplyError::syntax());
+        }
+        let start = bound(&rest[0], EntryId::MIN)?;
+        let end = bound(&rest[1], EntryId::MAX)?;
+        let count = parse_integer::<usize>(&rest[2])?;
+        let consumer = rest.get(3);
+        let ack_deadline = chrono::Duration::seconds(subscription.ack_deadline);
+        let entries = pending
+            .iter()
+            .filter(|d| start.contains(EntryId::from(*d), true))
+            .filter(|d| end.contains(EntryId::from(*d), false))
+            .filter(|d| consumer.is_none() || d.consumer.as_ref() == consumer)
+            .map(|d| (d, idle(d.deadline - ack_deadline)))
+            .filter(|(_, idle)| *idle >= min_idle)
+            .take(count)
+            .map(|(d, idle)| {
+                Value::Array(vec![
+                    Value::bulk(&EntryId::from(d).to_string()),
+                    Value::bulk(&consumer_name(d)),
+                    Value::Integer(idle),
+                    Value::Integer(i64::from(d.tries)),
+                ])
+            })
+            .collect();
+        Ok(Value::Array(entries))
+    }
+
+    // XLEN key
+    fn xlen(&self, key: &str) -> Reply {
+        let metrics = self.registry().namespace_metrics(DEFAULT_NAMESPACE)?;
+        let len = metrics.topics.get(key).map_or(0, |topic| topic.messages);
+        Ok(Value::Integer(len as i64))
+    }
+
+    // XINFO STREAM key | GROUPS key | CONSUMERS key group
+    fn xinfo(&self, args: &[String]) -> Reply {
+        let subcommand = args[0].to_ascii_uppercase();
+        let key = &args[1];
+        match (subcommand.as_str(), args.len()) {
+            ("STREAM", 2) => {
+                let topic = match self.registry().get_topic(DEFAULT_NAMESPACE, key) {
+                    Ok(topic) => topic,
+                    Err(ref e) if e.code == ErrorCode::TopicNotFound => {
+                        return Err(ReplyError::new("no such key"))
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let metrics = self.registry().namespace_metrics(DEFAULT_NAMESPACE)?;
+                let length = metrics.topics.get(key).map_or(0, |t| t.messages);
+                let groups = metrics
+                    .subscriptions
+                    .values()
+                    .filter(|s| s.topic_namespace == DEFAULT_NAMESPACE && s.topic == *key)
+                    .count();
+                Ok(Value::Array(vec![
+                    Value::bulk("length"),
+                    Value::Integer(length as i64),
+                    Value::bulk("groups"),
+                    Value::Integer(groups as i64),
+                    Value::bulk("partitions"),
+                    Value::Integer(i64::from(topic.partitions)),
+                    Value::bulk("message-ttl"),
+                    Value::Integer(topic.message_ttl),
+                ]))
+            }
+            ("GROUPS", 2) => {
+                if !self.topic_exists(key)? {
+                    return Err(ReplyError::new("no such key"));
+                }
+                let metrics = self.registry().namespace_metrics(DEFAULT_NAMESPACE)?;
+                let mut groups: Vec<_> = metrics
+                    .subscriptions
+                    .iter()
+                    .filter(|(_, s)| s.topic_namespace == DEFAULT_NAMESPACE && s.topic == *key)
+                    .collect();
+                groups.sort_by_key(|(name, _)| name.as_str());
+                let mut replies = Vec::with_capacity(groups.len());
+                for (name, subscription) in groups {
+                    let consumers = self.registry().consumers(DEFAULT_NAMESPACE, name)?;
+                    replies.push(Value::Array(vec![
+                        Value::bulk("name"),
+                        Value::bulk(name),
+                        Value::bulk("consumers"),
+                        Value::Integer(consumers.len() as i64),
+                        Value::bulk("pending"),
+                        Value::Integer(subscription.pending as i64),
+                    ]));
+                }
+                Ok(Value::Array(replies))
+            }
+            ("CONSUMERS", 3) => {
+                let group = &args[2];
+                self.group(key, group)?;
+                let consumers = self.registry().consumers(DEFAULT_NAMESPACE, group)?;
+                Ok(Value::Array(
+                    consumers
+                        .iter()
+                        .map(|consumer| {
+                            Value::Array(vec![
+                                Value::bulk("name"),
+                                Value::bulk(&consumer.id),
+                                Value::bulk("pending"),
+                                Value::Integer(consumer.in_flight as i64),
+                                Value::bulk("idle"),
+                                Value::Integer(idle(consumer.last_seen)),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+            ("STREAM", _) | ("GROUPS", _) | ("CONSUMERS", _) => Err(wrong_arity("XINFO")),
+            _ => Err(unknown_subcommand(&args[0], "XINFO")),
+        }
+    }
+}
+
+fn unknown_subcommand(subcommand: &str, command: &str) -> ReplyError {
+    ReplyError::new(&format!(
+        "unknown subcommand '{}'. Try {} HELP.",
+        subcommand, command
+    ))
+}
+
+/// A bound of an XPENDING range.
+enum Bound {
+    Inclusive(EntryId),
+    Exclusive(EntryId),
+}
+
+impl Bound {
+    // If `id` is within the bound, `start` tells if it is the start of the range.
+    fn contains(&self, id: EntryId, start: bool) -> bool {
+        match (self, start) {
+            (Bound::Inclusive(bound), true) => id >= *bound,
+            (Bound::Inclusive(bound), false) => id <= *bound,
+            (Bound::Exclusive(bound), true) => id > *bound,
+            (Bound::Exclusive(bound), false) => id < *bound,
+        }
+    }
+}
+
+fn bound(arg: &str, unbounded: EntryId) -> Result<Bound, ReplyError> {
+    Ok(match arg {
+        "-" | "+" => Bound::Inclusive(unbounded),
+        arg if arg.starts_with('(') => Bound::Exclusive(EntryId::parse(&arg[1..])?),
+        arg => Bound::Inclusive(EntryId::parse(arg)?),
+    })
+}
@@ -0,0 +1,169 @@
+//! Encoding and decoding of RESP2, the protocol of Redis.
+
+use std::io::{self, BufRead, Read, Write};
+
+/// The maximum length of a bulk string or of an inline command.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// The maximum number of elements of an array.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+
+/// A RESP value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Nil,
+    Array(Vec<Value>),
+    NilArray,
+}
+
+impl Value {
+    pub fn bulk(s: &str) -> Value {
+        Value::Bulk(s.as_bytes().to_vec())
+    }
+
+    pub fn ok() -> Value {
+        Value::Simple(String::from("OK"))
+    }
+
+    /// Read a value, returns None if the stream ends before a value.
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+        let line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if line.is_empty() {
+            return Err(invalid("empty line"));
+        }
+        let (kind, rest) = line.split_at(1);
+        let value = match kind {
+            "+" => Value::Simple(String::from(rest)),
+            "-" => Value::Error(String::from(rest)),
+            ":" => Value::Integer(parse(rest)?),
+            "$" => match parse::<i64>(rest)? {
+                -1 => Value::Nil,
+                len if len < 0 || len as usize > MAX_BULK_LEN => {
+                    return Err(invalid("invalid bulk length"))
+                }
+                len => {
+                    let mut bulk = vec![0; len as usize + 2];
+                    reader.read_exact(&mut bulk)?;
+                    if !bulk.ends_with(b"\r\n") {
+                        return Err(invalid("bulk string is not terminated by CRLF"));
+                    }
+                    bulk.truncate(len as usize);
+                    Value::Bulk(bulk)
+                }
+            },
+            "*" => match parse::<i64>(rest)? {
+                -1 => Value::NilArray,
+                len if len < 0 || len as usize > MAX_ARRAY_LEN => {
+                    return Err(invalid("invalid multibulk length"))
+                }
+                len => {
+                    let mut values = Vec::with_capacity(len as usize);
+                    for _ in 0..len {
+                        values.push(Value::read(reader)?.ok_or_else(eof)?);
+                    }
+                    Value::Array(values)
+                }
+            },
+            _ => return Err(invalid("unknown type")),
+        };
+        Ok(Some(value))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Value::Simple(s) => write!(writer, "+{}\r\n", s),
+            Value::Error(e) => write!(writer, "-{}\r\n", e),
+            Value::Integer(i) => write!(writer, ":{}\r\n", i),
+            Value::Bulk(bulk) => {
+                write!(writer, "${}\r\n", bulk.len())?;
+                writer.write_all(bulk)?;
+                writer.write_all(b"\r\n")
+            }
+            Value::Nil => writer.write_all(b"$-1\r\n"),
+            Value::Array(values) => {
+                write!(writer, "*{}\r\n", values.len())?;
+                for value in values {
+                    value.write(writer)?;
+                }
+                Ok(())
+            }
+            Value::NilArray => writer.write_all(b"*-1\r\n"),
+        }
+    }
+}
+
+/// Read the arguments of a command sent as an array of bulk strings or inline, returns None if
+/// the stream ends before a command.
+pub fn read_command(reader: &mut impl BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    loop {
+        let first = match reader.fill_buf()?.first() {
+            Some(first) => *first,
+            None => return Ok(None),
+        };
+        if first == b'*' {
+            return match Value::read(reader)? {
+                Some(Value::Array(values)) => values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::Bulk(bulk) => Ok(bulk),
+                        _ => Err(invalid("expected a bulk string")),
+                    })
+                    .collect::<io::Result<_>>()
+                    .map(Some),
+                _ => Err(invalid("expected an array of bulk strings")),
+            };
+        }
+        // An inline command, empty lines are skipped
+        let line = read_line(reader)?.ok_or_else(eof)?;
+        let args: Vec<Vec<u8>> = line
+            .split_whitespace()
+            .map(|arg| arg.as_bytes().to_vec())
+            .collect();
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+// Read a line without its CRLF, returns None at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_BULK_LEN as u64)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(eof());
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid("the line is not UTF-8"))
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> io::Result<T> {
+    s.parse().map_err(|_| invalid("invalid number"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Protocol error: {}", message),
+    )
+}
+
+fn eof() -> io::Error {
+    io::ErrorKind::UnexpectedEof.into()
+}
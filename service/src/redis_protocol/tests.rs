@@ -0,0 +1,239 @@
+use super::*;
+use crate::testing::{config, unused_port};
+use courier::Registry;
+
+// A Redis client sending commands as arrays of bulk strings.
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Value {
+        let args = command.split(' ').map(Value::bulk).collect();
+        Value::Array(args).write(&mut self.writer).unwrap();
+        Value::read(&mut self.reader).unwrap().unwrap()
+    }
+}
+
+fn bulk(s: &str) -> Value {
+    Value::bulk(s)
+}
+
+fn array(values: Vec<Value>) -> Value {
+    Value::Array(values)
+}
+
+fn error(value: Value) -> String {
+    match value {
+        Value::Error(e) => e,
+        value => panic!("unexpected {:?}", value),
+    }
+}
+
+#[test]
+fn resp_values() {
+    let values = vec![
+        Value::Simple(String::from("OK")),
+        Value::Error(String::from("ERR wrong")),
+        Value::Integer(-42),
+        bulk("hello\r\nworld"),
+        Value::Nil,
+        array(vec![bulk("a"), Value::Integer(1), array(Vec::new())]),
+        Value::NilArray,
+    ];
+    for value in values {
+        let mut buf = Vec::new();
+        value.write(&mut buf).unwrap();
+        assert_eq!(Value::read(&mut &buf[..]).unwrap(), Some(value));
+    }
+    assert_eq!(Value::read(&mut &b""[..]).unwrap(), None);
+    assert!(Value::read(&mut &b"$5\r\nabc\r\n"[..]).is_err());
+    assert!(Value::read(&mut &b"?\r\n"[..]).is_err());
+
+    // Commands are sent as arrays of bulk strings or inline
+    let mut input = &b"*2\r\n$4\r\nXLEN\r\n$5\r\ntemps\r\n\r\nPING  hello\r\n"[..];
+    let command = resp::read_command(&mut input).unwrap().unwrap();
+    assert_eq!(command, vec![b"XLEN".to_vec(), b"temps".to_vec()]);
+    let command = resp::read_command(&mut input).unwrap().unwrap();
+    assert_eq!(command, vec![b"PING".to_vec(), b"hello".to_vec()]);
+    assert_eq!(resp::read_command(&mut input).unwrap(), None);
+    assert!(resp::read_command(&mut &b"*1\r\n:1\r\n"[..]).is_err());
+}
+
+#[test]
+fn redis_protocol() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+    let mut client = Client::connect(port);
+
+    assert_eq!(client.command("PING"), Value::Simple(String::from("PONG")));
+    assert!(error(client.command("GET key")).starts_with("ERR unknown command"));
+    assert!(error(client.command("XLEN")).starts_with("ERR wrong number of arguments"));
+
+    // Entries are published to the topic
+    assert_eq!(client.command("XADD temps * data 21"), bulk("0-0"));
+    assert_eq!(
+        client.command("XADD temps MAXLEN ~ 100 * value 22 sensor a"),
+        bulk("1-0")
+    );
+    assert_eq!(client.command("XADD other NOMKSTREAM * data 1"), Value::Nil);
+    assert!(error(client.command("XADD temps 5-0 data 1")).starts_with("ERR"));
+    assert_eq!(client.command("XLEN temps"), Value::Integer(2));
+    let (messages, _) = registry
+        .read(DEFAULT_NAMESPACE, "temps", 0, None, 10)
+        .unwrap();
+    assert_eq!(&*messages[0].data, "21");
+    assert_eq!(&*messages[1].data, r#"{"sensor":"a","value":"22"}"#);
+
+    // Consumer groups are subscriptions
+    assert_eq!(client.command("XGROUP CREATE temps workers 0"), Value::ok());
+    assert!(error(client.command("XGROUP CREATE temps workers 0")).starts_with("BUSYGROUP"));
+    assert!(error(client.command("XGROUP CREATE other g $")).starts_with("ERR"));
+    assert_eq!(
+        client.command("XGROUP CREATE other g $ MKSTREAM"),
+        Value::ok()
+    );
+    assert_eq!(
+        registry
+            .get_subscription(DEFAULT_NAMESPACE, "workers")
+            .unwrap()
+            .topic,
+        "temps"
+    );
+    assert!(error(client.command("XREADGROUP GROUP g c STREAMS temps >")).starts_with("NOGROUP"));
+
+    // Messages are pulled by consumers
+    let entry = |id: &str, fields: &[&str]| {
+        array(vec![
+            bulk(id),
+            array(fields.iter().map(|f| bulk(f)).collect()),
+        ])
+    };
+    assert_eq!(
+        client.command("XREADGROUP GROUP workers alice COUNT 1 STREAMS temps >"),
+        array(vec![array(vec![
+            bulk("temps"),
+            array(vec![entry("0-0", &["data", "21"])])
+        ])])
+    );
+    assert_eq!(
+        client.command("XREADGROUP GROUP workers bob STREAMS temps >"),
+        array(vec![array(vec![
+            bulk("temps"),
+            array(vec![entry("1-0", &["sensor", "a", "value", "22"])])
+        ])])
+    );
+    assert_eq!(
+        client.command("XREADGROUP GROUP workers bob BLOCK 100 STREAMS temps >"),
+        Value::NilArray
+    );
+    assert_eq!(
+        client.command("XREADGROUP GROUP workers alice STREAMS temps 0"),
+        array(vec![array(vec![
+            bulk("temps"),
+            array(vec![entry("0-0", &["data", "21"])])
+        ])])
+    );
+
+    // Pending messages are listed and acked
+    assert_eq!(
+        client.command("XPENDING temps workers"),
+        array(vec![
+            Value::Integer(2),
+            bulk("0-0"),
+            bulk("1-0"),
+            array(vec![
+                array(vec![bulk("alice"), bulk("1")]),
+                array(vec![bulk("bob"), bulk("1")])
+            ])
+        ])
+    );
+    match client.command("XPENDING temps workers (0-0 + 10 bob") {
+        Value::Array(entries) => match &entries[..] {
+            [Value::Array(entry)] => {
+                assert_eq!(&entry[..2], &[bulk("1-0"), bulk("bob")]);
+                assert_eq!(entry[3], Value::Integer(1));
+            }
+            entries => panic!("unexpected {:?}", entries),
+        },
+        value => panic!("unexpected {:?}", value),
+    }
+    assert_eq!(
+        client.command("XPENDING temps workers - + 10 carol"),
+        array(Vec::new())
+    );
+    assert_eq!(
+        client.command("XACK temps workers 0-0 5-0"),
+        Value::Integer(1)
+    );
+    assert_eq!(
+        client.command("XINFO GROUPS temps"),
+        array(vec![array(vec![
+            bulk("name"),
+            bulk("workers"),
+            bulk("consumers"),
+            Value::Integer(2),
+            bulk("pending"),
+            Value::Integer(1)
+        ])])
+    );
+    match client.command("XINFO STREAM temps") {
+        Value::Array(info) => assert_eq!(
+            &info[..4],
+            &[
+                bulk("length"),
+                Value::Integer(2),
+                bulk("groups"),
+                Value::Integer(1)
+            ]
+        ),
+        value => panic!("unexpected {:?}", value),
+    }
+    match client.command("XINFO CONSUMERS temps workers") {
+        Value::Array(consumers) => assert_eq!(consumers.len(), 2),
+        value => panic!("unexpected {:?}", value),
+    }
+
+    // A blocked read returns once a message is published
+    let publisher = thread::spawn(move || {
+        thread::sleep(POLL_INTERVAL * 2);
+        Client::connect(port).command("XADD temps * data 23")
+    });
+    assert_eq!(
+        client.command("XREADGROUP GROUP workers alice NOACK BLOCK 0 STREAMS temps >"),
+        array(vec![array(vec![
+            bulk("temps"),
+            array(vec![entry("2-0", &["data", "23"])])
+        ])])
+    );
+    assert_eq!(publisher.join().unwrap(), bulk("2-0"));
+
+    // Removing a consumer releases its messages, destroying a group deletes the subscription
+    assert_eq!(
+        client.command("XGROUP DELCONSUMER temps workers bob"),
+        Value::Integer(1)
+    );
+    assert_eq!(
+        client.command("XGROUP DESTROY temps workers"),
+        Value::Integer(1)
+    );
+    assert!(registry
+        .get_subscription(DEFAULT_NAMESPACE, "workers")
+        .is_err());
+
+    assert_eq!(client.command("QUIT"), Value::ok());
+    assert_eq!(Value::read(&mut client.reader).unwrap(), None);
+}
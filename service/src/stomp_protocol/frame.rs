@@ -0,0 +1,204 @@
+//! Encoding and decoding of STOMP 1.2 frames.
+
+use std::io::{self, BufRead, Read, Write};
+
+/// The maximum length of the command or of a header line of a frame.
+const MAX_LINE_LEN: u64 = 64 * 1024;
+
+/// The maximum length of the body of a frame.
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+
+/// A STOMP frame, headers are kept in the order they were received or added.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub command: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(command: &str) -> Self {
+        Frame {
+            command: String::from(command),
+            ..Frame::default()
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Get the value of a header, the first occurrence of a repeated header wins.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The headers of CONNECT and CONNECTED frames are not escaped for compatibility with STOMP 1.0.
+    fn escaped(&self) -> bool {
+        !matches!(self.command.as_str(), "CONNECT" | "CONNECTED")
+    }
+
+    /// Read the next frame skipping heart-beats, returns None if the stream ends before a frame.
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Option<Frame>> {
+        let command = loop {
+            match read_line(reader)? {
+                None => return Ok(None),
+                // An empty line is a heart-beat
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+        let mut frame = Frame {
+            command,
+            ..Frame::default()
+        };
+        let escaped = frame.escaped();
+        loop {
+            let line = read_line(reader)?.ok_or_else(|| eof("headers"))?;
+            if line.is_empty() {
+                break;
+            }
+            let colon = line
+                .find(':')
+                .ok_or_else(|| invalid(format!("header '{}' has no value", line)))?;
+            let (name, value) = (&line[..colon], &line[colon + 1..]);
+            if escaped {
+                frame.headers.push((unescape(name)?, unescape(value)?));
+            } else {
+                frame
+                    .headers
+                    .push((String::from(name), String::from(value)));
+            }
+        }
+
+        match frame.header("content-length") {
+            Some(length) => {
+                let length: usize = length
+                    .parse()
+                    .map_err(|_| invalid(format!("'{}' is not a valid content-length", length)))?;
+                if length > MAX_BODY_LEN {
+                    return Err(invalid(format!("the body of {} bytes is too long", length)));
+                }
+                frame.body = vec![0; length];
+                reader.read_exact(&mut frame.body)?;
+                let mut nul = [0];
+                reader.read_exact(&mut nul)?;
+                if nul[0] != 0 {
+                    return Err(invalid(String::from("the body is not terminated by NUL")));
+                }
+            }
+            None => {
+                let mut limited = reader.by_ref().take(MAX_BODY_LEN as u64 + 1);
+                limited.read_until(0, &mut frame.body)?;
+                if frame.body.pop() != Some(0) {
+                    return Err(if frame.body.len() >= MAX_BODY_LEN {
+                        invalid(String::from("the body is too long"))
+                    } else {
+                        eof("body")
+                    });
+                }
+            }
+        }
+        Ok(Some(frame))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let escaped = self.escaped();
+        let mut buf = Vec::with_capacity(self.body.len() + 128);
+        buf.extend_from_slice(self.command.as_bytes());
+        buf.push(b'\n');
+        for (name, value) in &self.headers {
+            if escaped {
+                escape(name, &mut buf);
+                buf.push(b':');
+                escape(value, &mut buf);
+            } else {
+                buf.extend_from_slice(name.as_bytes());
+                buf.push(b':');
+                buf.extend_from_slice(value.as_bytes());
+            }
+            buf.push(b'\n');
+        }
+        buf.push(b'\n');
+        buf.extend_from_slice(&self.body);
+        buf.push(0);
+        // A frame is written at once so it is not interleaved with other frames
+        writer.write_all(&buf)
+    }
+}
+
+// Read a line without its EOL, returns None at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE_LEN)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(if line.len() as u64 >= MAX_LINE_LEN {
+            invalid(String::from("the line is too long"))
+        } else {
+            eof("line")
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid(String::from("the line is not UTF-8")))
+}
+
+fn escape(s: &str, buf: &mut Vec<u8>) {
+    for byte in s.bytes() {
+        match byte {
+            b'\r' => buf.extend_from_slice(b"\\r"),
+            b'\n' => buf.extend_from_slice(b"\\n"),
+            b':' => buf.extend_from_slice(b"\\c"),
+            b'\\' => buf.extend_from_slice(b"\\\\"),
+            byte => buf.push(byte),
+        }
+    }
+}
+
+fn unescape(s: &str) -> io::Result<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some('c') => unescaped.push(':'),
+            Some('\\') => unescaped.push('\\'),
+            _ => return Err(invalid(format!("'{}' has an invalid escape sequence", s))),
+        }
+    }
+    Ok(unescaped)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn eof(part: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("the stream ended in the {} of a frame", part),
+    )
+}
@@ -0,0 +1,585 @@
+//! A STOMP 1.2 listener mapped onto the topics and subscriptions of the registry.
+//!
+//! STOMP is served over TCP and over WebSockets on the same port, a connection starting with an
+//! HTTP `GET` request is upgraded to a WebSocket carrying STOMP frames.
+//!
+//! Destinations name a topic or a subscription of the default namespace:
+//!
+//! * `/topic/<topic>`: a SEND publishes to the topic, which is created with the default settings
+//!   if it does not exist. A SUBSCRIBE creates the subscription `stomp/<session>/<id>` of the
+//!   topic for the connection, it is deleted by the UNSUBSCRIBE or when the connection ends.
+//! * `/queue/<subscription>`: a SEND publishes to the topic of the subscription. A SUBSCRIBE
+//!   consumes the existing subscription, its messages are shared with its other consumers.
+//!
+//! With `ack:auto` messages are acked in the subscription as soon as they are sent. With
+//! `ack:client-individual` an ACK acks the message and a NACK resends it immediately, with
+//! `ack:client` they apply to every message of the subscription sent up to the acknowledged one.
+//! Messages which are not acknowledged are resent once their `ack_deadline` is reached or when the
+//! connection ends. Bodies must be UTF-8.
+
+mod frame;
+#[cfg(test)]
+mod tests;
+mod websocket;
+
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use uuid::Uuid;
+
+use self::frame::Frame;
+use self::websocket::{WebSocketReader, WebSocketWriter};
+use crate::delivery::{self, Subscriber};
+use crate::http_protocol::subscription_handlers::create_subscription;
+use crate::http_protocol::topic_handlers::ensure_topic;
+use crate::http_protocol::types::SubscriptionCreateConfig;
+use crate::http_protocol::SharedConfig;
+use courier::signal::Signal;
+use courier::{Error, ErrorCode, Message, RawMessage, SharedRegistry, DEFAULT_NAMESPACE};
+
+/// How long a client has to send its CONNECT frame.
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// The maximum number of messages waiting for an ACK or NACK per session.
+const MAX_IN_FLIGHT: usize = 100;
+
+/// The label of a subscription holding the session it was created for.
+const SESSION_LABEL: &str = "stomp-session";
+
+const TOPIC_PREFIX: &str = "/topic/";
+const QUEUE_PREFIX: &str = "/queue/";
+
+/// The state shared by the sessions of the STOMP listener.
+struct StompState {
+    registry: SharedRegistry,
+    config: SharedConfig,
+}
+
+/// Start the STOMP listener on `address` in the background.
+pub fn start(address: &str, registry: &SharedRegistry, config: &SharedConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving STOMP on '{}'.", listener.local_addr()?);
+    let state = Arc::new(StompState {
+        registry: Arc::clone(registry),
+        config: Arc::clone(config),
+    });
+    thread::Builder::new()
+        .name(String::from("stomp"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            if let Err(e) = serve(&state, stream) {
+                                debug!("STOMP connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Can not accept a STOMP connection: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// How the messages sent to a subscription are acknowledged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AckMode {
+    Auto,
+    Client,
+    ClientIndividual,
+}
+
+// A subscription of a session.
+#[derive(Clone, Debug)]
+struct Subscribed {
+    // The id the client chose for the subscription.
+    id: String,
+    destination: String,
+    // The name of the subscription in the registry.
+    name: String,
+    ack: AckMode,
+    // If the subscription was created for the session.
+    owned: bool,
+}
+
+// A message sent to the client which was not acknowledged yet.
+#[derive(Debug)]
+struct Delivery {
+    subscription: String,
+    name: String,
+    message: Uuid,
+}
+
+/// The session of a connected client.
+struct Session {
+    state: Arc<StompState>,
+    id: String,
+    writer: Mutex<Box<dyn Write + Send>>,
+    // Subscriptions by the id the client chose.
+    subscriptions: Mutex<HashMap<String, Subscribed>>,
+    // Messages waiting for an ACK or NACK by ack id, in the order they were sent.
+    in_flight: Mutex<BTreeMap<u64, Delivery>>,
+    next_ack: AtomicU64,
+    closed: AtomicBool,
+    signal: Arc<Signal>,
+}
+
+// Serve a connection until it is closed.
+fn serve(state: &Arc<StompState>, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    // STOMP frames never start with a `G` so the connection is a WebSocket handshake
+    let (mut reader, writer): (Box<dyn BufRead + Send>, Box<dyn Write + Send>) =
+        if reader.fill_buf()?.first() == Some(&b'G') {
+            let mut writer = stream.try_clone()?;
+            websocket::handshake(&mut reader, &mut writer)?;
+            let writer = Arc::new(Mutex::new(writer));
+            (
+                Box::new(BufReader::new(WebSocketReader::new(
+                    reader,
+                    Arc::clone(&writer),
+                ))),
+                Box::new(WebSocketWriter::new(writer)),
+            )
+        } else {
+            (Box::new(reader), Box::new(stream.try_clone()?))
+        };
+
+    let session = Arc::new(Session {
+        state: Arc::clone(state),
+        id: Uuid::new_v4().to_string(),
+        writer: Mutex::new(writer),
+        subscriptions: Mutex::new(HashMap::new()),
+        in_flight: Mutex::new(BTreeMap::new()),
+        next_ack: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        signal: Signal::new(),
+    });
+    let result = session.run(&mut reader, &stream);
+    session.close();
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+impl Session {
+    fn registry(&self) -> &SharedRegistry {
+        &self.state.registry
+    }
+
+    fn send(&self, frame: &Frame) -> io::Result<()> {
+        let mut writer = self.writer.lock();
+        frame.write(&mut *writer)?;
+        writer.flush()
+    }
+
+    // Send an ERROR frame, the connection is closed afterwards.
+    fn send_error(&self, error: &Error, receipt: Option<&str>) -> io::Result<()> {
+        // The code is serialized as a JSON string, for example `"TOPIC_NOT_FOUND"`
+        let code = serde_json::to_string(&error.code).unwrap_or_default();
+        let mut frame = Frame::new("ERROR")
+            .with_header("message", &error.message)
+            .with_header("courier-error-code", code.trim_matches('"'))
+            .with_header("content-type", "text/plain")
+            .with_body(error.to_string().into_bytes());
+        if let Some(receipt) = receipt {
+            frame = frame.with_header("receipt-id", receipt);
+        }
+        self.send(&frame)
+    }
+
+    // Accept the connection and handle the frames of the client until it disconnects.
+    fn run(self: &Arc<Self>, reader: &mut impl BufRead, stream: &TcpStream) -> io::Result<()> {
+        let connect = match Frame::read(reader)? {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        if let Err(e) = self.connect(&connect, stream) {
+            return self.send_error(&e, None);
+        }
+        let session = Arc::clone(self);
+        thread::spawn(move || {
+            if let Err(e) = delivery::deliver(session.registry(), &*session) {
+                debug!("Can not deliver to STOMP session '{}': {}", session.id, e);
+            }
+        });
+
+        let mut transactions = HashMap::new();
+        while let Some(frame) = Frame::read(reader)? {
+            let receipt = frame.header("receipt").map(String::from);
+            let disconnect = match self.handle(frame, &mut transactions) {
+                Ok(disconnect) => disconnect,
+                Err(e) => return self.send_error(&e, receipt.as_deref()),
+            };
+            if let Some(receipt) = receipt {
+                self.send(&Frame::new("RECEIPT").with_header("receipt-id", &receipt))?;
+            }
+            if disconnect {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // Negotiate the version and heart-beats of the session.
+    fn connect(&self, frame: &Frame, stream: &TcpStream) -> Result<(), Error> {
+        if frame.command != "CONNECT" && frame.command != "STOMP" {
+            return Err(Error::invalid_request(format!(
+                "Expected CONNECT but got {}",
+                frame.command
+            )));
+        }
+        let versions = frame.header("accept-version").unwrap_or("1.0");
+        if !versions.split(',').any(|version| version.trim() == "1.2") {
+            return Err(Error::invalid_request(String::from(
+                "Supported protocol versions are 1.2",
+            )));
+        }
+        // The server does not send heart-beats but expects those of the client
+        let client_heart_beat = frame
+            .header("heart-beat")
+            .and_then(|heart_beat| heart_beat.split(',').next())
+            .and_then(|interval| interval.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let timeout = match client_heart_beat {
+            0 => None,
+            interval => Some(time::Duration::from_millis(interval * 2)),
+        };
+        stream
+            .set_read_timeout(timeout)
+            .map_err(|e| Error::invalid_request(e.to_string()))?;
+        let connected = Frame::new("CONNECTED")
+            .with_header("version", "1.2")
+            .with_header("session", &self.id)
+            .with_header("server", &format!("courier/{}", env!("CARGO_PKG_VERSION")))
+            .with_header("heart-beat", &format!("0,{}", client_heart_beat));
+        self.send(&connected)
+            .map_err(|e| Error::invalid_request(e.to_string()))
+    }
+
+    // Handle a frame of the client, returns true if the client disconnected.
+    fn handle(
+        &self,
+        frame: Frame,
+        transactions: &mut HashMap<String, Vec<Frame>>,
+    ) -> Result<bool, Error> {
+        // The frames of a transaction are handled when it is committed
+        if let Some(transaction) = frame.header("transaction") {
+            if matches!(frame.command.as_str(), "SEND" | "ACK" | "NACK") {
+                let frames = transactions.get_mut(transaction).ok_or_else(|| {
+                    Error::invalid_request(format!("Unknown transaction '{}'", transaction))
+                })?;
+                frames.push(frame);
+                return Ok(false);
+            }
+        }
+        match frame.command.as_str() {
+            "SEND" => self.publish(&frame)?,
+            "SUBSCRIBE" => self.subscribe(&frame)?,
+            "UNSUBSCRIBE" => self.unsubscribe(required(&frame, "id")?),
+            "ACK" => self.acknowledge(required(&frame, "id")?, true)?,
+            "NACK" => self.acknowledge(required(&frame, "id")?, false)?,
+            "BEGIN" => {
+                let transaction = required(&frame, "transaction")?;
+                if transactions.contains_key(transaction) {
+                    return Err(Error::invalid_request(format!(
+                        "Transaction '{}' already began",
+                        transaction
+                    )));
+                }
+                transactions.insert(String::from(transaction), Vec::new());
+            }
+            "COMMIT" | "ABORT" => {
+                let transaction = required(&frame, "transaction")?;
+                let frames = transactions.remove(transaction).ok_or_else(|| {
+                    Error::invalid_request(format!("Unknown transaction '{}'", transaction))
+                })?;
+                if frame.command == "COMMIT" {
+                    for mut frame in frames {
+                        frame.headers.retain(|(name, _)| name != "transaction");
+                        self.handle(frame, transactions)?;
+                    }
+                }
+            }
+            "DISCONNECT" => return Ok(true),
+            command => {
+                return Err(Error::invalid_request(format!(
+                    "Unknown command '{}'",
+                    command
+                )))
+            }
+        }
+        Ok(false)
+    }
+
+    // Publish the body of a SEND frame.
+    fn publish(&self, frame: &Frame) -> Result<(), Error> {
+        let destination = required(frame, "destination")?;
+        let data = String::from_utf8(frame.body.clone())
+            .map_err(|_| Error::invalid_request(String::from("The body is not UTF-8")))?;
+        let registry = self.registry();
+        let (namespace, topic) = if let Some(topic) = destination.strip_prefix(TOPIC_PREFIX) {
+            ensure_topic(
+                DEFAULT_NAMESPACE,
+                topic,
+                registry,
+                &self.state.config.read(),
+            )?;
+            (String::from(DEFAULT_NAMESPACE), String::from(topic))
+        } else if let Some(name) = destination.strip_prefix(QUEUE_PREFIX) {
+            let subscription = registry.get_subscription(DEFAULT_NAMESPACE, name)?;
+            (subscription.topic_namespace, subscription.topic)
+        } else {
+            return Err(invalid_destination(destination));
+        };
+        let message = RawMessage {
+            data,
+            ..RawMessage::default()
+        };
+        registry.publish(&namespace, &topic, vec![message])?;
+        Ok(())
+    }
+
+    fn subscribe(&self, frame: &Frame) -> Result<(), Error> {
+        let id = required(frame, "id")?;
+        let destination = required(frame, "destination")?;
+        let ack = match frame.header("ack").unwrap_or("auto") {
+            "auto" => AckMode::Auto,
+            "client" => AckMode::Client,
+            "client-individual" => AckMode::ClientIndividual,
+            ack => {
+                return Err(Error::invalid_request(format!(
+                    "'{}' is not a valid ack mode",
+                    ack
+                )))
+            }
+        };
+        if self.subscriptions.lock().contains_key(id) {
+            return Err(Error::invalid_request(format!(
+                "Subscription id '{}' is already in use",
+                id
+            )));
+        }
+        let registry = self.registry();
+        let (name, owned) = if let Some(topic) = destination.strip_prefix(TOPIC_PREFIX) {
+            ensure_topic(
+                DEFAULT_NAMESPACE,
+                topic,
+                registry,
+                &self.state.config.read(),
+            )?;
+            let mut labels = HashMap::new();
+            labels.insert(String::from(SESSION_LABEL), self.id.clone());
+            let config = SubscriptionCreateConfig {
+                topic: String::from(topic),
+                topic_namespace: None,
+                ack_deadline: None,
+                ttl: None,
+                consumer_timeout: None,
+                historical: None,
+                labels: Some(labels),
+                description: None,
+            };
+            let name = format!("stomp/{}/{}", self.id, id);
+            create_subscription(
+                DEFAULT_NAMESPACE,
+                &name,
+                &config,
+                registry,
+                &self.state.config.read(),
+            )?;
+            (name, true)
+        } else if let Some(name) = destination.strip_prefix(QUEUE_PREFIX) {
+            registry.get_subscription(DEFAULT_NAMESPACE, name)?;
+            (String::from(name), false)
+        } else {
+            return Err(invalid_destination(destination));
+        };
+        let subscribed = Subscribed {
+            id: String::from(id),
+            destination: String::from(destination),
+            name,
+            ack,
+            owned,
+        };
+        self.subscriptions
+            .lock()
+            .insert(subscribed.id.clone(), subscribed);
+        self.signal.raise();
+        Ok(())
+    }
+
+    fn unsubscribe(&self, id: &str) {
+        if let Some(subscribed) = self.subscriptions.lock().remove(id) {
+            self.in_flight
+                .lock()
+                .retain(|_, delivery| delivery.subscription != id);
+            self.detach(&subscribed);
+        }
+    }
+
+    // Delete the subscription created for the session or hand the messages the session did not
+    // acknowledge back to the other consumers of the subscription.
+    fn detach(&self, subscribed: &Subscribed) {
+        let registry = self.registry();
+        let result = if subscribed.owned {
+            registry.delete_subscription(DEFAULT_NAMESPACE, &subscribed.name)
+        } else {
+            registry
+                .release_consumer(DEFAULT_NAMESPACE, &subscribed.name, &self.id)
+                .map(|_| ())
+        };
+        match result {
+            Ok(()) => {}
+            Err(ref e)
+                if e.code == ErrorCode::SubscriptionNotFound
+                    || e.code == ErrorCode::ConsumerNotFound => {}
+            Err(e) => warn!(
+                "Can not detach STOMP subscription '{}': {}",
+                subscribed.name, e
+            ),
+        }
+    }
+
+    // Ack or nack the message with `ack_id`, and every earlier message of its subscription if it
+    // acknowledges cumulatively.
+    fn acknowledge(&self, ack_id: &str, ack: bool) -> Result<(), Error> {
+        let unknown = || Error::invalid_request(format!("Unknown ack id '{}'", ack_id));
+        let ack_id: u64 = ack_id.parse().map_err(|_| unknown())?;
+        let mut in_flight = self.in_flight.lock();
+        let delivery = in_flight.get(&ack_id).ok_or_else(unknown)?;
+        let cumulative = self
+            .subscriptions
+            .lock()
+            .get(&delivery.subscription)
+            .map(|subscribed| subscribed.ack)
+            == Some(AckMode::Client);
+        let ack_ids: Vec<u64> = if cumulative {
+            let subscription = delivery.subscription.clone();
+            in_flight
+                .range(..=ack_id)
+                .filter(|(_, d)| d.subscription == subscription)
+                .map(|(id, _)| *id)
+                .collect()
+        } else {
+            vec![ack_id]
+        };
+        let deliveries: Vec<Delivery> = ack_ids
+            .iter()
+            .filter_map(|id| in_flight.remove(id))
+            .collect();
+        drop(in_flight);
+
+        let name = &deliveries[0].name;
+        let ids: Vec<Uuid> = deliveries.iter().map(|d| d.message).collect();
+        let registry = self.registry();
+        let result = if ack {
+            registry.ack(DEFAULT_NAMESPACE, name, &ids)
+        } else {
+            registry.nack(DEFAULT_NAMESPACE, name, &ids)
+        };
+        self.signal.raise();
+        result.map(|_| ())
+    }
+
+    // End the session.
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.signal.raise();
+        let subscriptions: Vec<_> = self.subscriptions.lock().drain().collect();
+        for (_, subscribed) in subscriptions {
+            self.detach(&subscribed);
+        }
+    }
+
+    fn send_message(&self, subscribed: &Subscribed, message: Message) -> io::Result<()> {
+        let id = message.id.to_string();
+        let mut frame = Frame::new("MESSAGE")
+            .with_header("subscription", &subscribed.id)
+            .with_header("message-id", &id)
+            .with_header("destination", &subscribed.destination)
+            .with_header("content-type", "text/plain;charset=utf-8")
+            .with_header("content-length", &message.data.len().to_string());
+        if subscribed.ack != AckMode::Auto {
+            let ack_id = self.next_ack.fetch_add(1, Ordering::SeqCst);
+            let mut in_flight = self.in_flight.lock();
+            // A redelivered message replaces its earlier delivery
+            in_flight.retain(|_, d| d.message != message.id || d.name != subscribed.name);
+            in_flight.insert(
+                ack_id,
+                Delivery {
+                    subscription: subscribed.id.clone(),
+                    name: subscribed.name.clone(),
+                    message: message.id,
+                },
+            );
+            frame = frame.with_header("ack", &ack_id.to_string());
+        }
+        self.send(&frame.with_body(message.data.as_bytes().to_vec()))?;
+        if subscribed.ack == AckMode::Auto {
+            let _ = self
+                .registry()
+                .ack(DEFAULT_NAMESPACE, &subscribed.name, &[message.id]);
+        }
+        Ok(())
+    }
+}
+
+impl Subscriber for Session {
+    type Subscription = Subscribed;
+
+    fn closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn signal(&self) -> &Arc<Signal> {
+        &self.signal
+    }
+
+    fn consumer(&self) -> &str {
+        &self.id
+    }
+
+    fn subscriptions(&self) -> Vec<Subscribed> {
+        self.subscriptions.lock().values().cloned().collect()
+    }
+
+    fn name(subscribed: &Subscribed) -> &str {
+        &subscribed.name
+    }
+
+    fn available(&self, _: &Subscribed) -> usize {
+        let available = MAX_IN_FLIGHT.saturating_sub(self.in_flight.lock().len());
+        available.min(self.state.config.read().default_max_messages.max(1))
+    }
+
+    fn send(&self, subscribed: &Subscribed, messages: Vec<Message>) -> io::Result<()> {
+        for message in messages {
+            self.send_message(subscribed, message)?;
+        }
+        Ok(())
+    }
+}
+
+fn required<'a>(frame: &'a Frame, header: &str) -> Result<&'a str, Error> {
+    frame.header(header).ok_or_else(|| {
+        Error::invalid_request(format!(
+            "The {} frame has no '{}' header",
+            frame.command, header
+        ))
+    })
+}
+
+fn invalid_destination(destination: &str) -> Error {
+    Error::invalid_request(format!(
+        "'{}' is not a valid destination, destinations start with '{}' or '{}'",
+        destination, TOPIC_PREFIX, QUEUE_PREFIX
+    ))
+}
@@ -0,0 +1,324 @@
+use super::*;
+use crate::testing::{config, unused_port, SETTLE_TIME};
+use chrono::Duration;
+use courier::Registry;
+use std::io::Read;
+
+// A STOMP client connected over TCP or a WebSocket.
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    websocket: bool,
+}
+
+impl Client {
+    fn connect(port: u16, websocket: bool) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            websocket,
+        };
+        if websocket {
+            client
+                .writer
+                .write_all(
+                    b"GET /stomp HTTP/1.1\r\n\
+                      Host: localhost\r\n\
+                      Upgrade: websocket\r\n\
+                      Connection: Upgrade\r\n\
+                      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                      Sec-WebSocket-Protocol: v10.stomp, v12.stomp\r\n\
+                      Sec-WebSocket-Version: 13\r\n\r\n",
+                )
+                .unwrap();
+            let mut response = Vec::new();
+            while !response.ends_with(b"\r\n\r\n") {
+                let mut line = Vec::new();
+                client.reader.read_until(b'\n', &mut line).unwrap();
+                response.extend(line);
+            }
+            let response = String::from_utf8(response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+            assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+            assert!(response.contains("Sec-WebSocket-Protocol: v12.stomp\r\n"));
+        }
+        client
+    }
+
+    fn send(&mut self, frame: Frame) {
+        let mut buf = Vec::new();
+        frame.write(&mut buf).unwrap();
+        if self.websocket {
+            websocket::write_frame(&mut self.writer, 0x1, &buf, Some([1, 2, 3, 4])).unwrap();
+        } else {
+            self.writer.write_all(&buf).unwrap();
+        }
+    }
+
+    fn receive(&mut self) -> Frame {
+        if self.websocket {
+            let (fin, opcode, payload) = websocket::read_frame(&mut self.reader).unwrap();
+            assert!(fin);
+            assert_eq!(opcode, 0x1);
+            Frame::read(&mut &payload[..]).unwrap().unwrap()
+        } else {
+            Frame::read(&mut self.reader).unwrap().unwrap()
+        }
+    }
+
+    fn login(port: u16, websocket: bool) -> Self {
+        let mut client = Client::connect(port, websocket);
+        client.send(
+            Frame::new("CONNECT")
+                .with_header("accept-version", "1.1,1.2")
+                .with_header("host", "localhost"),
+        );
+        let connected = client.receive();
+        assert_eq!(connected.command, "CONNECTED");
+        assert_eq!(connected.header("version"), Some("1.2"));
+        client
+    }
+
+    fn subscribe(&mut self, id: &str, destination: &str, ack: &str) {
+        self.send(
+            Frame::new("SUBSCRIBE")
+                .with_header("id", id)
+                .with_header("destination", destination)
+                .with_header("ack", ack)
+                .with_header("receipt", id),
+        );
+        let receipt = self.receive();
+        assert_eq!(receipt.command, "RECEIPT");
+        assert_eq!(receipt.header("receipt-id"), Some(id));
+    }
+
+    fn publish(&mut self, destination: &str, body: &str) {
+        self.send(
+            Frame::new("SEND")
+                .with_header("destination", destination)
+                .with_body(body.as_bytes().to_vec()),
+        );
+    }
+
+    fn receive_message(&mut self) -> Frame {
+        let frame = self.receive();
+        assert_eq!(frame.command, "MESSAGE", "{:?}", frame);
+        frame
+    }
+}
+
+fn body(frame: &Frame) -> &str {
+    std::str::from_utf8(&frame.body).unwrap()
+}
+
+#[test]
+fn stomp_frames() {
+    let frames = vec![
+        Frame::new("SEND")
+            .with_header("destination", "/topic/a:b")
+            .with_header("note", "line\r\nbreak\\")
+            .with_body(b"hello".to_vec()),
+        Frame::new("MESSAGE")
+            .with_header("content-length", "3")
+            .with_body(vec![b'a', 0, b'b']),
+        Frame::new("CONNECTED").with_header("server", "courier/1.0.0"),
+        Frame::new("DISCONNECT"),
+    ];
+    for frame in frames {
+        let mut buf = Vec::new();
+        frame.write(&mut buf).unwrap();
+        assert_eq!(Frame::read(&mut &buf[..]).unwrap(), Some(frame));
+    }
+
+    // Headers are escaped except in CONNECT and CONNECTED frames
+    let mut buf = Vec::new();
+    Frame::new("SEND")
+        .with_header("a:b", "c")
+        .write(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"SEND\na\\cb:c\n\n\0");
+    let mut buf = Vec::new();
+    Frame::new("CONNECT")
+        .with_header("login", "a:b")
+        .write(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"CONNECT\nlogin:a:b\n\n\0");
+
+    // Heart-beats and CRLF line endings are accepted, the first of repeated headers wins
+    let mut input = &b"\n\r\nSEND\r\nfoo:1\r\nfoo:2\r\n\r\nbody\0\n"[..];
+    let frame = Frame::read(&mut input).unwrap().unwrap();
+    assert_eq!(frame.command, "SEND");
+    assert_eq!(frame.header("foo"), Some("1"));
+    assert_eq!(frame.body, b"body");
+    assert_eq!(Frame::read(&mut input).unwrap(), None);
+
+    assert!(Frame::read(&mut &b"SEND\nfoo\n\n\0"[..]).is_err());
+    assert!(Frame::read(&mut &b"SEND\nfoo:\\t\n\n\0"[..]).is_err());
+    assert!(Frame::read(&mut &b"SEND\n\nbody"[..]).is_err());
+    assert!(Frame::read(&mut &b"SEND\ncontent-length:2\n\nbody\0"[..]).is_err());
+
+    // WebSocket frames
+    assert_eq!(
+        websocket::accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+    for len in &[0, 125, 126, 70_000] {
+        let payload = vec![7; *len];
+        let mut buf = Vec::new();
+        websocket::write_frame(&mut buf, 0x2, &payload, Some([9, 8, 7, 6])).unwrap();
+        let (fin, opcode, read) = websocket::read_frame(&mut &buf[..]).unwrap();
+        assert!(fin);
+        assert_eq!((opcode, read), (0x2, payload));
+    }
+}
+
+#[test]
+fn stomp_protocol() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+
+    // Only STOMP 1.2 is supported
+    let mut client = Client::connect(port, false);
+    client.send(Frame::new("CONNECT").with_header("accept-version", "1.0,1.1"));
+    let error = client.receive();
+    assert_eq!(error.command, "ERROR");
+    assert_eq!(error.header("courier-error-code"), Some("INVALID_REQUEST"));
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    // A topic destination creates a subscription for the session
+    let mut consumer = Client::login(port, false);
+    consumer.subscribe("0", "/topic/temps", "client-individual");
+    let page = registry
+        .list_subscriptions(DEFAULT_NAMESPACE, &Default::default())
+        .unwrap();
+    assert_eq!(page.items.len(), 1);
+    let name = page.items[0].name.clone();
+    assert!(name.starts_with("stomp/"));
+    assert_eq!(page.items[0].topic, "temps");
+
+    let mut producer = Client::login(port, false);
+    producer.send(
+        Frame::new("SEND")
+            .with_header("destination", "/topic/temps")
+            .with_header("receipt", "r1")
+            .with_body(b"21".to_vec()),
+    );
+    assert_eq!(producer.receive().header("receipt-id"), Some("r1"));
+
+    // A nacked message is resent immediately
+    let message = consumer.receive_message();
+    assert_eq!(message.header("subscription"), Some("0"));
+    assert_eq!(message.header("destination"), Some("/topic/temps"));
+    assert_eq!(body(&message), "21");
+    consumer.send(Frame::new("NACK").with_header("id", message.header("ack").unwrap()));
+    let resent = consumer.receive_message();
+    assert_eq!(resent.header("message-id"), message.header("message-id"));
+    consumer.send(Frame::new("ACK").with_header("id", resent.header("ack").unwrap()));
+    consumer.send(
+        Frame::new("UNSUBSCRIBE")
+            .with_header("id", "0")
+            .with_header("receipt", "r2"),
+    );
+    assert_eq!(consumer.receive().header("receipt-id"), Some("r2"));
+    assert!(registry.get_subscription(DEFAULT_NAMESPACE, &name).is_err());
+
+    // A queue destination consumes an existing subscription, `ack:client` is cumulative
+    registry
+        .create_subscription(
+            DEFAULT_NAMESPACE,
+            "jobs",
+            DEFAULT_NAMESPACE,
+            "temps",
+            Default::default(),
+            String::new(),
+            Duration::seconds(60),
+            Duration::seconds(0),
+            Duration::seconds(0),
+            false,
+        )
+        .unwrap();
+    consumer.subscribe("jobs", "/queue/jobs", "client");
+    producer.send(Frame::new("BEGIN").with_header("transaction", "t1"));
+    for body in &["a", "b", "c"] {
+        producer.send(
+            Frame::new("SEND")
+                .with_header("destination", "/queue/jobs")
+                .with_header("transaction", "t1")
+                .with_body(body.as_bytes().to_vec()),
+        );
+    }
+    producer.send(
+        Frame::new("COMMIT")
+            .with_header("transaction", "t1")
+            .with_header("receipt", "r3"),
+    );
+    assert_eq!(producer.receive().header("receipt-id"), Some("r3"));
+    let messages: Vec<Frame> = (0..3).map(|_| consumer.receive_message()).collect();
+    let bodies: Vec<&str> = messages.iter().map(body).collect();
+    assert_eq!(bodies, vec!["a", "b", "c"]);
+    consumer.send(
+        Frame::new("ACK")
+            .with_header("id", messages[1].header("ack").unwrap())
+            .with_header("receipt", "r4"),
+    );
+    assert_eq!(consumer.receive().header("receipt-id"), Some("r4"));
+    assert_eq!(
+        registry.consumers(DEFAULT_NAMESPACE, "jobs").unwrap()[0].in_flight,
+        1
+    );
+
+    // The messages the session did not acknowledge are released when it disconnects
+    consumer.send(Frame::new("DISCONNECT").with_header("receipt", "bye"));
+    assert_eq!(consumer.receive().header("receipt-id"), Some("bye"));
+    thread::sleep(SETTLE_TIME * 3);
+    assert!(registry
+        .consumers(DEFAULT_NAMESPACE, "jobs")
+        .unwrap()
+        .is_empty());
+    let messages = registry
+        .pull(DEFAULT_NAMESPACE, "jobs", 10, None, None)
+        .unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(&*messages[0].data, "c");
+
+    // Errors end the connection
+    producer.publish("/exchange/temps", "x");
+    let error = producer.receive();
+    assert_eq!(error.command, "ERROR");
+    assert!(error.header("message").unwrap().contains("/exchange/temps"));
+}
+
+#[test]
+fn stomp_websocket() {
+    let port = unused_port();
+    let registry = Registry::new();
+    start(&format!("127.0.0.1:{}", port), &registry, &config()).unwrap();
+
+    let mut client = Client::login(port, true);
+    client.subscribe("0", "/topic/events", "auto");
+    client.publish("/topic/events", "hello");
+    let message = client.receive_message();
+    assert_eq!(body(&message), "hello");
+    assert_eq!(message.header("ack"), None);
+
+    // Pings are answered and a close is echoed
+    websocket::write_frame(&mut client.writer, 0x9, b"ping", Some([1, 1, 1, 1])).unwrap();
+    let (_, opcode, payload) = websocket::read_frame(&mut client.reader).unwrap();
+    assert_eq!((opcode, &payload[..]), (0xa, &b"ping"[..]));
+    websocket::write_frame(&mut client.writer, 0x8, &[0x03, 0xe8], Some([1, 1, 1, 1])).unwrap();
+    let (_, opcode, payload) = websocket::read_frame(&mut client.reader).unwrap();
+    assert_eq!((opcode, &payload[..]), (0x8, &[0x03, 0xe8][..]));
+    thread::sleep(SETTLE_TIME * 3);
+    assert!(registry
+        .list_subscriptions(DEFAULT_NAMESPACE, &Default::default())
+        .unwrap()
+        .items
+        .is_empty());
+}
@@ -0,0 +1,248 @@
+//! The server side of WebSockets (RFC 6455) carrying STOMP frames.
+//!
+//! The messages of a WebSocket are read as one stream of bytes so frames split over several
+//! messages, or several frames in one message, are read like on a TCP connection. Every write is
+//! sent as one text message.
+
+use parking_lot::Mutex;
+use sha1::Sha1;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::Arc;
+
+/// The GUID the key of a handshake is hashed with.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The STOMP sub-protocols in order of preference.
+const SUBPROTOCOLS: [&str; 3] = ["v12.stomp", "v11.stomp", "v10.stomp"];
+
+/// The maximum length of the payload of a WebSocket frame.
+const MAX_PAYLOAD_LEN: u64 = 16 * 1024 * 1024;
+
+/// The maximum number of header lines of a handshake request.
+const MAX_HEADERS: usize = 100;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Compute the `Sec-WebSocket-Accept` header of the response to a handshake with `key`.
+pub fn accept_key(key: &str) -> String {
+    let mut hash = Sha1::new();
+    hash.update(key.as_bytes());
+    hash.update(WEBSOCKET_GUID.as_bytes());
+    base64::encode(&hash.digest().bytes())
+}
+
+/// Read the HTTP request of a WebSocket handshake and accept it.
+pub fn handshake(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut key = None;
+    let mut upgrade = false;
+    let mut protocols = Vec::new();
+    for _ in 0..MAX_HEADERS {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = match line.find(':') {
+            Some(colon) => (&line[..colon], line[colon + 1..].trim()),
+            None => continue,
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => key = Some(String::from(value)),
+            "sec-websocket-protocol" => {
+                protocols.extend(value.split(',').map(|p| String::from(p.trim())))
+            }
+            _ => {}
+        }
+    }
+
+    let key = match key {
+        Some(ref key) if upgrade && request_line.starts_with("GET ") => key,
+        _ => {
+            writer.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is not a WebSocket handshake", request_line.trim_end()),
+            ));
+        }
+    };
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n",
+        accept_key(key)
+    );
+    if let Some(protocol) = SUBPROTOCOLS
+        .iter()
+        .find(|p| protocols.iter().any(|offered| offered == *p))
+    {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+    }
+    response.push_str("\r\n");
+    writer.write_all(response.as_bytes())?;
+    writer.flush()
+}
+
+/// Write a frame, `mask` is only set by clients.
+pub fn write_frame(
+    writer: &mut impl Write,
+    opcode: u8,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(payload.len() + 14);
+    buf.push(0x80 | opcode);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => buf.push(mask_bit | len as u8),
+        len if len <= 0xffff => {
+            buf.push(mask_bit | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            buf.push(mask_bit | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            buf.extend_from_slice(&mask);
+            buf.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        }
+        None => buf.extend_from_slice(payload),
+    }
+    writer.write_all(&buf)?;
+    writer.flush()
+}
+
+/// Read a frame returning its FIN flag, opcode and unmasked payload.
+pub fn read_frame(reader: &mut impl Read) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0f;
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if len > MAX_PAYLOAD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the WebSocket frame of {} bytes is too long", len),
+        ));
+    }
+    let mask = if header[1] & 0x80 != 0 {
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask)?;
+        Some(mask)
+    } else {
+        None
+    };
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    if let Some(mask) = mask {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok((fin, opcode, payload))
+}
+
+/// Reads the payload of the data messages of a WebSocket, control frames are answered through
+/// the shared writer.
+pub struct WebSocketReader<R, W> {
+    reader: R,
+    writer: Arc<Mutex<W>>,
+    payload: Vec<u8>,
+    position: usize,
+    closed: bool,
+}
+
+impl<R: Read, W: Write> WebSocketReader<R, W> {
+    pub fn new(reader: R, writer: Arc<Mutex<W>>) -> Self {
+        WebSocketReader {
+            reader,
+            writer,
+            payload: Vec::new(),
+            position: 0,
+            closed: false,
+        }
+    }
+}
+
+impl<R: Read, W: Write> Read for WebSocketReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.payload.len() {
+            if self.closed {
+                return Ok(0);
+            }
+            let (_, opcode, payload) = read_frame(&mut self.reader)?;
+            match opcode {
+                CONTINUATION | TEXT | BINARY => {
+                    self.payload = payload;
+                    self.position = 0;
+                }
+                CLOSE => {
+                    self.closed = true;
+                    // Echo the status code of the client
+                    let status = &payload[..payload.len().min(2)];
+                    write_frame(&mut *self.writer.lock(), CLOSE, status, None)?;
+                }
+                PING => write_frame(&mut *self.writer.lock(), PONG, &payload, None)?,
+                PONG => {}
+                opcode => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown WebSocket opcode {}", opcode),
+                    ))
+                }
+            }
+        }
+        let len = buf.len().min(self.payload.len() - self.position);
+        buf[..len].copy_from_slice(&self.payload[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Sends every write as a text message through the shared writer.
+pub struct WebSocketWriter<W> {
+    writer: Arc<Mutex<W>>,
+}
+
+impl<W: Write> WebSocketWriter<W> {
+    pub fn new(writer: Arc<Mutex<W>>) -> Self {
+        WebSocketWriter { writer }
+    }
+}
+
+impl<W: Write> Write for WebSocketWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_frame(&mut *self.writer.lock(), TEXT, buf, None)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.lock().flush()
+    }
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by the tests of the apis.
+use parking_lot::RwLock;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time;
+
+use crate::http_protocol::{Config, SharedConfig};
+
+/// How long a session is given to handle what it received before the registry is checked.
+pub const SETTLE_TIME: time::Duration = time::Duration::from_millis(100);
+
+/// Get a localhost port nothing listens on.
+pub fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Get the configuration of a service started without any settings.
+pub fn config() -> SharedConfig {
+    Arc::new(RwLock::new(Config::default()))
+}
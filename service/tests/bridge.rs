@@ -0,0 +1,150 @@
+//! Runs two `courier` processes on localhost ports which bridge a topic to each other.
+
+mod common;
+
+use common::{courier, unused_port, wait_for, Process};
+use courier::bridge::BridgeMetrics;
+use courier_client::{Client, SubscriptionCreateConfig, TopicCreateConfig};
+use serde_derive::Deserialize;
+use std::thread;
+use std::time;
+
+#[derive(Deserialize)]
+struct BridgeList {
+    bridges: Vec<BridgeMetrics>,
+}
+
+// A courier instance which can be stopped and restarted.
+struct Instance {
+    origin: &'static str,
+    port: u16,
+    bridge: String,
+    process: Option<Process>,
+}
+
+impl Instance {
+    fn new(origin: &'static str, port: u16, remote_port: u16) -> Self {
+        let bridge = format!("to-remote=events@http://127.0.0.1:{}", remote_port);
+        let mut instance = Instance {
+            origin,
+            port,
+            bridge,
+            process: None,
+        };
+        instance.spawn();
+        instance
+    }
+
+    fn spawn(&mut self) {
+        self.process = Some(Process::spawn(
+            courier()
+                .args(["--host", "127.0.0.1", "--port"])
+                .arg(self.port.to_string())
+                .args(["--uncolored-log", "run", "--origin", self.origin])
+                .args(["--bridge", &self.bridge]),
+        ));
+    }
+
+    fn kill(&mut self) {
+        self.process = None;
+    }
+
+    fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    fn client(&self) -> Client {
+        Client::new(&self.url()).unwrap()
+    }
+
+    // Wait until the instance serves requests and create the bridged topic, the subscription of
+    // the bridge, and a subscription to read the topic.
+    fn setup(&self) {
+        let client = self.client();
+        wait_for(|| client.get_origin().is_ok());
+        client
+            .create_topic("events", &TopicCreateConfig::new())
+            .unwrap();
+        client
+            .create_subscription("to-remote", &SubscriptionCreateConfig::new("events"))
+            .unwrap();
+        // Read from the beginning, the bridge may forward messages before the subscription exists
+        let mut config = SubscriptionCreateConfig::new("events");
+        config.historical = Some(true);
+        client.create_subscription("reader", &config).unwrap();
+    }
+
+    fn bridge(&self) -> BridgeMetrics {
+        let url = format!("{}/api/v1/bridges", self.url());
+        let mut list: BridgeList = reqwest::get(&url).unwrap().json().unwrap();
+        list.bridges.remove(0)
+    }
+}
+
+#[test]
+fn bridge_both_directions() {
+    let (site_port, central_port) = (unused_port(), unused_port());
+    let site = Instance::new("site", site_port, central_port);
+    let mut central = Instance::new("central", central_port, site_port);
+    site.setup();
+    central.setup();
+    let (site_client, central_client) = (site.client(), central.client());
+
+    // Messages are forwarded to the remote with their origin and are not bridged back
+    site_client
+        .publish_one("events", String::from("a"))
+        .unwrap();
+    central_client
+        .publish_one("events", String::from("b"))
+        .unwrap();
+    wait_for(|| site.bridge().forwarded_all_time == 1 && site.bridge().skipped_all_time == 1);
+    wait_for(|| central.bridge().forwarded_all_time == 1 && central.bridge().skipped_all_time == 1);
+    let mut messages = central_client.pull("reader", 10).unwrap().messages;
+    messages.sort_by(|a, b| a.data.cmp(&b.data));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].data, "a");
+    assert_eq!(messages[0].origin, Some(String::from("site")));
+    assert_eq!(messages[1].data, "b");
+    assert_eq!(messages[1].origin, None);
+    let mut messages = site_client.pull("reader", 10).unwrap().messages;
+    messages.sort_by(|a, b| a.data.cmp(&b.data));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].origin, None);
+    assert_eq!(messages[1].origin, Some(String::from("central")));
+    let metrics = site.bridge();
+    assert_eq!(metrics.remote_origin, Some(String::from("central")));
+    assert_eq!(metrics.in_flight, 0);
+    assert_eq!(metrics.backlog, 0);
+    assert_eq!(metrics.lag, 0);
+    assert!(metrics.last_forwarded.is_some());
+
+    // Messages are held until the remote is back
+    central.kill();
+    site_client
+        .publish_one("events", String::from("c"))
+        .unwrap();
+    wait_for(|| site.bridge().error.is_some());
+    thread::sleep(time::Duration::from_millis(100));
+    let metrics = site.bridge();
+    assert_eq!(metrics.in_flight, 1);
+    assert!(metrics.lag >= 100);
+    assert!(metrics.failures_all_time >= 1);
+
+    // A failed batch is nacked and pulled again with the messages published since
+    site_client
+        .publish_one("events", String::from("d"))
+        .unwrap();
+    wait_for(|| site.bridge().in_flight == 2);
+    central.spawn();
+    central.setup();
+    wait_for(|| site.bridge().forwarded_all_time == 3);
+    let metrics = site.bridge();
+    assert_eq!(metrics.in_flight, 0);
+    assert_eq!(metrics.error, None);
+    let mut messages = central_client.pull("reader", 10).unwrap().messages;
+    messages.sort_by(|a, b| a.data.cmp(&b.data));
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].data, "c");
+    assert_eq!(messages[0].origin, Some(String::from("site")));
+    assert_eq!(messages[1].data, "d");
+}
@@ -0,0 +1,297 @@
+//! Runs a cluster of `courier` processes on localhost ports.
+
+mod common;
+
+use common::{courier, poll, unused_port, wait_for, Process};
+use courier::cluster::{ClusterStatus, Role};
+use courier_client::{Client, DeliveryStatus, SubscriptionCreateConfig, TopicCreateConfig};
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time;
+
+const NODES: u64 = 3;
+
+// A cluster node which can be stopped and restarted.
+struct Node {
+    http_port: u16,
+    process: Option<Process>,
+}
+
+impl Node {
+    fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.http_port)
+    }
+
+    fn client(&self) -> Client {
+        Client::new(&self.url()).unwrap()
+    }
+
+    fn status(&self) -> Option<ClusterStatus> {
+        let url = format!("{}/api/v1/cluster", self.url());
+        reqwest::get(&url)
+            .ok()?
+            .json::<Option<ClusterStatus>>()
+            .ok()?
+    }
+
+    fn kill(&mut self) {
+        self.process = None;
+    }
+}
+
+struct Cluster {
+    dir: PathBuf,
+    cluster_ports: Vec<u16>,
+    nodes: Vec<Node>,
+}
+
+impl Cluster {
+    fn start() -> Self {
+        let dir = std::env::temp_dir().join(format!("courier-cluster-{}", uuid::Uuid::new_v4()));
+        let mut cluster = Cluster {
+            dir,
+            cluster_ports: (0..NODES).map(|_| unused_port()).collect(),
+            nodes: Vec::new(),
+        };
+        for id in 1..=NODES {
+            cluster.nodes.push(Node {
+                http_port: unused_port(),
+                process: None,
+            });
+            cluster.spawn(id);
+        }
+        cluster
+    }
+
+    fn node(&mut self, id: u64) -> &mut Node {
+        &mut self.nodes[id as usize - 1]
+    }
+
+    fn spawn(&mut self, id: u64) {
+        let mut command = courier();
+        command
+            .args(["--host", "127.0.0.1", "--port"])
+            .arg(self.nodes[id as usize - 1].http_port.to_string())
+            .args(["--uncolored-log", "run", "--cluster-node-id"])
+            .arg(id.to_string())
+            .arg("--cluster-listen")
+            .arg(format!("127.0.0.1:{}", self.cluster_ports[id as usize - 1]))
+            .arg("--cluster-data-dir")
+            .arg(self.dir.join(id.to_string()));
+        for peer in (1..=NODES).filter(|&p| p != id) {
+            command.arg("--cluster-peer").arg(format!(
+                "{}=127.0.0.1:{}",
+                peer,
+                self.cluster_ports[peer as usize - 1]
+            ));
+        }
+        self.node(id).process = Some(Process::spawn(&mut command));
+    }
+
+    // Wait until a running node is the leader followed by every other running node.
+    fn wait_for_leader(&self) -> u64 {
+        poll(|| {
+            let statuses: Vec<ClusterStatus> = self
+                .nodes
+                .iter()
+                .filter(|n| n.process.is_some())
+                .filter_map(|n| n.status())
+                .collect();
+            let running = self.nodes.iter().filter(|n| n.process.is_some()).count();
+            let leader = statuses.iter().find(|s| s.role == Role::Leader)?;
+            if statuses.len() == running && statuses.iter().all(|s| s.leader == Some(leader.id)) {
+                Some(leader.id)
+            } else {
+                None
+            }
+        })
+    }
+
+    // Stop the leader and wait until the other nodes elect a new one.
+    fn fail_over(&mut self) -> (u64, u64) {
+        let leader = self.wait_for_leader();
+        self.node(leader).kill();
+        let new_leader = self.wait_for_leader();
+        assert_ne!(new_leader, leader);
+        (leader, new_leader)
+    }
+
+    // Wait until the node applied every committed entry of the leader.
+    fn wait_for_catch_up(&self, id: u64, leader: u64) {
+        wait_for(|| {
+            let leader = self.nodes[leader as usize - 1].status();
+            let node = self.nodes[id as usize - 1].status();
+            match (leader, node) {
+                (Some(leader), Some(node)) => node.last_applied >= leader.commit_index,
+                _ => false,
+            }
+        });
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for node in &mut self.nodes {
+            node.kill();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn cluster_failover_and_catch_up() {
+    let mut cluster = Cluster::start();
+    let leader = cluster.wait_for_leader();
+    let follower = (1..=NODES).find(|&id| id != leader).unwrap();
+
+    // Writes to a follower are forwarded to the leader
+    let client = cluster.node(follower).client();
+    client
+        .create_topic("cluster-topic", &TopicCreateConfig::new())
+        .unwrap();
+    client
+        .create_subscription(
+            "cluster-sub",
+            &SubscriptionCreateConfig::new("cluster-topic"),
+        )
+        .unwrap();
+    let published = client
+        .publish(
+            "cluster-topic",
+            vec![String::from("a"), String::from("b"), String::from("c")],
+        )
+        .unwrap();
+    assert_eq!(published.message_ids.len(), 3);
+
+    // Stop the leader, the remaining nodes elect a new one which has every message
+    let (leader, new_leader) = cluster.fail_over();
+    let survivor = (1..=NODES)
+        .find(|&id| id != leader && id != new_leader)
+        .unwrap();
+    let client = cluster.node(survivor).client();
+    let messages = client.pull("cluster-sub", 10).unwrap().messages;
+    let ids: Vec<_> = messages.iter().map(|m| m.id).collect();
+    assert_eq!(ids, published.message_ids);
+    let acked = client.ack("cluster-sub", vec![ids[0], ids[1]]).unwrap();
+    assert_eq!(acked.message_ids, vec![ids[0], ids[1]]);
+    client
+        .publish_one("cluster-topic", String::from("d"))
+        .unwrap();
+
+    // The old leader restarts and catches up with the writes it missed
+    cluster.spawn(leader);
+    let current_leader = cluster.wait_for_leader();
+    cluster.wait_for_catch_up(leader, current_leader);
+    let client = cluster.node(leader).client();
+    let subscription = client.get_subscription("cluster-sub").unwrap();
+    assert_eq!(subscription.name, "cluster-sub");
+    let status = client.get_message_status(ids[0]).unwrap();
+    assert_eq!(status.subscriptions.len(), 1);
+    assert_eq!(status.subscriptions[0].status, DeliveryStatus::Acked);
+    let topic_messages = client
+        .read("cluster-topic", None, Some(10))
+        .unwrap()
+        .messages;
+    assert_eq!(topic_messages.len(), 4);
+    assert_eq!(topic_messages[3].data, "d");
+
+    // Only the unacked message is left pending
+    let pulled = client.pull("cluster-sub", 10).unwrap().messages;
+    assert_eq!(pulled.len(), 1);
+    assert_eq!(pulled[0].data, "d");
+}
+
+#[test]
+fn cluster_lease_expiry_and_failover() {
+    let mut cluster = Cluster::start();
+    let leader = cluster.wait_for_leader();
+    let follower = (1..=NODES).find(|&id| id != leader).unwrap();
+
+    let client = cluster.node(follower).client();
+    client
+        .create_topic("lease-topic", &TopicCreateConfig::new())
+        .unwrap();
+    let mut config = SubscriptionCreateConfig::new("lease-topic");
+    config.ack_deadline = Some(1);
+    client.create_subscription("lease-sub", &config).unwrap();
+    let id = client
+        .publish_one("lease-topic", String::from("a"))
+        .unwrap()
+        .message_ids[0];
+    assert_eq!(client.pull("lease-sub", 10).unwrap().messages.len(), 1);
+
+    // The lease expires before the leader stops, the new leader redelivers the message
+    thread::sleep(time::Duration::from_millis(1500));
+    let (leader, new_leader) = cluster.fail_over();
+    let messages = cluster
+        .node(new_leader)
+        .client()
+        .pull("lease-sub", 10)
+        .unwrap()
+        .messages;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id, id);
+    assert_eq!(messages[0].tries, 2);
+
+    // Every node has the same deadline for the redelivered message, also the restarted one
+    cluster.spawn(leader);
+    let current_leader = cluster.wait_for_leader();
+    let leases: Vec<_> = (1..=NODES)
+        .map(|node| {
+            cluster.wait_for_catch_up(node, current_leader);
+            let status = cluster.node(node).client().get_message_status(id).unwrap();
+            // Whether the lease is pending or expired depends on when the node is asked
+            match status.subscriptions[0].status {
+                DeliveryStatus::Pending { tries, deadline }
+                | DeliveryStatus::Expired { tries, deadline } => (tries, deadline),
+                ref status => panic!("unexpected delivery status {:?}", status),
+            }
+        })
+        .collect();
+    assert_eq!(leases[0].0, 2);
+    assert!(leases.iter().all(|l| *l == leases[0]));
+}
+
+#[test]
+fn cluster_topic_ttl_needs_a_leader() {
+    let mut cluster = Cluster::start();
+    let leader = cluster.wait_for_leader();
+    let client = cluster.node(leader).client();
+    let mut config = TopicCreateConfig::new();
+    config.ttl = Some(2);
+    client.create_topic("short-topic", &config).unwrap();
+    client
+        .create_topic("kept-topic", &TopicCreateConfig::new())
+        .unwrap();
+    for id in 1..=NODES {
+        cluster.wait_for_catch_up(id, leader);
+    }
+
+    // Without a majority no leader commits the expiration, the remaining node keeps the topic
+    // past its ttl instead of expiring it on its own
+    let survivor = (1..=NODES).find(|&id| id != leader).unwrap();
+    for id in (1..=NODES).filter(|&id| id != survivor) {
+        cluster.node(id).kill();
+    }
+    thread::sleep(time::Duration::from_secs(3));
+    let client = cluster.node(survivor).client();
+    client.get_topic("short-topic").unwrap();
+
+    // Once a leader is elected the topic expires on every node
+    for id in (1..=NODES).filter(|&id| id != survivor) {
+        cluster.spawn(id);
+    }
+    cluster.wait_for_leader();
+    wait_for(|| {
+        cluster
+            .nodes
+            .iter()
+            .all(|n| n.client().get_topic("short-topic").is_err())
+    });
+    for id in 1..=NODES {
+        let client = cluster.node(id).client();
+        assert!(client.get_topic("short-topic").is_err());
+        client.get_topic("kept-topic").unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+//! Helpers shared by the tests running `courier` processes.
+// Every test uses only some of the helpers
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time;
+
+/// Get a localhost port nothing listens on.
+pub fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Create a command running the `courier` binary with its output discarded.
+pub fn courier() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_courier"));
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+/// A courier process which is killed when dropped.
+pub struct Process(Child);
+
+impl Process {
+    /// Spawn a process running `command`.
+    pub fn spawn(command: &mut Command) -> Self {
+        Process(command.spawn().unwrap())
+    }
+
+    /// Get the process id.
+    pub fn id(&self) -> u32 {
+        self.0.id()
+    }
+
+    /// Check if the process has not exited.
+    pub fn running(&mut self) -> bool {
+        self.0.try_wait().unwrap().is_none()
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Wait until `f` returns a value and return it or fail after 10 seconds.
+pub fn poll<T>(f: impl Fn() -> Option<T>) -> T {
+    for _ in 0..200 {
+        if let Some(value) = f() {
+            return value;
+        }
+        thread::sleep(time::Duration::from_millis(50));
+    }
+    panic!("timed out");
+}
+
+/// Wait until `f` returns true or fail after 10 seconds.
+pub fn wait_for(f: impl Fn() -> bool) {
+    poll(|| f().then_some(()))
+}
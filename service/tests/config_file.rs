@@ -0,0 +1,100 @@
+//! Runs a `courier` process configured by a file which is changed while it runs.
+#![cfg(unix)]
+
+mod common;
+
+use common::{courier, unused_port, wait_for, Process};
+use courier_client::{Client, TopicCreateConfig};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn write_config(path: &Path, port: u16, message_ttl: i64) {
+    let contents = format!(
+        "host = \"127.0.0.1\"\n\
+         port = {}\n\
+         default_topic_ttl = 100\n\
+         default_message_ttl = {}\n",
+        port, message_ttl
+    );
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn config_file_reload() {
+    let directory = std::env::temp_dir().join(format!("courier-config-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("courier.toml");
+    let port = unused_port();
+    write_config(&path, port, 60);
+
+    let mut process = Process::spawn(
+        courier()
+            .args(["--uncolored-log", "run", "--config"])
+            .arg(&path)
+            .args(["--default-topic-ttl", "30"]),
+    );
+    let client = Client::new(&format!("http://127.0.0.1:{}", port)).unwrap();
+    wait_for(|| client.heartbeat());
+
+    // The flags take precedence over the file
+    let create_topic = || {
+        client
+            .create_topic_with_uuid(&TopicCreateConfig::new())
+            .unwrap()
+    };
+    let topic = create_topic();
+    assert_eq!((topic.message_ttl, topic.ttl), (60, 30));
+
+    // The defaults are reloaded when the file changes
+    write_config(&path, port, 120);
+    wait_for(|| create_topic().message_ttl == 120);
+    assert_eq!(create_topic().ttl, 30);
+
+    // And on SIGHUP
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    write_config(&path, port, 240);
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    let status = Command::new("kill")
+        .args(["-HUP", &process.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    wait_for(|| create_topic().message_ttl == 240);
+    assert!(process.running());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn config_file_environment() {
+    let directory = std::env::temp_dir().join(format!("courier-config-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("courier.toml");
+    let port = unused_port();
+    write_config(&path, port, 60);
+
+    let _process = Process::spawn(
+        courier()
+            .args(["--uncolored-log", "run", "--config"])
+            .arg(&path)
+            .args(["--default-message-ttl", "45"])
+            .env("COURIER_DEFAULT_TOPIC_TTL", "30")
+            .env("COURIER_DEFAULT_MESSAGE_TTL", "90"),
+    );
+    let client = Client::new(&format!("http://127.0.0.1:{}", port)).unwrap();
+    wait_for(|| client.heartbeat());
+
+    // The environment takes precedence over the file and the flags over the environment
+    let topic = client
+        .create_topic_with_uuid(&TopicCreateConfig::new())
+        .unwrap();
+    assert_eq!((topic.message_ttl, topic.ttl), (45, 30));
+
+    fs::remove_dir_all(&directory).unwrap();
+}
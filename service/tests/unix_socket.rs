@@ -0,0 +1,60 @@
+//! Runs a `courier` process serving the api only on a Unix domain socket.
+#![cfg(unix)]
+
+mod common;
+
+use common::{courier, unused_port, wait_for, Process};
+use courier_client::{ApiError, Client, ErrorCode, ListOptions, TopicCreateConfig};
+use std::fs;
+use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+
+#[test]
+fn unix_socket_only() {
+    let directory = std::env::temp_dir().join(format!("courier-uds-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("courier.sock");
+    // A socket left behind by a previous run is replaced
+    drop(UnixListener::bind(&path).unwrap());
+
+    let port = unused_port();
+    let _process = Process::spawn(
+        courier()
+            .args(["--host", "127.0.0.1", "--port"])
+            .arg(port.to_string())
+            .args(["--uncolored-log", "run", "--unix-socket"])
+            .arg(&path)
+            .args(["--unix-socket-mode", "600", "--unix-socket-only"]),
+    );
+    let client = Client::with_unix_socket(&path).unwrap();
+    wait_for(|| client.heartbeat());
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+
+    // Bodies, queries and errors go over the socket
+    client
+        .create_topic("temps", &TopicCreateConfig::new())
+        .unwrap();
+    client
+        .create_topic("other", &TopicCreateConfig::new())
+        .unwrap();
+    let published = client.publish_one("temps", String::from("21")).unwrap();
+    let message = client
+        .get_message("temps", published.message_ids[0])
+        .unwrap();
+    assert_eq!(message.data, "21");
+    let mut options = ListOptions::new();
+    options.prefix = Some(String::from("te"));
+    let topics = client.list_topics_page(&options).unwrap().topics;
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0].name, "temps");
+    let error = client.get_topic("missing").unwrap_err();
+    let error = error.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(error.code, ErrorCode::TopicNotFound);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
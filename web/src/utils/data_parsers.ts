@@ -335,9 +335,11 @@ export function courierStateFromJson(
     } else {
       logError("Failed to parse CourierState 'updated' is not a date", json);
     }
-    if (isObject(json.topics)) {
-      for (const name of Object.keys(json.topics)) {
-        c.topicMap.set(name, topicMetricsFromJson(name, json.topics[name]));
+    // The dashboard only displays the default namespace
+    const namespace = isObject(json.namespaces) ? json.namespaces.default : {};
+    if (isObject(namespace) && isObject(namespace.topics)) {
+      for (const name of Object.keys(namespace.topics)) {
+        c.topicMap.set(name, topicMetricsFromJson(name, namespace.topics[name]));
 
         // Initialize a topic to subscription lookup
         c.topic2subscriptions.set(name, []);
@@ -345,14 +347,19 @@ export function courierStateFromJson(
     } else {
       logError("Failed to parse CourierState 'topics' is not an array", json);
     }
-    if (isObject(json.subscriptions)) {
-      for (const name of Object.keys(json.subscriptions)) {
-        const subscription = subscriptionMetricsFromJson(name, json.subscriptions[name]);
-        c.subscriptionMap.set(name, subscription);
+    if (isObject(namespace) && isObject(namespace.subscriptions)) {
+      for (const name of Object.keys(namespace.subscriptions)) {
+        const subscription = subscriptionMetricsFromJson(name, namespace.subscriptions[name]);
         const topicName = subscription.topic;
 
-        // Calculate computed fields
+        // Skip subscriptions to topics in other namespaces
         const topic = c.topicMap.get(topicName);
+        if (topic === undefined) {
+          continue;
+        }
+        c.subscriptionMap.set(name, subscription);
+
+        // Calculate computed fields
         const previousSubscription = previous.subscriptionMap.get(name);
         computeSubscriptionMetrics(subscription, topic, previousSubscription);
 